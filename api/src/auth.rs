@@ -13,12 +13,18 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::rest::{Error, ErrorKind};
 use crate::router::{Handler, HandlerObj, ResponseFuture};
+use crate::util::to_base64;
 use crate::web::response;
+use failure::ResultExt;
 use futures::future::ok;
 use hyper::header::{HeaderValue, AUTHORIZATION, WWW_AUTHENTICATE};
 use hyper::{Body, Request, Response, StatusCode};
 use ring::constant_time::verify_slices_are_equal;
+use std::fmt;
+use std::fs;
+use toml;
 
 lazy_static! {
 	pub static ref GOTTS_BASIC_REALM: HeaderValue =
@@ -68,6 +74,219 @@ impl Handler for BasicAuthMiddleware {
 	}
 }
 
+/// What an API token may be used for. The admin scope grants all the others.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiScope {
+	/// Reading the chain, pool and node data.
+	Read,
+	/// Pushing transactions to the pool.
+	PushTx,
+	/// Everything, the owner API included.
+	Admin,
+}
+
+impl fmt::Display for ApiScope {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			ApiScope::Read => write!(f, "read"),
+			ApiScope::PushTx => write!(f, "push_tx"),
+			ApiScope::Admin => write!(f, "admin"),
+		}
+	}
+}
+
+/// An API token, as listed in the API tokens file.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ApiToken {
+	/// Name of the token, only used in the logs and to rate limit it.
+	pub name: String,
+	/// The secret sent by the clients.
+	pub token: String,
+	pub scopes: Vec<ApiScope>,
+	/// Requests per minute allowed to this token, overriding the default rate
+	/// limit of the tokens.
+	#[serde(default)]
+	pub rate_limit: Option<u32>,
+}
+
+impl ApiToken {
+	/// Token with all the scopes, to keep the `api_secret` working along the
+	/// tokens file.
+	pub fn admin(name: &str, token: String) -> ApiToken {
+		ApiToken {
+			name: name.to_owned(),
+			token,
+			scopes: vec![ApiScope::Admin],
+			rate_limit: None,
+		}
+	}
+
+	/// See `ApiAccess::allows`.
+	pub fn allows(&self, scope: ApiScope) -> bool {
+		ApiAccess::from(self).allows(scope)
+	}
+}
+
+#[derive(Deserialize)]
+struct ApiTokensFile {
+	#[serde(default)]
+	tokens: Vec<ApiToken>,
+}
+
+/// Loads the API tokens from a TOML file with a `[[tokens]]` table per token:
+///
+/// ```toml
+/// [[tokens]]
+/// name = "explorer"
+/// token = "..."
+/// scopes = ["read"]
+/// rate_limit = 120
+/// ```
+pub fn load_api_tokens(path: &str) -> Result<Vec<ApiToken>, Error> {
+	let content = fs::read_to_string(path).context(ErrorKind::Internal(format!(
+		"failed to read the API tokens file {}",
+		path
+	)))?;
+	let file: ApiTokensFile = toml::from_str(&content)
+		.map_err(|e| ErrorKind::Internal(format!("invalid API tokens file {}: {}", path, e)))?;
+	Ok(file.tokens)
+}
+
+/// Access control of an API server, on top of its api secret.
+#[derive(Clone, Debug, Default)]
+pub struct ApiAccessConfig {
+	/// Tokens accepted, the api secret still being accepted as an admin token.
+	/// The api secret alone is checked if none.
+	pub tokens: Option<Vec<ApiToken>>,
+	/// Requests per minute allowed to a client address.
+	pub rate_limit_per_ip: Option<u32>,
+	/// Requests per minute allowed to a token without its own rate limit.
+	pub rate_limit_per_token: Option<u32>,
+}
+
+/// The token a request was authenticated with, added to the request
+/// extensions by the `TokenAuthMiddleware`.
+#[derive(Clone, Debug)]
+pub struct ApiAccess {
+	pub name: String,
+	pub scopes: Vec<ApiScope>,
+	pub rate_limit: Option<u32>,
+}
+
+impl ApiAccess {
+	/// Whether the token has the scope, or the admin one granting them all.
+	pub fn allows(&self, scope: ApiScope) -> bool {
+		self.scopes.contains(&scope) || self.scopes.contains(&ApiScope::Admin)
+	}
+}
+
+impl<'a> From<&'a ApiToken> for ApiAccess {
+	fn from(token: &'a ApiToken) -> ApiAccess {
+		ApiAccess {
+			name: token.name.clone(),
+			scopes: token.scopes.clone(),
+			rate_limit: token.rate_limit,
+		}
+	}
+}
+
+/// Token Authentication Middleware, accepting any of several tokens, each
+/// with its scopes. The token goes either as a bearer token or as the
+/// password of the basic authentication, like the api secret.
+pub struct TokenAuthMiddleware {
+	// the token, its bearer and basic authorization headers
+	tokens: Vec<(ApiToken, String, String)>,
+	scope: ApiScope,
+	route_scopes: Vec<(&'static str, ApiScope)>,
+	basic_realm: &'static HeaderValue,
+}
+
+impl TokenAuthMiddleware {
+	/// Middleware requiring the given scope on all routes.
+	pub fn new(
+		tokens: Vec<ApiToken>,
+		scope: ApiScope,
+		basic_realm: &'static HeaderValue,
+	) -> TokenAuthMiddleware {
+		let tokens = tokens
+			.into_iter()
+			.map(|t| {
+				let bearer = format!("Bearer {}", t.token);
+				let basic = format!("Basic {}", to_base64(&format!("gotts:{}", t.token)));
+				(t, bearer, basic)
+			})
+			.collect();
+		TokenAuthMiddleware {
+			tokens,
+			scope,
+			route_scopes: vec![],
+			basic_realm,
+		}
+	}
+
+	/// Requires another scope on a route.
+	pub fn with_route_scope(mut self, path: &'static str, scope: ApiScope) -> TokenAuthMiddleware {
+		self.route_scopes.push((path, scope));
+		self
+	}
+
+	fn required_scope(&self, path: &str) -> ApiScope {
+		let path = path.trim_end_matches('/');
+		self.route_scopes
+			.iter()
+			.find(|(p, _)| *p == path)
+			.map(|(_, s)| *s)
+			.unwrap_or(self.scope)
+	}
+
+	fn authenticate(&self, auth: &HeaderValue) -> Option<&ApiToken> {
+		let auth = auth.as_bytes();
+		self.tokens
+			.iter()
+			.find(|(_, bearer, basic)| {
+				verify_slices_are_equal(auth, bearer.as_bytes()).is_ok()
+					|| verify_slices_are_equal(auth, basic.as_bytes()).is_ok()
+			})
+			.map(|(t, _, _)| t)
+	}
+}
+
+impl Handler for TokenAuthMiddleware {
+	fn call(
+		&self,
+		mut req: Request<Body>,
+		mut handlers: Box<dyn Iterator<Item = HandlerObj>>,
+	) -> ResponseFuture {
+		let next_handler = match handlers.next() {
+			Some(h) => h,
+			None => return response(StatusCode::INTERNAL_SERVER_ERROR, "no handler found"),
+		};
+		if req.method().as_str() == "OPTIONS" {
+			return next_handler.call(req, handlers);
+		}
+		let access = match req.headers().get(AUTHORIZATION) {
+			Some(auth) => self.authenticate(auth).map(ApiAccess::from),
+			None => None,
+		};
+		match access {
+			Some(access) => {
+				let scope = self.required_scope(req.uri().path());
+				if !access.allows(scope) {
+					return response(
+						StatusCode::FORBIDDEN,
+						format!("token {} lacks the {} scope", access.name, scope),
+					);
+				}
+				req.extensions_mut().insert(access);
+				next_handler.call(req, handlers)
+			}
+			// Unauthorized 401
+			None => unauthorized_response(&self.basic_realm),
+		}
+	}
+}
+
 fn unauthorized_response(basic_realm: &HeaderValue) -> ResponseFuture {
 	let response = Response::builder()
 		.status(StatusCode::UNAUTHORIZED)
@@ -76,3 +295,111 @@ fn unauthorized_response(basic_realm: &HeaderValue) -> ResponseFuture {
 		.unwrap();
 	Box::new(ok(response))
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use futures::Future;
+
+	struct Ok200;
+
+	impl Handler for Ok200 {
+		fn get(&self, _req: Request<Body>) -> ResponseFuture {
+			response(StatusCode::OK, "")
+		}
+
+		fn post(&self, _req: Request<Body>) -> ResponseFuture {
+			response(StatusCode::OK, "")
+		}
+	}
+
+	fn token(name: &str, scopes: Vec<ApiScope>) -> ApiToken {
+		ApiToken {
+			name: name.to_owned(),
+			token: format!("{}-secret", name),
+			scopes,
+			rate_limit: None,
+		}
+	}
+
+	fn status(mw: &TokenAuthMiddleware, method: &str, path: &str, auth: Option<&str>) -> u16 {
+		let mut req = Request::builder();
+		req.method(method).uri(path);
+		if let Some(auth) = auth {
+			req.header(AUTHORIZATION, auth);
+		}
+		let handlers: Vec<HandlerObj> = vec![std::sync::Arc::new(Ok200)];
+		mw.call(
+			req.body(Body::empty()).unwrap(),
+			Box::new(handlers.into_iter()),
+		)
+		.wait()
+		.unwrap()
+		.status()
+		.as_u16()
+	}
+
+	#[test]
+	fn token_scopes() {
+		let mw = TokenAuthMiddleware::new(
+			vec![
+				token("explorer", vec![ApiScope::Read]),
+				token("wallet", vec![ApiScope::Read, ApiScope::PushTx]),
+				ApiToken::admin("api_secret", "admin-secret".to_owned()),
+			],
+			ApiScope::Read,
+			&GOTTS_BASIC_REALM,
+		)
+		.with_route_scope("/v1/pool/push_tx", ApiScope::PushTx);
+
+		assert_eq!(status(&mw, "GET", "/v1/status", None), 401);
+		assert_eq!(status(&mw, "GET", "/v1/status", Some("Bearer nope")), 401);
+		assert_eq!(
+			status(&mw, "GET", "/v1/status", Some("Bearer explorer-secret")),
+			200
+		);
+		assert_eq!(
+			status(
+				&mw,
+				"POST",
+				"/v1/pool/push_tx",
+				Some("Bearer explorer-secret")
+			),
+			403
+		);
+		assert_eq!(
+			status(
+				&mw,
+				"POST",
+				"/v1/pool/push_tx",
+				Some("Bearer wallet-secret")
+			),
+			200
+		);
+		let basic = format!("Basic {}", to_base64("gotts:admin-secret"));
+		assert_eq!(status(&mw, "POST", "/v1/pool/push_tx", Some(&basic)), 200);
+	}
+
+	#[test]
+	fn tokens_file() {
+		let file: ApiTokensFile = toml::from_str(
+			r#"
+			[[tokens]]
+			name = "explorer"
+			token = "abc"
+			scopes = ["read"]
+			rate_limit = 120
+
+			[[tokens]]
+			name = "wallet"
+			token = "def"
+			scopes = ["read", "push_tx"]
+			"#,
+		)
+		.unwrap();
+		assert_eq!(file.tokens.len(), 2);
+		assert_eq!(file.tokens[0].rate_limit, Some(120));
+		assert!(file.tokens[1].allows(ApiScope::PushTx));
+		assert!(!file.tokens[1].allows(ApiScope::Admin));
+	}
+}
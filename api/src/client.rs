@@ -15,18 +15,23 @@
 
 //! High level JSON/HTTP client API
 
+use crate::core::core::{OutputEx, Transaction, TxKernelApiEntry};
+use crate::core::ser::{self, ProtocolVersion};
+use crate::p2p::types::{PeerAddr, PeerInfoDisplay};
+use crate::p2p::{BanEntry, PeerData};
 use crate::rest::{Error, ErrorKind};
-use crate::util::to_base64;
+use crate::types::*;
+use crate::util::{to_base64, to_hex};
 use failure::{Fail, ResultExt};
-use futures::future::{err, ok, Either};
+use futures::future::{err, ok};
 use http::uri::{InvalidUri, Uri};
 use hyper::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, USER_AGENT};
 use hyper::rt::{Future, Stream};
-use hyper::{Body, Client, Request};
+use hyper::{Body, Client, Request, StatusCode};
 use hyper_rustls;
 use hyper_timeout::TimeoutConnector;
 use serde::{Deserialize, Serialize};
-use serde_json;
+use serde_json::{self, json};
 use std::time::Duration;
 use tokio::runtime::Runtime;
 
@@ -208,31 +213,315 @@ fn send_request_async(req: Request<Body>) -> Box<dyn Future<Item = String, Error
 			.request(req)
 			.map_err(|e| ErrorKind::RequestError(format!("Cannot make request: {}", e)).into())
 			.and_then(|resp| {
-				if !resp.status().is_success() {
-					Either::A(err(ErrorKind::RequestError(format!(
-						"Wrong response code: {}, with data: {:?}",
-						resp.status(),
-						resp.body()
-					))
-					.into()))
-				} else {
-					Either::B(
-						resp.into_body()
-							.map_err(|e| {
-								ErrorKind::RequestError(format!("Cannot read response body: {}", e))
-									.into()
-							})
-							.concat2()
-							.and_then(|ch| ok(String::from_utf8_lossy(&ch.to_vec()).to_string())),
-					)
-				}
+				let status = resp.status();
+				resp.into_body()
+					.map_err(|e| {
+						ErrorKind::RequestError(format!("Cannot read response body: {}", e)).into()
+					})
+					.concat2()
+					.and_then(move |ch| {
+						let data = String::from_utf8_lossy(&ch.to_vec()).to_string();
+						if status.is_success() {
+							ok(data)
+						} else {
+							err(decode_error(status, data))
+						}
+					})
 			}),
 	)
 }
 
+/// Maps an error response of the node API back to the error the handler
+/// failed with, as far as the status code tells.
+fn decode_error(status: StatusCode, data: String) -> Error {
+	match status {
+		StatusCode::NOT_FOUND => ErrorKind::NotFound.into(),
+		StatusCode::BAD_REQUEST => ErrorKind::Argument(data).into(),
+		StatusCode::INTERNAL_SERVER_ERROR => ErrorKind::Internal(data).into(),
+		_ => ErrorKind::ResponseError(format!(
+			"Wrong response code: {}, with data: {}",
+			status, data
+		))
+		.into(),
+	}
+}
+
 pub fn send_request(req: Request<Body>) -> Result<String, Error> {
 	let task = send_request_async(req);
 	let mut rt =
 		Runtime::new().context(ErrorKind::Internal("can't create Tokio runtime".to_owned()))?;
 	Ok(rt.block_on(task)?)
 }
+
+/// Typed client of the node API, a method per endpoint. The peers and bans
+/// are only served by the owner API, which needs to be set for them.
+#[derive(Clone, Debug)]
+pub struct NodeClient {
+	foreign_url: String,
+	foreign_secret: Option<String>,
+	owner_url: Option<String>,
+	owner_secret: Option<String>,
+}
+
+impl NodeClient {
+	/// Client of the foreign API at the given base url, like
+	/// "http://127.0.0.1:3513".
+	pub fn new(foreign_url: &str, api_secret: Option<String>) -> NodeClient {
+		NodeClient {
+			foreign_url: foreign_url.trim_end_matches('/').to_owned(),
+			foreign_secret: api_secret,
+			owner_url: None,
+			owner_secret: None,
+		}
+	}
+
+	/// Also talk to the owner API at the given base url.
+	pub fn with_owner_api(self, owner_url: &str, api_secret: Option<String>) -> NodeClient {
+		NodeClient {
+			owner_url: Some(owner_url.trim_end_matches('/').to_owned()),
+			owner_secret: api_secret,
+			..self
+		}
+	}
+
+	fn get<T>(&self, path: &str) -> Result<T, Error>
+	where
+		for<'de> T: Deserialize<'de>,
+	{
+		let url = format!("{}/{}", self.foreign_url, path);
+		get(&url, self.foreign_secret.clone())
+	}
+
+	fn owner_url(&self, path: &str) -> Result<String, Error> {
+		match self.owner_url {
+			Some(ref url) => Ok(format!("{}/{}", url, path)),
+			None => Err(ErrorKind::Argument("owner API url not set".to_owned()).into()),
+		}
+	}
+
+	fn owner_get<T>(&self, path: &str) -> Result<T, Error>
+	where
+		for<'de> T: Deserialize<'de>,
+	{
+		get(&self.owner_url(path)?, self.owner_secret.clone())
+	}
+
+	fn owner_post<IN>(&self, path: &str, input: &IN) -> Result<(), Error>
+	where
+		IN: Serialize,
+	{
+		post_no_ret(&self.owner_url(path)?, self.owner_secret.clone(), input)
+	}
+
+	/// Node status, the chain head and the number of connected peers.
+	pub fn status(&self) -> Result<Status, Error> {
+		self.get("v1/status")
+	}
+
+	/// Node and block header versions.
+	pub fn version(&self) -> Result<Version, Error> {
+		self.get("v1/version")
+	}
+
+	/// Chain head.
+	pub fn tip(&self) -> Result<Tip, Error> {
+		self.get("v1/chain")
+	}
+
+	/// Block by hash, height or output commitment.
+	pub fn block(&self, id: &str, include_merkle_proof: bool) -> Result<BlockPrintable, Error> {
+		if include_merkle_proof {
+			self.get(&format!("v1/blocks/{}", id))
+		} else {
+			self.get(&format!("v1/blocks/{}?no_merkle_proof", id))
+		}
+	}
+
+	/// Compact block by hash, height or output commitment.
+	pub fn compact_block(&self, id: &str) -> Result<CompactBlockPrintable, Error> {
+		self.get(&format!("v1/blocks/{}?compact", id))
+	}
+
+	/// A page of the blocks between the given heights, `next_height` telling
+	/// where the next page starts.
+	pub fn blocks(
+		&self,
+		start_height: u64,
+		end_height: u64,
+		include_proof: bool,
+	) -> Result<BlockListing, Error> {
+		self.get(&format!(
+			"v1/blocks?start_height={}&end_height={}&include_proof={}",
+			start_height, end_height, include_proof
+		))
+	}
+
+	/// Block header by hash, height or output commitment.
+	pub fn header(&self, id: &str) -> Result<BlockHeaderPrintable, Error> {
+		self.get(&format!("v1/headers/{}", id))
+	}
+
+	/// A page of `count` block headers from the given height, `next_height`
+	/// telling where the next page starts.
+	pub fn headers(&self, start_height: u64, count: u64) -> Result<HeaderListing, Error> {
+		self.get(&format!(
+			"v1/headers?start_height={}&count={}",
+			start_height, count
+		))
+	}
+
+	/// Unspent outputs by commitment (hex), the spent or unknown ones being
+	/// left out.
+	pub fn outputs(&self, commits: &[String]) -> Result<Vec<OutputEx>, Error> {
+		self.get(&format!("v1/chain/outputs/byids?id={}", commits.join(",")))
+	}
+
+	/// Kernels by excess (hex).
+	pub fn kernels(&self, excesses: &[String]) -> Result<Vec<TxKernelApiEntry>, Error> {
+		self.get(&format!("v1/chain/kernels/byids?id={}", excesses.join(",")))
+	}
+
+	/// Kernel by excess (hex), with the height it was mined at.
+	pub fn kernel(&self, excess: &str) -> Result<Option<LocatedTxKernel>, Error> {
+		self.get(&format!("v1/chain/kernel/{}", excess))
+	}
+
+	/// Transaction pool size.
+	pub fn pool_info(&self) -> Result<PoolInfo, Error> {
+		self.get("v1/pool")
+	}
+
+	/// Pushes a transaction to the pool, skipping the Dandelion stem phase
+	/// if fluff.
+	pub fn push_tx(&self, tx: &Transaction, fluff: bool) -> Result<(), Error> {
+		let url = format!("{}/{}", self.foreign_url, tx_path("push_tx", fluff));
+		post_no_ret(&url, self.foreign_secret.clone(), &tx_body(tx)?)
+	}
+
+	/// Pushes a package of dependent transactions, parents first, to the pool
+	/// to be accepted as a unit, skipping the Dandelion stem phase if fluff.
+	pub fn push_package(&self, txs: &[Transaction], fluff: bool) -> Result<(), Error> {
+		let url = format!("{}/{}", self.foreign_url, tx_path("push_package", fluff));
+		let txs_hex = txs.iter().map(tx_hex).collect::<Result<Vec<_>, _>>()?;
+		post_no_ret(
+			&url,
+			self.foreign_secret.clone(),
+			&json!({ "txs_hex": txs_hex }),
+		)
+	}
+
+	/// Checks a transaction against all the rules of the pool, as if pushed,
+	/// without adding nor relaying it.
+	pub fn validate_tx(&self, tx: &Transaction, fluff: bool) -> Result<TxValidation, Error> {
+		let url = format!("{}/{}", self.foreign_url, tx_path("validate_tx", fluff));
+		post(&url, self.foreign_secret.clone(), &tx_body(tx)?)
+	}
+
+	/// Where a transaction stands, by one of its kernel excesses (hex).
+	pub fn tx_status(&self, excess: &str) -> Result<TxStatusInfo, Error> {
+		self.get(&format!("v1/tx/status/{}", excess))
+	}
+
+	/// All the peers the node knows of (owner API).
+	pub fn peers_all(&self) -> Result<Vec<PeerData>, Error> {
+		self.owner_get("v1/peers/all")
+	}
+
+	/// Connected peers (owner API).
+	pub fn peers_connected(&self) -> Result<Vec<PeerInfoDisplay>, Error> {
+		self.owner_get("v1/peers/connected")
+	}
+
+	/// A peer the node knows of (owner API).
+	pub fn peer(&self, addr: PeerAddr) -> Result<PeerData, Error> {
+		self.owner_get(&format!("v1/peers/{}", addr))
+	}
+
+	/// Bans a peer (owner API).
+	pub fn ban_peer(&self, addr: PeerAddr) -> Result<(), Error> {
+		self.owner_post(&format!("v1/peers/{}/ban", addr), &json!({}))
+	}
+
+	/// Unbans a peer (owner API).
+	pub fn unban_peer(&self, addr: PeerAddr) -> Result<(), Error> {
+		self.owner_post(&format!("v1/peers/{}/unban", addr), &json!({}))
+	}
+
+	/// Banned subnets (owner API).
+	pub fn bans(&self) -> Result<Vec<BanEntry>, Error> {
+		self.owner_get("v1/bans")
+	}
+
+	/// Bans a subnet (owner API).
+	pub fn ban_subnet(&self, ban: &BanRequest) -> Result<(), Error> {
+		self.owner_post("v1/bans", ban)
+	}
+
+	/// Unbans a subnet (owner API).
+	pub fn unban_subnet(&self, unban: &UnbanRequest) -> Result<(), Error> {
+		self.owner_post("v1/bans/unban", unban)
+	}
+
+	/// Supply audit of the UTXO set at a height, the chain head if none
+	/// (owner API).
+	pub fn supply_audit(&self, height: Option<u64>) -> Result<SupplyAuditPrintable, Error> {
+		match height {
+			Some(height) => self.owner_get(&format!("v1/chain/supply?height={}", height)),
+			None => self.owner_get("v1/chain/supply"),
+		}
+	}
+}
+
+// Path of a pool route taking a transaction.
+fn tx_path(route: &str, fluff: bool) -> String {
+	if fluff {
+		format!("v1/pool/{}?fluff", route)
+	} else {
+		format!("v1/pool/{}", route)
+	}
+}
+
+// The hex of a transaction, as the pool routes take it.
+fn tx_hex(tx: &Transaction) -> Result<String, Error> {
+	let tx_bin = ser::ser_vec(tx, ProtocolVersion::local()).context(ErrorKind::Internal(
+		"Could not serialize the transaction".to_owned(),
+	))?;
+	Ok(to_hex(tx_bin))
+}
+
+fn tx_body(tx: &Transaction) -> Result<serde_json::Value, Error> {
+	Ok(json!({ "tx_hex": tx_hex(tx)? }))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn error_decoding() {
+		let e = decode_error(StatusCode::NOT_FOUND, String::new());
+		assert_eq!(e.kind(), &ErrorKind::NotFound);
+		let e = decode_error(StatusCode::BAD_REQUEST, "invalid excess hex".to_owned());
+		assert_eq!(
+			e.kind(),
+			&ErrorKind::Argument("invalid excess hex".to_owned())
+		);
+		let e = decode_error(StatusCode::UNAUTHORIZED, String::new());
+		match e.kind() {
+			ErrorKind::ResponseError(_) => {}
+			_ => panic!("unexpected error {}", e),
+		}
+	}
+
+	#[test]
+	fn owner_api_urls() {
+		let client = NodeClient::new("http://127.0.0.1:3513/", None);
+		assert!(client.owner_url("v1/peers/all").is_err());
+		let client = client.with_owner_api("http://127.0.0.1:3517", None);
+		assert_eq!(
+			client.owner_url("v1/peers/all").unwrap(),
+			"http://127.0.0.1:3517/v1/peers/all"
+		);
+		assert_eq!(client.foreign_url, "http://127.0.0.1:3513");
+	}
+}
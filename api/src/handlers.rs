@@ -15,93 +15,227 @@
 
 mod blocks_api;
 mod chain_api;
+mod metrics_api;
 mod peers_api;
 mod pool_api;
+mod rpc_api;
 mod server_api;
 mod transactions_api;
 mod utils;
 mod version_api;
 
 use self::blocks_api::BlockHandler;
+use self::blocks_api::BlockRangeHandler;
 use self::blocks_api::HeaderHandler;
+use self::blocks_api::HeaderRangeHandler;
 use self::chain_api::ChainCompactHandler;
 use self::chain_api::ChainHandler;
 use self::chain_api::ChainValidationHandler;
 use self::chain_api::KernelHandler;
 use self::chain_api::OutputHandler;
+use self::chain_api::ProofHandler;
+pub use self::chain_api::ProofSource;
+#[cfg(test)]
+pub(crate) use self::pool_api::{TxWrapper, TxsWrapper};
+use self::chain_api::SupplyAuditHandler;
 use self::chain_api::TxKernelHandler;
+use self::metrics_api::MetricsHandler;
+use self::peers_api::BansHandler;
 use self::peers_api::PeerHandler;
 use self::peers_api::PeersAllHandler;
 use self::peers_api::PeersConnectedHandler;
+use self::pool_api::PoolAdminHandler;
 use self::pool_api::PoolInfoHandler;
 use self::pool_api::PoolPushHandler;
+use self::pool_api::PoolPushPackageHandler;
+use self::pool_api::PoolValidateHandler;
+use self::pool_api::TxStatusHandler;
+use self::rpc_api::{ForeignRpc, OwnerRpc};
 use self::server_api::IndexHandler;
 use self::server_api::KernelDownloadHandler;
 use self::server_api::StatusHandler;
 use self::transactions_api::TxHashSetHandler;
 use self::version_api::VersionHandler;
-use crate::auth::{BasicAuthMiddleware, GOTTS_BASIC_REALM};
+use crate::auth::{
+	ApiAccessConfig, ApiScope, ApiToken, BasicAuthMiddleware, TokenAuthMiddleware,
+	GOTTS_BASIC_REALM,
+};
 use crate::chain;
+use crate::jsonrpc::RpcHandler;
+use crate::openapi::{OpenApiHandler, OPENAPI_PATH};
 use crate::p2p;
 use crate::pool;
 use crate::rest::*;
-use crate::router::{Router, RouterError};
+use crate::router::{RateLimitMiddleware, Router, RouterError};
 use crate::util;
 use crate::util::RwLock;
+use crate::ws::{EventHub, WebSocketHandler, ALL_TOPICS, FOREIGN_TOPICS};
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
 
-/// Start all server HTTP handlers. Register all of them with Router
-/// and runs the corresponding HTTP server.
+/// Start the foreign API HTTP handlers, the read-only chain data and pushing
+/// txs, safe to publish. Register all of them with Router and runs the
+/// corresponding HTTP server.
 ///
 /// Hyper currently has a bug that prevents clean shutdown. In order
 /// to avoid having references kept forever by handlers, we only pass
 /// weak references. Note that this likely means a crash if the handlers are
 /// used after a server shutdown (which should normally never happen,
 /// except during tests).
+#[allow(clippy::too_many_arguments)]
 pub fn start_rest_apis(
 	addr: String,
 	chain: Arc<chain::Chain>,
 	tx_pool: Arc<RwLock<pool::TransactionPool>>,
 	peers: Arc<p2p::Peers>,
+	proofs: Arc<dyn ProofSource>,
+	event_hub: Arc<EventHub>,
 	api_secret: Option<String>,
+	access: ApiAccessConfig,
 	tls_config: Option<TLSConfig>,
 ) -> bool {
+	let router = build_router(chain, tx_pool, peers, proofs, event_hub)
+		.expect("unable to build API router");
+	let scopes = [
+		("/v1/pool/push_tx", ApiScope::PushTx),
+		("/v1/pool/push_package", ApiScope::PushTx),
+	];
+	let server = ApiServerConfig {
+		name: "foreign",
+		api_secret,
+		access,
+		scope: ApiScope::Read,
+		route_scopes: &scopes,
+	};
+	start_api_server(server, addr, router, tls_config)
+}
+
+/// Start the owner API HTTP handlers, the peers, bans, chain maintenance and
+/// metrics, on their own address and behind their own secret.
+#[allow(clippy::too_many_arguments)]
+pub fn start_owner_rest_apis(
+	addr: String,
+	chain: Arc<chain::Chain>,
+	tx_pool: Arc<RwLock<pool::TransactionPool>>,
+	peers: Arc<p2p::Peers>,
+	sync_state: Arc<chain::SyncState>,
+	traffic: Arc<p2p::TrafficStats>,
+	event_hub: Arc<EventHub>,
+	api_secret: Option<String>,
+	access: ApiAccessConfig,
+	tls_config: Option<TLSConfig>,
+) -> bool {
+	let router = build_owner_router(chain, tx_pool, peers, sync_state, traffic, event_hub)
+		.expect("unable to build owner API router");
+	let server = ApiServerConfig {
+		name: "owner",
+		api_secret,
+		access,
+		scope: ApiScope::Admin,
+		route_scopes: &[],
+	};
+	start_api_server(server, addr, router, tls_config)
+}
+
+// How an API server is protected, the scope of the tokens being required on
+// all its routes but the ones with their own.
+struct ApiServerConfig<'a> {
+	name: &'a str,
+	api_secret: Option<String>,
+	access: ApiAccessConfig,
+	scope: ApiScope,
+	route_scopes: &'a [(&'static str, ApiScope)],
+}
+
+fn start_api_server(
+	server: ApiServerConfig<'_>,
+	addr: String,
+	mut router: Router,
+	tls_config: Option<TLSConfig>,
+) -> bool {
+	let name = server.name;
 	let mut apis = ApiServer::new();
-	let mut router = build_router(chain, tx_pool, peers).expect("unable to build API router");
-	if let Some(api_secret) = api_secret {
-		let api_basic_auth = format!(
-			"Basic {}",
-			util::to_base64(&format!("gotts:{}", api_secret))
-		);
-		let basic_auth_middleware =
-			Arc::new(BasicAuthMiddleware::new(api_basic_auth, &GOTTS_BASIC_REALM));
-		router.add_middleware(basic_auth_middleware);
+	let (per_ip, per_token) = (
+		server.access.rate_limit_per_ip,
+		server.access.rate_limit_per_token,
+	);
+	let with_tokens = server.access.tokens.is_some();
+	// before the authentication, so failed attempts are throttled too
+	if per_ip.is_some() {
+		router.add_middleware(Arc::new(RateLimitMiddleware::new(per_ip, None)));
+	}
+	match server.access.tokens {
+		Some(mut tokens) => {
+			if let Some(api_secret) = server.api_secret {
+				tokens.push(ApiToken::admin("api_secret", api_secret));
+			}
+			let mut token_auth_middleware =
+				TokenAuthMiddleware::new(tokens, server.scope, &GOTTS_BASIC_REALM);
+			for (path, scope) in server.route_scopes {
+				token_auth_middleware = token_auth_middleware.with_route_scope(*path, *scope);
+			}
+			router.add_middleware(Arc::new(token_auth_middleware));
+		}
+		None => {
+			if let Some(api_secret) = server.api_secret {
+				let api_basic_auth = format!(
+					"Basic {}",
+					util::to_base64(&format!("gotts:{}", api_secret))
+				);
+				let basic_auth_middleware =
+					Arc::new(BasicAuthMiddleware::new(api_basic_auth, &GOTTS_BASIC_REALM));
+				router.add_middleware(basic_auth_middleware);
+			}
+		}
+	}
+	// after the authentication, to know the token and its own limit
+	if per_token.is_some() || with_tokens {
+		router.add_middleware(Arc::new(RateLimitMiddleware::new(None, per_token)));
 	}
 
-	info!("Starting HTTP API server at {}.", addr);
+	info!("Starting HTTP {} API server at {}.", name, addr);
 	let socket_addr: SocketAddr = addr.parse().expect("unable to parse socket address");
 	let res = apis.start(socket_addr, router, tls_config);
 	match res {
 		Ok(_) => true,
 		Err(e) => {
-			error!("HTTP API server failed to start. Err: {}", e);
+			error!("HTTP {} API server failed to start. Err: {}", name, e);
 			false
 		}
 	}
 }
 
+/// Router of the foreign API.
 pub fn build_router(
 	chain: Arc<chain::Chain>,
 	tx_pool: Arc<RwLock<pool::TransactionPool>>,
 	peers: Arc<p2p::Peers>,
+	proofs: Arc<dyn ProofSource>,
+	event_hub: Arc<EventHub>,
+) -> Result<Router, RouterError> {
+	foreign_router(
+		Arc::downgrade(&chain),
+		Arc::downgrade(&tx_pool),
+		Arc::downgrade(&peers),
+		Arc::downgrade(&proofs),
+		Arc::downgrade(&event_hub),
+	)
+}
+
+// The handlers only get weak references, see start_rest_apis.
+fn foreign_router(
+	chain: Weak<chain::Chain>,
+	tx_pool: Weak<RwLock<pool::TransactionPool>>,
+	peers: Weak<p2p::Peers>,
+	proofs: Weak<dyn ProofSource>,
+	event_hub: Weak<EventHub>,
 ) -> Result<Router, RouterError> {
 	let route_list = vec![
 		"get blocks/xxx?no_merkle_proof".to_string(),
+		"get blocks?start_height=101&end_height=200&include_proof&format=binary".to_string(),
 		"get headers/xxx".to_string(),
+		"get headers?start_height=101&count=100&format=binary".to_string(),
 		"get chain".to_string(),
-		// "post chain/compact".to_string(),
-		// "get chain/validate".to_string(),
 		"get chain/outputs/byids?id=xxx,yyy,zzz".to_string(),
 		// "get chain/outputs/byheight?start_height=101&end_height=200".to_string(),
 		"get chain/kernels/byids?id=xxx,yyy,zzz".to_string(),
@@ -114,90 +248,292 @@ pub fn build_router(
 		"get txhashset/outputs?start_index=1&max=100".to_string(),
 		"get txhashset/nit-outputs?start_index=1&max=100".to_string(),
 		"get txhashset/merkleproof?id=xxx".to_string(),
+		"get proofs/output/xxx".to_string(),
+		"get proofs/kernel/xxx".to_string(),
 		"get pool".to_string(),
 		"post pool/push_tx".to_string(),
-		"post peers/a.b.c.d:p/ban".to_string(),
-		"post peers/a.b.c.d:p/unban".to_string(),
-		"get peers/all".to_string(),
-		"get peers/connected".to_string(),
-		"get peers/a.b.c.d".to_string(),
+		"post pool/push_package".to_string(),
+		"post pool/validate_tx".to_string(),
+		"get tx/status/xxx".to_string(),
 		"get version".to_string(),
+		"get ws?topics=tip,block,pool (WebSocket)".to_string(),
+		"post v2/foreign (JSON-RPC 2.0)".to_string(),
+		"get openapi.json".to_string(),
 	];
 	let index_handler = IndexHandler { list: route_list };
 
 	let output_handler = OutputHandler {
-		chain: Arc::downgrade(&chain),
+		chain: chain.clone(),
 	};
 	let kernel_handler = KernelHandler {
-		chain: Arc::downgrade(&chain),
+		chain: chain.clone(),
 	};
 	let txkernel_handler = TxKernelHandler {
-		chain: Arc::downgrade(&chain),
+		chain: chain.clone(),
 	};
 
 	let block_handler = BlockHandler {
-		chain: Arc::downgrade(&chain),
+		chain: chain.clone(),
 	};
 	let header_handler = HeaderHandler {
-		chain: Arc::downgrade(&chain),
+		chain: chain.clone(),
 	};
-	let chain_tip_handler = ChainHandler {
-		chain: Arc::downgrade(&chain),
+	let block_range_handler = BlockRangeHandler {
+		chain: chain.clone(),
 	};
-	let _chain_compact_handler = ChainCompactHandler {
-		chain: Arc::downgrade(&chain),
+	let header_range_handler = HeaderRangeHandler {
+		chain: chain.clone(),
 	};
-	let _chain_validation_handler = ChainValidationHandler {
-		chain: Arc::downgrade(&chain),
+	let chain_tip_handler = ChainHandler {
+		chain: chain.clone(),
 	};
 	let status_handler = StatusHandler {
-		chain: Arc::downgrade(&chain),
-		peers: Arc::downgrade(&peers),
-	};
-	let _kernel_download_handler = KernelDownloadHandler {
-		peers: Arc::downgrade(&peers),
+		chain: chain.clone(),
+		peers: peers.clone(),
 	};
 	let txhashset_handler = TxHashSetHandler {
-		chain: Arc::downgrade(&chain),
+		chain: chain.clone(),
 	};
+	let proof_handler = Arc::new(ProofHandler {
+		proofs: proofs.clone(),
+	});
 	let pool_info_handler = PoolInfoHandler {
-		tx_pool: Arc::downgrade(&tx_pool),
+		tx_pool: tx_pool.clone(),
 	};
 	let pool_push_handler = PoolPushHandler {
-		tx_pool: Arc::downgrade(&tx_pool),
+		tx_pool: tx_pool.clone(),
 	};
-	let peers_all_handler = PeersAllHandler {
-		peers: Arc::downgrade(&peers),
+	let pool_push_package_handler = PoolPushPackageHandler {
+		tx_pool: tx_pool.clone(),
 	};
-	let peers_connected_handler = PeersConnectedHandler {
-		peers: Arc::downgrade(&peers),
+	let pool_validate_handler = PoolValidateHandler {
+		tx_pool: tx_pool.clone(),
+		with_stem: false,
 	};
-	let peer_handler = PeerHandler {
-		peers: Arc::downgrade(&peers),
+	let tx_status_handler = TxStatusHandler {
+		chain: chain.clone(),
+		tx_pool: tx_pool.clone(),
+		with_stem: false,
 	};
 	let version_handler = VersionHandler {
-		chain: Arc::downgrade(&chain),
+		chain: chain.clone(),
+	};
+	let ws_handler = WebSocketHandler {
+		hub: event_hub.clone(),
+		topics: FOREIGN_TOPICS.to_vec(),
 	};
+	let foreign_rpc_handler = RpcHandler::new(ForeignRpc {
+		chain: chain.clone(),
+		tx_pool: tx_pool.clone(),
+		peers: peers.clone(),
+		proofs: proofs.clone(),
+	})
+	.with_method_scope("push_transaction", ApiScope::PushTx)
+	.with_method_scope("push_package", ApiScope::PushTx);
 
 	let mut router = Router::new();
 
 	router.add_route("/v1/", Arc::new(index_handler))?;
+	router.add_route("/v1/blocks", Arc::new(block_range_handler))?;
 	router.add_route("/v1/blocks/*", Arc::new(block_handler))?;
+	router.add_route("/v1/headers", Arc::new(header_range_handler))?;
 	router.add_route("/v1/headers/*", Arc::new(header_handler))?;
 	router.add_route("/v1/chain", Arc::new(chain_tip_handler))?;
 	router.add_route("/v1/chain/outputs/*", Arc::new(output_handler))?;
 	router.add_route("/v1/chain/kernels/*", Arc::new(txkernel_handler))?;
 	router.add_route("/v1/chain/kernel/*", Arc::new(kernel_handler))?;
-	// router.add_route("/v1/chain/compact", Arc::new(chain_compact_handler))?;
-	// router.add_route("/v1/chain/validate", Arc::new(chain_validation_handler))?;
 	router.add_route("/v1/txhashset/*", Arc::new(txhashset_handler))?;
+	router.add_route("/v1/proofs/output/*", proof_handler.clone())?;
+	router.add_route("/v1/proofs/kernel/*", proof_handler)?;
 	router.add_route("/v1/status", Arc::new(status_handler))?;
-	// router.add_route("/v1/kerneldownload", Arc::new(kernel_download_handler))?;
 	router.add_route("/v1/pool", Arc::new(pool_info_handler))?;
 	router.add_route("/v1/pool/push_tx", Arc::new(pool_push_handler))?;
+	router.add_route("/v1/pool/push_package", Arc::new(pool_push_package_handler))?;
+	router.add_route("/v1/pool/validate_tx", Arc::new(pool_validate_handler))?;
+	router.add_route("/v1/tx/status/*", Arc::new(tx_status_handler))?;
+	router.add_route("/v1/version", Arc::new(version_handler))?;
+	router.add_route("/v1/ws", Arc::new(ws_handler))?;
+	router.add_route("/v2/foreign", Arc::new(foreign_rpc_handler))?;
+	add_openapi_route(&mut router, "Gotts foreign API")?;
+	Ok(router)
+}
+
+/// Router of the owner API.
+pub fn build_owner_router(
+	chain: Arc<chain::Chain>,
+	tx_pool: Arc<RwLock<pool::TransactionPool>>,
+	peers: Arc<p2p::Peers>,
+	sync_state: Arc<chain::SyncState>,
+	traffic: Arc<p2p::TrafficStats>,
+	event_hub: Arc<EventHub>,
+) -> Result<Router, RouterError> {
+	owner_router(
+		Arc::downgrade(&chain),
+		Arc::downgrade(&tx_pool),
+		Arc::downgrade(&peers),
+		Arc::downgrade(&sync_state),
+		Arc::downgrade(&traffic),
+		Arc::downgrade(&event_hub),
+	)
+}
+
+// The handlers only get weak references, see start_rest_apis.
+fn owner_router(
+	chain: Weak<chain::Chain>,
+	tx_pool: Weak<RwLock<pool::TransactionPool>>,
+	peers: Weak<p2p::Peers>,
+	sync_state: Weak<chain::SyncState>,
+	traffic: Weak<p2p::TrafficStats>,
+	event_hub: Weak<EventHub>,
+) -> Result<Router, RouterError> {
+	let route_list = vec![
+		"get status".to_string(),
+		"post chain/compact".to_string(),
+		"get chain/validate".to_string(),
+		"get chain/supply?height=<height>".to_string(),
+		"post peers/a.b.c.d:p/ban".to_string(),
+		"post peers/a.b.c.d:p/unban".to_string(),
+		"get peers/all".to_string(),
+		"get peers/connected".to_string(),
+		"get peers/a.b.c.d".to_string(),
+		"get bans".to_string(),
+		"post bans".to_string(),
+		"post bans/unban".to_string(),
+		"post bans/import".to_string(),
+		"get pool/entries".to_string(),
+		"post pool/clear".to_string(),
+		"post pool/validate_tx".to_string(),
+		"get tx/status/xxx".to_string(),
+		"get metrics (Prometheus)".to_string(),
+		"get ws?topics=tip,block,pool,peers (WebSocket)".to_string(),
+		"post v2/owner (JSON-RPC 2.0)".to_string(),
+		"get openapi.json".to_string(),
+	];
+	let index_handler = IndexHandler { list: route_list };
+
+	let status_handler = StatusHandler {
+		chain: chain.clone(),
+		peers: peers.clone(),
+	};
+	let chain_compact_handler = ChainCompactHandler {
+		chain: chain.clone(),
+	};
+	let chain_validation_handler = ChainValidationHandler {
+		chain: chain.clone(),
+	};
+	let supply_audit_handler = SupplyAuditHandler {
+		chain: chain.clone(),
+	};
+	let _kernel_download_handler = KernelDownloadHandler {
+		peers: peers.clone(),
+	};
+	let peers_all_handler = PeersAllHandler {
+		peers: peers.clone(),
+	};
+	let peers_connected_handler = PeersConnectedHandler {
+		peers: peers.clone(),
+	};
+	let peer_handler = PeerHandler {
+		peers: peers.clone(),
+	};
+	let bans_handler = Arc::new(BansHandler {
+		peers: peers.clone(),
+	});
+	let pool_admin_handler = PoolAdminHandler {
+		tx_pool: tx_pool.clone(),
+	};
+	let tx_status_handler = TxStatusHandler {
+		chain: chain.clone(),
+		tx_pool: tx_pool.clone(),
+		with_stem: true,
+	};
+	let metrics_handler = MetricsHandler {
+		chain: chain.clone(),
+		tx_pool: tx_pool.clone(),
+		peers: peers.clone(),
+		sync_state: sync_state.clone(),
+		traffic: traffic.clone(),
+	};
+	let ws_handler = WebSocketHandler {
+		hub: event_hub.clone(),
+		topics: ALL_TOPICS.to_vec(),
+	};
+	let owner_rpc_handler = RpcHandler::new(OwnerRpc {
+		chain: chain.clone(),
+		tx_pool: tx_pool.clone(),
+		peers: peers.clone(),
+	});
+
+	let mut router = Router::new();
+
+	router.add_route("/v1/", Arc::new(index_handler))?;
+	router.add_route("/v1/status", Arc::new(status_handler))?;
+	router.add_route("/v1/chain/compact", Arc::new(chain_compact_handler))?;
+	router.add_route("/v1/chain/validate", Arc::new(chain_validation_handler))?;
+	router.add_route("/v1/chain/supply", Arc::new(supply_audit_handler))?;
+	// router.add_route("/v1/kerneldownload", Arc::new(kernel_download_handler))?;
 	router.add_route("/v1/peers/all", Arc::new(peers_all_handler))?;
 	router.add_route("/v1/peers/connected", Arc::new(peers_connected_handler))?;
 	router.add_route("/v1/peers/**", Arc::new(peer_handler))?;
-	router.add_route("/v1/version", Arc::new(version_handler))?;
+	router.add_route("/v1/bans", bans_handler.clone())?;
+	router.add_route("/v1/bans/*", bans_handler)?;
+	router.add_route("/v1/pool/*", Arc::new(pool_admin_handler))?;
+	router.add_route("/v1/tx/status/*", Arc::new(tx_status_handler))?;
+	router.add_route("/v1/metrics", Arc::new(metrics_handler))?;
+	router.add_route("/v1/ws", Arc::new(ws_handler))?;
+	router.add_route("/v2/owner", Arc::new(owner_rpc_handler))?;
+	add_openapi_route(&mut router, "Gotts owner API")?;
 	Ok(router)
 }
+
+// Serves the OpenAPI document of all the routes registered so far (and its
+// own), to be called last.
+fn add_openapi_route(router: &mut Router, title: &str) -> Result<(), RouterError> {
+	let mut routes = router.routes().to_vec();
+	routes.push(OPENAPI_PATH);
+	router.add_route(OPENAPI_PATH, Arc::new(OpenApiHandler::new(title, &routes)))?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::openapi;
+	use crate::util::secp::pedersen::Commitment;
+
+	struct NoProofs;
+
+	impl ProofSource for NoProofs {
+		fn output_proof(&self, _commit: Commitment) -> Result<Option<chain::OutputProof>, Error> {
+			Ok(None)
+		}
+
+		fn kernel_proof(&self, _excess: Commitment) -> Result<Option<chain::KernelProof>, Error> {
+			Ok(None)
+		}
+	}
+
+	#[test]
+	fn routes_documented() {
+		let foreign = foreign_router(
+			Weak::new(),
+			Weak::new(),
+			Weak::new(),
+			Weak::<NoProofs>::new(),
+			Weak::new(),
+		)
+		.unwrap();
+		let owner = owner_router(
+			Weak::new(),
+			Weak::new(),
+			Weak::new(),
+			Weak::new(),
+			Weak::new(),
+			Weak::new(),
+		)
+		.unwrap();
+		for route in foreign.routes().iter().chain(owner.routes()) {
+			assert!(openapi::documented(route), "no ROUTE_DOCS entry for {}", route);
+		}
+	}
+}
@@ -14,18 +14,32 @@
 // limitations under the License.
 use super::utils::{get_output, w};
 use crate::chain;
+use crate::core::core;
 use crate::core::core::hash::Hash;
 use crate::core::core::hash::Hashed;
+use crate::core::ser::{self, ProtocolVersion, Writeable};
 use crate::rest::*;
 use crate::router::{Handler, ResponseFuture};
 use crate::types::*;
 use crate::util;
 use crate::web::*;
 use failure::ResultExt;
+use futures::future::ok;
+use hyper::header::{HeaderValue, CONTENT_TYPE};
 use hyper::{Body, Request, StatusCode};
 use regex::Regex;
 use std::sync::Weak;
 
+/// Max number of blocks in a page of a block range.
+pub const MAX_BLOCKS_PER_PAGE: u64 = 100;
+
+/// Max number of headers in a page of a header range.
+pub const MAX_HEADERS_PER_PAGE: u64 = 1_000;
+
+/// Response header holding the start height of the next page of a binary
+/// range, absent on the last page.
+const NEXT_HEIGHT_HEADER: &'static str = "x-next-height";
+
 /// Gets block headers given either a hash or height or an output commit.
 /// GET /v1/headers/<hash>
 /// GET /v1/headers/<height>
@@ -36,7 +50,7 @@ pub struct HeaderHandler {
 }
 
 impl HeaderHandler {
-	fn get_header(&self, input: String) -> Result<BlockHeaderPrintable, Error> {
+	pub fn get_header(&self, input: String) -> Result<BlockHeaderPrintable, Error> {
 		// will fail quick if the provided isn't a commitment
 		if let Ok(h) = self.get_header_for_output(input.clone()) {
 			return Ok(h);
@@ -88,14 +102,14 @@ pub struct BlockHandler {
 }
 
 impl BlockHandler {
-	fn get_block(&self, h: &Hash, include_merkle_proof: bool) -> Result<BlockPrintable, Error> {
+	pub fn get_block(&self, h: &Hash, include_merkle_proof: bool) -> Result<BlockPrintable, Error> {
 		let chain = w(&self.chain)?;
 		let block = chain.get_block(h).context(ErrorKind::NotFound)?;
 		BlockPrintable::from_block(&block, chain, include_merkle_proof)
 			.map_err(|_| ErrorKind::Internal("chain error".to_owned()).into())
 	}
 
-	fn get_compact_block(&self, h: &Hash) -> Result<CompactBlockPrintable, Error> {
+	pub fn get_compact_block(&self, h: &Hash) -> Result<CompactBlockPrintable, Error> {
 		let chain = w(&self.chain)?;
 		let block = chain.get_block(h).context(ErrorKind::NotFound)?;
 		CompactBlockPrintable::from_compact_block(&block.into(), chain)
@@ -103,7 +117,7 @@ impl BlockHandler {
 	}
 
 	// Try to decode the string as a height or a hash.
-	fn parse_input(&self, input: String) -> Result<Hash, Error> {
+	pub fn parse_input(&self, input: String) -> Result<Hash, Error> {
 		if let Ok(height) = input.parse() {
 			match w(&self.chain)?.get_header_by_height(height) {
 				Ok(header) => return Ok(header.hash()),
@@ -166,3 +180,271 @@ impl Handler for BlockHandler {
 		result_with_cuckoo_solution_to_response(self.get_block(&h, include_merkle_proof))
 	}
 }
+
+/// Gets a range of block headers by height, a page of at most
+/// `MAX_HEADERS_PER_PAGE` at a time.
+/// GET /v1/headers?start_height=101&count=100
+///
+/// The `next_height` of the response is the `start_height` of the next page,
+/// none once the header head is reached.
+///
+/// Optionally get the headers serialized instead of JSON, as a u64 count
+/// followed by the headers, by passing "?format=binary". The start height of
+/// the next page is then in the `X-Next-Height` response header.
+pub struct HeaderRangeHandler {
+	pub chain: Weak<chain::Chain>,
+}
+
+impl HeaderRangeHandler {
+	fn get_headers(
+		&self,
+		start_height: u64,
+		count: u64,
+	) -> Result<(Vec<core::BlockHeader>, Option<u64>), Error> {
+		if count == 0 {
+			return Err(ErrorKind::Argument("count must be at least 1".to_owned()))?;
+		}
+		let chain = w(&self.chain)?;
+		let head = chain
+			.header_head()
+			.map_err(|e| ErrorKind::Internal(format!("can't get header head: {}", e)))?;
+		let end_height = start_height.saturating_add(count.saturating_sub(1));
+		let (last, next_height) =
+			page_bounds(start_height, end_height, head.height, MAX_HEADERS_PER_PAGE)?;
+		let mut headers = vec![];
+		for height in start_height..=last {
+			headers.push(
+				chain
+					.get_header_by_height(height)
+					.context(ErrorKind::NotFound)?,
+			);
+		}
+		Ok((headers, next_height))
+	}
+
+	pub fn get_header_listing(
+		&self,
+		start_height: u64,
+		count: u64,
+	) -> Result<HeaderListing, Error> {
+		let (headers, next_height) = self.get_headers(start_height, count)?;
+		Ok(HeaderListing {
+			headers: headers
+				.iter()
+				.map(BlockHeaderPrintable::from_header)
+				.collect(),
+			next_height,
+		})
+	}
+
+	fn parse_params(&self, req: &Request<Body>) -> Result<(u64, u64, bool), Error> {
+		let params = QueryParams::from(req.uri().query());
+		let start_height = parse_param!(params, "start_height", 0);
+		let count = parse_param!(params, "count", MAX_HEADERS_PER_PAGE);
+		Ok((start_height, count, is_binary(&params)))
+	}
+}
+
+impl Handler for HeaderRangeHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		let (start_height, count, binary) = match self.parse_params(&req) {
+			Ok(p) => p,
+			Err(e) => return response_on_error(e),
+		};
+		if binary {
+			binary_range_response(self.get_headers(start_height, count))
+		} else {
+			match self.get_header_listing(start_height, count) {
+				Ok(listing) => json_response(&listing),
+				Err(e) => response_on_error(e),
+			}
+		}
+	}
+}
+
+/// Gets a range of blocks by height, a page of at most `MAX_BLOCKS_PER_PAGE`
+/// at a time.
+/// GET /v1/blocks?start_height=101&end_height=200
+///
+/// The `next_height` of the response is the `start_height` of the next page,
+/// none once `end_height` or the chain head is reached.
+///
+/// Optionally include the Merkle proofs of the outputs by passing
+/// "?include_proof".
+///
+/// Optionally get the blocks serialized instead of JSON, as a u64 count
+/// followed by the blocks, by passing "?format=binary". The start height of
+/// the next page is then in the `X-Next-Height` response header.
+pub struct BlockRangeHandler {
+	pub chain: Weak<chain::Chain>,
+}
+
+impl BlockRangeHandler {
+	fn get_blocks(
+		&self,
+		start_height: u64,
+		end_height: u64,
+	) -> Result<(Vec<core::Block>, Option<u64>), Error> {
+		let chain = w(&self.chain)?;
+		let head = chain
+			.head()
+			.map_err(|e| ErrorKind::Internal(format!("can't get head: {}", e)))?;
+		let (last, next_height) =
+			page_bounds(start_height, end_height, head.height, MAX_BLOCKS_PER_PAGE)?;
+		let mut blocks = vec![];
+		for height in start_height..=last {
+			let header = chain
+				.get_header_by_height(height)
+				.context(ErrorKind::NotFound)?;
+			// pruned blocks are not found, rather than silently skipped
+			blocks.push(
+				chain
+					.get_block(&header.hash())
+					.context(ErrorKind::NotFound)?,
+			);
+		}
+		Ok((blocks, next_height))
+	}
+
+	pub fn get_block_listing(
+		&self,
+		start_height: u64,
+		end_height: u64,
+		include_proof: bool,
+	) -> Result<BlockListing, Error> {
+		let (blocks, next_height) = self.get_blocks(start_height, end_height)?;
+		let chain = w(&self.chain)?;
+		let blocks = blocks
+			.iter()
+			.map(|b| BlockPrintable::from_block(b, chain.clone(), include_proof))
+			.collect::<Result<Vec<_>, _>>()
+			.map_err(|_| ErrorKind::Internal("chain error".to_owned()))?;
+		Ok(BlockListing {
+			blocks,
+			next_height,
+		})
+	}
+
+	fn parse_params(&self, req: &Request<Body>) -> Result<(u64, u64, bool, bool), Error> {
+		let params = QueryParams::from(req.uri().query());
+		let start_height: u64 = parse_param!(params, "start_height", 0);
+		let end_height = parse_param!(
+			params,
+			"end_height",
+			start_height.saturating_add(MAX_BLOCKS_PER_PAGE - 1)
+		);
+		let include_proof = params
+			.get("include_proof")
+			.map(|v| v != "false")
+			.unwrap_or(false);
+		Ok((start_height, end_height, include_proof, is_binary(&params)))
+	}
+}
+
+impl Handler for BlockRangeHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		let (start_height, end_height, include_proof, binary) = match self.parse_params(&req) {
+			Ok(p) => p,
+			Err(e) => return response_on_error(e),
+		};
+		if binary {
+			binary_range_response(self.get_blocks(start_height, end_height))
+		} else {
+			match self.get_block_listing(start_height, end_height, include_proof) {
+				Ok(listing) => json_response(&listing),
+				Err(e) => response_on_error(e),
+			}
+		}
+	}
+}
+
+/// Last height of the page starting at `start_height`, bounded by the
+/// requested end height, the tip and the page size. Along with the start of
+/// the next page, if there's anything left to get.
+fn page_bounds(
+	start_height: u64,
+	end_height: u64,
+	tip_height: u64,
+	max_per_page: u64,
+) -> Result<(u64, Option<u64>), Error> {
+	if end_height < start_height {
+		return Err(ErrorKind::Argument(
+			"end height is lower than start height".to_owned(),
+		))?;
+	}
+	let end_height = end_height.min(tip_height);
+	let last = end_height.min(start_height.saturating_add(max_per_page - 1));
+	let next_height = if last < end_height {
+		Some(last + 1)
+	} else {
+		None
+	};
+	Ok((last, next_height))
+}
+
+fn is_binary(params: &QueryParams) -> bool {
+	params.get("format").map(|f| f == "binary").unwrap_or(false)
+}
+
+/// Serializes a page of a range as a u64 count followed by the items.
+fn binary_range_response<T: Writeable>(
+	res: Result<(Vec<T>, Option<u64>), Error>,
+) -> ResponseFuture {
+	let (items, next_height) = match res {
+		Ok(r) => r,
+		Err(e) => return response_on_error(e),
+	};
+	let version = ProtocolVersion::local();
+	let bin = ser::ser_vec(&(items.len() as u64), version).and_then(|mut bin| {
+		bin.append(&mut ser::ser_vec(&items, version)?);
+		Ok(bin)
+	});
+	let bin = match bin {
+		Ok(bin) => bin,
+		Err(e) => {
+			return response(
+				StatusCode::INTERNAL_SERVER_ERROR,
+				format!("can't serialize: {}", e),
+			)
+		}
+	};
+	let mut resp = just_response(StatusCode::OK, bin);
+	let headers = resp.headers_mut();
+	headers.insert(
+		CONTENT_TYPE,
+		HeaderValue::from_static("application/octet-stream"),
+	);
+	if let Some(height) = next_height {
+		headers.insert(NEXT_HEIGHT_HEADER, HeaderValue::from(height));
+	}
+	Box::new(ok(resp))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn range_pages() {
+		// whole range within a page
+		assert_eq!(page_bounds(10, 20, 100, 50).unwrap(), (20, None));
+		// range bigger than a page
+		assert_eq!(page_bounds(10, 200, 1_000, 50).unwrap(), (59, Some(60)));
+		// range beyond the tip
+		assert_eq!(page_bounds(90, 200, 100, 50).unwrap(), (100, None));
+		assert_eq!(page_bounds(60, 200, 100, 20).unwrap(), (79, Some(80)));
+		// single block
+		assert_eq!(page_bounds(5, 5, 100, 50).unwrap(), (5, None));
+		assert!(page_bounds(20, 10, 100, 50).is_err());
+	}
+
+	#[test]
+	fn empty_header_range() {
+		let handler = HeaderRangeHandler { chain: Weak::new() };
+		let e = handler.get_headers(10, 0).unwrap_err();
+		assert_eq!(
+			e.kind(),
+			&ErrorKind::Argument("count must be at least 1".to_owned())
+		);
+	}
+}
@@ -33,7 +33,7 @@ pub struct ChainHandler {
 }
 
 impl ChainHandler {
-	fn get_tip(&self) -> Result<Tip, Error> {
+	pub fn get_tip(&self) -> Result<Tip, Error> {
 		let head = w(&self.chain)?
 			.head()
 			.map_err(|e| ErrorKind::Internal(format!("can't get head: {}", e)))?;
@@ -65,6 +65,56 @@ impl Handler for ChainValidationHandler {
 	}
 }
 
+/// Supply audit handler. Audits the UTXO set at a height, the chain head by
+/// default: its explicit value against the coinbase issued, and the kernel
+/// sums. Iterates over the whole UTXO set.
+/// GET /v1/chain/supply?height=<height>
+pub struct SupplyAuditHandler {
+	pub chain: Weak<chain::Chain>,
+}
+
+impl SupplyAuditHandler {
+	pub fn get_supply_audit(&self, height: Option<u64>) -> Result<SupplyAuditPrintable, Error> {
+		let chain = w(&self.chain)?;
+		let head = chain
+			.head_header()
+			.map_err(|e| ErrorKind::Internal(format!("can't get head: {}", e)))?;
+		let header = match height {
+			None => head,
+			Some(height) if height > head.height => return Err(ErrorKind::NotFound)?,
+			Some(height) => chain
+				.get_header_by_height(height)
+				.map_err(|_| ErrorKind::NotFound)?,
+		};
+		let audit = chain.supply_audit(&header).map_err(|e| match e.kind() {
+			chain::ErrorKind::BeyondHorizon(_) => {
+				ErrorKind::Argument(format!("{}, an archive node can audit older blocks", e))
+			}
+			_ => ErrorKind::Internal(format!("supply audit failed: {}", e)),
+		})?;
+		Ok(SupplyAuditPrintable::from_audit(&audit))
+	}
+
+	fn parse_height(&self, req: &Request<Body>) -> Result<Option<u64>, Error> {
+		let params = QueryParams::from(req.uri().query());
+		match params.get("height") {
+			None => Ok(None),
+			Some(height) => height.parse().map(Some).map_err(|_| {
+				ErrorKind::RequestError("invalid value of parameter height".to_owned()).into()
+			}),
+		}
+	}
+}
+
+impl Handler for SupplyAuditHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		result_to_response(
+			self.parse_height(&req)
+				.and_then(|height| self.get_supply_audit(height)),
+		)
+	}
+}
+
 /// Chain compaction handler. Trigger a compaction of the chain state to regain
 /// storage space.
 /// POST /v1/chain/compact
@@ -99,6 +149,13 @@ impl TxKernelHandler {
 		let params = QueryParams::from(query);
 		params.process_multival_param("id", |id| excesses.push(id.to_owned()));
 
+		self.kernels_by_excesses(excesses)
+	}
+
+	pub fn kernels_by_excesses(
+		&self,
+		excesses: Vec<String>,
+	) -> Result<Vec<TxKernelApiEntry>, Error> {
 		let mut tx_kernels: Vec<TxKernelApiEntry> = vec![];
 		let chain = w(&self.chain)?;
 		for ref id in excesses {
@@ -150,6 +207,10 @@ impl OutputHandler {
 		let params = QueryParams::from(query);
 		params.process_multival_param("id", |id| commitments.push(id.to_owned()));
 
+		self.outputs_by_commits(commitments)
+	}
+
+	pub fn outputs_by_commits(&self, commitments: Vec<String>) -> Result<Vec<OutputEx>, Error> {
 		let mut outputs: Vec<OutputEx> = vec![];
 		for x in commitments {
 			match self.get_output(&x) {
@@ -252,14 +313,6 @@ impl KernelHandler {
 			.rsplit('/')
 			.next()
 			.ok_or(ErrorKind::RequestError("missing excess".into()))?;
-		let excess = util::from_hex(excess.to_owned())
-			.map_err(|_| ErrorKind::RequestError("invalid excess hex".into()))?;
-		if excess.len() != 33 {
-			return Err(ErrorKind::RequestError("invalid excess length".into()).into());
-		}
-		let excess = Commitment::from_vec(excess);
-
-		let chain = w(&self.chain)?;
 
 		let mut min_height: Option<u64> = None;
 		let mut max_height: Option<u64> = None;
@@ -271,21 +324,48 @@ impl KernelHandler {
 				let h = h
 					.parse()
 					.map_err(|_| ErrorKind::RequestError("invalid minimum height".into()))?;
-				// Default is genesis
-				min_height = if h == 0 { None } else { Some(h) };
+				min_height = Some(h);
 			}
 			if let Some(h) = params.get("max_height") {
 				let h = h
 					.parse()
 					.map_err(|_| ErrorKind::RequestError("invalid maximum height".into()))?;
-				// Default is current head
+				max_height = Some(h);
+			}
+		}
+
+		self.get_kernel_between(excess, min_height, max_height)
+	}
+
+	/// Kernel with the given (hex) excess, searched between the optional
+	/// heights.
+	pub fn get_kernel_between(
+		&self,
+		excess: &str,
+		min_height: Option<u64>,
+		max_height: Option<u64>,
+	) -> Result<Option<LocatedTxKernel>, Error> {
+		let excess = parse_commitment(excess, "excess")?;
+
+		let chain = w(&self.chain)?;
+
+		// Default is genesis
+		let min_height = min_height.filter(|h| *h > 0);
+		// Default is current head
+		let max_height = match max_height {
+			Some(h) => {
 				let head_height = chain
 					.head()
 					.map_err(|e| ErrorKind::Internal(format!("{}", e)))?
 					.height;
-				max_height = if h >= head_height { None } else { Some(h) };
+				if h >= head_height {
+					None
+				} else {
+					Some(h)
+				}
 			}
-		}
+			None => None,
+		};
 
 		let kernel = chain
 			.get_kernel_height(&excess, min_height, max_height)
@@ -304,3 +384,61 @@ impl Handler for KernelHandler {
 		result_to_response(self.get_kernel(req))
 	}
 }
+
+/// Outputs and kernels with their merkle proofs against our header chain.
+/// Provided by the node, from its own chain or from its peers when it only
+/// has the headers (light mode).
+pub trait ProofSource: Sync + Send {
+	/// The unspent output with that commitment, if any. From our peers, it is
+	/// unspent as of the header of the proof, a few blocks behind our head at
+	/// most (see `chain::MAX_PROOF_AGE`).
+	fn output_proof(&self, commit: Commitment) -> Result<Option<chain::OutputProof>, Error>;
+
+	/// The tx kernel with that excess, if any.
+	fn kernel_proof(&self, excess: Commitment) -> Result<Option<chain::KernelProof>, Error>;
+}
+
+/// Merkle proof handler, an output or a kernel with its proof of inclusion,
+/// for clients only following the header chain.
+/// GET /v1/proofs/output/<commit>
+/// GET /v1/proofs/kernel/<excess>
+pub struct ProofHandler {
+	pub proofs: Weak<dyn ProofSource>,
+}
+
+impl ProofHandler {
+	/// Unspent output with the given (hex) commitment and its proof.
+	pub fn get_output_proof(&self, commit: &str) -> Result<Option<OutputProofPrintable>, Error> {
+		let commit = parse_commitment(commit, "commitment")?;
+		let proof = w(&self.proofs)?.output_proof(commit)?;
+		Ok(proof.as_ref().map(OutputProofPrintable::from_proof))
+	}
+
+	/// Kernel with the given (hex) excess and its proof.
+	pub fn get_kernel_proof(&self, excess: &str) -> Result<Option<KernelProofPrintable>, Error> {
+		let excess = parse_commitment(excess, "excess")?;
+		let proof = w(&self.proofs)?.kernel_proof(excess)?;
+		Ok(proof.as_ref().map(KernelProofPrintable::from_proof))
+	}
+}
+
+impl Handler for ProofHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		let mut elements = req.uri().path().trim_end_matches('/').rsplit('/');
+		match (elements.next(), elements.next()) {
+			(Some(commit), Some("output")) => result_to_response(self.get_output_proof(commit)),
+			(Some(excess), Some("kernel")) => result_to_response(self.get_kernel_proof(excess)),
+			_ => response(StatusCode::BAD_REQUEST, "invalid url"),
+		}
+	}
+}
+
+// Parses a hex commitment (output commitment or kernel excess).
+fn parse_commitment(hex: &str, name: &str) -> Result<Commitment, Error> {
+	let commit = util::from_hex(hex.to_owned())
+		.map_err(|_| ErrorKind::RequestError(format!("invalid {} hex", name)))?;
+	if commit.len() != 33 {
+		return Err(ErrorKind::RequestError(format!("invalid {} length", name)).into());
+	}
+	Ok(Commitment::from_vec(commit))
+}
@@ -0,0 +1,324 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::utils::w;
+use crate::chain;
+use crate::chain::SyncStatus;
+use crate::p2p;
+use crate::pool;
+use crate::rest::*;
+use crate::router::{Handler, ResponseFuture};
+use crate::util::RwLock;
+use crate::web::*;
+use futures::future::ok;
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::{Body, Request, StatusCode};
+use std::fmt::Write;
+use std::sync::Weak;
+
+/// Content type of the Prometheus text exposition format.
+const PROMETHEUS_CONTENT_TYPE: &'static str = "text/plain; version=0.0.4";
+
+/// All the sync statuses, exported as a 0/1 gauge each.
+const SYNC_STATUSES: [&'static str; 11] = [
+	"initial",
+	"no_sync",
+	"awaiting_peers",
+	"header_sync",
+	"txhashset_download",
+	"txhashset_setup",
+	"txhashset_validation",
+	"txhashset_save",
+	"txhashset_done",
+	"body_sync",
+	"shutdown",
+];
+
+/// Node metrics in the Prometheus text format, for scraping.
+/// GET /v1/metrics
+pub struct MetricsHandler {
+	pub chain: Weak<chain::Chain>,
+	pub tx_pool: Weak<RwLock<pool::TransactionPool>>,
+	pub peers: Weak<p2p::Peers>,
+	pub sync_state: Weak<chain::SyncState>,
+	pub traffic: Weak<p2p::TrafficStats>,
+}
+
+impl MetricsHandler {
+	pub fn get_metrics(&self) -> Result<String, Error> {
+		let chain = w(&self.chain)?;
+		let mut m = Metrics::new();
+
+		let head = chain
+			.head_header()
+			.map_err(|e| ErrorKind::Internal(format!("can't get head: {}", e)))?;
+		let header_head = chain
+			.header_head()
+			.map_err(|e| ErrorKind::Internal(format!("can't get header head: {}", e)))?;
+		let prev_difficulty = chain
+			.get_previous_header(&head)
+			.map(|prev| prev.total_difficulty().to_num())
+			.unwrap_or(0);
+		m.gauge(
+			"gotts_chain_height",
+			"Height of the chain head.",
+			head.height,
+		);
+		m.gauge(
+			"gotts_chain_header_height",
+			"Height of the header chain head.",
+			header_head.height,
+		);
+		m.gauge(
+			"gotts_chain_difficulty",
+			"Difficulty of the block at the chain head.",
+			head.total_difficulty()
+				.to_num()
+				.saturating_sub(prev_difficulty),
+		);
+		m.gauge(
+			"gotts_chain_total_difficulty",
+			"Total difficulty of the chain head.",
+			head.total_difficulty().to_num(),
+		);
+
+		let latencies = chain.block_latencies();
+		m.histogram(
+			"gotts_block_processing_seconds",
+			"Time spent processing blocks through the chain pipeline.",
+			&latencies,
+		);
+		m.gauge(
+			"gotts_chain_db_size_bytes",
+			"Size of the chain LMDB database on disk.",
+			chain.db_size(),
+		);
+
+		let status = sync_status_name(w(&self.sync_state)?.status());
+		m.header(
+			"gotts_sync_status",
+			"Current sync status, 1 for the active one.",
+			"gauge",
+		);
+		for s in SYNC_STATUSES.iter() {
+			let value = if *s == status { 1 } else { 0 };
+			m.sample("gotts_sync_status", &[("status", *s)], value);
+		}
+
+		let peers = w(&self.peers)?;
+		m.header(
+			"gotts_peers_connected",
+			"Connected peers by direction.",
+			"gauge",
+		);
+		m.sample(
+			"gotts_peers_connected",
+			&[("direction", "inbound")],
+			peers.incoming_connected_peers().len(),
+		);
+		m.sample(
+			"gotts_peers_connected",
+			&[("direction", "outbound")],
+			peers.outgoing_connected_peers().len(),
+		);
+
+		let traffic = w(&self.traffic)?.snapshot();
+		let families = [
+			(
+				"gotts_p2p_sent_messages_total",
+				"P2P messages sent by message type.",
+			),
+			(
+				"gotts_p2p_sent_bytes_total",
+				"P2P bytes sent by message type.",
+			),
+			(
+				"gotts_p2p_received_messages_total",
+				"P2P messages received by message type.",
+			),
+			(
+				"gotts_p2p_received_bytes_total",
+				"P2P bytes received by message type.",
+			),
+		];
+		for (i, (name, help)) in families.iter().enumerate() {
+			m.header(name, help, "counter");
+			for t in &traffic {
+				let value = match i {
+					0 => t.sent_msgs,
+					1 => t.sent_bytes,
+					2 => t.received_msgs,
+					_ => t.received_bytes,
+				};
+				let msg_type = format!("{:?}", t.msg_type);
+				m.sample(name, &[("type", msg_type.as_str())], value);
+			}
+		}
+
+		let pool_arc = w(&self.tx_pool)?;
+		let pool = pool_arc.read();
+		m.header(
+			"gotts_pool_size",
+			"Transactions in the pool, by pool.",
+			"gauge",
+		);
+		m.sample("gotts_pool_size", &[("pool", "txpool")], pool.txpool.size());
+		m.sample(
+			"gotts_pool_size",
+			&[("pool", "stempool")],
+			pool.stempool.size(),
+		);
+		m.sample(
+			"gotts_pool_size",
+			&[("pool", "reorg_cache")],
+			pool.reorg_cache.read().len(),
+		);
+
+		Ok(m.finish())
+	}
+}
+
+impl Handler for MetricsHandler {
+	fn get(&self, _req: Request<Body>) -> ResponseFuture {
+		match self.get_metrics() {
+			Ok(text) => {
+				let mut resp = just_response(StatusCode::OK, text);
+				resp.headers_mut().insert(
+					CONTENT_TYPE,
+					HeaderValue::from_static(PROMETHEUS_CONTENT_TYPE),
+				);
+				Box::new(ok(resp))
+			}
+			Err(e) => response_on_error(e),
+		}
+	}
+}
+
+fn sync_status_name(status: SyncStatus) -> &'static str {
+	match status {
+		SyncStatus::Initial => "initial",
+		SyncStatus::NoSync => "no_sync",
+		SyncStatus::AwaitingPeers(_) => "awaiting_peers",
+		SyncStatus::HeaderSync { .. } => "header_sync",
+		SyncStatus::TxHashsetDownload { .. } => "txhashset_download",
+		SyncStatus::TxHashsetSetup => "txhashset_setup",
+		SyncStatus::TxHashsetValidation { .. } => "txhashset_validation",
+		SyncStatus::TxHashsetSave => "txhashset_save",
+		SyncStatus::TxHashsetDone => "txhashset_done",
+		SyncStatus::BodySync { .. } => "body_sync",
+		SyncStatus::Shutdown => "shutdown",
+	}
+}
+
+/// Builds a page in the Prometheus text exposition format.
+struct Metrics {
+	buf: String,
+}
+
+impl Metrics {
+	fn new() -> Metrics {
+		Metrics { buf: String::new() }
+	}
+
+	/// Writes the HELP and TYPE lines of a metric family.
+	fn header(&mut self, name: &str, help: &str, kind: &str) {
+		let _ = writeln!(self.buf, "# HELP {} {}", name, help);
+		let _ = writeln!(self.buf, "# TYPE {} {}", name, kind);
+	}
+
+	/// Writes a single sample, with its labels if any.
+	fn sample<T: ToString>(&mut self, name: &str, labels: &[(&str, &str)], value: T) {
+		self.buf.push_str(name);
+		if !labels.is_empty() {
+			let labels: Vec<String> = labels
+				.iter()
+				.map(|(k, v)| format!("{}=\"{}\"", k, escape_label(v)))
+				.collect();
+			let _ = write!(self.buf, "{{{}}}", labels.join(","));
+		}
+		let _ = writeln!(self.buf, " {}", value.to_string());
+	}
+
+	/// A gauge without labels.
+	fn gauge<T: ToString>(&mut self, name: &str, help: &str, value: T) {
+		self.header(name, help, "gauge");
+		self.sample(name, &[], value);
+	}
+
+	fn histogram(&mut self, name: &str, help: &str, h: &chain::LatencyHistogram) {
+		self.header(name, help, "histogram");
+		let bucket = format!("{}_bucket", name);
+		for (le, count) in &h.buckets {
+			self.sample(&bucket, &[("le", le.to_string().as_str())], count);
+		}
+		self.sample(&bucket, &[("le", "+Inf")], h.count);
+		self.sample(&format!("{}_sum", name), &[], h.sum);
+		self.sample(&format!("{}_count", name), &[], h.count);
+	}
+
+	fn finish(self) -> String {
+		self.buf
+	}
+}
+
+fn escape_label(value: &str) -> String {
+	value
+		.replace('\\', "\\\\")
+		.replace('"', "\\\"")
+		.replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::Duration;
+
+	#[test]
+	fn text_format() {
+		let mut m = Metrics::new();
+		m.gauge("gotts_chain_height", "Height of the chain head.", 42);
+		m.header("gotts_pool_size", "Transactions in the pool.", "gauge");
+		m.sample("gotts_pool_size", &[("pool", "txpool")], 3);
+		assert_eq!(
+			m.finish(),
+			"# HELP gotts_chain_height Height of the chain head.\n\
+			 # TYPE gotts_chain_height gauge\n\
+			 gotts_chain_height 42\n\
+			 # HELP gotts_pool_size Transactions in the pool.\n\
+			 # TYPE gotts_pool_size gauge\n\
+			 gotts_pool_size{pool=\"txpool\"} 3\n"
+		);
+	}
+
+	#[test]
+	fn histogram_buckets() {
+		let mut h = chain::LatencyHistogram::new(&[0.1, 1.0]);
+		h.observe(Duration::from_millis(50));
+		h.observe(Duration::from_millis(500));
+		h.observe(Duration::from_secs(2));
+
+		let mut m = Metrics::new();
+		m.histogram("t", "Test.", &h);
+		let text = m.finish();
+		assert!(text.contains("t_bucket{le=\"0.1\"} 1\n"));
+		assert!(text.contains("t_bucket{le=\"1\"} 2\n"));
+		assert!(text.contains("t_bucket{le=\"+Inf\"} 3\n"));
+		assert!(text.contains("t_count 3\n"));
+	}
+
+	#[test]
+	fn label_escaping() {
+		assert_eq!(escape_label("a\"b\\c"), "a\\\"b\\\\c");
+	}
+}
@@ -13,11 +13,16 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::utils::w;
+use super::utils::{parse_peer_addr, w};
 use crate::p2p;
 use crate::p2p::types::{PeerAddr, PeerInfoDisplay, ReasonForBan};
+use crate::p2p::BanEntry;
+use crate::rest::*;
 use crate::router::{Handler, ResponseFuture};
+use crate::types::{BanRequest, UnbanRequest};
 use crate::web::*;
+use futures::future::ok;
+use futures::Future;
 use hyper::{Body, Request, StatusCode};
 use std::sync::Weak;
 
@@ -60,19 +65,16 @@ impl Handler for PeerHandler {
 		let command = right_path_element!(req);
 
 		// We support both "ip" and "ip:port" here for peer_addr.
-		// "ip:port" is only really useful for local usernet testing on loopback address.
 		// Normally we map peers to ip and only allow a single peer per ip address.
-		let peer_addr;
-		if let Ok(ip_addr) = command.parse() {
-			peer_addr = PeerAddr::from_ip(ip_addr);
-		} else if let Ok(addr) = command.parse() {
-			peer_addr = PeerAddr(addr);
-		} else {
-			return response(
-				StatusCode::BAD_REQUEST,
-				format!("peer address unrecognized: {}", req.uri().path()),
-			);
-		}
+		let peer_addr = match parse_peer_addr(command) {
+			Ok(addr) => addr,
+			Err(_) => {
+				return response(
+					StatusCode::BAD_REQUEST,
+					format!("peer address unrecognized: {}", req.uri().path()),
+				);
+			}
+		};
 
 		match w_fut!(&self.peers).get_peer(peer_addr) {
 			Ok(peer) => json_response(&peer),
@@ -110,3 +112,97 @@ impl Handler for PeerHandler {
 		response(StatusCode::OK, "{}")
 	}
 }
+
+/// Subnet bans
+/// GET /v1/bans
+/// POST /v1/bans
+/// POST /v1/bans/unban
+/// POST /v1/bans/import
+///
+/// The list returned by GET can be posted as is to /v1/bans/import on
+/// another node.
+pub struct BansHandler {
+	pub peers: Weak<p2p::Peers>,
+}
+
+impl Handler for BansHandler {
+	fn get(&self, _req: Request<Body>) -> ResponseFuture {
+		json_response_pretty(&w_fut!(&self.peers).bans())
+	}
+
+	fn post(&self, req: Request<Body>) -> ResponseFuture {
+		let peers = w_fut!(&self.peers);
+		let command = right_path_element!(req).to_owned();
+		let res: Box<dyn Future<Item = String, Error = Error> + Send> = match command.as_str() {
+			"bans" => Box::new(parse_body(req).and_then(move |ban: BanRequest| {
+				ban_subnet(&peers, ban)?;
+				Ok("{}".to_owned())
+			})),
+			"unban" => Box::new(parse_body(req).and_then(move |unban: UnbanRequest| {
+				unban_subnet(&peers, unban)?;
+				Ok("{}".to_owned())
+			})),
+			"import" => Box::new(parse_body(req).and_then(move |bans: Vec<BanEntry>| {
+				let count = peers
+					.import_bans(bans)
+					.map_err(|e| ErrorKind::Internal(format!("import failed: {:?}", e)))?;
+				Ok(format!("{{\"imported\":{}}}", count))
+			})),
+			_ => return response(StatusCode::BAD_REQUEST, "invalid command"),
+		};
+		Box::new(
+			res.and_then(|body| ok(just_response(StatusCode::OK, body)))
+				.or_else(|e| {
+					let status = match e.kind() {
+						ErrorKind::NotFound => StatusCode::NOT_FOUND,
+						ErrorKind::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+						_ => StatusCode::BAD_REQUEST,
+					};
+					ok(just_response(status, format!("{}", e)))
+				}),
+		)
+	}
+}
+
+/// Bans a subnet (or a single address).
+pub fn ban_subnet(peers: &p2p::Peers, ban: BanRequest) -> Result<(), Error> {
+	let duration = match (ban.permanent, ban.duration) {
+		(true, None) => None,
+		(true, Some(_)) => {
+			return Err(ErrorKind::Argument("a permanent ban has no duration".to_owned()).into());
+		}
+		(false, Some(d)) if d <= 0 => {
+			return Err(ErrorKind::Argument(format!("invalid ban duration: {}", d)).into());
+		}
+		(false, d) => Some(d.unwrap_or(peers.ban_window())),
+	};
+	peers
+		.ban_subnet(BanEntry::new(
+			ban.subnet,
+			ReasonForBan::ManualBan,
+			ban.note.unwrap_or_default(),
+			duration,
+		))
+		.map_err(|e| ErrorKind::Internal(format!("ban failed: {:?}", e)))?;
+	Ok(())
+}
+
+/// Removes the ban of a subnet, or of a single address banned on its own.
+pub fn unban_subnet(peers: &p2p::Peers, unban: UnbanRequest) -> Result<(), Error> {
+	let subnet = unban.subnet;
+	let res = peers.unban_subnet(&subnet);
+	// a single address may also have been banned on its own
+	if subnet.is_single() {
+		let addr = PeerAddr::from_ip(subnet.addr());
+		let banned = match peers.get_peer(addr) {
+			Ok(p) => p.flags == p2p::State::Banned,
+			Err(_) => false,
+		};
+		if banned {
+			peers.unban_peer(addr);
+			return Ok(());
+		}
+	}
+	res.map_err(|_| ErrorKind::NotFound)?;
+	Ok(())
+}
@@ -14,6 +14,7 @@
 // limitations under the License.
 
 use super::utils::w;
+use crate::chain;
 use crate::core::core::hash::Hashed;
 use crate::core::core::Transaction;
 use crate::core::ser::{self, ProtocolVersion};
@@ -22,6 +23,7 @@ use crate::rest::*;
 use crate::router::{Handler, ResponseFuture};
 use crate::types::*;
 use crate::util;
+use crate::util::secp::pedersen::Commitment;
 use crate::util::RwLock;
 use crate::web::*;
 use failure::ResultExt;
@@ -47,10 +49,80 @@ impl Handler for PoolInfoHandler {
 	}
 }
 
+/// Get the status of a transaction by one of its kernel excesses, whether
+/// it's unknown (or rejected), in the stempool, in the txpool, confirmed or
+/// reorged out. Stem txs are under embargo, reported as unknown unless
+/// with_stem (on the owner API).
+/// GET /v1/tx/status/<kernel excess>
+pub struct TxStatusHandler {
+	pub chain: Weak<chain::Chain>,
+	pub tx_pool: Weak<RwLock<pool::TransactionPool>>,
+	pub with_stem: bool,
+}
+
+impl TxStatusHandler {
+	pub fn get_tx_status(&self, excess: &str) -> Result<TxStatusInfo, Error> {
+		let excess = util::from_hex(excess.to_owned())
+			.map_err(|_| ErrorKind::RequestError("invalid excess hex".into()))?;
+		if excess.len() != 33 {
+			return Err(ErrorKind::RequestError("invalid excess length".into()).into());
+		}
+		let excess = Commitment::from_vec(excess);
+
+		let chain = w(&self.chain)?;
+		if let Some((_, height, _)) = chain
+			.get_kernel_height(&excess, None, None)
+			.map_err(|e| ErrorKind::Internal(format!("{}", e)))?
+		{
+			let head = chain
+				.head()
+				.map_err(|e| ErrorKind::Internal(format!("{}", e)))?;
+			return Ok(TxStatusInfo {
+				status: TxStatus::Confirmed,
+				height: Some(height),
+				confirmations: Some(head.height.saturating_sub(height) + 1),
+				last_error: None,
+			});
+		}
+
+		let pool_arc = w(&self.tx_pool)?;
+		let pool = pool_arc.read();
+		let status = if pool.txpool.contains_kernel(&excess) {
+			TxStatus::Pool
+		} else if self.with_stem && pool.stempool.contains_kernel(&excess) {
+			TxStatus::Stem
+		} else if pool.mined_in(&excess).is_some() {
+			// mined in a block we saw, no longer in our chain
+			TxStatus::ReorgedOut
+		} else {
+			TxStatus::Unknown
+		};
+		let last_error = match status {
+			TxStatus::Unknown | TxStatus::ReorgedOut => {
+				pool.rejection(&excess).map(|x| x.error.clone())
+			}
+			_ => None,
+		};
+		Ok(TxStatusInfo {
+			status,
+			height: None,
+			confirmations: None,
+			last_error,
+		})
+	}
+}
+
+impl Handler for TxStatusHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		let excess = right_path_element!(req);
+		result_to_response(self.get_tx_status(excess))
+	}
+}
+
 /// Dummy wrapper for the hex-encoded serialized transaction.
 #[derive(Serialize, Deserialize)]
-struct TxWrapper {
-	tx_hex: String,
+pub(crate) struct TxWrapper {
+	pub(crate) tx_hex: String,
 }
 
 /// Push new transaction to our local transaction pool.
@@ -71,45 +143,166 @@ impl PoolPushHandler {
 
 		Box::new(
 			parse_body(req)
-				.and_then(move |wrapper: TxWrapper| {
-					util::from_hex(wrapper.tx_hex)
-						.map_err(|e| ErrorKind::RequestError(format!("Bad request: {}", e)).into())
+				.and_then(move |wrapper: TxWrapper| push_tx(&pool_arc, wrapper.tx_hex, fluff)),
+		)
+	}
+}
+
+fn deserialize_tx(tx_hex: String) -> Result<Transaction, Error> {
+	let tx_bin = util::from_hex(tx_hex)
+		.map_err(|e| ErrorKind::RequestError(format!("Bad request: {}", e)))?;
+
+	// TODO - pass protocol version in via the api call?
+	let version = ProtocolVersion::local();
+
+	let tx: Transaction = ser::deserialize(&mut &tx_bin[..], version)
+		.map_err(|e| ErrorKind::RequestError(format!("Bad request: {}", e)))?;
+	Ok(tx)
+}
+
+/// Deserializes a hex transaction and pushes it to our transaction pool,
+/// skipping the Dandelion stem phase if fluff.
+pub fn push_tx(
+	pool_arc: &RwLock<pool::TransactionPool>,
+	tx_hex: String,
+	fluff: bool,
+) -> Result<(), Error> {
+	let tx = deserialize_tx(tx_hex)?;
+
+	let source = pool::TxSource::PushApi;
+	let tx_hash = tx.hash();
+	info!(
+		"Pushing transaction {} to pool (inputs: {}, outputs: {}, kernels: {})",
+		tx_hash,
+		tx.inputs().len(),
+		tx.outputs().len(),
+		tx.kernels().len(),
+	);
+
+	//  Push to tx pool.
+	let mut tx_pool = pool_arc.write();
+	let header = tx_pool
+		.blockchain
+		.chain_head()
+		.context(ErrorKind::Internal("Failed to get chain head".to_owned()))?;
+	let res = tx_pool.add_to_pool(source, tx, !fluff, &header);
+	let err_str = if let Err(e) = &res {
+		error!("Pushing transaction {} to pool failed: e = {}", tx_hash, e);
+		format!("Failed to update pool with e = {}", e)
+	} else {
+		String::new()
+	};
+	res.context(ErrorKind::Internal(err_str))?;
+
+	Ok(())
+}
+
+/// Dummy wrapper for the hex-encoded serialized transactions of a package.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct TxsWrapper {
+	pub(crate) txs_hex: Vec<String>,
+}
+
+/// Push a package of dependent transactions, parents first, to our local
+/// transaction pool, accepted (and relayed) as a unit.
+/// POST /v1/pool/push_package
+pub struct PoolPushPackageHandler {
+	pub tx_pool: Weak<RwLock<pool::TransactionPool>>,
+}
+
+/// Deserializes hex transactions and pushes them to our transaction pool as
+/// a package, skipping the Dandelion stem phase if fluff.
+pub fn push_package(
+	pool_arc: &RwLock<pool::TransactionPool>,
+	txs_hex: Vec<String>,
+	fluff: bool,
+) -> Result<(), Error> {
+	let txs = txs_hex
+		.into_iter()
+		.map(deserialize_tx)
+		.collect::<Result<Vec<_>, _>>()?;
+	if txs.is_empty() {
+		return Err(ErrorKind::RequestError("empty package".to_owned()).into());
+	}
+	info!("Pushing package of {} transactions to pool", txs.len());
+
+	let mut tx_pool = pool_arc.write();
+	let header = tx_pool
+		.blockchain
+		.chain_head()
+		.context(ErrorKind::Internal("Failed to get chain head".to_owned()))?;
+	let res = tx_pool.add_package_to_pool(pool::TxSource::PushApi, txs, !fluff, &header);
+	let err_str = if let Err(e) = &res {
+		error!("Pushing package to pool failed: e = {}", e);
+		format!("Failed to update pool with e = {}", e)
+	} else {
+		String::new()
+	};
+	res.context(ErrorKind::Internal(err_str))?;
+
+	Ok(())
+}
+
+impl Handler for PoolPushPackageHandler {
+	fn post(&self, req: Request<Body>) -> ResponseFuture {
+		let params = QueryParams::from(req.uri().query());
+		let fluff = params.get("fluff").is_some();
+		let pool_arc = w_fut!(&self.tx_pool);
+		Box::new(
+			parse_body(req)
+				.and_then(move |wrapper: TxsWrapper| {
+					push_package(&pool_arc, wrapper.txs_hex, fluff)
 				})
-				.and_then(move |tx_bin| {
-					// TODO - pass protocol version in via the api call?
-					let version = ProtocolVersion::local();
+				.then(result_to_response),
+		)
+	}
+}
+
+/// Checks a transaction against all the rules of the pool, as if pushed with
+/// the same fluff param, without adding it to the pool nor relaying it.
+/// POST /v1/pool/validate_tx
+///
+/// The stempool is only checked with `with_stem`, on the owner API, the
+/// foreign one must not tell anything about the txs under embargo.
+pub struct PoolValidateHandler {
+	pub tx_pool: Weak<RwLock<pool::TransactionPool>>,
+	pub with_stem: bool,
+}
+
+/// Deserializes a hex transaction and dry runs it through our transaction
+/// pool, reporting which rules it breaks.
+pub fn validate_tx(
+	pool_arc: &RwLock<pool::TransactionPool>,
+	tx_hex: String,
+	fluff: bool,
+	with_stem: bool,
+) -> Result<TxValidation, Error> {
+	let tx = deserialize_tx(tx_hex)?;
+
+	let tx_pool = pool_arc.read();
+	let header = tx_pool
+		.blockchain
+		.chain_head()
+		.context(ErrorKind::Internal("Failed to get chain head".to_owned()))?;
+	let checks = tx_pool.dry_run(&tx, !fluff, with_stem, &header);
+	Ok(TxValidation {
+		valid: checks.iter().all(|c| c.passed()),
+		checks,
+	})
+}
 
-					ser::deserialize(&mut &tx_bin[..], version)
-						.map_err(|e| ErrorKind::RequestError(format!("Bad request: {}", e)).into())
+impl Handler for PoolValidateHandler {
+	fn post(&self, req: Request<Body>) -> ResponseFuture {
+		let params = QueryParams::from(req.uri().query());
+		let fluff = params.get("fluff").is_some();
+		let pool_arc = w_fut!(&self.tx_pool);
+		let with_stem = self.with_stem;
+		Box::new(
+			parse_body(req)
+				.and_then(move |wrapper: TxWrapper| {
+					validate_tx(&pool_arc, wrapper.tx_hex, fluff, with_stem)
 				})
-				.and_then(move |tx: Transaction| {
-					let source = pool::TxSource::PushApi;
-					let tx_hash = tx.hash();
-					info!(
-						"Pushing transaction {} to pool (inputs: {}, outputs: {}, kernels: {})",
-						tx_hash,
-						tx.inputs().len(),
-						tx.outputs().len(),
-						tx.kernels().len(),
-					);
-
-					//  Push to tx pool.
-					let mut tx_pool = pool_arc.write();
-					let header = tx_pool
-						.blockchain
-						.chain_head()
-						.context(ErrorKind::Internal("Failed to get chain head".to_owned()))?;
-					let res = tx_pool.add_to_pool(source, tx, !fluff, &header);
-					let err_str = if let Err(e) = &res {
-						error!("Pushing transaction {} to pool failed: e = {}", tx_hash, e);
-						format!("Failed to update pool with e = {}", e)
-					} else {
-						String::new()
-					};
-					res.context(ErrorKind::Internal(err_str))?;
-
-					Ok(())
-				}),
+				.then(result_to_response),
 		)
 	}
 }
@@ -128,3 +321,58 @@ impl Handler for PoolPushHandler {
 		)
 	}
 }
+
+/// Pool administration, on the owner API.
+/// GET /v1/pool/entries
+/// POST /v1/pool/clear
+/// POST /v1/pool/validate_tx, with the stempool
+pub struct PoolAdminHandler {
+	pub tx_pool: Weak<RwLock<pool::TransactionPool>>,
+}
+
+/// Every transaction in our txpool and our stempool.
+pub fn pool_entries(pool_arc: &RwLock<pool::TransactionPool>) -> Vec<PoolEntryPrintable> {
+	let tx_pool = pool_arc.read();
+	let txpool = tx_pool
+		.txpool
+		.entries
+		.iter()
+		.map(|x| PoolEntryPrintable::from_entry(x, "txpool"));
+	let stempool = tx_pool
+		.stempool
+		.entries
+		.iter()
+		.map(|x| PoolEntryPrintable::from_entry(x, "stempool"));
+	txpool.chain(stempool).collect()
+}
+
+/// Removes every transaction from our txpool and our stempool, returning how
+/// many were removed.
+pub fn clear_pool(pool_arc: &RwLock<pool::TransactionPool>) -> usize {
+	let removed = pool_arc.write().clear();
+	warn!("Cleared {} transactions from the pool", removed.len());
+	removed.len()
+}
+
+impl Handler for PoolAdminHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		let command = right_path_element!(req);
+		match command {
+			"entries" => json_response(&pool_entries(&w_fut!(&self.tx_pool))),
+			_ => response(StatusCode::BAD_REQUEST, "invalid path"),
+		}
+	}
+
+	fn post(&self, req: Request<Body>) -> ResponseFuture {
+		let command = right_path_element!(req);
+		match command {
+			"clear" => json_response(&clear_pool(&w_fut!(&self.tx_pool))),
+			"validate_tx" => PoolValidateHandler {
+				tx_pool: self.tx_pool.clone(),
+				with_stem: true,
+			}
+			.post(req),
+			_ => response(StatusCode::BAD_REQUEST, "invalid command"),
+		}
+	}
+}
@@ -0,0 +1,341 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::blocks_api::{BlockHandler, HeaderHandler};
+use super::chain_api::{
+	ChainHandler, KernelHandler, OutputHandler, ProofHandler, ProofSource, SupplyAuditHandler,
+	TxKernelHandler,
+};
+use super::peers_api::{ban_subnet, unban_subnet};
+use super::pool_api::{clear_pool, pool_entries, push_package, push_tx, validate_tx};
+use super::server_api::StatusHandler;
+use super::transactions_api::TxHashSetHandler;
+use super::utils::{parse_peer_addr, w};
+use super::version_api::VersionHandler;
+use crate::chain;
+use crate::core::core::hash::Hash;
+use crate::core::core::OutputFeatures;
+use crate::jsonrpc::{parse_params, to_result, RpcApi, RpcError, METHOD_NOT_FOUND};
+use crate::p2p;
+use crate::p2p::types::{PeerInfoDisplay, ReasonForBan};
+use crate::pool;
+use crate::rest::*;
+use crate::types::*;
+use crate::util::RwLock;
+use enum_primitive::FromPrimitive;
+use serde_json::Value;
+use std::sync::Weak;
+
+/// Foreign JSON-RPC api, the chain data and pushing txs.
+/// POST /v2/foreign
+///
+/// Methods: get_version, get_tip, get_status, get_header, get_block,
+/// get_compact_block, get_outputs, get_kernels, get_kernel, get_output_proof,
+/// get_kernel_proof, get_txhashset_roots, get_last_outputs, get_last_kernels,
+/// get_txhashset_outputs, get_nit_outputs, get_merkle_proof, get_pool_info,
+/// push_transaction, push_package, validate_transaction.
+pub struct ForeignRpc {
+	pub chain: Weak<chain::Chain>,
+	pub tx_pool: Weak<RwLock<pool::TransactionPool>>,
+	pub peers: Weak<p2p::Peers>,
+	pub proofs: Weak<dyn ProofSource>,
+}
+
+impl ForeignRpc {
+	fn block_hash(&self, params: &GetBlockParams) -> Result<Hash, Error> {
+		let block_handler = BlockHandler {
+			chain: self.chain.clone(),
+		};
+		match (params.height, &params.hash) {
+			(Some(height), _) => block_handler.parse_input(height.to_string()),
+			(None, Some(hash)) => block_handler.parse_input(hash.clone()),
+			(None, None) => Err(ErrorKind::Argument("height or hash needed".to_owned()))?,
+		}
+	}
+
+	fn get_header(&self, params: GetHeaderParams) -> Result<BlockHeaderPrintable, Error> {
+		let input = match (params.height, params.hash, params.commit) {
+			(Some(height), _, _) => height.to_string(),
+			(None, Some(hash), _) => hash,
+			(None, None, Some(commit)) => commit,
+			(None, None, None) => {
+				return Err(ErrorKind::Argument(
+					"height, hash or commit needed".to_owned(),
+				))?;
+			}
+		};
+		HeaderHandler {
+			chain: self.chain.clone(),
+		}
+		.get_header(input)
+	}
+
+	fn get_block(&self, params: GetBlockParams) -> Result<BlockPrintable, Error> {
+		let h = self.block_hash(&params)?;
+		BlockHandler {
+			chain: self.chain.clone(),
+		}
+		.get_block(&h, params.include_merkle_proof.unwrap_or(true))
+	}
+
+	fn get_compact_block(&self, params: GetBlockParams) -> Result<CompactBlockPrintable, Error> {
+		let h = self.block_hash(&params)?;
+		BlockHandler {
+			chain: self.chain.clone(),
+		}
+		.get_compact_block(&h)
+	}
+
+	fn txhashset_handler(&self) -> TxHashSetHandler {
+		TxHashSetHandler {
+			chain: self.chain.clone(),
+		}
+	}
+
+	fn get_pool_info(&self) -> Result<PoolInfo, Error> {
+		let pool = w(&self.tx_pool)?;
+		let pool = pool.read();
+		Ok(PoolInfo {
+			pool_size: pool.total_size(),
+		})
+	}
+}
+
+impl RpcApi for ForeignRpc {
+	fn call(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+		match method {
+			"get_version" => to_result(
+				VersionHandler {
+					chain: self.chain.clone(),
+				}
+				.get_version(),
+			),
+			"get_tip" => to_result(
+				ChainHandler {
+					chain: self.chain.clone(),
+				}
+				.get_tip(),
+			),
+			"get_status" => to_result(
+				StatusHandler {
+					chain: self.chain.clone(),
+					peers: self.peers.clone(),
+				}
+				.get_status(),
+			),
+			"get_header" => to_result(self.get_header(parse_params(params)?)),
+			"get_block" => to_result(self.get_block(parse_params(params)?)),
+			"get_compact_block" => to_result(self.get_compact_block(parse_params(params)?)),
+			"get_outputs" => {
+				let params: GetOutputsParams = parse_params(params)?;
+				to_result(
+					OutputHandler {
+						chain: self.chain.clone(),
+					}
+					.outputs_by_commits(params.commits),
+				)
+			}
+			"get_kernels" => {
+				let params: GetKernelsParams = parse_params(params)?;
+				to_result(
+					TxKernelHandler {
+						chain: self.chain.clone(),
+					}
+					.kernels_by_excesses(params.excesses),
+				)
+			}
+			"get_kernel" => {
+				let params: GetKernelParams = parse_params(params)?;
+				to_result(
+					KernelHandler {
+						chain: self.chain.clone(),
+					}
+					.get_kernel_between(&params.excess, params.min_height, params.max_height),
+				)
+			}
+			"get_output_proof" => {
+				let params: GetOutputProofParams = parse_params(params)?;
+				to_result(
+					ProofHandler {
+						proofs: self.proofs.clone(),
+					}
+					.get_output_proof(&params.commit),
+				)
+			}
+			"get_kernel_proof" => {
+				let params: GetKernelProofParams = parse_params(params)?;
+				to_result(
+					ProofHandler {
+						proofs: self.proofs.clone(),
+					}
+					.get_kernel_proof(&params.excess),
+				)
+			}
+			"get_txhashset_roots" => to_result(self.txhashset_handler().get_roots()),
+			"get_last_outputs" => {
+				let params: GetLastNodesParams = parse_params(params)?;
+				to_result(
+					self.txhashset_handler()
+						.get_last_n_output(params.n.unwrap_or(10)),
+				)
+			}
+			"get_last_kernels" => {
+				let params: GetLastNodesParams = parse_params(params)?;
+				to_result(
+					self.txhashset_handler()
+						.get_last_n_kernel(params.n.unwrap_or(10)),
+				)
+			}
+			"get_txhashset_outputs" => {
+				let params: GetOutputRangeParams = parse_params(params)?;
+				to_result(
+					self.txhashset_handler()
+						.outputs(params.start_index.unwrap_or(1), params.max.unwrap_or(100)),
+				)
+			}
+			"get_nit_outputs" => {
+				let params: GetOutputRangeParams = parse_params(params)?;
+				to_result(
+					self.txhashset_handler()
+						.nit_outputs(params.start_index.unwrap_or(1), params.max.unwrap_or(100)),
+				)
+			}
+			"get_merkle_proof" => {
+				let params: GetMerkleProofParams = parse_params(params)?;
+				let features = OutputFeatures::from_u8(params.features.unwrap_or(0))
+					.ok_or_else(|| RpcError::invalid_params("invalid features".to_owned()))?;
+				to_result(
+					self.txhashset_handler()
+						.get_merkle_proof_for_output(&params.commit, features),
+				)
+			}
+			"get_pool_info" => to_result(self.get_pool_info()),
+			"push_transaction" => {
+				let params: PushTransactionParams = parse_params(params)?;
+				let res = w(&self.tx_pool).and_then(|p| push_tx(&p, params.tx_hex, params.fluff));
+				to_result(res)
+			}
+			"push_package" => {
+				let params: PushPackageParams = parse_params(params)?;
+				let res =
+					w(&self.tx_pool).and_then(|p| push_package(&p, params.txs_hex, params.fluff));
+				to_result(res)
+			}
+			"validate_transaction" => {
+				let params: PushTransactionParams = parse_params(params)?;
+				let res = w(&self.tx_pool)
+					.and_then(|p| validate_tx(&p, params.tx_hex, params.fluff, false));
+				to_result(res)
+			}
+			_ => Err(RpcError::new(METHOD_NOT_FOUND, "Method not found")),
+		}
+	}
+}
+
+/// Owner JSON-RPC api, the peers, bans, chain maintenance and pool
+/// administration.
+/// POST /v2/owner
+///
+/// Methods: get_peers, get_connected_peers, get_peer, ban_peer, unban_peer,
+/// get_bans, ban_subnet, unban_subnet, compact_chain, validate_chain,
+/// get_supply_audit, get_pool_entries, clear_pool, validate_transaction.
+pub struct OwnerRpc {
+	pub chain: Weak<chain::Chain>,
+	pub tx_pool: Weak<RwLock<pool::TransactionPool>>,
+	pub peers: Weak<p2p::Peers>,
+}
+
+impl OwnerRpc {
+	fn get_connected_peers(&self) -> Result<Vec<PeerInfoDisplay>, Error> {
+		Ok(w(&self.peers)?
+			.connected_peers()
+			.iter()
+			.map(|p| p.info.clone().into())
+			.collect())
+	}
+
+	fn get_peer(&self, params: PeerAddrParams) -> Result<p2p::PeerData, Error> {
+		let addr = parse_peer_addr(&params.addr)?;
+		w(&self.peers)?
+			.get_peer(addr)
+			.map_err(|_| ErrorKind::NotFound.into())
+	}
+
+	fn ban_peer(&self, params: PeerAddrParams) -> Result<(), Error> {
+		let addr = parse_peer_addr(&params.addr)?;
+		w(&self.peers)?.ban_peer(addr, ReasonForBan::ManualBan);
+		Ok(())
+	}
+
+	fn unban_peer(&self, params: PeerAddrParams) -> Result<(), Error> {
+		let addr = parse_peer_addr(&params.addr)?;
+		w(&self.peers)?.unban_peer(addr);
+		Ok(())
+	}
+
+	fn compact_chain(&self) -> Result<(), Error> {
+		w(&self.chain)?
+			.compact()
+			.map_err(|e| ErrorKind::Internal(format!("compact failed: {}", e)).into())
+	}
+
+	fn validate_chain(&self) -> Result<(), Error> {
+		w(&self.chain)?
+			.validate(true)
+			.map_err(|e| ErrorKind::Internal(format!("validate failed: {}", e)).into())
+	}
+
+	fn get_supply_audit(
+		&self,
+		params: GetSupplyAuditParams,
+	) -> Result<SupplyAuditPrintable, Error> {
+		SupplyAuditHandler {
+			chain: self.chain.clone(),
+		}
+		.get_supply_audit(params.height)
+	}
+}
+
+impl RpcApi for OwnerRpc {
+	fn call(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+		match method {
+			"get_peers" => to_result(w(&self.peers).map(|p| p.all_peers())),
+			"get_connected_peers" => to_result(self.get_connected_peers()),
+			"get_peer" => to_result(self.get_peer(parse_params(params)?)),
+			"ban_peer" => to_result(self.ban_peer(parse_params(params)?)),
+			"unban_peer" => to_result(self.unban_peer(parse_params(params)?)),
+			"get_bans" => to_result(w(&self.peers).map(|p| p.bans())),
+			"ban_subnet" => {
+				let params: BanRequest = parse_params(params)?;
+				to_result(w(&self.peers).and_then(|p| ban_subnet(&p, params)))
+			}
+			"unban_subnet" => {
+				let params: UnbanRequest = parse_params(params)?;
+				to_result(w(&self.peers).and_then(|p| unban_subnet(&p, params)))
+			}
+			"compact_chain" => to_result(self.compact_chain()),
+			"validate_chain" => to_result(self.validate_chain()),
+			"get_supply_audit" => to_result(self.get_supply_audit(parse_params(params)?)),
+			"get_pool_entries" => to_result(w(&self.tx_pool).map(|p| pool_entries(&p))),
+			"clear_pool" => to_result(w(&self.tx_pool).map(|p| clear_pool(&p))),
+			"validate_transaction" => {
+				let params: PushTransactionParams = parse_params(params)?;
+				let res = w(&self.tx_pool)
+					.and_then(|p| validate_tx(&p, params.tx_hex, params.fluff, true));
+				to_result(res)
+			}
+			_ => Err(RpcError::new(METHOD_NOT_FOUND, "Method not found")),
+		}
+	}
+}
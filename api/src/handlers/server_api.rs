@@ -43,7 +43,7 @@ pub struct KernelDownloadHandler {
 
 impl Handler for KernelDownloadHandler {
 	fn post(&self, _req: Request<Body>) -> ResponseFuture {
-		if let Some(peer) = w_fut!(&self.peers).most_work_peer() {
+		if let Some(peer) = w_fut!(&self.peers).most_work_peer(p2p::Capabilities::FULL_NODE) {
 			match peer.send_kernel_data_request() {
 				Ok(_) => response(StatusCode::OK, "{}"),
 				Err(e) => response(
@@ -68,7 +68,7 @@ pub struct StatusHandler {
 }
 
 impl StatusHandler {
-	fn get_status(&self) -> Result<Status, Error> {
+	pub fn get_status(&self) -> Result<Status, Error> {
 		let head = w(&self.chain)?
 			.head()
 			.map_err(|e| ErrorKind::Internal(format!("can't get head: {}", e)))?;
@@ -49,22 +49,22 @@ pub struct TxHashSetHandler {
 
 impl TxHashSetHandler {
 	// gets roots
-	fn get_roots(&self) -> Result<TxHashSet, Error> {
+	pub fn get_roots(&self) -> Result<TxHashSet, Error> {
 		Ok(TxHashSet::from_head(w(&self.chain)?))
 	}
 
 	// gets last n outputs inserted in to the tree
-	fn get_last_n_output(&self, distance: u64) -> Result<Vec<TxHashSetNode>, Error> {
+	pub fn get_last_n_output(&self, distance: u64) -> Result<Vec<TxHashSetNode>, Error> {
 		Ok(TxHashSetNode::get_last_n_output(w(&self.chain)?, distance))
 	}
 
 	// gets last n kernels inserted in to the tree
-	fn get_last_n_kernel(&self, distance: u64) -> Result<Vec<TxHashSetNode>, Error> {
+	pub fn get_last_n_kernel(&self, distance: u64) -> Result<Vec<TxHashSetNode>, Error> {
 		Ok(TxHashSetNode::get_last_n_kernel(w(&self.chain)?, distance))
 	}
 
 	// allows traversal of utxo set
-	fn outputs(&self, start_index: u64, mut max: u64) -> Result<OutputListing, Error> {
+	pub fn outputs(&self, start_index: u64, mut max: u64) -> Result<OutputListing, Error> {
 		//set a limit here
 		if max > 10_000 {
 			max = 10_000;
@@ -87,7 +87,7 @@ impl TxHashSetHandler {
 	}
 
 	// allows traversal of utxo set, non-interactive transaction outputs only.
-	fn nit_outputs(&self, start_index: u64, mut max: u64) -> Result<OutputListing, Error> {
+	pub fn nit_outputs(&self, start_index: u64, mut max: u64) -> Result<OutputListing, Error> {
 		//set a limit here
 		if max > 10_000 {
 			max = 10_000;
@@ -111,7 +111,7 @@ impl TxHashSetHandler {
 
 	// return a dummy output with merkle proof for position filled out
 	// (to avoid having to create a new type to pass around)
-	fn get_merkle_proof_for_output(
+	pub fn get_merkle_proof_for_output(
 		&self,
 		id: &str,
 		features: OutputFeatures,
@@ -15,6 +15,7 @@
 
 use crate::chain;
 use crate::core::core::{OutputEx, OutputIdentifier};
+use crate::p2p::PeerAddr;
 use crate::rest::*;
 use crate::util;
 use crate::util::secp::pedersen::Commitment;
@@ -24,7 +25,7 @@ use std::sync::{Arc, Weak};
 // All handlers use `Weak` references instead of `Arc` to avoid cycles that
 // can never be destroyed. These 2 functions are simple helpers to reduce the
 // boilerplate of dealing with `Weak`.
-pub fn w<T>(weak: &Weak<T>) -> Result<Arc<T>, Error> {
+pub fn w<T: ?Sized>(weak: &Weak<T>) -> Result<Arc<T>, Error> {
 	weak.upgrade()
 		.ok_or_else(|| ErrorKind::Internal("failed to upgrade weak refernce".to_owned()).into())
 }
@@ -75,3 +76,18 @@ pub fn get_output(
 	}
 	Err(ErrorKind::NotFound)?
 }
+
+/// Parses a peer address, either "ip" or "ip:port" ("ip:port" is only really
+/// useful for local usernet testing on loopback address).
+pub fn parse_peer_addr(addr: &str) -> Result<PeerAddr, Error> {
+	if let Ok(ip_addr) = addr.parse() {
+		Ok(PeerAddr::from_ip(ip_addr))
+	} else if let Ok(addr) = addr.parse() {
+		Ok(PeerAddr(addr))
+	} else {
+		Err(ErrorKind::Argument(format!(
+			"peer address unrecognized: {}",
+			addr
+		)))?
+	}
+}
@@ -0,0 +1,478 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! JSON-RPC 2.0 over HTTP POST, single and batch requests. An api only has to
+//! map a method name and its (by-name) params to a result, see `RpcApi`.
+
+use crate::auth::{ApiAccess, ApiScope};
+use crate::rest::{Error, ErrorKind};
+use crate::router::{Handler, ResponseFuture};
+use crate::web::just_response;
+use futures::{Future, Stream};
+use hyper::{Body, Request, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{self, Value};
+use std::sync::Arc;
+
+/// The only JSON-RPC version we speak.
+pub const JSONRPC_VERSION: &'static str = "2.0";
+
+/// Invalid JSON was received.
+pub const PARSE_ERROR: i64 = -32700;
+/// The JSON sent is not a valid request object.
+pub const INVALID_REQUEST: i64 = -32600;
+/// The method does not exist.
+pub const METHOD_NOT_FOUND: i64 = -32601;
+/// Invalid method parameters (api::ErrorKind::Argument and RequestError).
+pub const INVALID_PARAMS: i64 = -32602;
+/// Internal error (api::ErrorKind::Internal).
+pub const INTERNAL_ERROR: i64 = -32603;
+/// The requested object doesn't exist (api::ErrorKind::NotFound).
+pub const NOT_FOUND: i64 = -32001;
+/// The result couldn't be built (api::ErrorKind::ResponseError).
+pub const RESPONSE_ERROR: i64 = -32002;
+/// The API token lacks the scope of the method.
+pub const FORBIDDEN: i64 = -32003;
+
+/// Most requests a batch may hold, so a single POST can't bypass the rate
+/// limit of its token by calling hundreds of methods.
+pub const MAX_BATCH_SIZE: usize = 100;
+
+/// A JSON-RPC request. Requests without id are notifications, executed
+/// without any response, a null id still gets one.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RpcRequest {
+	/// Always "2.0"
+	pub jsonrpc: String,
+	/// Name of the method
+	pub method: String,
+	/// Params of the method, by name
+	#[serde(default)]
+	pub params: Value,
+	/// Request id, echoed in the response
+	#[serde(
+		default,
+		deserialize_with = "present_id",
+		skip_serializing_if = "Option::is_none"
+	)]
+	pub id: Option<Value>,
+}
+
+// Only called when the id field is present, so a null id isn't mistaken for
+// a missing one.
+fn present_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+	Value::deserialize(deserializer).map(Some)
+}
+
+impl RpcRequest {
+	/// New request, with its params serialized by name.
+	pub fn new<T: Serialize>(method: &str, params: &T, id: u64) -> Result<RpcRequest, Error> {
+		let params = serde_json::to_value(params)
+			.map_err(|e| ErrorKind::RequestError(format!("invalid params: {}", e)))?;
+		Ok(RpcRequest {
+			jsonrpc: JSONRPC_VERSION.to_owned(),
+			method: method.to_owned(),
+			params,
+			id: Some(Value::from(id)),
+		})
+	}
+}
+
+/// A JSON-RPC error object.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RpcError {
+	/// One of the error codes above
+	pub code: i64,
+	/// Short description of the error
+	pub message: String,
+	/// Additional information
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub data: Option<Value>,
+}
+
+impl RpcError {
+	/// New error without additional information.
+	pub fn new(code: i64, message: &str) -> RpcError {
+		RpcError {
+			code,
+			message: message.to_owned(),
+			data: None,
+		}
+	}
+
+	/// Invalid params, the reason as additional information.
+	pub fn invalid_params(reason: String) -> RpcError {
+		RpcError {
+			code: INVALID_PARAMS,
+			message: "Invalid params".to_owned(),
+			data: Some(Value::String(reason)),
+		}
+	}
+}
+
+impl From<Error> for RpcError {
+	fn from(e: Error) -> RpcError {
+		let (code, data) = match e.kind() {
+			ErrorKind::Argument(msg) => (INVALID_PARAMS, Some(msg.clone())),
+			ErrorKind::RequestError(msg) => (INVALID_PARAMS, Some(msg.clone())),
+			ErrorKind::NotFound => (NOT_FOUND, None),
+			ErrorKind::Internal(msg) => (INTERNAL_ERROR, Some(msg.clone())),
+			ErrorKind::ResponseError(msg) => (RESPONSE_ERROR, Some(msg.clone())),
+		};
+		let message = match code {
+			INVALID_PARAMS => "Invalid params",
+			NOT_FOUND => "Not found",
+			RESPONSE_ERROR => "Response error",
+			_ => "Internal error",
+		};
+		RpcError {
+			code,
+			message: message.to_owned(),
+			data: data.map(Value::String),
+		}
+	}
+}
+
+/// A JSON-RPC response, with either a result or an error.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RpcResponse {
+	/// Always "2.0"
+	pub jsonrpc: String,
+	/// Result of the method, if successful
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub result: Option<Value>,
+	/// Error, if the method failed
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub error: Option<RpcError>,
+	/// Id of the request, null if it couldn't be read
+	pub id: Value,
+}
+
+impl RpcResponse {
+	fn new(id: Value, res: Result<Value, RpcError>) -> RpcResponse {
+		let (result, error) = match res {
+			Ok(v) => (Some(v), None),
+			Err(e) => (None, Some(e)),
+		};
+		RpcResponse {
+			jsonrpc: JSONRPC_VERSION.to_owned(),
+			result,
+			error,
+			id,
+		}
+	}
+
+	/// The result of the method, deserialized.
+	pub fn into_result<T: DeserializeOwned>(self) -> Result<T, RpcError> {
+		if let Some(e) = self.error {
+			return Err(e);
+		}
+		serde_json::from_value(self.result.unwrap_or(Value::Null))
+			.map_err(|e| RpcError::new(PARSE_ERROR, &format!("invalid result: {}", e)))
+	}
+}
+
+/// A set of methods callable over JSON-RPC.
+pub trait RpcApi {
+	/// Calls a method with its params, Null if the request had none.
+	fn call(&self, method: &str, params: Value) -> Result<Value, RpcError>;
+}
+
+/// Deserializes the (by-name) params of a method, missing params being all
+/// the optional ones.
+pub fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+	let params = match params {
+		Value::Null => Value::Object(Default::default()),
+		p => p,
+	};
+	serde_json::from_value(params).map_err(|e| RpcError::invalid_params(format!("{}", e)))
+}
+
+/// Serializes the result of a method.
+pub fn to_result<T: Serialize>(res: Result<T, Error>) -> Result<Value, RpcError> {
+	let res = res?;
+	serde_json::to_value(res).map_err(|e| {
+		RpcError::from(Error::from(ErrorKind::ResponseError(format!(
+			"can't serialize result: {}",
+			e
+		))))
+	})
+}
+
+fn handle_request(api: &dyn RpcApi, req: Value) -> Option<RpcResponse> {
+	let req: RpcRequest = match serde_json::from_value(req) {
+		Ok(req) => req,
+		Err(e) => {
+			let mut err = RpcError::new(INVALID_REQUEST, "Invalid Request");
+			err.data = Some(Value::String(format!("{}", e)));
+			return Some(RpcResponse::new(Value::Null, Err(err)));
+		}
+	};
+	let res = if req.jsonrpc != JSONRPC_VERSION {
+		Err(RpcError::new(INVALID_REQUEST, "Invalid Request"))
+	} else {
+		api.call(&req.method, req.params)
+	};
+	match req.id {
+		Some(id) => Some(RpcResponse::new(id, res)),
+		// notification
+		None => None,
+	}
+}
+
+/// Handles the body of a JSON-RPC POST, a single request or a batch of at
+/// most MAX_BATCH_SIZE. None if there is nothing to respond (notifications
+/// only).
+pub fn handle_body(api: &dyn RpcApi, body: &[u8]) -> Option<Value> {
+	let body: Value = match serde_json::from_slice(body) {
+		Ok(v) => v,
+		Err(e) => {
+			let mut err = RpcError::new(PARSE_ERROR, "Parse error");
+			err.data = Some(Value::String(format!("{}", e)));
+			return serde_json::to_value(RpcResponse::new(Value::Null, Err(err))).ok();
+		}
+	};
+	match body {
+		Value::Array(reqs) => {
+			if reqs.is_empty() {
+				let err = RpcError::new(INVALID_REQUEST, "Invalid Request");
+				return serde_json::to_value(RpcResponse::new(Value::Null, Err(err))).ok();
+			}
+			if reqs.len() > MAX_BATCH_SIZE {
+				let mut err = RpcError::new(INVALID_REQUEST, "Invalid Request");
+				err.data = Some(Value::String(format!(
+					"batch of {} requests, at most {} allowed",
+					reqs.len(),
+					MAX_BATCH_SIZE
+				)));
+				return serde_json::to_value(RpcResponse::new(Value::Null, Err(err))).ok();
+			}
+			let responses: Vec<_> = reqs
+				.into_iter()
+				.filter_map(|req| handle_request(api, req))
+				.collect();
+			if responses.is_empty() {
+				None
+			} else {
+				serde_json::to_value(responses).ok()
+			}
+		}
+		req => handle_request(api, req).and_then(|res| serde_json::to_value(res).ok()),
+	}
+}
+
+/// Api whose methods may require a scope of the token the request was
+/// authenticated with, if any.
+struct ScopedApi<'a> {
+	api: &'a dyn RpcApi,
+	access: Option<ApiAccess>,
+	method_scopes: &'a [(&'static str, ApiScope)],
+}
+
+impl<'a> RpcApi for ScopedApi<'a> {
+	fn call(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+		if let Some(ref access) = self.access {
+			let scope = self.method_scopes.iter().find(|(m, _)| *m == method);
+			if let Some((_, scope)) = scope {
+				if !access.allows(*scope) {
+					let mut err = RpcError::new(FORBIDDEN, "Forbidden");
+					err.data = Some(Value::String(format!(
+						"token {} lacks the {} scope",
+						access.name, scope
+					)));
+					return Err(err);
+				}
+			}
+		}
+		self.api.call(method, params)
+	}
+}
+
+/// Serves a JSON-RPC api on POST.
+pub struct RpcHandler<A> {
+	api: Arc<A>,
+	method_scopes: Arc<Vec<(&'static str, ApiScope)>>,
+}
+
+impl<A> RpcHandler<A>
+where
+	A: RpcApi + Send + Sync + 'static,
+{
+	/// Handler serving the methods of that api.
+	pub fn new(api: A) -> RpcHandler<A> {
+		RpcHandler {
+			api: Arc::new(api),
+			method_scopes: Arc::new(vec![]),
+		}
+	}
+
+	/// Requires a scope of the API token to call a method, when the requests
+	/// are authenticated with tokens.
+	pub fn with_method_scope(mut self, method: &'static str, scope: ApiScope) -> RpcHandler<A> {
+		Arc::make_mut(&mut self.method_scopes).push((method, scope));
+		self
+	}
+}
+
+impl<A> Handler for RpcHandler<A>
+where
+	A: RpcApi + Send + Sync + 'static,
+{
+	fn post(&self, req: Request<Body>) -> ResponseFuture {
+		let api = self.api.clone();
+		let method_scopes = self.method_scopes.clone();
+		let access = req.extensions().get::<ApiAccess>().cloned();
+		Box::new(req.into_body().concat2().map(move |body| {
+			let api = ScopedApi {
+				api: &*api,
+				access,
+				method_scopes: &method_scopes,
+			};
+			match handle_body(&api, &body.to_vec()) {
+				Some(res) => match serde_json::to_string(&res) {
+					Ok(json) => just_response(StatusCode::OK, json),
+					Err(e) => just_response(
+						StatusCode::INTERNAL_SERVER_ERROR,
+						format!("can't create json response: {}", e),
+					),
+				},
+				None => just_response(StatusCode::NO_CONTENT, ""),
+			}
+		}))
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use serde_json::json;
+
+	struct TestApi;
+
+	#[derive(Deserialize)]
+	struct AddParams {
+		a: u64,
+		b: u64,
+	}
+
+	impl RpcApi for TestApi {
+		fn call(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+			match method {
+				"add" => {
+					let p: AddParams = parse_params(params)?;
+					to_result(Ok(p.a + p.b))
+				}
+				"missing" => to_result::<()>(Err(ErrorKind::NotFound.into())),
+				_ => Err(RpcError::new(METHOD_NOT_FOUND, "Method not found")),
+			}
+		}
+	}
+
+	fn call(body: &str) -> Option<Value> {
+		handle_body(&TestApi, body.as_bytes())
+	}
+
+	#[test]
+	fn single_requests() {
+		let res = call(r#"{"jsonrpc":"2.0","method":"add","params":{"a":1,"b":2},"id":1}"#);
+		assert_eq!(res, Some(json!({"jsonrpc":"2.0","result":3,"id":1})));
+
+		let res: RpcResponse = serde_json::from_value(
+			call(r#"{"jsonrpc":"2.0","method":"add","params":{"a":1},"id":"x"}"#).unwrap(),
+		)
+		.unwrap();
+		assert_eq!(res.id, json!("x"));
+		assert_eq!(res.into_result::<u64>().unwrap_err().code, INVALID_PARAMS);
+
+		let res: RpcResponse =
+			serde_json::from_value(call(r#"{"jsonrpc":"2.0","method":"missing","id":2}"#).unwrap())
+				.unwrap();
+		assert_eq!(res.error.unwrap().code, NOT_FOUND);
+
+		// notification
+		assert_eq!(
+			call(r#"{"jsonrpc":"2.0","method":"add","params":{"a":1,"b":2}}"#),
+			None
+		);
+
+		// a null id is not a notification
+		let res = call(r#"{"jsonrpc":"2.0","method":"add","params":{"a":1,"b":2},"id":null}"#);
+		assert_eq!(res, Some(json!({"jsonrpc":"2.0","result":3,"id":null})));
+
+		let res = call(r#"{"jsonrpc":"2.0","method""#).unwrap();
+		assert_eq!(res["error"]["code"], json!(PARSE_ERROR));
+		assert_eq!(res["id"], Value::Null);
+	}
+
+	#[test]
+	fn batch_requests() {
+		let res = call(
+			r#"[
+				{"jsonrpc":"2.0","method":"add","params":{"a":1,"b":2},"id":1},
+				{"jsonrpc":"2.0","method":"add","params":{"a":3,"b":4}},
+				{"jsonrpc":"2.0","method":"sub","id":2},
+				{"foo":"bar"}
+			]"#,
+		)
+		.unwrap();
+		let res = res.as_array().unwrap();
+		assert_eq!(res.len(), 3);
+		assert_eq!(res[0]["result"], json!(3));
+		assert_eq!(res[1]["error"]["code"], json!(METHOD_NOT_FOUND));
+		assert_eq!(res[2]["error"]["code"], json!(INVALID_REQUEST));
+
+		let res = call("[]").unwrap();
+		assert_eq!(res["error"]["code"], json!(INVALID_REQUEST));
+	}
+
+	#[test]
+	fn batch_size_limit() {
+		let batch = |n: usize| {
+			let reqs: Vec<_> = (0..n)
+				.map(|i| json!({"jsonrpc":"2.0","method":"add","params":{"a":i,"b":1},"id":i}))
+				.collect();
+			call(&Value::Array(reqs).to_string()).unwrap()
+		};
+
+		let res = batch(MAX_BATCH_SIZE);
+		assert_eq!(res.as_array().unwrap().len(), MAX_BATCH_SIZE);
+
+		let res = batch(MAX_BATCH_SIZE + 1);
+		assert_eq!(res["error"]["code"], json!(INVALID_REQUEST));
+		assert_eq!(res["id"], Value::Null);
+	}
+
+	#[test]
+	fn method_scopes() {
+		let method_scopes = vec![("add", ApiScope::PushTx)];
+		let scoped = |scopes: Vec<ApiScope>| ScopedApi {
+			api: &TestApi,
+			access: Some(ApiAccess {
+				name: "explorer".to_owned(),
+				scopes,
+				rate_limit: None,
+			}),
+			method_scopes: &method_scopes,
+		};
+		let req = r#"{"jsonrpc":"2.0","method":"add","params":{"a":1,"b":2},"id":1}"#;
+
+		let res = handle_body(&scoped(vec![ApiScope::Read]), req.as_bytes()).unwrap();
+		assert_eq!(res["error"]["code"], json!(FORBIDDEN));
+		let res = handle_body(&scoped(vec![ApiScope::PushTx]), req.as_bytes()).unwrap();
+		assert_eq!(res["result"], json!(3));
+		let res = handle_body(&scoped(vec![ApiScope::Admin]), req.as_bytes()).unwrap();
+		assert_eq!(res["result"], json!(3));
+	}
+}
@@ -36,12 +36,19 @@ mod web;
 pub mod auth;
 pub mod client;
 mod handlers;
+pub mod jsonrpc;
+pub mod openapi;
 mod rest;
 mod router;
 mod types;
+pub mod ws;
 
-pub use crate::auth::{BasicAuthMiddleware, GOTTS_BASIC_REALM};
-pub use crate::handlers::start_rest_apis;
+pub use crate::auth::{
+	load_api_tokens, ApiAccess, ApiAccessConfig, ApiScope, ApiToken, BasicAuthMiddleware,
+	TokenAuthMiddleware, GOTTS_BASIC_REALM,
+};
+pub use crate::client::NodeClient;
+pub use crate::handlers::{start_owner_rest_apis, start_rest_apis, ProofSource};
 pub use crate::rest::*;
 pub use crate::router::*;
 pub use crate::types::*;
@@ -0,0 +1,1319 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! OpenAPI document of the node APIs, generated from the routes actually
+//! registered on a router, so it can't drift from what's served.
+
+use crate::router::{Handler, ResponseFuture};
+use crate::web::response;
+use hyper::{Body, Request, StatusCode};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeSet;
+
+/// Route the OpenAPI document is served at, on both APIs.
+pub const OPENAPI_PATH: &'static str = "/v1/openapi.json";
+
+const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+
+/// A parameter of an operation.
+struct Param {
+	name: &'static str,
+	/// "path" or "query"
+	location: &'static str,
+	required: bool,
+	description: &'static str,
+}
+
+/// An operation (method) on a path.
+struct Op {
+	method: &'static str,
+	summary: &'static str,
+	params: &'static [Param],
+	/// Rust type of the request body, if any, its schema in the components
+	request: Option<&'static str>,
+	/// Rust type of the response body, its schema in the components
+	response: &'static str,
+}
+
+/// Documentation of a router route, a wildcard route possibly serving several
+/// paths.
+struct RouteDoc {
+	route: &'static str,
+	paths: &'static [(&'static str, &'static [Op])],
+}
+
+const fn path(name: &'static str, description: &'static str) -> Param {
+	Param {
+		name,
+		location: "path",
+		required: true,
+		description,
+	}
+}
+
+const fn query(name: &'static str, description: &'static str) -> Param {
+	Param {
+		name,
+		location: "query",
+		required: false,
+		description,
+	}
+}
+
+const fn get(summary: &'static str, params: &'static [Param], response: &'static str) -> Op {
+	Op {
+		method: "get",
+		summary,
+		params,
+		request: None,
+		response,
+	}
+}
+
+const fn post(
+	summary: &'static str,
+	params: &'static [Param],
+	request: Option<&'static str>,
+	response: &'static str,
+) -> Op {
+	Op {
+		method: "post",
+		summary,
+		params,
+		request,
+		response,
+	}
+}
+
+const BLOCK_ID: Param = path("id", "Block hash, height or output commitment");
+const PEER_ADDR: Param = path("addr", "Peer address, ip or ip:port");
+const IDS: Param = query("id", "Comma separated list, or repeated parameter");
+
+const ROUTE_DOCS: &'static [RouteDoc] = &[
+	RouteDoc {
+		route: "/v1/",
+		paths: &[("/v1/", &[get("List of the routes", &[], "Vec<String>")])],
+	},
+	RouteDoc {
+		route: "/v1/blocks",
+		paths: &[(
+			"/v1/blocks",
+			&[get(
+				"Range of blocks by height, a page at a time",
+				&[
+					query("start_height", "First height of the range"),
+					query("end_height", "Last height of the range"),
+					query("include_proof", "Include the outputs Merkle proofs"),
+					query("format", "\"binary\" for ser-encoded blocks"),
+				],
+				"BlockListing",
+			)],
+		)],
+	},
+	RouteDoc {
+		route: "/v1/blocks/*",
+		paths: &[(
+			"/v1/blocks/{id}",
+			&[get(
+				"Block by hash, height or output commitment",
+				&[
+					BLOCK_ID,
+					query("compact", "Get the compact block"),
+					query("no_merkle_proof", "Skip the outputs Merkle proofs"),
+				],
+				"BlockPrintable",
+			)],
+		)],
+	},
+	RouteDoc {
+		route: "/v1/headers",
+		paths: &[(
+			"/v1/headers",
+			&[get(
+				"Range of block headers by height, a page at a time",
+				&[
+					query("start_height", "First height of the range"),
+					query("count", "Number of headers, at least 1"),
+					query("format", "\"binary\" for ser-encoded headers"),
+				],
+				"HeaderListing",
+			)],
+		)],
+	},
+	RouteDoc {
+		route: "/v1/headers/*",
+		paths: &[(
+			"/v1/headers/{id}",
+			&[get(
+				"Block header by hash, height or output commitment",
+				&[BLOCK_ID],
+				"BlockHeaderPrintable",
+			)],
+		)],
+	},
+	RouteDoc {
+		route: "/v1/chain",
+		paths: &[("/v1/chain", &[get("Chain head", &[], "Tip")])],
+	},
+	RouteDoc {
+		route: "/v1/chain/outputs/*",
+		paths: &[(
+			"/v1/chain/outputs/byids",
+			&[get(
+				"Unspent outputs by commitment",
+				&[IDS],
+				"Vec<OutputEx>",
+			)],
+		)],
+	},
+	RouteDoc {
+		route: "/v1/chain/kernels/*",
+		paths: &[(
+			"/v1/chain/kernels/byids",
+			&[get("Kernels by excess", &[IDS], "Vec<TxKernelApiEntry>")],
+		)],
+	},
+	RouteDoc {
+		route: "/v1/chain/kernel/*",
+		paths: &[(
+			"/v1/chain/kernel/{excess}",
+			&[get(
+				"Kernel by excess, with the height it was mined at",
+				&[
+					path("excess", "Kernel excess, hex"),
+					query("min_height", "Lowest height to search from"),
+					query("max_height", "Highest height to search to"),
+				],
+				"Option<LocatedTxKernel>",
+			)],
+		)],
+	},
+	RouteDoc {
+		route: "/v1/chain/compact",
+		paths: &[(
+			"/v1/chain/compact",
+			&[post("Compact the chain", &[], None, "()")],
+		)],
+	},
+	RouteDoc {
+		route: "/v1/chain/validate",
+		paths: &[(
+			"/v1/chain/validate",
+			&[get("Validate the whole chain state", &[], "()")],
+		)],
+	},
+	RouteDoc {
+		route: "/v1/chain/supply",
+		paths: &[(
+			"/v1/chain/supply",
+			&[get(
+				"Supply audit of the UTXO set",
+				&[query(
+					"height",
+					"Height of the block to audit, the head by default",
+				)],
+				"SupplyAuditPrintable",
+			)],
+		)],
+	},
+	RouteDoc {
+		route: "/v1/txhashset/*",
+		paths: &[
+			(
+				"/v1/txhashset/roots",
+				&[get("Roots of the txhashset MMRs", &[], "TxHashSet")],
+			),
+			(
+				"/v1/txhashset/lastoutputs",
+				&[get(
+					"Last outputs of the output MMR",
+					&[query("n", "Number of outputs, 10 by default")],
+					"Vec<TxHashSetNode>",
+				)],
+			),
+			(
+				"/v1/txhashset/lastkernels",
+				&[get(
+					"Last kernels of the kernel MMR",
+					&[query("n", "Number of kernels, 10 by default")],
+					"Vec<TxHashSetNode>",
+				)],
+			),
+			(
+				"/v1/txhashset/outputs",
+				&[get(
+					"Unspent outputs by MMR index",
+					&[
+						query("start_index", "First MMR index"),
+						query("max", "Max number of outputs"),
+					],
+					"OutputListing",
+				)],
+			),
+			(
+				"/v1/txhashset/nit-outputs",
+				&[get(
+					"Unspent non-interactive outputs by MMR index",
+					&[
+						query("start_index", "First MMR index"),
+						query("max", "Max number of outputs"),
+					],
+					"OutputListing",
+				)],
+			),
+			(
+				"/v1/txhashset/merkleproof",
+				&[get(
+					"Merkle proof of an output",
+					&[query("id", "Output commitment")],
+					"OutputPrintable",
+				)],
+			),
+		],
+	},
+	RouteDoc {
+		route: "/v1/proofs/output/*",
+		paths: &[(
+			"/v1/proofs/output/{commit}",
+			&[get(
+				"Unspent output with its Merkle proof against a header of our chain",
+				&[path("commit", "Output commitment, hex")],
+				"Option<OutputProofPrintable>",
+			)],
+		)],
+	},
+	RouteDoc {
+		route: "/v1/proofs/kernel/*",
+		paths: &[(
+			"/v1/proofs/kernel/{excess}",
+			&[get(
+				"Kernel with its Merkle proof against a header of our chain",
+				&[path("excess", "Kernel excess, hex")],
+				"Option<KernelProofPrintable>",
+			)],
+		)],
+	},
+	RouteDoc {
+		route: "/v1/status",
+		paths: &[("/v1/status", &[get("Node status", &[], "Status")])],
+	},
+	RouteDoc {
+		route: "/v1/pool",
+		paths: &[("/v1/pool", &[get("Transaction pool size", &[], "PoolInfo")])],
+	},
+	RouteDoc {
+		route: "/v1/pool/push_tx",
+		paths: &[(
+			"/v1/pool/push_tx",
+			&[post(
+				"Push a transaction to the pool",
+				&[query("fluff", "Skip the Dandelion stem phase")],
+				Some("TxWrapper"),
+				"()",
+			)],
+		)],
+	},
+	RouteDoc {
+		route: "/v1/pool/push_package",
+		paths: &[(
+			"/v1/pool/push_package",
+			&[post(
+				"Push a package of dependent transactions, parents first, to the pool as a unit",
+				&[query("fluff", "Skip the Dandelion stem phase")],
+				Some("TxsWrapper"),
+				"()",
+			)],
+		)],
+	},
+	RouteDoc {
+		route: "/v1/pool/validate_tx",
+		paths: &[(
+			"/v1/pool/validate_tx",
+			&[post(
+				"Dry run a transaction through the txpool rules, without adding nor relaying it",
+				&[query("fluff", "Check it as fluffed rather than stemmed")],
+				Some("TxWrapper"),
+				"TxValidation",
+			)],
+		)],
+	},
+	RouteDoc {
+		route: "/v1/tx/status/*",
+		paths: &[(
+			"/v1/tx/status/{excess}",
+			&[get(
+				"Status of a transaction across the pool and the chain",
+				&[path("excess", "Kernel excess, hex")],
+				"TxStatusInfo",
+			)],
+		)],
+	},
+	RouteDoc {
+		route: "/v1/version",
+		paths: &[("/v1/version", &[get("Node version", &[], "Version")])],
+	},
+	RouteDoc {
+		route: "/v1/peers/all",
+		paths: &[(
+			"/v1/peers/all",
+			&[get("All the peers we know of", &[], "Vec<PeerData>")],
+		)],
+	},
+	RouteDoc {
+		route: "/v1/peers/connected",
+		paths: &[(
+			"/v1/peers/connected",
+			&[get("Connected peers", &[], "Vec<PeerInfoDisplay>")],
+		)],
+	},
+	RouteDoc {
+		route: "/v1/peers/**",
+		paths: &[
+			(
+				"/v1/peers/{addr}",
+				&[get("A peer we know of", &[PEER_ADDR], "PeerData")],
+			),
+			(
+				"/v1/peers/{addr}/ban",
+				&[post("Ban a peer", &[PEER_ADDR], None, "()")],
+			),
+			(
+				"/v1/peers/{addr}/unban",
+				&[post("Unban a peer", &[PEER_ADDR], None, "()")],
+			),
+		],
+	},
+	RouteDoc {
+		route: "/v1/bans",
+		paths: &[(
+			"/v1/bans",
+			&[
+				get("Subnet bans", &[], "Vec<BanEntry>"),
+				post("Ban a subnet", &[], Some("BanRequest"), "()"),
+			],
+		)],
+	},
+	RouteDoc {
+		route: "/v1/bans/*",
+		paths: &[
+			(
+				"/v1/bans/unban",
+				&[post("Unban a subnet", &[], Some("UnbanRequest"), "()")],
+			),
+			(
+				"/v1/bans/import",
+				&[post("Import subnet bans", &[], Some("Vec<BanEntry>"), "()")],
+			),
+		],
+	},
+	RouteDoc {
+		route: "/v1/pool/*",
+		paths: &[
+			(
+				"/v1/pool/entries",
+				&[get(
+					"Transactions in the txpool and the stempool",
+					&[],
+					"Vec<PoolEntryPrintable>",
+				)],
+			),
+			(
+				"/v1/pool/clear",
+				&[post(
+					"Remove every transaction from the pool, returning how many",
+					&[],
+					None,
+					"usize",
+				)],
+			),
+			(
+				"/v1/pool/validate_tx",
+				&[post(
+					"Dry run a transaction through the pool rules, the stempool included",
+					&[query("fluff", "Check it as fluffed rather than stemmed")],
+					Some("TxWrapper"),
+					"TxValidation",
+				)],
+			),
+		],
+	},
+	RouteDoc {
+		route: "/v1/metrics",
+		paths: &[(
+			"/v1/metrics",
+			&[get("Metrics in the Prometheus text format", &[], "text")],
+		)],
+	},
+	RouteDoc {
+		route: "/v1/ws",
+		paths: &[(
+			"/v1/ws",
+			&[get(
+				"WebSocket subscription to node events",
+				&[query("topics", "Comma separated topics")],
+				"Event",
+			)],
+		)],
+	},
+	RouteDoc {
+		route: "/v2/foreign",
+		paths: &[(
+			"/v2/foreign",
+			&[post(
+				"Foreign JSON-RPC 2.0 API",
+				&[],
+				Some("RpcRequest"),
+				"RpcResponse",
+			)],
+		)],
+	},
+	RouteDoc {
+		route: "/v2/owner",
+		paths: &[(
+			"/v2/owner",
+			&[post(
+				"Owner JSON-RPC 2.0 API",
+				&[],
+				Some("RpcRequest"),
+				"RpcResponse",
+			)],
+		)],
+	},
+	RouteDoc {
+		route: OPENAPI_PATH,
+		paths: &[(
+			OPENAPI_PATH,
+			&[get("This OpenAPI document", &[], "OpenAPI")],
+		)],
+	},
+];
+
+fn string() -> Value {
+	json!({ "type": "string" })
+}
+
+fn hex() -> Value {
+	json!({ "type": "string", "pattern": "^[0-9a-f]*$" })
+}
+
+fn date_time() -> Value {
+	json!({ "type": "string", "format": "date-time" })
+}
+
+fn integer() -> Value {
+	json!({ "type": "integer" })
+}
+
+fn boolean() -> Value {
+	json!({ "type": "boolean" })
+}
+
+fn any() -> Value {
+	json!({})
+}
+
+fn array(items: Value) -> Value {
+	json!({ "type": "array", "items": items })
+}
+
+fn reference(name: &str) -> Value {
+	json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn strings(values: &[&str]) -> Value {
+	json!({ "type": "string", "enum": values })
+}
+
+/// Null or missing, the property isn't required in its object.
+fn nullable(schema: Value) -> Value {
+	match schema {
+		Value::Object(mut map) if !map.contains_key("$ref") => {
+			map.insert("nullable".to_owned(), Value::Bool(true));
+			Value::Object(map)
+		}
+		schema => json!({ "allOf": [schema], "nullable": true }),
+	}
+}
+
+fn object(properties: &[(&str, Value)]) -> Value {
+	let required: Vec<&str> = properties
+		.iter()
+		.filter(|(_, schema)| schema["nullable"] != true)
+		.map(|(name, _)| *name)
+		.collect();
+	let properties: Map<String, Value> = properties
+		.iter()
+		.map(|(name, schema)| (name.to_string(), schema.clone()))
+		.collect();
+	let mut value = json!({ "type": "object", "properties": properties });
+	if !required.is_empty() {
+		value["required"] = json!(required);
+	}
+	value
+}
+
+/// A serde (externally tagged) enum, unit variants as strings and the others
+/// as an object keyed by the variant.
+fn tagged(units: &[&str], variants: &[(&str, Value)]) -> Value {
+	let mut one_of = vec![];
+	if !units.is_empty() {
+		one_of.push(strings(units));
+	}
+	for (name, schema) in variants {
+		one_of.push(object(&[(*name, schema.clone())]));
+	}
+	json!({ "oneOf": one_of })
+}
+
+/// JSON Schema of a type of the API, as serialized by serde. Panics on a
+/// type without schema, the tests making sure all the documented types have
+/// one.
+fn schema(name: &str) -> Value {
+	match name {
+		"Version" => object(&[
+			("node_version", string()),
+			("block_header_version", integer()),
+		]),
+		"Tip" => object(&[
+			("height", integer()),
+			("last_block_pushed", hex()),
+			("prev_block_to_last", hex()),
+			("total_difficulty", integer()),
+		]),
+		"Status" => object(&[
+			("protocol_version", integer()),
+			("user_agent", string()),
+			("connections", integer()),
+			("tip", reference("Tip")),
+		]),
+		"TxHashSet" => object(&[("output_root_hash", hex()), ("kernel_root_hash", hex())]),
+		"TxHashSetNode" => object(&[("hash", hex())]),
+		"Commitment" => json!({ "description": "Pedersen commitment, 33 bytes" }),
+		"MerkleProof" => object(&[("mmr_size", integer()), ("path", array(hex()))]),
+		"OutputFeaturesEx" => tagged(
+			&[],
+			&[
+				("Plain", object(&[("spath", hex())])),
+				("Coinbase", object(&[("spath", hex())])),
+				(
+					"SigLocked",
+					object(&[("locker", json!({ "type": "object" }))]),
+				),
+			],
+		),
+		"Output" => object(&[
+			("features", reference("OutputFeaturesEx")),
+			("commit", reference("Commitment")),
+			("value", integer()),
+		]),
+		"OutputEx" => object(&[
+			("output", reference("Output")),
+			("height", integer()),
+			("mmr_index", integer()),
+		]),
+		"OutputPrintable" => object(&[
+			("output", reference("Output")),
+			("output_type", strings(&["Coinbase", "Transaction"])),
+			("spent", boolean()),
+			("block_height", nullable(integer())),
+			("merkle_proof", nullable(reference("MerkleProof"))),
+			("mmr_index", integer()),
+		]),
+		"KernelFeatures" => tagged(
+			&["Coinbase"],
+			&[
+				("Plain", object(&[("fee", integer())])),
+				(
+					"HeightLocked",
+					object(&[("fee", integer()), ("lock_height", integer())]),
+				),
+			],
+		),
+		"TxKernel" => object(&[
+			("features", reference("KernelFeatures")),
+			("excess", reference("Commitment")),
+			("excess_sig", hex()),
+		]),
+		"TxKernelApiEntry" => object(&[("height", integer()), ("kernel", reference("TxKernel"))]),
+		"LocatedTxKernel" => object(&[
+			("tx_kernel", reference("TxKernel")),
+			("height", integer()),
+			("mmr_index", integer()),
+		]),
+		"BlockHeaderPrintable" => object(&[
+			("hash", hex()),
+			("version", integer()),
+			("height", integer()),
+			("previous", hex()),
+			("prev_root", hex()),
+			("timestamp", date_time()),
+			("output_i_root", hex()),
+			("output_ii_root", hex()),
+			("kernel_root", hex()),
+			("nonce", integer()),
+			("edge_bits", integer()),
+			("cuckoo_solution", array(integer())),
+			("total_difficulty", integer()),
+			("secondary_scaling", integer()),
+		]),
+		"BlockPrintable" => object(&[
+			("header", reference("BlockHeaderPrintable")),
+			("inputs", array(hex())),
+			("outputs", array(reference("OutputPrintable"))),
+			("kernels", array(reference("TxKernel"))),
+		]),
+		"BlockListing" => object(&[
+			("blocks", array(reference("BlockPrintable"))),
+			("next_height", nullable(integer())),
+		]),
+		"HeaderListing" => object(&[
+			("headers", array(reference("BlockHeaderPrintable"))),
+			("next_height", nullable(integer())),
+		]),
+		"OutputListing" => object(&[
+			("highest_index", integer()),
+			("last_retrieved_index", integer()),
+			("outputs", array(reference("OutputPrintable"))),
+		]),
+		"OutputProofPrintable" => object(&[
+			("output", reference("Output")),
+			("height", integer()),
+			("mmr_index", integer()),
+			("header_hash", hex()),
+			("merkle_proof", reference("MerkleProof")),
+		]),
+		"KernelProofPrintable" => object(&[
+			("tx_kernel", reference("TxKernel")),
+			("height", integer()),
+			("mmr_index", integer()),
+			("header_hash", hex()),
+			("merkle_proof", reference("MerkleProof")),
+		]),
+		"TxStatusInfo" => object(&[
+			(
+				"status",
+				strings(&["unknown", "stem", "pool", "confirmed", "reorged-out"]),
+			),
+			("height", nullable(integer())),
+			("confirmations", nullable(integer())),
+			("last_error", nullable(string())),
+		]),
+		"TxRuleCheck" => object(&[
+			(
+				"rule",
+				strings(&[
+					"not_duplicate",
+					"capacity",
+					"fee",
+					"inputs_exist",
+					"tx_valid",
+					"lock_height",
+					"coinbase_maturity",
+					"no_pool_double_spend",
+					"chain_state",
+				]),
+			),
+			("status", strings(&["passed", "failed", "skipped"])),
+			("error", nullable(string())),
+		]),
+		"TxValidation" => object(&[
+			("valid", boolean()),
+			("checks", array(reference("TxRuleCheck"))),
+		]),
+		"SupplyAuditPrintable" => object(&[
+			("height", integer()),
+			("hash", hex()),
+			("utxo_value", integer()),
+			("issued", integer()),
+			("supply_matches", boolean()),
+			("plain_outputs", integer()),
+			("coinbase_outputs", integer()),
+			("sig_locked_outputs", integer()),
+			("kernel_sums_valid", boolean()),
+			("utxo_sum", nullable(hex())),
+			("kernel_sum", nullable(hex())),
+			("error", nullable(string())),
+		]),
+		"PoolInfo" => object(&[("pool_size", integer())]),
+		"PoolEntryPrintable" => object(&[
+			("pool", strings(&["txpool", "stempool"])),
+			("tx_hash", hex()),
+			("kernels", array(hex())),
+			("fee", integer()),
+			("src", string()),
+			("tx_at", date_time()),
+		]),
+		"PeerAddr" => json!({ "type": "string", "description": "ip:port" }),
+		"Subnet" => json!({
+			"type": "string",
+			"description": "CIDR notation, or a single ip address",
+		}),
+		"Capabilities" => object(&[("bits", integer())]),
+		"ReasonForBan" => strings(&[
+			"None",
+			"BadBlock",
+			"BadCompactBlock",
+			"BadBlockHeader",
+			"BadTxHashSet",
+			"ManualBan",
+			"FraudHeight",
+			"BadHandshake",
+			"Misbehaviour",
+		]),
+		"PeerData" => object(&[
+			("addr", reference("PeerAddr")),
+			("capabilities", reference("Capabilities")),
+			("user_agent", string()),
+			("flags", strings(&["Healthy", "Banned", "Defunct"])),
+			("last_banned", integer()),
+			("ban_reason", reference("ReasonForBan")),
+			("last_connected", integer()),
+			(
+				"score",
+				object(&[("value", integer()), ("updated", integer())]),
+			),
+		]),
+		"PeerInfoDisplay" => object(&[
+			("capabilities", reference("Capabilities")),
+			("user_agent", string()),
+			("version", integer()),
+			("addr", reference("PeerAddr")),
+			("direction", strings(&["Inbound", "Outbound"])),
+			("total_difficulty", integer()),
+			("height", integer()),
+		]),
+		"BanEntry" => object(&[
+			("subnet", reference("Subnet")),
+			("reason", reference("ReasonForBan")),
+			("note", string()),
+			("banned_at", integer()),
+			("expires", nullable(integer())),
+		]),
+		"BanRequest" => object(&[
+			("subnet", reference("Subnet")),
+			("duration", nullable(integer())),
+			("permanent", nullable(boolean())),
+			("note", nullable(string())),
+		]),
+		"UnbanRequest" => object(&[("subnet", reference("Subnet"))]),
+		"Event" => object(&[
+			("topic", strings(&["tip", "block", "pool", "peers"])),
+			("event", string()),
+			("data", any()),
+		]),
+		"RpcRequest" => object(&[
+			("jsonrpc", strings(&["2.0"])),
+			("method", string()),
+			("params", nullable(json!({ "type": "object" }))),
+			("id", nullable(any())),
+		]),
+		"RpcError" => object(&[
+			("code", integer()),
+			("message", string()),
+			("data", nullable(any())),
+		]),
+		"RpcResponse" => object(&[
+			("jsonrpc", strings(&["2.0"])),
+			("result", nullable(any())),
+			("error", nullable(reference("RpcError"))),
+			("id", any()),
+		]),
+		"TxWrapper" => object(&[("tx_hex", hex())]),
+		"TxsWrapper" => object(&[("txs_hex", array(hex()))]),
+		"OpenAPI" => json!({ "type": "object", "description": "OpenAPI 3.0 document" }),
+		_ => panic!("no OpenAPI schema for {}", name),
+	}
+}
+
+/// Schema of a documented type: a reference to its schema in the
+/// components, or to the schemas of the items for vectors and options.
+fn type_schema(ty: &str) -> Value {
+	if ty.starts_with("Vec<") && ty.ends_with('>') {
+		return array(type_schema(&ty[4..ty.len() - 1]));
+	}
+	if ty.starts_with("Option<") && ty.ends_with('>') {
+		return nullable(type_schema(&ty[7..ty.len() - 1]));
+	}
+	match ty {
+		"String" | "text" => string(),
+		"usize" | "u64" => integer(),
+		_ => reference(ty),
+	}
+}
+
+/// Names of the component schemas referenced in a value.
+fn references(value: &Value, names: &mut BTreeSet<String>) {
+	match value {
+		Value::Object(map) => {
+			if let Some(Value::String(r)) = map.get("$ref") {
+				names.insert(r.trim_start_matches("#/components/schemas/").to_owned());
+			}
+			map.values().for_each(|v| references(v, names));
+		}
+		Value::Array(values) => values.iter().for_each(|v| references(v, names)),
+		_ => (),
+	}
+}
+
+/// Whether a router route has its documentation.
+pub fn documented(route: &str) -> bool {
+	ROUTE_DOCS.iter().any(|d| d.route == route)
+}
+
+/// Builds the OpenAPI 3.0 document of the given routes. Routes without
+/// documentation are still listed, as a bare GET.
+pub fn document(title: &str, routes: &[&str]) -> Value {
+	let mut paths = Map::new();
+	for route in routes {
+		match ROUTE_DOCS.iter().find(|d| d.route == *route) {
+			Some(doc) => {
+				for (path, ops) in doc.paths {
+					let ops = ops.iter().map(|op| (op.method.to_owned(), operation(op)));
+					paths.insert(path.to_string(), Value::Object(ops.collect()));
+				}
+			}
+			None => {
+				let path = route.trim_end_matches("/*").trim_end_matches("/**");
+				paths.insert(
+					path.to_owned(),
+					json!({ "get": { "responses": { "200": { "description": "OK" } } } }),
+				);
+			}
+		}
+	}
+	let paths = Value::Object(paths);
+
+	// the schemas referenced by the paths, and the ones they reference
+	let mut schemas = Map::new();
+	let mut pending = BTreeSet::new();
+	references(&paths, &mut pending);
+	while let Some(name) = pending.iter().next().cloned() {
+		pending.remove(&name);
+		let schema = schema(&name);
+		let mut names = BTreeSet::new();
+		references(&schema, &mut names);
+		schemas.insert(name, schema);
+		pending.extend(names.into_iter().filter(|n| !schemas.contains_key(n)));
+	}
+
+	json!({
+		"openapi": "3.0.0",
+		"info": { "title": title, "version": VERSION },
+		"paths": paths,
+		"components": { "schemas": schemas },
+	})
+}
+
+fn operation(op: &Op) -> Value {
+	let params: Vec<Value> = op
+		.params
+		.iter()
+		.map(|p| {
+			json!({
+				"name": p.name,
+				"in": p.location,
+				"required": p.required,
+				"description": p.description,
+				"schema": { "type": "string" },
+			})
+		})
+		.collect();
+	let content_type = match op.response {
+		"text" => "text/plain",
+		_ => "application/json",
+	};
+	let mut ok = json!({ "description": op.response });
+	if op.response != "()" {
+		let mut content = Map::new();
+		content.insert(
+			content_type.to_owned(),
+			json!({ "schema": type_schema(op.response) }),
+		);
+		ok["content"] = Value::Object(content);
+	}
+	let mut value = json!({
+		"summary": op.summary,
+		"parameters": params,
+		"responses": {
+			"200": ok,
+			"400": { "description": "Bad request" },
+			"404": { "description": "Not found" },
+			"500": { "description": "Internal error" },
+		},
+	});
+	if let Some(request) = op.request {
+		value["requestBody"] = json!({
+			"required": true,
+			"content": { "application/json": { "schema": type_schema(request) } },
+		});
+	}
+	value
+}
+
+/// Serves the OpenAPI document of a router.
+/// GET /v1/openapi.json
+pub struct OpenApiHandler {
+	pub doc: String,
+}
+
+impl OpenApiHandler {
+	pub fn new(title: &str, routes: &[&str]) -> OpenApiHandler {
+		OpenApiHandler {
+			doc: document(title, routes).to_string(),
+		}
+	}
+}
+
+impl Handler for OpenApiHandler {
+	fn get(&self, _req: Request<Body>) -> ResponseFuture {
+		response(StatusCode::OK, self.doc.clone())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::core::core::hash::ZERO_HASH;
+	use crate::core::core::merkle_proof::MerkleProof;
+	use crate::core::core::{
+		BlockHeader, Output, OutputEx, OutputFeaturesEx, TxKernel, TxKernelApiEntry,
+	};
+	use crate::core::libtx::proof::{SecuredPath, SECURED_PATH_SIZE};
+	use crate::core::pow::Difficulty;
+	use crate::core::ser::ProtocolVersion;
+	use crate::handlers::{TxWrapper, TxsWrapper};
+	use crate::jsonrpc::{RpcError, RpcRequest, RpcResponse};
+	use crate::p2p::types::PeerInfoDisplay;
+	use crate::p2p::{
+		BanEntry, Capabilities, Direction, PeerAddr, PeerData, PeerScore, ReasonForBan, State,
+		Subnet,
+	};
+	use crate::pool::{TxRule, TxRuleCheck, TxRuleStatus};
+	use crate::types::*;
+	use crate::util::secp::pedersen::Commitment;
+	use crate::ws::{Event, Topic};
+	use serde::Serialize;
+
+	#[test]
+	fn document_paths() {
+		let doc = document(
+			"test",
+			&[
+				"/v1/status",
+				"/v1/peers/**",
+				"/v1/undocumented/*",
+				OPENAPI_PATH,
+			],
+		);
+		assert_eq!(doc["openapi"], "3.0.0");
+		assert_eq!(doc["info"]["title"], "test");
+
+		let paths = doc["paths"].as_object().unwrap();
+		assert_eq!(paths.len(), 6);
+		assert_eq!(
+			paths["/v1/status"]["get"]["responses"]["200"]["description"],
+			"Status"
+		);
+		assert!(paths["/v1/peers/{addr}/ban"]["post"].is_object());
+		assert_eq!(
+			paths["/v1/peers/{addr}"]["get"]["parameters"][0]["in"],
+			"path"
+		);
+		assert!(paths["/v1/undocumented"]["get"].is_object());
+		assert!(paths.contains_key(OPENAPI_PATH));
+	}
+
+	#[test]
+	fn documented_schemas() {
+		let routes: Vec<&str> = ROUTE_DOCS.iter().map(|d| d.route).collect();
+		let doc = document("test", &routes);
+		let schemas = doc["components"]["schemas"].as_object().unwrap();
+
+		// every referenced schema is defined in the components
+		let mut names = BTreeSet::new();
+		references(&doc, &mut names);
+		for name in &names {
+			assert!(schemas.contains_key(name));
+		}
+
+		let status = &doc["paths"]["/v1/status"]["get"]["responses"]["200"];
+		assert_eq!(
+			status["content"]["application/json"]["schema"]["$ref"],
+			"#/components/schemas/Status"
+		);
+		assert_eq!(
+			schemas["Status"]["properties"]["tip"]["$ref"],
+			"#/components/schemas/Tip"
+		);
+	}
+
+	fn to_value<T: Serialize>(value: T) -> Value {
+		serde_json::to_value(value).unwrap()
+	}
+
+	// Instances of the documented object types, as serialized. Options
+	// skipped when None are set in one of them at least.
+	fn samples(name: &str) -> Vec<Value> {
+		let commit = Commitment::from_vec(vec![8; 33]);
+		let output = Output {
+			features: OutputFeaturesEx::Plain {
+				spath: SecuredPath([0; SECURED_PATH_SIZE]),
+			},
+			commit,
+			value: 1,
+		};
+		let kernel = TxKernel::empty();
+		let proof = MerkleProof {
+			mmr_size: 1,
+			path: vec![ZERO_HASH],
+		};
+		let header = BlockHeaderPrintable::from_header(&BlockHeader::default());
+		let output_printable = OutputPrintable {
+			output,
+			output_type: OutputType::Transaction,
+			spent: false,
+			block_height: Some(1),
+			merkle_proof: Some(proof.clone()),
+			mmr_index: 1,
+		};
+		let tip = Tip {
+			height: 1,
+			last_block_pushed: "00".to_owned(),
+			prev_block_to_last: "00".to_owned(),
+			total_difficulty: 1,
+		};
+		let addr = PeerAddr("10.0.0.1:3414".parse().unwrap());
+		let subnet: Subnet = "10.0.0.0/8".parse().unwrap();
+		let rpc_error = RpcError::invalid_params("missing a".to_owned());
+		let value = match name {
+			"Version" => to_value(Version {
+				node_version: "0.0.6".to_owned(),
+				block_header_version: 1,
+			}),
+			"Tip" => to_value(tip),
+			"Status" => to_value(Status {
+				protocol_version: 1,
+				user_agent: "gotts".to_owned(),
+				connections: 1,
+				tip,
+			}),
+			"TxHashSet" => to_value(TxHashSet {
+				output_root_hash: "00".to_owned(),
+				kernel_root_hash: "00".to_owned(),
+			}),
+			"TxHashSetNode" => to_value(TxHashSetNode {
+				hash: "00".to_owned(),
+			}),
+			"MerkleProof" => to_value(proof),
+			"Output" => to_value(output),
+			"OutputEx" => to_value(OutputEx {
+				output,
+				height: 1,
+				mmr_index: 1,
+			}),
+			"OutputPrintable" => to_value(output_printable),
+			"TxKernel" => to_value(kernel),
+			"TxKernelApiEntry" => to_value(TxKernelApiEntry {
+				height: 1,
+				kernel: kernel,
+			}),
+			"LocatedTxKernel" => to_value(LocatedTxKernel {
+				tx_kernel: kernel,
+				height: 1,
+				mmr_index: 1,
+			}),
+			"BlockHeaderPrintable" => to_value(header),
+			"BlockPrintable" => to_value(BlockPrintable {
+				header: header,
+				inputs: vec![],
+				outputs: vec![],
+				kernels: vec![],
+			}),
+			"BlockListing" => to_value(BlockListing {
+				blocks: vec![],
+				next_height: Some(1),
+			}),
+			"HeaderListing" => to_value(HeaderListing {
+				headers: vec![header],
+				next_height: Some(1),
+			}),
+			"OutputListing" => to_value(OutputListing {
+				highest_index: 1,
+				last_retrieved_index: 1,
+				outputs: vec![output_printable],
+			}),
+			"OutputProofPrintable" => to_value(OutputProofPrintable {
+				output,
+				height: 1,
+				mmr_index: 1,
+				header_hash: "00".to_owned(),
+				merkle_proof: proof,
+			}),
+			"KernelProofPrintable" => to_value(KernelProofPrintable {
+				tx_kernel: kernel,
+				height: 1,
+				mmr_index: 1,
+				header_hash: "00".to_owned(),
+				merkle_proof: proof,
+			}),
+			"TxStatusInfo" => to_value(TxStatusInfo {
+				status: TxStatus::Confirmed,
+				height: Some(1),
+				confirmations: Some(1),
+				last_error: Some("fee".to_owned()),
+			}),
+			"TxRuleCheck" => to_value(TxRuleCheck {
+				rule: TxRule::Fee,
+				status: TxRuleStatus::Failed,
+				error: Some("low fee".to_owned()),
+			}),
+			"TxValidation" => to_value(TxValidation {
+				valid: true,
+				checks: vec![],
+			}),
+			"SupplyAuditPrintable" => to_value(SupplyAuditPrintable {
+				height: 1,
+				hash: "00".to_owned(),
+				utxo_value: 1,
+				issued: 1,
+				supply_matches: true,
+				plain_outputs: 1,
+				coinbase_outputs: 1,
+				sig_locked_outputs: 0,
+				kernel_sums_valid: true,
+				utxo_sum: Some("00".to_owned()),
+				kernel_sum: Some("00".to_owned()),
+				error: Some("sums don't match".to_owned()),
+			}),
+			"PoolInfo" => to_value(PoolInfo { pool_size: 1 }),
+			"PoolEntryPrintable" => to_value(PoolEntryPrintable {
+				pool: "txpool".to_owned(),
+				tx_hash: "00".to_owned(),
+				kernels: vec![],
+				fee: 1,
+				src: "broadcast".to_owned(),
+				tx_at: "2019-01-01T00:00:00+00:00".to_owned(),
+			}),
+			"Capabilities" => to_value(Capabilities::FULL_NODE),
+			"PeerData" => to_value(PeerData {
+				addr: addr,
+				capabilities: Capabilities::FULL_NODE,
+				user_agent: "gotts".to_owned(),
+				flags: State::Healthy,
+				last_banned: 0,
+				ban_reason: ReasonForBan::None,
+				last_connected: 0,
+				score: PeerScore::default(),
+			}),
+			"PeerInfoDisplay" => to_value(PeerInfoDisplay {
+				capabilities: Capabilities::FULL_NODE,
+				user_agent: "gotts".to_owned(),
+				version: ProtocolVersion::local(),
+				addr,
+				direction: Direction::Outbound,
+				total_difficulty: Difficulty::min(),
+				height: 1,
+			}),
+			"BanEntry" => to_value(BanEntry::new(
+				subnet,
+				ReasonForBan::ManualBan,
+				"spam".to_owned(),
+				Some(60),
+			)),
+			"BanRequest" => to_value(BanRequest {
+				subnet: subnet,
+				duration: Some(60),
+				permanent: false,
+				note: Some("spam".to_owned()),
+			}),
+			"UnbanRequest" => to_value(UnbanRequest { subnet }),
+			"Event" => to_value(Event {
+				topic: Topic::Tip,
+				event: "new_tip".to_owned(),
+				data: Value::Null,
+			}),
+			"RpcRequest" => to_value(RpcRequest::new("get_tip", &json!({}), 1).unwrap()),
+			"RpcError" => to_value(rpc_error),
+			"RpcResponse" => {
+				return vec![
+					to_value(RpcResponse {
+						jsonrpc: "2.0".to_owned(),
+						result: Some(Value::Null),
+						error: None,
+						id: json!(1),
+					}),
+					to_value(RpcResponse {
+						jsonrpc: "2.0".to_owned(),
+						result: None,
+						error: Some(rpc_error),
+						id: json!(1),
+					}),
+				];
+			}
+			"TxWrapper" => to_value(TxWrapper {
+				tx_hex: "00".to_owned(),
+			}),
+			"TxsWrapper" => to_value(TxsWrapper {
+				txs_hex: vec!["00".to_owned()],
+			}),
+			_ => return vec![],
+		};
+		vec![value]
+	}
+
+	#[test]
+	fn schemas_match_types() {
+		let routes: Vec<&str> = ROUTE_DOCS.iter().map(|d| d.route).collect();
+		let doc = document("test", &routes);
+		for (name, schema) in doc["components"]["schemas"].as_object().unwrap() {
+			let properties = match schema["properties"].as_object() {
+				Some(properties) => properties,
+				None => continue,
+			};
+			let samples = samples(name);
+			assert!(!samples.is_empty(), "no sample of {}", name);
+
+			let mut actual = BTreeSet::new();
+			for sample in &samples {
+				let sample = sample.as_object().unwrap();
+				for required in schema["required"].as_array().into_iter().flatten() {
+					let required = required.as_str().unwrap();
+					assert!(sample.contains_key(required), "{}.{}", name, required);
+				}
+				actual.extend(sample.keys());
+			}
+			let expected: BTreeSet<_> = properties.keys().collect();
+			assert_eq!(expected, actual, "{}", name);
+		}
+
+		assert_eq!(
+			serde_json::to_value(TxStatus::ReorgedOut).unwrap(),
+			schema("TxStatusInfo")["properties"]["status"]["enum"][4]
+		);
+	}
+
+	#[test]
+	#[should_panic]
+	fn unknown_schema() {
+		schema("NoSuchType");
+	}
+
+	#[test]
+	fn documented_routes() {
+		// all the documented routes are distinct
+		for (i, doc) in ROUTE_DOCS.iter().enumerate() {
+			assert!(ROUTE_DOCS[i + 1..].iter().all(|d| d.route != doc.route));
+		}
+	}
+}
@@ -22,9 +22,12 @@
 use crate::router::{Handler, HandlerObj, ResponseFuture, Router};
 use crate::web::response;
 use failure::{Backtrace, Context, Fail, ResultExt};
+use futures::future;
 use futures::sync::oneshot;
 use futures::Stream;
 use hyper::rt::Future;
+use hyper::server::conn::AddrStream;
+use hyper::service::make_service_fn;
 use hyper::{rt, Body, Request, Server, StatusCode};
 use rustls;
 use rustls::internal::pemfile;
@@ -33,8 +36,8 @@ use std::fs::File;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::{io, thread};
-use tokio_rustls::ServerConfigExt;
-use tokio_tcp;
+use tokio_rustls::{ServerConfigExt, TlsStream};
+use tokio_tcp::{self, TcpStream};
 
 /// Errors that can be returned by an ApiEndpoint implementation.
 #[derive(Debug)]
@@ -192,7 +195,9 @@ impl ApiServer {
 			.name("apis".to_string())
 			.spawn(move || {
 				let server = Server::bind(&addr)
-					.serve(router)
+					.serve(make_service_fn(move |conn: &AddrStream| {
+						future::ok::<_, hyper::Error>(router.for_client(conn.remote_addr()))
+					}))
 					// TODO graceful shutdown is unstable, investigate
 					//.with_graceful_shutdown(rx)
 					.map_err(|e| eprintln!("HTTP API server error: {}", e));
@@ -234,7 +239,15 @@ impl ApiServer {
 					})
 					.filter_map(|x| x);
 				let server = Server::builder(tls)
-					.serve(router)
+					.serve(make_service_fn(
+						move |conn: &TlsStream<TcpStream, rustls::ServerSession>| {
+							let router = match conn.get_ref().0.peer_addr() {
+								Ok(addr) => router.for_client(addr),
+								Err(_) => router.clone(),
+							};
+							future::ok::<_, hyper::Error>(router)
+						},
+					))
 					.map_err(|e| eprintln!("HTTP API server error: {}", e));
 
 				rt::run(server);
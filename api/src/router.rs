@@ -13,14 +13,20 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::auth::ApiAccess;
+use crate::util::Mutex;
 use futures::future;
 use hyper;
+use hyper::header::RETRY_AFTER;
 use hyper::rt::Future;
 use hyper::service::{NewService, Service};
 use hyper::{Body, Method, Request, Response, StatusCode};
 use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 lazy_static! {
 	static ref WILDCARD_HASH: u64 = calculate_hash(&"*");
@@ -99,8 +105,17 @@ pub enum RouterError {
 #[derive(Clone)]
 pub struct Router {
 	nodes: Vec<Node>,
+	// registered routes, in registration order
+	routes: Vec<&'static str>,
+	// client of the connection this router serves, if known
+	remote_addr: Option<SocketAddr>,
 }
 
+/// Address of the client of a request, added to the request extensions by
+/// the router serving its connection.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RemoteAddr(pub SocketAddr);
+
 #[derive(Debug, Clone, Copy)]
 struct NodeId(usize);
 
@@ -122,7 +137,20 @@ impl Router {
 		let root = Node::new(calculate_hash(&""), None);
 		let mut nodes = vec![];
 		nodes.push(root);
-		Router { nodes }
+		Router {
+			nodes,
+			routes: vec![],
+			remote_addr: None,
+		}
+	}
+
+	/// Router serving the connection of a client, adding its address to the
+	/// requests.
+	pub fn for_client(&self, remote_addr: SocketAddr) -> Router {
+		Router {
+			remote_addr: Some(remote_addr),
+			..self.clone()
+		}
 	}
 
 	pub fn add_middleware(&mut self, mw: HandlerObj) {
@@ -173,6 +201,7 @@ impl Router {
 		}
 		match self.node(node_id).value() {
 			None => {
+				self.routes.push(route);
 				let node = self.node_mut(node_id);
 				node.set_value(value);
 				Ok(node)
@@ -181,6 +210,11 @@ impl Router {
 		}
 	}
 
+	/// All the routes registered so far, in registration order.
+	pub fn routes(&self) -> &[&'static str] {
+		&self.routes
+	}
+
 	pub fn get(&self, path: &str) -> Result<impl Iterator<Item = HandlerObj>, RouterError> {
 		let keys = generate_path(path);
 		let mut handlers = vec![];
@@ -210,7 +244,10 @@ impl Service for Router {
 	type Error = hyper::Error;
 	type Future = ResponseFuture;
 
-	fn call(&mut self, req: Request<Self::ReqBody>) -> Self::Future {
+	fn call(&mut self, mut req: Request<Self::ReqBody>) -> Self::Future {
+		if let Some(addr) = self.remote_addr {
+			req.extensions_mut().insert(RemoteAddr(addr));
+		}
 		match self.get(req.uri().path()) {
 			Err(_) => not_found(),
 			Ok(mut handlers) => match handlers.next() {
@@ -274,6 +311,169 @@ impl Node {
 	}
 }
 
+/// Buckets kept before dropping the idle ones.
+const MAX_RATE_LIMIT_BUCKETS: usize = 10_000;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum RateLimitKey {
+	Ip(IpAddr),
+	Token(String),
+}
+
+/// Token bucket refilled at `per_minute` tokens a minute, up to as many.
+struct TokenBucket {
+	per_minute: u32,
+	tokens: f64,
+	last: Instant,
+}
+
+impl TokenBucket {
+	fn new(per_minute: u32, now: Instant) -> TokenBucket {
+		let per_minute = per_minute.max(1);
+		TokenBucket {
+			per_minute,
+			tokens: per_minute as f64,
+			last: now,
+		}
+	}
+
+	fn refill(&mut self, now: Instant) {
+		let elapsed = now.duration_since(self.last);
+		let elapsed = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+		self.tokens =
+			(self.tokens + elapsed * self.per_minute as f64 / 60.0).min(self.per_minute as f64);
+		self.last = now;
+	}
+
+	/// How long until there is a token to take, if there is none now.
+	fn wait(&mut self, now: Instant) -> Option<Duration> {
+		self.refill(now);
+		if self.tokens >= 1.0 {
+			None
+		} else {
+			let secs = (1.0 - self.tokens) * 60.0 / self.per_minute as f64;
+			Some(Duration::from_millis((secs * 1000.0).ceil() as u64))
+		}
+	}
+
+	fn take(&mut self) {
+		self.tokens -= 1.0;
+	}
+
+	fn is_full(&mut self, now: Instant) -> bool {
+		self.refill(now);
+		self.tokens >= self.per_minute as f64
+	}
+}
+
+/// Rate limiting middleware, answering 429 with a Retry-After once a client
+/// goes over its requests per minute. Every request counts against the limit
+/// of its client address, and the ones authenticated with a token also
+/// against the limit of the token. The address limit is best applied before
+/// the authentication middleware, so failed attempts count too, and the token
+/// one after it.
+pub struct RateLimitMiddleware {
+	per_ip: Option<u32>,
+	per_token: Option<u32>,
+	buckets: Mutex<HashMap<RateLimitKey, TokenBucket>>,
+}
+
+impl RateLimitMiddleware {
+	/// Limits of the requests per minute by client address and by token, the
+	/// token limit being overridden by the one of the token if any.
+	pub fn new(per_ip: Option<u32>, per_token: Option<u32>) -> RateLimitMiddleware {
+		RateLimitMiddleware {
+			per_ip,
+			per_token,
+			buckets: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Takes a request from the buckets of its client and token, or tells how
+	/// long until it can be served.
+	fn check(
+		&self,
+		remote: Option<IpAddr>,
+		access: Option<&ApiAccess>,
+		now: Instant,
+	) -> Result<(), Duration> {
+		let mut limits = vec![];
+		if let (Some(ip), Some(limit)) = (remote, self.per_ip) {
+			limits.push((RateLimitKey::Ip(ip), limit));
+		}
+		if let Some(access) = access {
+			if let Some(limit) = access.rate_limit.or(self.per_token) {
+				limits.push((RateLimitKey::Token(access.name.clone()), limit));
+			}
+		}
+		if limits.is_empty() {
+			return Ok(());
+		}
+
+		let mut buckets = self.buckets.lock();
+		if buckets.len() > MAX_RATE_LIMIT_BUCKETS {
+			buckets.retain(|_, b| !b.is_full(now));
+		}
+		// only take from the buckets if all of them allow the request
+		let mut wait = None;
+		for (key, limit) in &limits {
+			let bucket = buckets
+				.entry(key.clone())
+				.or_insert_with(|| TokenBucket::new(*limit, now));
+			if let Some(w) = bucket.wait(now) {
+				wait = Some(wait.map_or(w, |x: Duration| x.max(w)));
+			}
+		}
+		if let Some(wait) = wait {
+			return Err(wait);
+		}
+		for (key, _) in &limits {
+			if let Some(bucket) = buckets.get_mut(key) {
+				bucket.take();
+			}
+		}
+		Ok(())
+	}
+}
+
+impl Handler for RateLimitMiddleware {
+	fn call(
+		&self,
+		req: Request<Body>,
+		mut handlers: Box<dyn Iterator<Item = HandlerObj>>,
+	) -> ResponseFuture {
+		let remote = req.extensions().get::<RemoteAddr>().map(|a| a.0.ip());
+		let access = req.extensions().get::<ApiAccess>();
+		if let Err(wait) = self.check(remote, access, Instant::now()) {
+			debug!(
+				"Rate limited {} {} from {:?}",
+				req.method(),
+				req.uri().path(),
+				remote
+			);
+			return too_many_requests(wait);
+		}
+		match handlers.next() {
+			Some(handler) => handler.call(req, handlers),
+			None => not_found(),
+		}
+	}
+}
+
+fn too_many_requests(wait: Duration) -> ResponseFuture {
+	// Retry-After is in whole seconds
+	let mut secs = wait.as_secs();
+	if wait.subsec_nanos() > 0 || secs == 0 {
+		secs += 1;
+	}
+	let response = Response::builder()
+		.status(StatusCode::TOO_MANY_REQUESTS)
+		.header(RETRY_AFTER, secs.to_string())
+		.body(Body::from("too many requests"))
+		.unwrap();
+	Box::new(future::ok(response))
+}
+
 pub fn not_found() -> ResponseFuture {
 	let mut response = Response::new(Body::empty());
 	*response.status_mut() = StatusCode::NOT_FOUND;
@@ -337,6 +537,50 @@ mod tests {
 		routes
 			.add_route("/v1/zzz/*/zzz", Arc::new(HandlerImpl(6)))
 			.unwrap();
+		assert_eq!(
+			routes.routes(),
+			&[
+				"/v1/users",
+				"/v1/users/xxx",
+				"/v1/users/xxx/yyy",
+				"/v1/zzz/*",
+				"/v1/zzz/*/zzz"
+			]
+		);
+	}
+
+	#[test]
+	fn rate_limits() {
+		let mw = RateLimitMiddleware::new(Some(2), Some(60));
+		let ip: IpAddr = "10.0.0.1".parse().unwrap();
+		let other: IpAddr = "10.0.0.2".parse().unwrap();
+		let now = Instant::now();
+
+		assert!(mw.check(Some(ip), None, now).is_ok());
+		assert!(mw.check(Some(ip), None, now).is_ok());
+		// 2 a minute, the next one in 30s
+		assert_eq!(mw.check(Some(ip), None, now), Err(Duration::from_secs(30)));
+		assert!(mw.check(Some(other), None, now).is_ok());
+		assert!(mw
+			.check(Some(ip), None, now + Duration::from_secs(30))
+			.is_ok());
+
+		// a token with its own limit, on top of the address one
+		let access = ApiAccess {
+			name: "explorer".to_owned(),
+			scopes: vec![],
+			rate_limit: Some(1),
+		};
+		let later = now + Duration::from_secs(120);
+		assert!(mw.check(Some(other), Some(&access), later).is_ok());
+		assert_eq!(
+			mw.check(Some(ip), Some(&access), later),
+			Err(Duration::from_secs(60))
+		);
+		// the address bucket wasn't taken from by the rejected request
+		assert!(mw.check(Some(ip), None, later).is_ok());
+		assert!(mw.check(Some(ip), None, later).is_ok());
+		assert!(mw.check(Some(ip), None, later).is_err());
 	}
 
 	#[test]
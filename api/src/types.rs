@@ -22,6 +22,7 @@ use crate::core::core::merkle_proof::MerkleProof;
 use crate::core::core::TxKernel;
 use crate::core::{core, ser};
 use crate::p2p;
+use crate::pool;
 use crate::util;
 use crate::util::secp::pedersen;
 
@@ -384,6 +385,24 @@ pub struct OutputListing {
 	pub outputs: Vec<OutputPrintable>,
 }
 
+/// A page of blocks, in increasing height order.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BlockListing {
+	/// The blocks, printable
+	pub blocks: Vec<BlockPrintable>,
+	/// The start height of the next page, none if we reached the chain head
+	pub next_height: Option<u64>,
+}
+
+/// A page of block headers, in increasing height order.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HeaderListing {
+	/// The block headers, printable
+	pub headers: Vec<BlockHeaderPrintable>,
+	/// The start height of the next page, none if we reached the header head
+	pub next_height: Option<u64>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct LocatedTxKernel {
 	pub tx_kernel: TxKernel,
@@ -391,12 +410,204 @@ pub struct LocatedTxKernel {
 	pub mmr_index: u64,
 }
 
+/// An unspent output with the merkle proof of its inclusion at a header of
+/// our chain.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OutputProofPrintable {
+	/// The unspent output
+	pub output: core::Output,
+	/// Height of the block which contains the output
+	pub height: u64,
+	/// MMR position of the output
+	pub mmr_index: u64,
+	/// Hash of the block header the proof is against
+	pub header_hash: String,
+	/// Merkle proof against the output root of that header
+	pub merkle_proof: MerkleProof,
+}
+
+impl OutputProofPrintable {
+	pub fn from_proof(proof: &chain::OutputProof) -> OutputProofPrintable {
+		OutputProofPrintable {
+			output: proof.output.clone(),
+			height: proof.height,
+			mmr_index: proof.mmr_index,
+			header_hash: proof.header_hash.to_hex(),
+			merkle_proof: proof.proof.clone(),
+		}
+	}
+}
+
+/// A tx kernel with the merkle proof of its inclusion at a header of our
+/// chain.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KernelProofPrintable {
+	/// The tx kernel
+	pub tx_kernel: TxKernel,
+	/// Height of the block which contains the kernel
+	pub height: u64,
+	/// MMR position of the kernel
+	pub mmr_index: u64,
+	/// Hash of the block header the proof is against
+	pub header_hash: String,
+	/// Merkle proof against the kernel root of that header
+	pub merkle_proof: MerkleProof,
+}
+
+impl KernelProofPrintable {
+	pub fn from_proof(proof: &chain::KernelProof) -> KernelProofPrintable {
+		KernelProofPrintable {
+			tx_kernel: proof.kernel.clone(),
+			height: proof.height,
+			mmr_index: proof.mmr_index,
+			header_hash: proof.header_hash.to_hex(),
+			merkle_proof: proof.proof.clone(),
+		}
+	}
+}
+
+/// Where a transaction stands, across the pool and the chain.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TxStatus {
+	/// Never seen, or rejected (see the last error)
+	Unknown,
+	/// In the stempool, going through the Dandelion stem phase (owner API
+	/// only, unknown on the foreign one)
+	Stem,
+	/// In the txpool, waiting to be mined
+	Pool,
+	/// Mined in a block of the chain
+	Confirmed,
+	/// Mined in a block we saw that got reorged out, not back in the chain
+	/// nor in the pool
+	ReorgedOut,
+}
+
+/// Status of a transaction, looked up by one of its kernel excesses.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TxStatusInfo {
+	/// Where the transaction stands
+	pub status: TxStatus,
+	/// Height of the block it was mined in, if confirmed
+	pub height: Option<u64>,
+	/// Number of confirmations, 1 when mined in the chain head
+	pub confirmations: Option<u64>,
+	/// Why the pool last rejected (or evicted) it, if it did recently
+	pub last_error: Option<String>,
+}
+
+/// Outcome of a transaction dry run against the rules of the pool.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TxValidation {
+	/// Whether the pool would accept the transaction
+	pub valid: bool,
+	/// Every rule checked, in order
+	pub checks: Vec<pool::TxRuleCheck>,
+}
+
+/// Supply audit of the UTXO set at a block.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SupplyAuditPrintable {
+	/// Height of the block audited
+	pub height: u64,
+	/// Hash of the block audited
+	pub hash: String,
+	/// Total explicit value of the unspent outputs
+	pub utxo_value: u64,
+	/// Coinbase issued up to the block, per the block reward
+	pub issued: u64,
+	/// Whether the unspent outputs hold exactly the coinbase issued
+	pub supply_matches: bool,
+	/// Number of unspent plain outputs
+	pub plain_outputs: u64,
+	/// Number of unspent coinbase outputs
+	pub coinbase_outputs: u64,
+	/// Number of unspent SigLocked outputs
+	pub sig_locked_outputs: u64,
+	/// Whether the outputs and kernels sums match, and match the block sums
+	pub kernel_sums_valid: bool,
+	/// Hex sum of the unspent outputs, if the sums could be computed
+	pub utxo_sum: Option<String>,
+	/// Hex sum of the kernel excesses, if the sums could be computed
+	pub kernel_sum: Option<String>,
+	/// Why the sums could not be computed or don't match
+	pub error: Option<String>,
+}
+
+impl SupplyAuditPrintable {
+	pub fn from_audit(audit: &chain::SupplyAudit) -> SupplyAuditPrintable {
+		let (utxo_sum, kernel_sum, error) = match audit.kernel_sums {
+			Ok((utxo_sum, kernel_sum)) => (
+				Some(util::to_hex(utxo_sum.0.to_vec())),
+				Some(util::to_hex(kernel_sum.0.to_vec())),
+				None,
+			),
+			Err(ref e) => (None, None, Some(e.clone())),
+		};
+		let kernel_sums_valid = audit.kernel_sums_valid();
+		let error = match error {
+			None if !kernel_sums_valid => Some("sums don't match the block sums".to_owned()),
+			e => e,
+		};
+		SupplyAuditPrintable {
+			height: audit.header.height,
+			hash: util::to_hex(audit.header.hash().to_vec()),
+			utxo_value: audit.utxos.value,
+			issued: audit.issued,
+			supply_matches: audit.supply_matches(),
+			plain_outputs: audit.utxos.plain,
+			coinbase_outputs: audit.utxos.coinbase,
+			sig_locked_outputs: audit.utxos.sig_locked,
+			kernel_sums_valid,
+			utxo_sum,
+			kernel_sum,
+			error,
+		}
+	}
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct PoolInfo {
 	/// Size of the pool
 	pub pool_size: usize,
 }
 
+/// A transaction waiting in the txpool, or in the stempool.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PoolEntryPrintable {
+	/// Pool the transaction is in, "txpool" or "stempool"
+	pub pool: String,
+	/// Hash of the transaction
+	pub tx_hash: String,
+	/// Hex excesses of the kernels
+	pub kernels: Vec<String>,
+	/// Total fee of the transaction
+	pub fee: u64,
+	/// Where the transaction came from
+	pub src: String,
+	/// When the transaction was added to the pool
+	pub tx_at: String,
+}
+
+impl PoolEntryPrintable {
+	pub fn from_entry(entry: &pool::PoolEntry, pool: &str) -> PoolEntryPrintable {
+		PoolEntryPrintable {
+			pool: pool.to_owned(),
+			tx_hash: entry.tx.hash().to_hex(),
+			kernels: entry
+				.tx
+				.kernels()
+				.iter()
+				.map(|k| util::to_hex(k.excess.0.to_vec()))
+				.collect(),
+			fee: entry.tx.fee(),
+			src: format!("{:?}", entry.src),
+			tx_at: entry.tx_at.to_rfc3339(),
+		}
+	}
+}
+
 #[cfg(test)]
 mod test {
 	use super::*;
@@ -481,3 +692,156 @@ mod test {
 		assert_eq!(deserialized.output.id().hash(), deserialized.output.hash());
 	}
 }
+
+/// Ban of a subnet, or of a single address, requested through the API.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BanRequest {
+	/// Subnet in CIDR notation ("10.12.0.0/16") or single ip address
+	pub subnet: p2p::Subnet,
+	/// Duration of the ban in seconds, the node ban window if not set
+	#[serde(default)]
+	pub duration: Option<i64>,
+	/// Permanent ban, without duration
+	#[serde(default)]
+	pub permanent: bool,
+	/// Note kept along with the ban
+	#[serde(default)]
+	pub note: Option<String>,
+}
+
+/// Removal of a subnet ban requested through the API.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UnbanRequest {
+	/// Subnet exactly as it was banned
+	pub subnet: p2p::Subnet,
+}
+
+/// Params of the get_header JSON-RPC method, the header being looked up by
+/// height, hash or output commitment (the first one set).
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct GetHeaderParams {
+	#[serde(default)]
+	pub height: Option<u64>,
+	#[serde(default)]
+	pub hash: Option<String>,
+	#[serde(default)]
+	pub commit: Option<String>,
+}
+
+/// Params of the get_block and get_compact_block JSON-RPC methods, the block
+/// being looked up by height or hash (the first one set).
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct GetBlockParams {
+	#[serde(default)]
+	pub height: Option<u64>,
+	#[serde(default)]
+	pub hash: Option<String>,
+	/// Whether to include the merkle proofs of the outputs, true if not set
+	#[serde(default)]
+	pub include_merkle_proof: Option<bool>,
+}
+
+/// Params of the get_supply_audit JSON-RPC method, the chain head if no
+/// height is set.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct GetSupplyAuditParams {
+	#[serde(default)]
+	pub height: Option<u64>,
+}
+
+/// Params of the get_outputs JSON-RPC method.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GetOutputsParams {
+	/// Hex commitments of the (unspent) outputs
+	pub commits: Vec<String>,
+}
+
+/// Params of the get_kernels JSON-RPC method.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GetKernelsParams {
+	/// Hex excesses of the kernels
+	pub excesses: Vec<String>,
+}
+
+/// Params of the get_kernel JSON-RPC method, searching the kernel between
+/// optional heights.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GetKernelParams {
+	/// Hex excess of the kernel
+	pub excess: String,
+	#[serde(default)]
+	pub min_height: Option<u64>,
+	#[serde(default)]
+	pub max_height: Option<u64>,
+}
+
+/// Params of the get_output_proof JSON-RPC method.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GetOutputProofParams {
+	/// Hex commitment of the unspent output
+	pub commit: String,
+}
+
+/// Params of the get_kernel_proof JSON-RPC method.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GetKernelProofParams {
+	/// Hex excess of the kernel
+	pub excess: String,
+}
+
+/// Params of the get_last_outputs and get_last_kernels JSON-RPC methods.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GetLastNodesParams {
+	/// Number of nodes, 10 by default
+	#[serde(default)]
+	pub n: Option<u64>,
+}
+
+/// Params of the get_txhashset_outputs and get_nit_outputs JSON-RPC methods,
+/// a range of the UTXO set by MMR index.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GetOutputRangeParams {
+	/// First MMR index, 1 by default
+	#[serde(default)]
+	pub start_index: Option<u64>,
+	/// Max number of outputs, 100 by default
+	#[serde(default)]
+	pub max: Option<u64>,
+}
+
+/// Params of the get_merkle_proof JSON-RPC method.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GetMerkleProofParams {
+	/// Hex commitment of the (unspent) output
+	pub commit: String,
+	/// Output features, plain by default
+	#[serde(default)]
+	pub features: Option<u8>,
+}
+
+/// Params of the push_transaction JSON-RPC method.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PushTransactionParams {
+	/// Hex of the serialized transaction
+	pub tx_hex: String,
+	/// Whether to skip the Dandelion stem phase
+	#[serde(default)]
+	pub fluff: bool,
+}
+
+/// Params of the push_package JSON-RPC method.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PushPackageParams {
+	/// Hex of the serialized transactions, parents first
+	pub txs_hex: Vec<String>,
+	/// Whether to skip the Dandelion stem phase
+	#[serde(default)]
+	pub fluff: bool,
+}
+
+/// Params of the owner JSON-RPC methods on a single peer.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PeerAddrParams {
+	/// Ip address, or "ip:port"
+	pub addr: String,
+}
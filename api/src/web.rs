@@ -28,7 +28,7 @@ where
 }
 
 /// Response on error
-fn response_on_error(e: Error) -> ResponseFuture {
+pub fn response_on_error(e: Error) -> ResponseFuture {
 	match e.kind() {
 		ErrorKind::Argument(msg) => response(StatusCode::BAD_REQUEST, msg.clone()),
 		ErrorKind::RequestError(msg) => response(StatusCode::BAD_REQUEST, msg.clone()),
@@ -0,0 +1,570 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! WebSocket subscriptions to the node events: new tip, accepted blocks, txs
+//! entering and leaving the pool, peers connecting and disconnecting. The
+//! server hooks publish the events to the `EventHub`, which pushes them to
+//! the clients subscribed to their topic.
+
+use crate::p2p::types::PeerAddr;
+use crate::router::{Handler, ResponseFuture};
+use crate::util::{self, RwLock};
+use crate::web::{just_response, response, QueryParams};
+use futures::future::{self, loop_fn, Either, Loop};
+use futures::sync::mpsc;
+use futures::{Future, Stream};
+use hyper::header::{
+	HeaderValue, CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_VERSION,
+	UPGRADE,
+};
+use hyper::{Body, Request, StatusCode};
+use ring::digest;
+use serde::Serialize;
+use serde_json::{self, Value};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use tokio::io::{read_exact, write_all, AsyncRead};
+
+/// Magic string of the WebSocket handshake (RFC 6455).
+const WS_GUID: &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Max number of WebSocket subscribers.
+const MAX_SUBSCRIBERS: usize = 256;
+
+/// Max number of frames queued for a subscriber, a subscriber not keeping up
+/// is dropped.
+const SUBSCRIBER_QUEUE_SIZE: usize = 1024;
+
+/// Max payload of a frame sent by a client, only small commands are expected.
+const MAX_CLIENT_PAYLOAD: u64 = 4096;
+
+const OP_TEXT: u8 = 0x1;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+/// Topics a client can subscribe to.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Topic {
+	/// The head of the chain changed (event: new_tip)
+	Tip,
+	/// A block has been accepted, extending the chain, on a fork or through a
+	/// reorg (event: block_accepted)
+	Block,
+	/// A tx entered or left the pool (events: tx_accepted, tx_evicted)
+	Pool,
+	/// A peer connected or disconnected (events: peer_connected,
+	/// peer_disconnected)
+	Peers,
+}
+
+/// Topics of the foreign API, the peers being for the owner only.
+pub const FOREIGN_TOPICS: &'static [Topic] = &[Topic::Tip, Topic::Block, Topic::Pool];
+
+/// All the topics, for the owner API.
+pub const ALL_TOPICS: &'static [Topic] = &[Topic::Tip, Topic::Block, Topic::Pool, Topic::Peers];
+
+/// An event, pushed to the subscribers of its topic.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Event {
+	pub topic: Topic,
+	/// Name of the event
+	pub event: String,
+	pub data: Value,
+}
+
+/// A command of a client, adding or removing topics of its subscription.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Command {
+	/// "subscribe" or "unsubscribe"
+	pub method: String,
+	pub topics: Vec<Topic>,
+}
+
+/// Answer to a command, the topics now subscribed to or an error.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CommandReply {
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub subscribed: Option<Vec<Topic>>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub error: Option<String>,
+}
+
+/// Data of the block_accepted event.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlockAcceptedEvent {
+	pub hash: String,
+	pub height: u64,
+	pub prev_hash: String,
+	/// "head", "fork" or "reorg"
+	pub status: String,
+	/// Depth of the reorg, if any
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub reorg_depth: Option<u64>,
+}
+
+/// Data of the tx_accepted and tx_evicted events.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PoolTxEvent {
+	pub hash: String,
+	/// Excess commitments of the tx kernels
+	pub kernels: Vec<String>,
+	pub fee: u64,
+	/// Block the pool was reconciled against when the tx left it, none if
+	/// evicted over capacity or removed by the node owner (tx_evicted only)
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub block_hash: Option<String>,
+}
+
+/// Data of the peer_disconnected event, peer_connected having the peer info.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PeerDisconnectedEvent {
+	pub addr: PeerAddr,
+}
+
+struct Subscriber {
+	topics: HashSet<Topic>,
+	sender: mpsc::Sender<Vec<u8>>,
+}
+
+/// Dispatches the node events to the WebSocket clients subscribed to their
+/// topic.
+pub struct EventHub {
+	next_id: AtomicUsize,
+	subscribers: RwLock<HashMap<usize, Subscriber>>,
+}
+
+impl EventHub {
+	/// New hub, without subscribers.
+	pub fn new() -> EventHub {
+		EventHub {
+			next_id: AtomicUsize::new(0),
+			subscribers: RwLock::new(HashMap::new()),
+		}
+	}
+
+	/// Number of connected subscribers.
+	pub fn subscriber_count(&self) -> usize {
+		self.subscribers.read().len()
+	}
+
+	/// Publishes an event to the subscribers of its topic. The subscribers not
+	/// keeping up with the events (full queue) are dropped.
+	pub fn publish<T: Serialize>(&self, topic: Topic, name: &str, data: &T) {
+		if !self
+			.subscribers
+			.read()
+			.values()
+			.any(|s| s.topics.contains(&topic))
+		{
+			return;
+		}
+		let event = serde_json::to_value(data).and_then(|data| {
+			serde_json::to_vec(&Event {
+				topic,
+				event: name.to_owned(),
+				data,
+			})
+		});
+		let frame = match event {
+			Ok(event) => encode_frame(OP_TEXT, &event),
+			Err(e) => {
+				error!("ws: failed to serialize {} event: {}", name, e);
+				return;
+			}
+		};
+
+		let mut subscribers = self.subscribers.write();
+		let mut dropped = vec![];
+		for (id, subscriber) in subscribers.iter_mut() {
+			if subscriber.topics.contains(&topic)
+				&& subscriber.sender.try_send(frame.clone()).is_err()
+			{
+				dropped.push(*id);
+			}
+		}
+		for id in dropped {
+			debug!("ws: dropping subscriber {}, not keeping up", id);
+			subscribers.remove(&id);
+		}
+	}
+
+	fn subscribe(&self, topics: Vec<Topic>) -> Option<(usize, mpsc::Receiver<Vec<u8>>)> {
+		let mut subscribers = self.subscribers.write();
+		if subscribers.len() >= MAX_SUBSCRIBERS {
+			return None;
+		}
+		let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+		let (sender, receiver) = mpsc::channel(SUBSCRIBER_QUEUE_SIZE);
+		subscribers.insert(
+			id,
+			Subscriber {
+				topics: topics.into_iter().collect(),
+				sender,
+			},
+		);
+		Some((id, receiver))
+	}
+
+	// Adds (or removes) topics, returning all the topics subscribed to.
+	fn update(&self, id: usize, topics: &[Topic], add: bool) -> Option<Vec<Topic>> {
+		let mut subscribers = self.subscribers.write();
+		let subscriber = subscribers.get_mut(&id)?;
+		for topic in topics {
+			if add {
+				subscriber.topics.insert(*topic);
+			} else {
+				subscriber.topics.remove(topic);
+			}
+		}
+		let mut topics: Vec<_> = subscriber.topics.iter().cloned().collect();
+		topics.sort();
+		Some(topics)
+	}
+
+	// Queues a frame for a subscriber, false if it's gone.
+	fn send(&self, id: usize, frame: Vec<u8>) -> bool {
+		match self.subscribers.write().get_mut(&id) {
+			Some(subscriber) => subscriber.sender.try_send(frame).is_ok(),
+			None => false,
+		}
+	}
+
+	// Drops a subscriber, its queue ends once flushed.
+	fn unsubscribe(&self, id: usize) {
+		self.subscribers.write().remove(&id);
+	}
+}
+
+/// WebSocket endpoint, clients subscribe to topics and get their events.
+/// GET /v1/ws?topics=tip,block
+///
+/// Commands: {"method": "subscribe", "topics": ["pool"]} or
+/// {"method": "unsubscribe", "topics": ["block"]}, answered with the topics
+/// subscribed to: {"subscribed": ["pool", "tip"]}.
+/// Events: {"topic": "tip", "event": "new_tip", "data": {...}}
+pub struct WebSocketHandler {
+	pub hub: Weak<EventHub>,
+	/// Topics the clients can subscribe to on this endpoint
+	pub topics: Vec<Topic>,
+}
+
+impl Handler for WebSocketHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		let hub = match self.hub.upgrade() {
+			Some(hub) => hub,
+			None => {
+				return response(
+					StatusCode::INTERNAL_SERVER_ERROR,
+					"weak reference upgrade failed",
+				);
+			}
+		};
+		let key = match websocket_key(&req) {
+			Some(key) => key,
+			None => return response(StatusCode::BAD_REQUEST, "websocket upgrade expected"),
+		};
+
+		let mut topics = vec![];
+		let mut not_allowed = None;
+		QueryParams::from(req.uri().query()).process_multival_param("topics", |t| {
+			match serde_json::from_value(Value::String(t.to_owned())) {
+				Ok(topic) if self.topics.contains(&topic) => topics.push(topic),
+				_ => not_allowed = Some(t.to_owned()),
+			}
+		});
+		if let Some(t) = not_allowed {
+			return response(StatusCode::BAD_REQUEST, format!("topic not allowed: {}", t));
+		}
+		let (id, receiver) = match hub.subscribe(topics) {
+			Some(subscription) => subscription,
+			None => return response(StatusCode::SERVICE_UNAVAILABLE, "too many subscribers"),
+		};
+
+		let allowed = self.topics.clone();
+		let conn_hub = hub.clone();
+		let conn = req
+			.into_body()
+			.on_upgrade()
+			.map_err(|e| debug!("ws: upgrade failed: {}", e))
+			.and_then(move |upgraded| {
+				let (reader, writer) = upgraded.split();
+				let writing = receiver
+					.map_err(|_| io::Error::new(io::ErrorKind::Other, "ws queue failed"))
+					.fold(writer, |writer, frame| {
+						write_all(writer, frame).map(|(writer, _)| writer)
+					})
+					.map(|_| ());
+				let reading = read_commands(reader, conn_hub, id, allowed);
+				// done when the client or the hub is done
+				writing.select2(reading).then(|_| Ok(()))
+			})
+			.then(move |_: Result<(), ()>| {
+				hub.unsubscribe(id);
+				Ok(())
+			});
+		hyper::rt::spawn(conn);
+
+		let mut resp = just_response(StatusCode::SWITCHING_PROTOCOLS, "");
+		let headers = resp.headers_mut();
+		headers.insert(UPGRADE, HeaderValue::from_static("websocket"));
+		headers.insert(CONNECTION, HeaderValue::from_static("Upgrade"));
+		match HeaderValue::from_str(&accept_key(&key)) {
+			Ok(accept) => {
+				headers.insert(SEC_WEBSOCKET_ACCEPT, accept);
+			}
+			Err(_) => return response(StatusCode::BAD_REQUEST, "invalid websocket key"),
+		}
+		Box::new(future::ok(resp))
+	}
+}
+
+// The key of a WebSocket handshake, None if it isn't one.
+fn websocket_key(req: &Request<Body>) -> Option<String> {
+	let headers = req.headers();
+	let upgrade = headers.get(UPGRADE)?.to_str().ok()?;
+	let version = headers.get(SEC_WEBSOCKET_VERSION)?.to_str().ok()?;
+	if !upgrade.eq_ignore_ascii_case("websocket") || version.trim() != "13" {
+		return None;
+	}
+	headers
+		.get(SEC_WEBSOCKET_KEY)?
+		.to_str()
+		.ok()
+		.map(|k| k.trim().to_owned())
+}
+
+/// Accept key of the handshake answer for the key of a client.
+pub fn accept_key(key: &str) -> String {
+	let mut ctx = digest::Context::new(&digest::SHA1);
+	ctx.update(key.as_bytes());
+	ctx.update(WS_GUID.as_bytes());
+	util::bytes_to_base64(ctx.finish().as_ref())
+}
+
+/// A single (final, unmasked) frame, as sent by the server.
+pub fn encode_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+	let mut frame = Vec::with_capacity(payload.len() + 10);
+	frame.push(0x80 | opcode);
+	let len = payload.len();
+	if len < 126 {
+		frame.push(len as u8);
+	} else if len <= 0xffff {
+		frame.push(126);
+		frame.extend_from_slice(&(len as u16).to_be_bytes());
+	} else {
+		frame.push(127);
+		frame.extend_from_slice(&(len as u64).to_be_bytes());
+	}
+	frame.extend_from_slice(payload);
+	frame
+}
+
+// Reads a frame of a client, its opcode and unmasked payload. Fragmented
+// messages aren't supported, commands being small.
+fn read_frame<R>(reader: R) -> Box<dyn Future<Item = (R, u8, Vec<u8>), Error = io::Error> + Send>
+where
+	R: AsyncRead + Send + 'static,
+{
+	Box::new(read_exact(reader, [0u8; 2]).and_then(|(reader, head)| {
+		let opcode = head[0] & 0x0f;
+		let masked = head[1] & 0x80 != 0;
+		let len: Box<dyn Future<Item = (R, u64), Error = io::Error> + Send> = match head[1] & 0x7f {
+			126 => Box::new(
+				read_exact(reader, [0u8; 2])
+					.map(|(reader, len)| (reader, u16::from_be_bytes(len) as u64)),
+			),
+			127 => Box::new(
+				read_exact(reader, [0u8; 8]).map(|(reader, len)| (reader, u64::from_be_bytes(len))),
+			),
+			len => Box::new(future::ok((reader, len as u64))),
+		};
+		len.and_then(move |(reader, len)| {
+			// the frames of a client are always masked
+			if !masked || len > MAX_CLIENT_PAYLOAD {
+				return Either::A(future::err(io::Error::new(
+					io::ErrorKind::InvalidData,
+					"invalid client frame",
+				)));
+			}
+			Either::B(
+				read_exact(reader, [0u8; 4]).and_then(move |(reader, mask)| {
+					read_exact(reader, vec![0u8; len as usize]).map(move |(reader, mut payload)| {
+						for (i, b) in payload.iter_mut().enumerate() {
+							*b ^= mask[i % 4];
+						}
+						(reader, opcode, payload)
+					})
+				}),
+			)
+		})
+	}))
+}
+
+// Reads the frames of a client until it's gone, answering its commands and
+// pings through the queue of its subscription.
+fn read_commands<R>(
+	reader: R,
+	hub: Arc<EventHub>,
+	id: usize,
+	allowed: Vec<Topic>,
+) -> impl Future<Item = (), Error = io::Error>
+where
+	R: AsyncRead + Send + 'static,
+{
+	loop_fn(reader, move |reader| {
+		let hub = hub.clone();
+		let allowed = allowed.clone();
+		read_frame(reader).map(move |(reader, opcode, payload)| {
+			let reply = match opcode {
+				OP_TEXT => encode_frame(OP_TEXT, &handle_command(&hub, id, &allowed, &payload)),
+				OP_PING => encode_frame(OP_PONG, &payload),
+				OP_CLOSE => {
+					// echo the close, the connection ends once the queue is flushed
+					hub.send(id, encode_frame(OP_CLOSE, &[]));
+					hub.unsubscribe(id);
+					return Loop::Continue(reader);
+				}
+				_ => return Loop::Continue(reader),
+			};
+			if hub.send(id, reply) {
+				Loop::Continue(reader)
+			} else {
+				Loop::Break(())
+			}
+		})
+	})
+}
+
+fn handle_command(hub: &EventHub, id: usize, allowed: &[Topic], payload: &[u8]) -> Vec<u8> {
+	let res = serde_json::from_slice::<Command>(payload)
+		.map_err(|e| format!("invalid command: {}", e))
+		.and_then(|cmd| {
+			if let Some(topic) = cmd.topics.iter().find(|t| !allowed.contains(t)) {
+				return Err(format!("topic not allowed: {:?}", topic));
+			}
+			let add = match cmd.method.as_str() {
+				"subscribe" => true,
+				"unsubscribe" => false,
+				method => return Err(format!("unknown method: {}", method)),
+			};
+			hub.update(id, &cmd.topics, add)
+				.ok_or_else(|| "not subscribed".to_owned())
+		});
+	let reply = match res {
+		Ok(topics) => CommandReply {
+			subscribed: Some(topics),
+			error: None,
+		},
+		Err(e) => CommandReply {
+			subscribed: None,
+			error: Some(e),
+		},
+	};
+	serde_json::to_vec(&reply).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use std::io::Cursor;
+
+	fn client_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+		let mask = [0x37, 0xfa, 0x21, 0x3d];
+		let mut frame = vec![0x80 | opcode];
+		if payload.len() < 126 {
+			frame.push(0x80 | payload.len() as u8);
+		} else {
+			frame.push(0x80 | 126);
+			frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+		}
+		frame.extend_from_slice(&mask);
+		frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+		frame
+	}
+
+	#[test]
+	fn handshake_accept_key() {
+		// example of RFC 6455
+		assert_eq!(
+			accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+			"s3pPLMBiTxaQ9kYGzpF+9s8Ui/0="
+		);
+	}
+
+	#[test]
+	fn frames() {
+		assert_eq!(encode_frame(OP_TEXT, b"Hello"), b"\x81\x05Hello".to_vec());
+		let frame = encode_frame(OP_TEXT, &[0u8; 300]);
+		assert_eq!(&frame[..4], &[0x81, 126, 0x01, 0x2c]);
+		assert_eq!(frame.len(), 304);
+
+		let (_, opcode, payload) = read_frame(Cursor::new(client_frame(OP_TEXT, b"Hello")))
+			.wait()
+			.unwrap();
+		assert_eq!((opcode, payload), (OP_TEXT, b"Hello".to_vec()));
+		let (_, opcode, payload) = read_frame(Cursor::new(client_frame(OP_PING, &[7u8; 200])))
+			.wait()
+			.unwrap();
+		assert_eq!((opcode, payload), (OP_PING, vec![7u8; 200]));
+
+		// unmasked frames of a client are refused
+		assert!(read_frame(Cursor::new(encode_frame(OP_TEXT, b"Hello")))
+			.wait()
+			.is_err());
+	}
+
+	#[test]
+	fn hub_topics() {
+		let hub = EventHub::new();
+		let (tip_id, tip_rx) = hub.subscribe(vec![Topic::Tip]).unwrap();
+		let (pool_id, pool_rx) = hub.subscribe(vec![Topic::Pool]).unwrap();
+		assert_eq!(hub.subscriber_count(), 2);
+
+		hub.publish(Topic::Tip, "new_tip", &1u64);
+		let reply = handle_command(
+			&hub,
+			pool_id,
+			FOREIGN_TOPICS,
+			br#"{"method": "subscribe", "topics": ["tip"]}"#,
+		);
+		let reply: CommandReply = serde_json::from_slice(&reply).unwrap();
+		assert_eq!(reply.subscribed, Some(vec![Topic::Tip, Topic::Pool]));
+		hub.publish(Topic::Tip, "new_tip", &2u64);
+
+		// peers are owner only
+		let reply = handle_command(
+			&hub,
+			tip_id,
+			FOREIGN_TOPICS,
+			br#"{"method": "subscribe", "topics": ["peers"]}"#,
+		);
+		let reply: CommandReply = serde_json::from_slice(&reply).unwrap();
+		assert!(reply.subscribed.is_none() && reply.error.is_some());
+
+		hub.unsubscribe(tip_id);
+		hub.unsubscribe(pool_id);
+		let tip_frames: Vec<_> = tip_rx.wait().map(|f| f.unwrap()).collect();
+		let pool_frames: Vec<_> = pool_rx.wait().map(|f| f.unwrap()).collect();
+		assert_eq!(tip_frames.len(), 2);
+		assert_eq!(pool_frames.len(), 1);
+		let event: Event = serde_json::from_slice(&pool_frames[0][2..]).unwrap();
+		assert_eq!(event.topic, Topic::Tip);
+		assert_eq!(event.event, "new_tip");
+		assert_eq!(event.data, Value::from(2u64));
+	}
+}
@@ -18,29 +18,33 @@
 
 use crate::core::core::hash::{Hash, Hashed, ZERO_HASH};
 use crate::core::core::merkle_proof::MerkleProof;
+use crate::core::core::pmmr::SegmentIdentifier;
 use crate::core::core::verifier_cache::VerifierCache;
 use crate::core::core::{
 	Block, BlockHeader, BlockSums, Committed, Input, Output, OutputEx, OutputFeatures, OutputI,
 	OutputIdentifier, Transaction, TxKernel, TxKernelApiEntry,
 };
-use crate::core::global;
 use crate::core::pow;
 use crate::core::ser::{ProtocolVersion, Readable, StreamingReader};
+use crate::core::{consensus, global};
 use crate::error::{Error, ErrorKind};
 use crate::pipe;
 use crate::store;
 use crate::txhashset;
 use crate::txhashset::{PMMRHandle, TxHashSet};
 use crate::types::{
-	BlockStatus, ChainAdapter, NoStatus, Options, OutputFeaturePosHeight, OutputMMRPosition, Tip,
-	TxHashSetRoots, TxHashsetWriteStatus,
+	BlockStatus, ChainAdapter, KernelProof, LatencyHistogram, NoStatus, Options,
+	OutputFeaturePosHeight, OutputMMRPosition, OutputProof, SegmentType, SupplyAudit, Tip,
+	TxHashSetRoots, TxHashSetSegment, TxHashsetWriteStatus, BLOCK_LATENCY_BUCKETS,
+	TXHASHSET_SEGMENT_HEIGHT,
 };
 use crate::util::secp::pedersen::Commitment;
-use crate::util::RwLock;
+use crate::util::{Mutex, RwLock};
 use gotts_store::Error::NotFoundErr;
 use std::collections::HashMap;
 use std::fs::{self, File};
 use std::io::Read;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
@@ -52,6 +56,11 @@ pub const MAX_ORPHAN_SIZE: usize = 200;
 /// When evicting, very old orphans are evicted first
 const MAX_ORPHAN_AGE_SECS: u64 = 300;
 
+/// How far behind our header head the header of a merkle proof received from
+/// a peer may be. The older the header, the more likely the output has been
+/// spent since.
+pub const MAX_PROOF_AGE: u64 = 3;
+
 #[derive(Debug, Clone)]
 struct Orphan {
 	block: Block,
@@ -159,6 +168,12 @@ pub struct Chain {
 	archive_mode: bool,
 	pruning_kernel_index: bool,
 	genesis: BlockHeader,
+	// copy of the txhashset at the archive header, to serve segments from
+	segmenter: RwLock<Option<(Hash, txhashset::TxHashSet)>>,
+	// time spent in the block processing pipeline
+	block_latencies: RwLock<LatencyHistogram>,
+	// last supply audit, held while auditing so audits run one at a time
+	supply_audit: Mutex<Option<SupplyAudit>>,
 }
 
 impl Chain {
@@ -205,6 +220,9 @@ impl Chain {
 			archive_mode,
 			pruning_kernel_index,
 			genesis: genesis.header.clone(),
+			segmenter: RwLock::new(None),
+			block_latencies: RwLock::new(LatencyHistogram::new(&BLOCK_LATENCY_BUCKETS)),
+			supply_audit: Mutex::new(None),
 		})
 	}
 
@@ -274,6 +292,27 @@ impl Chain {
 		res
 	}
 
+	/// Time spent processing blocks through the pipeline since startup,
+	/// including the ones rejected or kept as orphans.
+	pub fn block_latencies(&self) -> LatencyHistogram {
+		self.block_latencies.read().clone()
+	}
+
+	/// Size on disk of the chain LMDB database, in bytes.
+	pub fn db_size(&self) -> u64 {
+		let lmdb_dir = PathBuf::from(&self.db_root).join("lmdb");
+		fs::read_dir(lmdb_dir)
+			.map(|entries| {
+				entries
+					.filter_map(|e| e.ok())
+					.filter_map(|e| e.metadata().ok())
+					.filter(|m| m.is_file())
+					.map(|m| m.len())
+					.sum()
+			})
+			.unwrap_or(0)
+	}
+
 	fn determine_status(&self, head: Option<Tip>, prev_head: Tip) -> BlockStatus {
 		// We have more work if the chain head is updated.
 		let is_more_work = head.is_some();
@@ -307,7 +346,9 @@ impl Chain {
 
 			let prev_head = ctx.batch.head()?;
 
+			let start = Instant::now();
 			let maybe_new_head = pipe::process_block(&b, &mut ctx);
+			self.block_latencies.write().observe(start.elapsed());
 
 			// We have flushed txhashset extension changes to disk
 			// but not yet committed the batch.
@@ -583,6 +624,54 @@ impl Chain {
 		})
 	}
 
+	/// Audits the supply at the given block: the explicit value of the unspent
+	/// outputs against the coinbase issued up to there, and the kernel sums.
+	/// Rewinds the txhashset, so only possible within the horizon on a
+	/// pruned node (`ErrorKind::BeyondHorizon` below it), and iterates over
+	/// the whole UTXO set. Audits run one at a time, the last one being kept
+	/// for further requests on the same block.
+	pub fn supply_audit(&self, header: &BlockHeader) -> Result<SupplyAudit, Error> {
+		if !self.archive_mode {
+			let horizon = global::cut_through_horizon() as u64;
+			if header.height < self.head()?.height.saturating_sub(horizon) {
+				return Err(ErrorKind::BeyondHorizon(horizon).into());
+			}
+		}
+
+		let mut last_audit = self.supply_audit.lock();
+		if let Some(ref audit) = *last_audit {
+			if audit.header.hash() == header.hash() {
+				return Ok(audit.clone());
+			}
+		}
+
+		let block_sums = self.get_block_sums(&header.hash())?;
+
+		let mut header_pmmr = self.header_pmmr.write();
+		let mut txhashset = self.txhashset.write();
+		let audit = txhashset::extending_readonly(&mut header_pmmr, &mut txhashset, |ext| {
+			pipe::rewind_and_apply_fork(&header, ext)?;
+			let ref extension = ext.extension;
+			let utxos = extension.utxo_stats();
+			let kernel_sums = extension
+				.validate_kernel_sums(&self.genesis)
+				.map_err(|e| e.to_string());
+
+			// one reward per block, the genesis one included if it had any
+			let genesis_had_reward = self.genesis.kernel_mmr_size > 0;
+			let rewards = header.height + if genesis_had_reward { 1 } else { 0 };
+			Ok(SupplyAudit {
+				header: header.clone(),
+				utxos,
+				issued: rewards.saturating_mul(consensus::reward(0)),
+				kernel_sums,
+				block_sums,
+			})
+		})?;
+		*last_audit = Some(audit.clone());
+		Ok(audit)
+	}
+
 	/// Sets the txhashset roots on a brand new block by applying the block on
 	/// the current txhashset state.
 	pub fn set_txhashset_roots(&self, b: &mut Block) -> Result<(), Error> {
@@ -656,6 +745,89 @@ impl Chain {
 		txhashset.merkle_proof(output)
 	}
 
+	/// Unspent output for the provided commitment, with a merkle proof of its
+	/// inclusion against our head header, to be sent to light nodes.
+	pub fn get_output_proof(&self, commit: &Commitment) -> Result<OutputProof, Error> {
+		let mut txhashset = self.txhashset.write();
+		let header = self.head_header()?;
+		let pos = txhashset.is_unspent(commit)?;
+		let output = match pos.features {
+			OutputFeatures::Plain | OutputFeatures::Coinbase => txhashset
+				.output_i_by_position(pos.position)
+				.map(|o| o.into_output()),
+			OutputFeatures::SigLocked => txhashset
+				.output_ii_by_position(pos.position)
+				.map(|o| o.into_output()),
+		}
+		.ok_or(ErrorKind::OutputNotFound)?;
+		let proof = txhashset.merkle_proof(&output.id())?;
+		Ok(OutputProof {
+			output,
+			height: pos.height,
+			mmr_index: pos.position,
+			header_hash: header.hash(),
+			proof,
+		})
+	}
+
+	/// Tx kernel with the provided excess, with a merkle proof of its
+	/// inclusion against our head header, to be sent to light nodes.
+	pub fn get_kernel_proof(&self, excess: &Commitment) -> Result<KernelProof, Error> {
+		let (kernel, height, mmr_index) = match self.store.get_txkernel_pos_height(excess) {
+			Ok((pos, height)) => {
+				let kernel = self
+					.txhashset
+					.read()
+					.txkernel_by_insertion_index(pos)
+					.ok_or(ErrorKind::TxKernelNotFound)?;
+				(kernel, height, pos)
+			}
+			Err(_) => self
+				.get_kernel_height(excess, None, None)?
+				.ok_or(ErrorKind::TxKernelNotFound)?,
+		};
+		let mut txhashset = self.txhashset.write();
+		let header = self.head_header()?;
+		let proof = txhashset.kernel_merkle_proof(mmr_index)?;
+		Ok(KernelProof {
+			kernel,
+			height,
+			mmr_index,
+			header_hash: header.hash(),
+			proof,
+		})
+	}
+
+	/// Checks an output proof received from a peer against our header chain,
+	/// returning the header it was built for. The header must be at most
+	/// MAX_PROOF_AGE blocks behind our header head.
+	pub fn verify_output_proof(&self, proof: &OutputProof) -> Result<BlockHeader, Error> {
+		let header = self.get_block_header(&proof.header_hash)?;
+		self.is_on_current_chain(&header)?;
+		self.check_proof_age(&header)?;
+		proof.verify(&header)?;
+		Ok(header)
+	}
+
+	/// Checks a kernel proof received from a peer against our header chain,
+	/// returning the header it was built for. The header must be at most
+	/// MAX_PROOF_AGE blocks behind our header head.
+	pub fn verify_kernel_proof(&self, proof: &KernelProof) -> Result<BlockHeader, Error> {
+		let header = self.get_block_header(&proof.header_hash)?;
+		self.is_on_current_chain(&header)?;
+		self.check_proof_age(&header)?;
+		proof.verify(&header)?;
+		Ok(header)
+	}
+
+	fn check_proof_age(&self, header: &BlockHeader) -> Result<(), Error> {
+		let age = self.header_head()?.height.saturating_sub(header.height);
+		if age > MAX_PROOF_AGE {
+			return Err(ErrorKind::StaleProof(age).into());
+		}
+		Ok(())
+	}
+
 	/// Returns current txhashset roots.
 	pub fn get_txhashset_roots(&self) -> TxHashSetRoots {
 		self.txhashset.read().roots()
@@ -735,6 +907,113 @@ impl Chain {
 		self.get_header_by_height(txhashset_height)
 	}
 
+	/// Builds a segment of the txhashset at the archive header we offer to
+	/// peers. Segments are served from a copy of the txhashset rewound to that
+	/// header, made on the first request and kept until the archive header
+	/// moves on.
+	pub fn txhashset_segment(
+		&self,
+		h: Hash,
+		segment_type: SegmentType,
+		id: SegmentIdentifier,
+	) -> Result<TxHashSetSegment, Error> {
+		if id.height > TXHASHSET_SEGMENT_HEIGHT {
+			return Err(ErrorKind::InvalidSegment(format!("height {}", id.height)).into());
+		}
+		let header = self.txhashset_archive_header()?;
+		if header.hash() != h {
+			return Err(ErrorKind::InvalidSegment(format!("not our archive header {}", h)).into());
+		}
+
+		{
+			let segmenter = self.segmenter.read();
+			if let Some((hash, ref txhashset)) = *segmenter {
+				if hash == h {
+					return txhashset.segment(&header, segment_type, id);
+				}
+			}
+		}
+
+		let mut segmenter = self.segmenter.write();
+		// check again, another request may have prepared it in the meantime
+		if let Some((hash, ref txhashset)) = *segmenter {
+			if hash == h {
+				return txhashset.segment(&header, segment_type, id);
+			}
+		}
+		if let Some((_, mut previous)) = segmenter.take() {
+			previous.release_backend_files();
+		}
+
+		// rewind and snapshot the leaf sets as for the txhashset archive,
+		// and take a copy of our txhashset files while they're consistent
+		let segmenter_dir = {
+			let mut header_pmmr = self.header_pmmr.write();
+			let mut txhashset = self.txhashset.write();
+			txhashset::extending_readonly(&mut header_pmmr, &mut txhashset, |ext| {
+				pipe::rewind_and_apply_fork(&header, ext)?;
+				let ref mut extension = ext.extension;
+				extension.snapshot()?;
+				Ok(())
+			})?;
+			txhashset::segmenter_copy(self.db_root.clone(), &header)?
+		};
+		let txhashset = TxHashSet::open(segmenter_dir, self.store.clone(), Some(&header))?;
+		debug!(
+			"txhashset_segment: serving segments at {} / {}",
+			header.height, h
+		);
+
+		let res = txhashset.segment(&header, segment_type, id);
+		*segmenter = Some((h, txhashset));
+		res
+	}
+
+	/// The txhashset segments we still need to download for the provided
+	/// (archive) header. Segments already received, even before a restart,
+	/// are not requested again.
+	pub fn txhashset_segments_missing(
+		&self,
+		h: Hash,
+	) -> Result<Vec<(SegmentType, SegmentIdentifier)>, Error> {
+		let header = self.get_block_header(&h)?;
+		let store = txhashset::SegmentStore::open(&self.get_tmp_dir(), &header)?;
+		Ok(store.missing())
+	}
+
+	/// Number of txhashset segments for the provided (archive) header we saved
+	/// so far, with their size in bytes, and the total number of segments.
+	pub fn txhashset_segments_downloaded(&self, h: Hash) -> Result<(u64, u64, u64), Error> {
+		let header = self.get_block_header(&h)?;
+		let store = txhashset::SegmentStore::open(&self.get_tmp_dir(), &header)?;
+		let (count, bytes) = store.downloaded()?;
+		Ok((count, bytes, store.all_segments().len() as u64))
+	}
+
+	/// The peers which sent us copies of the txhashset segments for the
+	/// provided (archive) header that got disputed, per segment.
+	pub fn txhashset_segments_disputed(
+		&self,
+		h: Hash,
+	) -> Result<HashMap<(SegmentType, u64), Vec<SocketAddr>>, Error> {
+		let header = self.get_block_header(&h)?;
+		let store = txhashset::SegmentStore::open(&self.get_tmp_dir(), &header)?;
+		Ok(store.disputed_senders())
+	}
+
+	/// Validates a txhashset segment received from a peer against the
+	/// provided (archive) header and saves it.
+	pub fn txhashset_segment_write(
+		&self,
+		h: Hash,
+		segment: &TxHashSetSegment,
+		sender: SocketAddr,
+	) -> Result<(), Error> {
+		let header = self.get_block_header(&h)?;
+		let store = txhashset::SegmentStore::open(&self.get_tmp_dir(), &header)?;
+		store.save(segment, sender)
+	}
+
 	// Special handling to make sure the whole kernel set matches each of its
 	// roots in each block header, without truncation. We go back header by
 	// header, rewind and check each root. This fixes a potential weakness in
@@ -948,7 +1227,7 @@ impl Chain {
 		txhashset::clean_txhashset_folder(&sandbox_dir);
 		txhashset::zip_write(sandbox_dir.clone(), txhashset_data.try_clone()?, &header)?;
 
-		let mut txhashset = txhashset::TxHashSet::open(
+		let txhashset = txhashset::TxHashSet::open(
 			sandbox_dir
 				.to_str()
 				.expect("invalid sandbox folder")
@@ -957,8 +1236,86 @@ impl Chain {
 			Some(&header),
 		)?;
 
+		self.txhashset_validate_and_replace(&header, sandbox_dir, txhashset, status)?;
+		Ok(false)
+	}
+
+	/// Writes the txhashset rebuilt from the segments we downloaded for the
+	/// provided (archive) header. If it turns out to be invalid the output
+	/// segments are disputed and need downloading again, otherwise they're
+	/// discarded and we return the peers which sent us disputed copies
+	/// disagreeing with the valid txhashset.
+	pub fn txhashset_write_segments(
+		&self,
+		h: Hash,
+		status: &dyn TxHashsetWriteStatus,
+	) -> Result<Vec<SocketAddr>, Error> {
+		status.on_setup();
+
+		let mut hashes: Option<Vec<Hash>> = None;
+		if !self.check_txhashset_needed("txhashset_write_segments".to_owned(), &mut hashes)? {
+			warn!("txhashset_write_segments: txhashset segments received but not needed!");
+			return Err(ErrorKind::InvalidTxHashSet("not needed".to_owned()).into());
+		}
+
+		let header = self.get_block_header(&h)?;
+		let store = txhashset::SegmentStore::open(&self.get_tmp_dir(), &header)?;
+		if !store.missing().is_empty() {
+			return Err(ErrorKind::InvalidTxHashSet("segments missing".to_owned()).into());
+		}
+
+		// Rebuild the txhashset in the sandbox (in the Gotts specific tmp dir)
+		let sandbox_dir = self.get_tmp_dir();
+		txhashset::clean_txhashset_folder(&sandbox_dir);
+		let mut txhashset = txhashset::TxHashSet::open(
+			sandbox_dir
+				.to_str()
+				.expect("invalid sandbox folder")
+				.to_owned(),
+			self.store.clone(),
+			None,
+		)?;
+		let res = txhashset.rebuild_from_segments(&store).and_then(|_| {
+			self.txhashset_validate_and_replace(&header, sandbox_dir, txhashset, status)
+		});
+		if let Err(e) = res {
+			// only the spent flags of the output segments can be wrong
+			if let Err(e) = store.dispute_outputs() {
+				warn!(
+					"txhashset_write_segments: fail to dispute segments. err: {}",
+					e
+				);
+			}
+			return Err(e);
+		}
+
+		let dishonest = store.dishonest_senders().unwrap_or_else(|e| {
+			warn!(
+				"txhashset_write_segments: fail to check disputed segments. err: {}",
+				e
+			);
+			vec![]
+		});
+		if let Err(e) = store.clear() {
+			warn!(
+				"txhashset_write_segments: fail to clean segments. err: {}",
+				e
+			);
+		}
+		Ok(dishonest)
+	}
+
+	// Validates the txhashset built in the sandbox at the provided header and
+	// replaces ours with it.
+	fn txhashset_validate_and_replace(
+		&self,
+		header: &BlockHeader,
+		sandbox_dir: PathBuf,
+		mut txhashset: txhashset::TxHashSet,
+		status: &dyn TxHashsetWriteStatus,
+	) -> Result<(), Error> {
 		// Validate the full kernel history (kernel MMR root for every block header).
-		self.validate_kernel_history(&header, &txhashset)?;
+		self.validate_kernel_history(header, &txhashset)?;
 
 		// all good, prepare a new batch and update all the required records
 		debug!("txhashset_write: rewinding a 2nd time (writeable)");
@@ -967,7 +1324,7 @@ impl Chain {
 		let mut batch = self.store.batch()?;
 		txhashset::extending(&mut header_pmmr, &mut txhashset, &mut batch, |ext| {
 			let extension = &mut ext.extension;
-			extension.rewind(&header)?;
+			extension.rewind(header)?;
 
 			// Validate the extension, generating the utxo_sum and kernel_sum.
 			// Full validation, including rangeproofs and kernel signature verification.
@@ -991,7 +1348,7 @@ impl Chain {
 
 		// Save the new head to the db and rebuild the header by height index.
 		{
-			let tip = Tip::from_header(&header);
+			let tip = Tip::from_header(header);
 			batch.save_body_head(&tip)?;
 
 			// Reset the body tail to the body head after a txhashset write
@@ -1018,11 +1375,8 @@ impl Chain {
 			txhashset::txhashset_replace(sandbox_dir.clone(), PathBuf::from(self.db_root.clone()))?;
 
 			// Re-open on db root dir
-			txhashset = txhashset::TxHashSet::open(
-				self.db_root.clone(),
-				self.store.clone(),
-				Some(&header),
-			)?;
+			txhashset =
+				txhashset::TxHashSet::open(self.db_root.clone(), self.store.clone(), Some(header))?;
 
 			// Replace the chain txhashset with the newly built one.
 			*txhashset_ref = txhashset;
@@ -1035,7 +1389,7 @@ impl Chain {
 
 		status.on_done();
 
-		Ok(false)
+		Ok(())
 	}
 
 	/// Cleanup old blocks from the db.
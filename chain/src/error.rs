@@ -93,6 +93,16 @@ pub enum ErrorKind {
 	/// Error validating a Merkle proof (coinbase output)
 	#[fail(display = "Error validating merkle proof")]
 	MerkleProof,
+	/// Merkle proof against a header too far behind our header head
+	#[fail(display = "Merkle proof against a header {} blocks old", _0)]
+	StaleProof(u64),
+	/// Block below the cut-through horizon of a pruned node, its chain state
+	/// can't be rewound to
+	#[fail(
+		display = "Block beyond the horizon, only the last {} blocks can be rewound to",
+		_0
+	)]
+	BeyondHorizon(u64),
 	/// Output not found
 	#[fail(display = "Output not found")]
 	OutputNotFound,
@@ -117,6 +127,9 @@ pub enum ErrorKind {
 	/// We've been provided a bad txhashset
 	#[fail(display = "Invalid TxHashSet: {}", _0)]
 	InvalidTxHashSet(String),
+	/// We've been provided a bad txhashset segment
+	#[fail(display = "Invalid Segment: {}", _0)]
+	InvalidSegment(String),
 	/// Internal issue when trying to save or load data from store
 	#[fail(display = "Store Error: {}, reason: {}", _1, _0)]
 	StoreErr(store::Error, String),
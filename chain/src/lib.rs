@@ -43,9 +43,11 @@ pub mod types;
 
 // Re-export the base interface
 
-pub use crate::chain::{Chain, MAX_ORPHAN_SIZE};
+pub use crate::chain::{Chain, MAX_ORPHAN_SIZE, MAX_PROOF_AGE};
 pub use crate::error::{Error, ErrorKind};
 pub use crate::store::ChainStore;
 pub use crate::types::{
-	BlockStatus, ChainAdapter, Options, SyncState, SyncStatus, Tip, TxHashsetWriteStatus,
+	BlockStatus, ChainAdapter, KernelProof, LatencyHistogram, Options, OutputProof, SegmentType,
+	SupplyAudit, SyncState, SyncStatus, Tip, TxHashSetSegment, TxHashsetWriteStatus, UtxoStats,
+	BLOCK_LATENCY_BUCKETS, TXHASHSET_SEGMENT_HEIGHT,
 };
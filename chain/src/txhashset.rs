@@ -17,9 +17,11 @@
 //! kernel) more conveniently and transactionally.
 
 mod rewindable_kernel_view;
+mod segments;
 mod txhashset;
 mod utxo_view;
 
 pub use self::rewindable_kernel_view::*;
+pub use self::segments::*;
 pub use self::txhashset::*;
 pub use self::utxo_view::*;
@@ -0,0 +1,269 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Storage of the txhashset segments downloaded during state sync. Each
+//! segment is validated against the archive header roots and saved in its own
+//! file as soon as it's received, so a download interrupted by a restart
+//! resumes with the segments we're still missing.
+//!
+//! The header roots don't commit to the leaf set, so the spent flags of the
+//! output segments are only checked once the whole txhashset is validated.
+//! We keep track of the peer each segment came from: when that validation
+//! fails the output segments are set aside as disputed and downloaded again,
+//! from other peers, and once the txhashset validates the peers whose copies
+//! disagree with it are known to have lied.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use crate::core::core::hash::Hashed;
+use crate::core::core::pmmr::{Segment, SegmentIdentifier};
+use crate::core::core::{BlockHeader, OutputI, OutputII};
+use crate::core::ser::{self, Readable};
+use crate::error::{Error, ErrorKind};
+use crate::types::{SegmentType, TxHashSetSegment, TXHASHSET_SEGMENT_HEIGHT};
+
+const SEGMENTS_SUBDIR: &str = "txhashset_segments";
+const DISPUTED_SUBDIR: &str = "disputed";
+
+/// Segments of the txhashset at a given (archive) header, saved on disk.
+pub struct SegmentStore {
+	dir: PathBuf,
+	header: BlockHeader,
+}
+
+impl SegmentStore {
+	/// Opens the segment store for the provided header under root_dir,
+	/// creating it if necessary. Segments saved for any other header are
+	/// discarded, they can't be used anymore.
+	pub fn open(root_dir: &Path, header: &BlockHeader) -> Result<SegmentStore, Error> {
+		let parent = root_dir.join(SEGMENTS_SUBDIR);
+		let dir = parent.join(header.hash().to_hex());
+		if parent.exists() {
+			for entry in fs::read_dir(&parent)? {
+				let path = entry?.path();
+				if path != dir {
+					debug!("segment_store: removing stale segments {:?}", path);
+					let _ = fs::remove_dir_all(path);
+				}
+			}
+		}
+		fs::create_dir_all(&dir)?;
+		Ok(SegmentStore {
+			dir,
+			header: header.clone(),
+		})
+	}
+
+	/// The header the segments are for.
+	pub fn header(&self) -> &BlockHeader {
+		&self.header
+	}
+
+	fn path(&self, segment_type: SegmentType, idx: u64) -> PathBuf {
+		self.dir.join(format!("{:?}_{}.seg", segment_type, idx))
+	}
+
+	// Path of the nth disputed copy of a segment.
+	fn disputed_path(&self, segment_type: SegmentType, idx: u64, n: usize) -> PathBuf {
+		self.dir
+			.join(DISPUTED_SUBDIR)
+			.join(format!("{:?}_{}_{}.seg", segment_type, idx, n))
+	}
+
+	// The disputed copies of a segment we kept.
+	fn disputed_paths(&self, segment_type: SegmentType, idx: u64) -> Vec<PathBuf> {
+		(0..)
+			.map(|n| self.disputed_path(segment_type, idx, n))
+			.take_while(|path| path.exists())
+			.collect()
+	}
+
+	// The peer a segment file was received from, if we know it.
+	fn sender_of(path: &Path) -> Option<SocketAddr> {
+		fs::read_to_string(path.with_extension("peer"))
+			.ok()
+			.and_then(|addr| addr.trim().parse().ok())
+	}
+
+	/// All the segments making up the txhashset at our header.
+	pub fn all_segments(&self) -> Vec<(SegmentType, SegmentIdentifier)> {
+		SegmentType::all()
+			.into_iter()
+			.flat_map(|t| {
+				SegmentIdentifier::all_segments(t.mmr_size(&self.header), TXHASHSET_SEGMENT_HEIGHT)
+					.into_iter()
+					.map(move |id| (t, id))
+			})
+			.collect()
+	}
+
+	/// The segments we still need to download.
+	pub fn missing(&self) -> Vec<(SegmentType, SegmentIdentifier)> {
+		self.all_segments()
+			.into_iter()
+			.filter(|(t, id)| !self.contains(*t, id))
+			.collect()
+	}
+
+	/// Number of segments we saved and their total size in bytes.
+	pub fn downloaded(&self) -> Result<(u64, u64), Error> {
+		let mut count = 0;
+		let mut bytes = 0;
+		for entry in fs::read_dir(&self.dir)? {
+			let entry = entry?;
+			if entry
+				.path()
+				.extension()
+				.map(|x| x == "seg")
+				.unwrap_or(false)
+			{
+				count += 1;
+				bytes += entry.metadata()?.len();
+			}
+		}
+		Ok((count, bytes))
+	}
+
+	/// Do we have this segment already?
+	pub fn contains(&self, segment_type: SegmentType, id: &SegmentIdentifier) -> bool {
+		self.path(segment_type, id.idx).exists()
+	}
+
+	/// Validates the segment against our header and saves it, along with the
+	/// peer we received it from.
+	pub fn save(&self, segment: &TxHashSetSegment, sender: SocketAddr) -> Result<(), Error> {
+		let id = segment.identifier();
+		if id.height != TXHASHSET_SEGMENT_HEIGHT {
+			return Err(ErrorKind::InvalidSegment(format!("height {}", id.height)).into());
+		}
+		segment
+			.validate(&self.header)
+			.map_err(|e| ErrorKind::InvalidSegment(format!("{:?}", e)))?;
+
+		// write to a temp file first, a segment file is always complete
+		let path = self.path(segment.segment_type(), id.idx);
+		let tmp_path = path.with_extension("tmp");
+		{
+			let mut writer = BufWriter::new(File::create(&tmp_path)?);
+			match segment {
+				TxHashSetSegment::OutputI(s) => ser::serialize_default(&mut writer, s),
+				TxHashSetSegment::OutputII(s) => ser::serialize_default(&mut writer, s),
+				TxHashSetSegment::Kernel(s) => ser::serialize_default(&mut writer, s),
+			}
+			.map_err(|e| ErrorKind::SerErr(e))?;
+			writer.flush()?;
+			writer.get_ref().sync_all()?;
+		}
+		fs::write(path.with_extension("peer"), sender.to_string())?;
+		fs::rename(tmp_path, path)?;
+		Ok(())
+	}
+
+	/// Loads a segment we saved.
+	pub fn load<T: Readable>(
+		&self,
+		segment_type: SegmentType,
+		idx: u64,
+	) -> Result<Segment<T>, Error> {
+		let file = File::open(self.path(segment_type, idx))?;
+		let segment = ser::deserialize_default(&mut BufReader::new(file))
+			.map_err(|e| ErrorKind::SerErr(e))?;
+		Ok(segment)
+	}
+
+	/// Sets the output segments aside as disputed, after the txhashset
+	/// rebuilt from them failed validation, so they're downloaded again. The
+	/// kernel segments are fully proven by the kernel root and kept.
+	pub fn dispute_outputs(&self) -> Result<(), Error> {
+		fs::create_dir_all(self.dir.join(DISPUTED_SUBDIR))?;
+		for (segment_type, id) in self.all_segments() {
+			if segment_type == SegmentType::Kernel || !self.contains(segment_type, &id) {
+				continue;
+			}
+			let path = self.path(segment_type, id.idx);
+			let n = self.disputed_paths(segment_type, id.idx).len();
+			let disputed = self.disputed_path(segment_type, id.idx, n);
+			if path.with_extension("peer").exists() {
+				fs::rename(path.with_extension("peer"), disputed.with_extension("peer"))?;
+			}
+			fs::rename(path, disputed)?;
+		}
+		Ok(())
+	}
+
+	/// The peers we received disputed copies of each segment from, better
+	/// not asked for that segment again.
+	pub fn disputed_senders(&self) -> HashMap<(SegmentType, u64), Vec<SocketAddr>> {
+		let mut senders = HashMap::new();
+		for (segment_type, id) in self.all_segments() {
+			let addrs: Vec<_> = self
+				.disputed_paths(segment_type, id.idx)
+				.iter()
+				.filter_map(|path| SegmentStore::sender_of(path))
+				.collect();
+			if !addrs.is_empty() {
+				senders.insert((segment_type, id.idx), addrs);
+			}
+		}
+		senders
+	}
+
+	/// Once the txhashset rebuilt from our segments is validated, the peers
+	/// which sent us disputed copies of a segment with a different leaf set.
+	pub fn dishonest_senders(&self) -> Result<Vec<SocketAddr>, Error> {
+		let mut senders = vec![];
+		for (segment_type, id) in self.all_segments() {
+			let disputed = self.disputed_paths(segment_type, id.idx);
+			if disputed.is_empty() {
+				continue;
+			}
+			let unspent = self.load_unspent(segment_type, &self.path(segment_type, id.idx))?;
+			for path in disputed {
+				if self.load_unspent(segment_type, &path)? != unspent {
+					if let Some(addr) = SegmentStore::sender_of(&path) {
+						if !senders.contains(&addr) {
+							senders.push(addr);
+						}
+					}
+				}
+			}
+		}
+		Ok(senders)
+	}
+
+	// Unspent leaves of the output segment saved in this file.
+	fn load_unspent(&self, segment_type: SegmentType, path: &Path) -> Result<Vec<u64>, Error> {
+		let mut reader = BufReader::new(File::open(path)?);
+		let unspent = match segment_type {
+			SegmentType::OutputI => {
+				ser::deserialize_default::<Segment<OutputI>>(&mut reader).map(|s| s.unspent())
+			}
+			SegmentType::OutputII => {
+				ser::deserialize_default::<Segment<OutputII>>(&mut reader).map(|s| s.unspent())
+			}
+			SegmentType::Kernel => Ok(vec![]),
+		};
+		unspent.map_err(|e| ErrorKind::SerErr(e).into())
+	}
+
+	/// Removes all the segments, once the txhashset is rebuilt.
+	pub fn clear(self) -> Result<(), Error> {
+		fs::remove_dir_all(&self.dir)?;
+		Ok(())
+	}
+}
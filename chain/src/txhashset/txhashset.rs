@@ -19,7 +19,9 @@
 use crate::core::core::committed::Committed;
 use crate::core::core::hash::{Hash, Hashed};
 use crate::core::core::merkle_proof::MerkleProof;
-use crate::core::core::pmmr::{self, Backend, ReadonlyPMMR, RewindablePMMR, PMMR};
+use crate::core::core::pmmr::{
+	self, Backend, ReadonlyPMMR, RewindablePMMR, Segment, SegmentIdentifier, PMMR,
+};
 use crate::core::core::{
 	Block, BlockHeader, Input, Output, OutputFeatures, OutputI, OutputII, OutputIdentifier,
 	TxKernel,
@@ -27,9 +29,10 @@ use crate::core::core::{
 use crate::core::ser::{PMMRIndexHashable, PMMRable};
 use crate::error::{Error, ErrorKind};
 use crate::store::{Batch, ChainStore};
-use crate::txhashset::{RewindableKernelView, UTXOView};
+use crate::txhashset::{RewindableKernelView, SegmentStore, UTXOView};
 use crate::types::{
-	OutputFeaturePosHeight, OutputMMRPosition, Tip, TxHashSetRoots, TxHashsetWriteStatus,
+	OutputFeaturePosHeight, OutputMMRPosition, SegmentType, Tip, TxHashSetRoots, TxHashSetSegment,
+	TxHashsetWriteStatus, UtxoStats, TXHASHSET_SEGMENT_HEIGHT,
 };
 use crate::util::secp::pedersen::Commitment;
 use crate::util::{file, secp_static, zip};
@@ -49,6 +52,8 @@ const KERNEL_SUBDIR: &'static str = "kernel";
 
 const TXHASHSET_ZIP: &'static str = "txhashset_snapshot";
 
+const SEGMENTER_SUBDIR: &'static str = "txhashset_segmenter";
+
 /// Convenience wrapper around a single prunable MMR backend.
 pub struct PMMRHandle<T: PMMRable> {
 	/// The backend storage for the MMR.
@@ -328,6 +333,87 @@ impl TxHashSet {
 		}
 	}
 
+	/// Build a segment of one of our MMRs as of the provided header. The MMRs
+	/// must not have been rewound beyond that header.
+	pub fn segment(
+		&self,
+		header: &BlockHeader,
+		segment_type: SegmentType,
+		id: SegmentIdentifier,
+	) -> Result<TxHashSetSegment, Error> {
+		let res = match segment_type {
+			SegmentType::OutputI => {
+				let pmmr =
+					ReadonlyPMMR::at(&self.output_i_pmmr_h.backend, header.output_i_mmr_size);
+				Segment::from_pmmr(id, &pmmr, true).map(TxHashSetSegment::OutputI)
+			}
+			SegmentType::OutputII => {
+				let pmmr =
+					ReadonlyPMMR::at(&self.output_ii_pmmr_h.backend, header.output_ii_mmr_size);
+				Segment::from_pmmr(id, &pmmr, true).map(TxHashSetSegment::OutputII)
+			}
+			SegmentType::Kernel => {
+				let pmmr = ReadonlyPMMR::at(&self.kernel_pmmr_h.backend, header.kernel_mmr_size);
+				Segment::from_pmmr(id, &pmmr, false).map(TxHashSetSegment::Kernel)
+			}
+		};
+		res.map_err(|e| ErrorKind::InvalidSegment(format!("{:?}", e)).into())
+	}
+
+	/// Rebuild our (empty) MMRs from the segments in the store, checking the
+	/// resulting roots against the store header.
+	pub fn rebuild_from_segments(&mut self, store: &SegmentStore) -> Result<(), Error> {
+		let header = store.header().clone();
+		let height = TXHASHSET_SEGMENT_HEIGHT;
+
+		let root = self
+			.output_i_pmmr_h
+			.backend
+			.rebuild_from_segments(header.output_i_mmr_size, height, |idx| {
+				store
+					.load(SegmentType::OutputI, idx)
+					.map_err(|e| e.to_string())
+			})
+			.map_err(|e| ErrorKind::TxHashSetErr(e))?;
+		if root != header.output_i_root {
+			return Err(ErrorKind::InvalidRoot.into());
+		}
+
+		let root = self
+			.output_ii_pmmr_h
+			.backend
+			.rebuild_from_segments(header.output_ii_mmr_size, height, |idx| {
+				store
+					.load(SegmentType::OutputII, idx)
+					.map_err(|e| e.to_string())
+			})
+			.map_err(|e| ErrorKind::TxHashSetErr(e))?;
+		if root != header.output_ii_root {
+			return Err(ErrorKind::InvalidRoot.into());
+		}
+
+		let root = self
+			.kernel_pmmr_h
+			.backend
+			.rebuild_from_segments(header.kernel_mmr_size, height, |idx| {
+				store
+					.load(SegmentType::Kernel, idx)
+					.map_err(|e| e.to_string())
+			})
+			.map_err(|e| ErrorKind::TxHashSetErr(e))?;
+		if root != header.kernel_root {
+			return Err(ErrorKind::InvalidRoot.into());
+		}
+
+		self.output_i_pmmr_h.backend.sync_rebuilt()?;
+		self.output_ii_pmmr_h.backend.sync_rebuilt()?;
+		self.kernel_pmmr_h.backend.sync_rebuilt()?;
+		self.output_i_pmmr_h.last_pos = header.output_i_mmr_size;
+		self.output_ii_pmmr_h.last_pos = header.output_ii_mmr_size;
+		self.kernel_pmmr_h.last_pos = header.kernel_mmr_size;
+		Ok(())
+	}
+
 	/// Return Commit's MMR position and block height
 	pub fn get_output_pos_height(
 		&self,
@@ -355,6 +441,13 @@ impl TxHashSet {
 		}
 	}
 
+	/// build a new merkle proof for the kernel at the given position.
+	pub fn kernel_merkle_proof(&mut self, pos: u64) -> Result<MerkleProof, Error> {
+		PMMR::at(&mut self.kernel_pmmr_h.backend, self.kernel_pmmr_h.last_pos)
+			.merkle_proof(pos)
+			.map_err(|_| ErrorKind::MerkleProof.into())
+	}
+
 	/// Compact the MMR data files and flush the rm logs
 	pub fn compact(
 		&mut self,
@@ -1266,6 +1359,22 @@ impl<'a> Extension<'a> {
 		Ok(())
 	}
 
+	/// Total value and number (by features) of the unspent outputs.
+	pub fn utxo_stats(&self) -> UtxoStats {
+		let mut stats = UtxoStats::default();
+		for pos in self.output_i_pmmr.leaf_pos_iter() {
+			if let Some(out) = self.output_i_pmmr.get_data(pos) {
+				stats.add(out.id.features, out.value);
+			}
+		}
+		for pos in self.output_ii_pmmr.leaf_pos_iter() {
+			if let Some(out) = self.output_ii_pmmr.get_data(pos) {
+				stats.add(out.id.features, out.value);
+			}
+		}
+		stats
+	}
+
 	/// Validate full kernel sums against the provided header (for overage and kernel_offset).
 	/// This is an expensive operation as we need to retrieve all the UTXOs and kernels
 	/// from the respective MMRs.
@@ -1462,6 +1571,28 @@ pub fn zip_read(root_dir: String, header: &BlockHeader) -> Result<File, Error> {
 	Ok(zip_file)
 }
 
+/// Copies the txhashset files (including the leaf_set snapshots) in a
+/// dedicated directory, from which we serve the segments of the txhashset at
+/// the provided header. Returns the root dir of the copy.
+pub fn segmenter_copy(root_dir: String, header: &BlockHeader) -> Result<String, Error> {
+	let txhashset_path = Path::new(&root_dir).join(TXHASHSET_SUBDIR);
+	let segmenter_path = Path::new(&root_dir).join(SEGMENTER_SUBDIR);
+	if segmenter_path.exists() {
+		fs::remove_dir_all(&segmenter_path)?;
+	}
+	fs::create_dir_all(&segmenter_path)?;
+	file::copy_dir_to(&txhashset_path, &segmenter_path.join(TXHASHSET_SUBDIR))?;
+	debug!(
+		"segmenter_copy: txhashset copied for {} at {}",
+		header.hash(),
+		header.height
+	);
+	segmenter_path
+		.to_str()
+		.map(|s| s.to_owned())
+		.ok_or_else(|| ErrorKind::Other("invalid file path".to_owned()).into())
+}
+
 // Explicit list of files to extract from our zip archive.
 // We include *only* these files when building the txhashset zip.
 // We extract *only* these files when receiving a txhashset zip.
@@ -17,12 +17,18 @@
 
 use chrono::prelude::{DateTime, Utc};
 use std::sync::Arc;
+use std::time::Duration;
 
 use crate::core::core::hash::{Hash, Hashed, ZERO_HASH};
-use crate::core::core::{Block, BlockHeader, OutputFeatures};
+use crate::core::core::merkle_proof::MerkleProof;
+use crate::core::core::pmmr::{Segment, SegmentError, SegmentIdentifier};
+use crate::core::core::{
+	Block, BlockHeader, BlockSums, Output, OutputFeatures, OutputI, OutputII, TxKernel,
+};
 use crate::core::pow::Difficulty;
 use crate::core::ser::{self, Readable, Reader, Writeable, Writer};
-use crate::error::Error;
+use crate::error::{Error, ErrorKind};
+use crate::util::secp::pedersen::Commitment;
 use crate::util::RwLock;
 
 bitflags! {
@@ -89,6 +95,7 @@ pub enum SyncStatus {
 pub struct SyncState {
 	current: RwLock<SyncStatus>,
 	sync_error: Arc<RwLock<Option<Error>>>,
+	segments_hash: RwLock<Option<Hash>>,
 }
 
 impl SyncState {
@@ -97,6 +104,7 @@ impl SyncState {
 		SyncState {
 			current: RwLock::new(SyncStatus::Initial),
 			sync_error: Arc::new(RwLock::new(None)),
+			segments_hash: RwLock::new(None),
 		}
 	}
 
@@ -149,6 +157,16 @@ impl SyncState {
 	pub fn clear_sync_error(&self) {
 		*self.sync_error.write() = None;
 	}
+
+	/// Hash of the header we're downloading txhashset segments for, if any.
+	pub fn txhashset_segments_hash(&self) -> Option<Hash> {
+		*self.segments_hash.read()
+	}
+
+	/// Sets (or resets) the header we're downloading txhashset segments for.
+	pub fn set_txhashset_segments_hash(&self, hash: Option<Hash>) {
+		*self.segments_hash.write() = hash;
+	}
 }
 
 impl TxHashsetWriteStatus for SyncState {
@@ -216,6 +234,253 @@ pub struct TxHashSetRoots {
 	pub kernel_root: Hash,
 }
 
+/// Height of the txhashset segments exchanged for state sync, each segment
+/// holds up to 2^9 = 512 leaves.
+pub const TXHASHSET_SEGMENT_HEIGHT: u8 = 9;
+
+/// The txhashset MMRs which can be downloaded in segments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SegmentType {
+	/// Output I MMR
+	OutputI = 0,
+	/// Output II MMR
+	OutputII = 1,
+	/// Kernel MMR
+	Kernel = 2,
+}
+
+impl SegmentType {
+	/// All the segment types, in the order we rebuild the txhashset.
+	pub fn all() -> Vec<SegmentType> {
+		vec![
+			SegmentType::OutputI,
+			SegmentType::OutputII,
+			SegmentType::Kernel,
+		]
+	}
+
+	/// Size of the corresponding MMR at this header.
+	pub fn mmr_size(&self, header: &BlockHeader) -> u64 {
+		match self {
+			SegmentType::OutputI => header.output_i_mmr_size,
+			SegmentType::OutputII => header.output_ii_mmr_size,
+			SegmentType::Kernel => header.kernel_mmr_size,
+		}
+	}
+
+	/// Root of the corresponding MMR at this header.
+	pub fn root(&self, header: &BlockHeader) -> Hash {
+		match self {
+			SegmentType::OutputI => header.output_i_root,
+			SegmentType::OutputII => header.output_ii_root,
+			SegmentType::Kernel => header.kernel_root,
+		}
+	}
+}
+
+impl Writeable for SegmentType {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u8(*self as u8)
+	}
+}
+
+impl Readable for SegmentType {
+	fn read(reader: &mut dyn Reader) -> Result<SegmentType, ser::Error> {
+		match reader.read_u8()? {
+			0 => Ok(SegmentType::OutputI),
+			1 => Ok(SegmentType::OutputII),
+			2 => Ok(SegmentType::Kernel),
+			_ => Err(ser::Error::CorruptedData),
+		}
+	}
+}
+
+/// A segment of one of the txhashset MMRs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TxHashSetSegment {
+	/// Output I MMR segment
+	OutputI(Segment<OutputI>),
+	/// Output II MMR segment
+	OutputII(Segment<OutputII>),
+	/// Kernel MMR segment
+	Kernel(Segment<TxKernel>),
+}
+
+impl TxHashSetSegment {
+	/// The MMR this segment is from.
+	pub fn segment_type(&self) -> SegmentType {
+		match self {
+			TxHashSetSegment::OutputI(_) => SegmentType::OutputI,
+			TxHashSetSegment::OutputII(_) => SegmentType::OutputII,
+			TxHashSetSegment::Kernel(_) => SegmentType::Kernel,
+		}
+	}
+
+	/// Identifier of the segment within its MMR.
+	pub fn identifier(&self) -> SegmentIdentifier {
+		match self {
+			TxHashSetSegment::OutputI(s) => s.identifier,
+			TxHashSetSegment::OutputII(s) => s.identifier,
+			TxHashSetSegment::Kernel(s) => s.identifier,
+		}
+	}
+
+	/// Validates the segment against the corresponding root of the header.
+	pub fn validate(&self, header: &BlockHeader) -> Result<(), SegmentError> {
+		let segment_type = self.segment_type();
+		let mmr_size = segment_type.mmr_size(header);
+		let root = segment_type.root(header);
+		match self {
+			TxHashSetSegment::OutputI(s) => s.validate(mmr_size, root),
+			TxHashSetSegment::OutputII(s) => s.validate(mmr_size, root),
+			TxHashSetSegment::Kernel(s) => {
+				// the kernel MMR is never pruned
+				if let Some((pos, _)) = s.hashes.first() {
+					return Err(SegmentError::UnexpectedNode(*pos));
+				}
+				if let Some(pos) = s.spent.first() {
+					return Err(SegmentError::UnexpectedNode(*pos));
+				}
+				s.validate(mmr_size, root)
+			}
+		}
+	}
+}
+
+impl Writeable for TxHashSetSegment {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.segment_type().write(writer)?;
+		match self {
+			TxHashSetSegment::OutputI(s) => s.write(writer),
+			TxHashSetSegment::OutputII(s) => s.write(writer),
+			TxHashSetSegment::Kernel(s) => s.write(writer),
+		}
+	}
+}
+
+impl Readable for TxHashSetSegment {
+	fn read(reader: &mut dyn Reader) -> Result<TxHashSetSegment, ser::Error> {
+		match SegmentType::read(reader)? {
+			SegmentType::OutputI => Ok(TxHashSetSegment::OutputI(Segment::read(reader)?)),
+			SegmentType::OutputII => Ok(TxHashSetSegment::OutputII(Segment::read(reader)?)),
+			SegmentType::Kernel => Ok(TxHashSetSegment::Kernel(Segment::read(reader)?)),
+		}
+	}
+}
+
+/// An unspent output with a merkle proof of its inclusion in the output MMR
+/// at a given block header, for light nodes not having the UTXO set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputProof {
+	/// The unspent output
+	pub output: Output,
+	/// Height of the block which contains the output
+	pub height: u64,
+	/// MMR position of the output
+	pub mmr_index: u64,
+	/// Hash of the block header the proof is against
+	pub header_hash: Hash,
+	/// Merkle proof of the output against the output root of the header
+	pub proof: MerkleProof,
+}
+
+impl OutputProof {
+	/// Checks the proof against the (output i or output ii) root of the header
+	/// it was built for.
+	pub fn verify(&self, header: &BlockHeader) -> Result<(), Error> {
+		let (root, mmr_size) = match self.output.features.as_flag() {
+			OutputFeatures::Plain | OutputFeatures::Coinbase => {
+				(header.output_i_root, header.output_i_mmr_size)
+			}
+			OutputFeatures::SigLocked => (header.output_ii_root, header.output_ii_mmr_size),
+		};
+		if header.hash() != self.header_hash
+			|| self.proof.mmr_size != mmr_size
+			|| self.height > header.height
+		{
+			return Err(ErrorKind::MerkleProof.into());
+		}
+		self.proof
+			.verify(root, &self.output, self.mmr_index)
+			.map_err(|_| ErrorKind::MerkleProof.into())
+	}
+}
+
+impl Writeable for OutputProof {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.output.write(writer)?;
+		writer.write_u64(self.height)?;
+		writer.write_u64(self.mmr_index)?;
+		self.header_hash.write(writer)?;
+		self.proof.write(writer)
+	}
+}
+
+impl Readable for OutputProof {
+	fn read(reader: &mut dyn Reader) -> Result<OutputProof, ser::Error> {
+		Ok(OutputProof {
+			output: Output::read(reader)?,
+			height: reader.read_u64()?,
+			mmr_index: reader.read_u64()?,
+			header_hash: Hash::read(reader)?,
+			proof: MerkleProof::read(reader)?,
+		})
+	}
+}
+
+/// A tx kernel with a merkle proof of its inclusion in the kernel MMR at a
+/// given block header, for light nodes not having the kernel set.
+#[derive(Debug, Clone)]
+pub struct KernelProof {
+	/// The tx kernel
+	pub kernel: TxKernel,
+	/// Height of the block which contains the kernel
+	pub height: u64,
+	/// MMR position of the kernel
+	pub mmr_index: u64,
+	/// Hash of the block header the proof is against
+	pub header_hash: Hash,
+	/// Merkle proof of the kernel against the kernel root of the header
+	pub proof: MerkleProof,
+}
+
+impl KernelProof {
+	/// Checks the proof against the kernel root of the header it was built for.
+	pub fn verify(&self, header: &BlockHeader) -> Result<(), Error> {
+		if header.hash() != self.header_hash
+			|| self.proof.mmr_size != header.kernel_mmr_size
+			|| self.height > header.height
+		{
+			return Err(ErrorKind::MerkleProof.into());
+		}
+		self.proof
+			.verify(header.kernel_root, &self.kernel, self.mmr_index)
+			.map_err(|_| ErrorKind::MerkleProof.into())
+	}
+}
+
+impl Writeable for KernelProof {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.kernel.write(writer)?;
+		writer.write_u64(self.height)?;
+		writer.write_u64(self.mmr_index)?;
+		self.header_hash.write(writer)?;
+		self.proof.write(writer)
+	}
+}
+
+impl Readable for KernelProof {
+	fn read(reader: &mut dyn Reader) -> Result<KernelProof, ser::Error> {
+		Ok(KernelProof {
+			kernel: TxKernel::read(reader)?,
+			height: reader.read_u64()?,
+			mmr_index: reader.read_u64()?,
+			header_hash: Hash::read(reader)?,
+			proof: MerkleProof::read(reader)?,
+		})
+	}
+}
+
 /// A helper to hold the output pmmr position of the txhashset in order to keep them
 /// readable.
 #[derive(Debug)]
@@ -387,6 +652,104 @@ impl ChainAdapter for NoopAdapter {
 	fn block_accepted(&self, _b: &Block, _status: BlockStatus, _opts: Options) {}
 }
 
+/// Upper bounds (in seconds) of the buckets of the block processing latency
+/// histogram.
+pub const BLOCK_LATENCY_BUCKETS: [f64; 10] =
+	[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 10.0];
+
+/// Unspent outputs of the chain state at a block.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UtxoStats {
+	/// Total explicit value of the unspent outputs.
+	pub value: u64,
+	/// Number of plain outputs.
+	pub plain: u64,
+	/// Number of coinbase outputs.
+	pub coinbase: u64,
+	/// Number of SigLocked outputs.
+	pub sig_locked: u64,
+}
+
+impl UtxoStats {
+	/// Counts an unspent output.
+	pub fn add(&mut self, features: OutputFeatures, value: u64) {
+		self.value = self.value.saturating_add(value);
+		match features {
+			OutputFeatures::Plain => self.plain += 1,
+			OutputFeatures::Coinbase => self.coinbase += 1,
+			OutputFeatures::SigLocked => self.sig_locked += 1,
+		}
+	}
+}
+
+/// Supply audit of the chain state at a block, see `Chain::supply_audit`.
+#[derive(Debug, Clone)]
+pub struct SupplyAudit {
+	/// The block audited.
+	pub header: BlockHeader,
+	/// The unspent outputs at the block.
+	pub utxos: UtxoStats,
+	/// Coinbase issued up to the block, the block rewards net of the fees
+	/// (which only move value around).
+	pub issued: u64,
+	/// Sums of the unspent outputs and of the kernels, or why they don't match.
+	pub kernel_sums: Result<(Commitment, Commitment), String>,
+	/// Sums stored along the block when it was processed.
+	pub block_sums: BlockSums,
+}
+
+impl SupplyAudit {
+	/// Whether the unspent outputs hold exactly the coinbase issued.
+	pub fn supply_matches(&self) -> bool {
+		self.utxos.value == self.issued
+	}
+
+	/// Whether the sums of the outputs and kernels match, and match the block
+	/// sums.
+	pub fn kernel_sums_valid(&self) -> bool {
+		match self.kernel_sums {
+			Ok((utxo_sum, kernel_sum)) => {
+				utxo_sum == self.block_sums.utxo_sum && kernel_sum == self.block_sums.kernel_sum
+			}
+			Err(_) => false,
+		}
+	}
+}
+
+/// Histogram of the time taken to process blocks, cumulative since startup,
+/// in the shape Prometheus expects: each bucket counts the blocks processed
+/// within its upper bound.
+#[derive(Debug, Clone)]
+pub struct LatencyHistogram {
+	/// Upper bound (in seconds) and count of each bucket
+	pub buckets: Vec<(f64, u64)>,
+	/// Number of blocks processed
+	pub count: u64,
+	/// Total time spent processing them, in seconds
+	pub sum: f64,
+}
+
+impl LatencyHistogram {
+	/// An empty histogram over the provided bucket bounds.
+	pub fn new(bounds: &[f64]) -> LatencyHistogram {
+		LatencyHistogram {
+			buckets: bounds.iter().map(|b| (*b, 0)).collect(),
+			count: 0,
+			sum: 0.0,
+		}
+	}
+
+	/// Records a single measure.
+	pub fn observe(&mut self, elapsed: Duration) {
+		let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+		for bucket in self.buckets.iter_mut().filter(|b| secs <= b.0) {
+			bucket.1 += 1;
+		}
+		self.count += 1;
+		self.sum += secs;
+	}
+}
+
 /// Status of an accepted block.
 #[derive(Debug, Clone, PartialEq)]
 pub enum BlockStatus {
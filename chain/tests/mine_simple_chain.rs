@@ -87,6 +87,40 @@ fn mine_short_chain() {
 	clean_output_dir(chain_dir);
 	let chain = mine_chain(chain_dir, 4);
 	assert_eq!(chain.head().unwrap().height, 3);
+
+	// The unspent outputs hold the coinbase issued, at the head and earlier.
+	for height in &[1, 3] {
+		let header = chain.get_header_by_height(*height).unwrap();
+		let audit = chain.supply_audit(&header).unwrap();
+		assert!(audit.supply_matches());
+		assert!(audit.kernel_sums_valid());
+		assert_eq!(audit.utxos.plain + audit.utxos.sig_locked, 0);
+	}
+	clean_output_dir(chain_dir);
+}
+
+#[test]
+fn supply_audit_horizon() {
+	let chain_dir = ".gotts.supply_audit_horizon";
+	clean_output_dir(chain_dir);
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let horizon = global::cut_through_horizon() as u64;
+	let chain = mine_chain(chain_dir, horizon + 4);
+	let head = chain.head().unwrap();
+
+	// the last audit is kept for further requests
+	let header = chain.get_header_by_height(head.height - horizon).unwrap();
+	let audit = chain.supply_audit(&header).unwrap();
+	let again = chain.supply_audit(&header).unwrap();
+	assert_eq!(again.header.hash(), audit.header.hash());
+	assert_eq!(again.utxos.value, audit.utxos.value);
+
+	// the chain state can't be rewound below the horizon on a pruned node
+	let header = chain
+		.get_header_by_height(head.height - horizon - 1)
+		.unwrap();
+	let e = chain.supply_audit(&header).unwrap_err();
+	assert_eq!(e.kind(), chain::ErrorKind::BeyondHorizon(horizon));
 	clean_output_dir(chain_dir);
 }
 
@@ -807,6 +841,54 @@ fn output_header_mappings() {
 	clean_output_dir(".gotts_header_for_output");
 }
 
+/// Test the outputs and kernels merkle proofs served to light nodes
+#[test]
+fn output_and_kernel_proofs() {
+	let chain_dir = ".gotts.merkle_proofs";
+	clean_output_dir(chain_dir);
+	{
+		let chain = mine_chain(chain_dir, 6);
+		let head = chain.head().unwrap();
+		let block = chain.get_block(&head.last_block_h).unwrap();
+
+		let commit = block.outputs()[0].commit;
+		let mut proof = chain.get_output_proof(&commit).unwrap();
+		assert_eq!(proof.output.commit, commit);
+		assert_eq!(proof.height, head.height);
+		assert_eq!(proof.header_hash, head.last_block_h);
+		let header = chain.verify_output_proof(&proof).unwrap();
+		assert_eq!(header.height, head.height);
+
+		// a proof for another position doesn't verify
+		proof.mmr_index += 1;
+		assert!(chain.verify_output_proof(&proof).is_err());
+
+		let excess = block.kernels()[0].excess;
+		let mut proof = chain.get_kernel_proof(&excess).unwrap();
+		assert_eq!(proof.kernel.excess, excess);
+		assert_eq!(proof.height, head.height);
+		chain.verify_kernel_proof(&proof).unwrap();
+
+		// the proofs are against the header they were built for
+		let prev = chain.get_previous_header(&block.header).unwrap();
+		proof.header_hash = prev.hash();
+		let e = chain.verify_kernel_proof(&proof).unwrap_err();
+		assert_eq!(e.kind(), chain::ErrorKind::MerkleProof);
+
+		// and not too far behind our head, an output may have been spent since
+		let old = chain
+			.get_header_by_height(head.height - chain::MAX_PROOF_AGE - 1)
+			.unwrap();
+		proof.header_hash = old.hash();
+		let e = chain.verify_kernel_proof(&proof).unwrap_err();
+		assert_eq!(
+			e.kind(),
+			chain::ErrorKind::StaleProof(chain::MAX_PROOF_AGE + 1)
+		);
+	}
+	clean_output_dir(chain_dir);
+}
+
 fn prepare_block<K>(kc: &K, prev: &BlockHeader, chain: &Chain, diff: u64) -> Block
 where
 	K: Keychain,
@@ -49,7 +49,22 @@ fn comments() -> HashMap<String, String> {
 #private key for the TLS certificate
 #tls_certificate_key = \"\"
 
-#the address on which services will listen, e.g. Transaction Pool
+#path of a TOML file of API tokens, accepted by both APIs as bearer tokens
+#(or as the basic auth password) along the api secrets, one table each:
+#[[tokens]]
+#name = \"explorer\"
+#token = \"...\"
+#scopes = [\"read\"]  #read, push_tx or admin (the owner API needs admin)
+#rate_limit = 120   #requests per minute, overriding api_rate_limit_per_token
+#api_tokens_path = \".api_tokens.toml\"
+
+#requests per minute allowed to a client address and to an API token on
+#the APIs, answered with 429 and a Retry-After beyond, not limited if unset
+#api_rate_limit_per_ip = 600
+#api_rate_limit_per_token = 1200
+
+#the address on which the foreign API listens: the chain data and pushing
+#transactions, safe to publish
 "
 		.to_string(),
 	);
@@ -57,12 +72,33 @@ fn comments() -> HashMap<String, String> {
 	retval.insert(
 		"api_secret_path".to_string(),
 		"
-#path of the secret token used by the API to authenticate the calls
+#path of the secret token used by the foreign API to authenticate the calls
 #comment the it to disable basic auth
 "
 		.to_string(),
 	);
 
+	retval.insert(
+		"owner_api_http_addr".to_string(),
+		"
+#the address on which the owner API listens: the peers, bans, chain
+#maintenance (compaction, validation) and the Prometheus metrics at
+#/v1/metrics, keep it private
+#comment it to not start the owner API
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"owner_api_secret_path".to_string(),
+		"
+#path of the secret token used by the owner API to authenticate the calls,
+#distinct from the foreign API one
+#comment it to disable basic auth
+"
+		.to_string(),
+	);
+
 	retval.insert(
 		"db_root".to_string(),
 		"
@@ -112,6 +148,15 @@ fn comments() -> HashMap<String, String> {
 		.to_string(),
 	);
 
+	retval.insert(
+		"light_mode".to_string(),
+		"
+#run the node in \"light\" mode, syncing the block headers only (no blocks, no txhashset)
+#and asking the peers for outputs and kernels with merkle proofs
+"
+		.to_string(),
+	);
+
 	retval.insert(
 		"skip_sync_wait".to_string(),
 		"
@@ -227,6 +272,22 @@ fn comments() -> HashMap<String, String> {
 		.to_string(),
 	);
 
+	retval.insert(
+		"relay_count".to_string(),
+		"
+#number of outbound relay peers per epoch, inbound stem txs are routed to one of them
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"embargo_jitter_secs".to_string(),
+		"
+#mean of the random (exponential) delay added to the embargo timer of each stem tx
+"
+		.to_string(),
+	);
+
 	retval.insert(
 		"[server.p2p_config]".to_string(),
 		"#test miner wallet URL (burns if this doesn't exist)
@@ -280,6 +341,16 @@ fn comments() -> HashMap<String, String> {
 #a list of preferred peers to connect to
 #peers_preferred = [\"192.168.0.1:3514\",\"192.168.0.2:3514\"]
 
+#additional addresses to listen on, i.e. to accept IPv6 connections too
+#listen_addrs = [\"[::]:3514\"]
+#publicly reachable addresses advertised to our peers, if they differ from
+#the ones we listen on (NAT, port forwarding)
+#external_addrs = [\"203.0.113.7:3514\", \"[2001:db8::7]:3514\"]
+#set to false to refuse all incoming connections
+#listen = true
+#SOCKS5 proxy (i.e. Tor) used for all outbound connections and DNS seeds lookups
+#socks5_proxy = \"127.0.0.1:9050\"
+
 #how long a banned peer should stay banned
 #ban_window = 10800
 
@@ -289,6 +360,11 @@ fn comments() -> HashMap<String, String> {
 #maximum number of outbound peer connections
 #peer_max_outbound_count = 8
 
+#maximum number of outbound peers in the same /16 (IPv4) or /32 (IPv6) subnet,
+#protects against an attacker surrounding us with nodes it controls
+#(loopback addresses are not limited)
+#peer_max_outbound_per_subnet = 1
+
 #preferred minimum number of outbound peers (we'll actively keep trying to add peers
 #until we get to at least this number)
 #peer_min_preferred_outbound_count = 8
@@ -302,6 +378,35 @@ fn comments() -> HashMap<String, String> {
 # A preferred dandelion_peer, mainly used for testing dandelion
 # dandelion_peer = \"10.0.0.1:13144\"
 
+#offer an encrypted and authenticated transport (Noise XX) to peers supporting it,
+#the node static key is kept in p2p_node.key in the data dir
+#encryption = false
+
+#refuse peers not supporting the encrypted transport (implies encryption = true)
+#require_encryption = false
+
+#announce new blocks with their headers first to the peers asking for it, who
+#then only request the blocks extending their best chain
+#headers_announce = true
+
+#bandwidth limits in bytes per second, 0 or unset for no limit. The txhashset
+#and block limits are budgets for serving txhashset archives and relaying
+#blocks, shared by all peers and counted on top of the upload limits.
+#max_upload_rate = 0
+#max_download_rate = 0
+#peer_max_upload_rate = 0
+#peer_max_download_rate = 0
+#txhashset_max_upload_rate = 0
+#block_max_upload_rate = 0
+
+#body sync requests blocks from all the peers with more work, up to
+#block_download_window blocks ahead of our head and peer_max_block_requests at
+#a time from each peer. Blocks not received after block_request_timeout seconds
+#are requested from another peer.
+#block_download_window = 128
+#peer_max_block_requests = 16
+#block_request_timeout = 15
+
 "
 		.to_string(),
 	);
@@ -348,6 +453,15 @@ fn comments() -> HashMap<String, String> {
 		.to_string(),
 	);
 
+	retval.insert(
+		"max_rejected_cache_size".to_string(),
+		"
+#number of recently rejected transactions to remember the reason of, for
+#the transaction status API
+"
+		.to_string(),
+	);
+
 	retval.insert(
 		"[server.stratum_mining_config]".to_string(),
 		"
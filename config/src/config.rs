@@ -41,6 +41,8 @@ const GOTTS_HOME: &'static str = ".gotts";
 const GOTTS_CHAIN_DIR: &'static str = "chain_data";
 /// Node API secret
 pub const API_SECRET_FILE_NAME: &'static str = ".api_secret";
+/// Owner API secret
+pub const OWNER_API_SECRET_FILE_NAME: &'static str = ".owner_api_secret";
 
 fn get_gotts_path(chain_type: &global::ChainTypes) -> Result<PathBuf, ConfigError> {
 	// Check if gotts dir exists
@@ -97,10 +99,13 @@ pub fn check_api_secret(api_secret_path: &PathBuf) -> Result<(), ConfigError> {
 }
 
 /// Check that the api secret file exists and is valid
-fn check_api_secret_file(chain_type: &global::ChainTypes) -> Result<(), ConfigError> {
+fn check_api_secret_file(
+	chain_type: &global::ChainTypes,
+	file_name: &str,
+) -> Result<(), ConfigError> {
 	let gotts_path = get_gotts_path(chain_type)?;
 	let mut api_secret_path = gotts_path.clone();
-	api_secret_path.push(API_SECRET_FILE_NAME);
+	api_secret_path.push(file_name);
 	if !api_secret_path.exists() {
 		init_api_secret(&api_secret_path)
 	} else {
@@ -110,7 +115,8 @@ fn check_api_secret_file(chain_type: &global::ChainTypes) -> Result<(), ConfigEr
 
 /// Handles setup and detection of paths for node
 pub fn initial_setup_server(chain_type: &global::ChainTypes) -> Result<GlobalConfig, ConfigError> {
-	check_api_secret_file(chain_type)?;
+	check_api_secret_file(chain_type, API_SECRET_FILE_NAME)?;
+	check_api_secret_file(chain_type, OWNER_API_SECRET_FILE_NAME)?;
 	// Use config file if current directory if it exists, .gotts home otherwise
 	if let Some(p) = check_config_current_dir(SERVER_CONFIG_FILE_NAME) {
 		GlobalConfig::new(p.to_str().unwrap())
@@ -165,6 +171,7 @@ impl GlobalConfig {
 			global::ChainTypes::Mainnet => {}
 			global::ChainTypes::Floonet => {
 				defaults.api_http_addr = "127.0.0.1:13513".to_owned();
+				defaults.owner_api_http_addr = Some("127.0.0.1:13517".to_owned());
 				defaults.p2p_config.port = 13514;
 				defaults
 					.stratum_mining_config
@@ -179,6 +186,7 @@ impl GlobalConfig {
 			}
 			global::ChainTypes::UserTesting => {
 				defaults.api_http_addr = "127.0.0.1:23513".to_owned();
+				defaults.owner_api_http_addr = Some("127.0.0.1:23517".to_owned());
 				defaults.p2p_config.port = 23514;
 				defaults.p2p_config.seeding_type = p2p::Seeding::None;
 				defaults
@@ -254,6 +262,10 @@ impl GlobalConfig {
 		secret_path.push(API_SECRET_FILE_NAME);
 		self.members.as_mut().unwrap().server.api_secret_path =
 			Some(secret_path.to_str().unwrap().to_owned());
+		let mut owner_secret_path = gotts_home.clone();
+		owner_secret_path.push(OWNER_API_SECRET_FILE_NAME);
+		self.members.as_mut().unwrap().server.owner_api_secret_path =
+			Some(owner_secret_path.to_str().unwrap().to_owned());
 		let mut log_path = gotts_home.clone();
 		log_path.push(SERVER_LOG_FILE_NAME);
 		self.members
@@ -87,6 +87,26 @@ where
 		}
 	}
 
+	/// Get the hash at provided position in the MMR, ignoring the remove log.
+	/// Returns None if the position has been compacted away.
+	pub fn get_from_file(&self, pos: u64) -> Option<Hash> {
+		if pos > self.last_pos {
+			None
+		} else {
+			self.backend.get_from_file(pos)
+		}
+	}
+
+	/// Get the data element at provided position in the MMR, ignoring the
+	/// remove log. Returns None if the leaf has been compacted away.
+	pub fn get_data_from_file(&self, pos: u64) -> Option<T::E> {
+		if pos > self.last_pos || !is_leaf(pos) {
+			None
+		} else {
+			self.backend.get_data_from_file(pos)
+		}
+	}
+
 	/// Iterator over current (unpruned, unremoved) leaf positions.
 	pub fn leaf_pos_iter(&self) -> impl Iterator<Item = u64> + '_ {
 		self.backend.leaf_pos_iter()
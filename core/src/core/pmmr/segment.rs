@@ -0,0 +1,484 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Segments of a PMMR, a contiguous range of leaves along with everything
+//! needed to prove them against the MMR root. This is what allows a node to
+//! download the txhashset in chunks, from many peers, validating every chunk
+//! independently as it comes.
+//!
+//! Segment `idx` of height `h` covers the leaves `[idx * 2^h, (idx + 1) * 2^h)`.
+//! A full segment is a perfect subtree whose root is proven with a regular
+//! Merkle proof. The last segment may be partial, its subtrees are then the
+//! rightmost peaks of the MMR and the proof is made of the peaks on its left.
+
+use std::collections::BTreeMap;
+
+use crate::core::hash::Hash;
+use crate::core::merkle_proof::MerkleProof;
+use crate::core::pmmr::{self, Backend, ReadonlyPMMR};
+use crate::ser::{self, PMMRIndexHashable, PMMRable, Readable, Reader, Writeable, Writer};
+
+/// Max height of a segment, 2^16 leaves is already far more than what fits
+/// in a p2p message.
+pub const MAX_SEGMENT_HEIGHT: u8 = 16;
+
+/// Segment errors.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SegmentError {
+	/// The segment lies beyond the last leaf of the MMR.
+	NonExistent,
+	/// Segment height out of the supported range.
+	InvalidHeight(u8),
+	/// Data of a leaf in the segment is missing.
+	MissingLeaf(u64),
+	/// Hash of a (pruned) node in the segment is missing.
+	MissingHash(u64),
+	/// Unexpected node, outside of the segment or never used.
+	UnexpectedNode(u64),
+	/// The segment doesn't hash to the MMR root.
+	RootMismatch,
+}
+
+/// Identifies a segment by its height and index.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SegmentIdentifier {
+	/// Height of the segment subtree, a segment holds 2^height leaves.
+	pub height: u8,
+	/// Zero-based index of the segment.
+	pub idx: u64,
+}
+
+impl Writeable for SegmentIdentifier {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u8(self.height)?;
+		writer.write_u64(self.idx)
+	}
+}
+
+impl Readable for SegmentIdentifier {
+	fn read(reader: &mut dyn Reader) -> Result<SegmentIdentifier, ser::Error> {
+		let height = reader.read_u8()?;
+		if height > MAX_SEGMENT_HEIGHT {
+			return Err(ser::Error::CorruptedData);
+		}
+		let idx = reader.read_u64()?;
+		Ok(SegmentIdentifier { height, idx })
+	}
+}
+
+impl SegmentIdentifier {
+	/// Number of segments of the given height needed to cover a MMR of the
+	/// provided size.
+	pub fn count_segments_required(mmr_size: u64, height: u8) -> u64 {
+		let n_leaves = pmmr::n_leaves(mmr_size);
+		let capacity = 1u64 << height;
+		(n_leaves + capacity - 1) / capacity
+	}
+
+	/// All the segments of the given height needed to cover a MMR of the
+	/// provided size.
+	pub fn all_segments(mmr_size: u64, height: u8) -> Vec<SegmentIdentifier> {
+		(0..SegmentIdentifier::count_segments_required(mmr_size, height))
+			.map(|idx| SegmentIdentifier { height, idx })
+			.collect()
+	}
+
+	/// Max number of leaves in the segment.
+	pub fn capacity(&self) -> u64 {
+		1 << self.height
+	}
+
+	/// Zero-based insertion index of the first leaf of the segment.
+	pub fn leaf_offset(&self) -> u64 {
+		self.idx * self.capacity()
+	}
+
+	/// Is this segment complete (a perfect subtree) in a MMR of this size?
+	pub fn is_full(&self, mmr_size: u64) -> bool {
+		self.leaf_offset() + self.capacity() <= pmmr::n_leaves(mmr_size)
+	}
+
+	/// Positions of the first leaf and of the last node of the segment in
+	/// a MMR of the provided size. None if the segment lies beyond the MMR.
+	pub fn segment_pos_range(&self, mmr_size: u64) -> Option<(u64, u64)> {
+		if self.leaf_offset() >= pmmr::n_leaves(mmr_size) {
+			return None;
+		}
+		let first = pmmr::insertion_to_pmmr_index(self.leaf_offset() + 1);
+		let last = if self.is_full(mmr_size) {
+			// root of the subtree, right above its rightmost leaf
+			let last_leaf = pmmr::insertion_to_pmmr_index(self.leaf_offset() + self.capacity());
+			last_leaf + self.height as u64
+		} else {
+			mmr_size
+		};
+		Some((first, last))
+	}
+
+	/// Roots of the subtrees making up the segment: the subtree root for a
+	/// full segment, the rightmost peaks of the MMR for a partial one.
+	pub fn subtree_roots(&self, mmr_size: u64) -> Vec<u64> {
+		match self.segment_pos_range(mmr_size) {
+			None => vec![],
+			Some((_, last)) if self.is_full(mmr_size) => vec![last],
+			Some((first, _)) => pmmr::peaks(mmr_size)
+				.into_iter()
+				.filter(|x| *x >= first)
+				.collect(),
+		}
+	}
+}
+
+/// A segment of a PMMR, with the data of every leaf still in the MMR, the
+/// hashes of the pruned subtrees and the proof of the segment against the
+/// MMR root.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment<T> {
+	/// Identifier of the segment.
+	pub identifier: SegmentIdentifier,
+	/// Roots of the pruned subtrees within the segment, (pos, hash). If the
+	/// whole segment has been pruned, this is the single pruned root covering
+	/// it (which may extend beyond the segment).
+	pub hashes: Vec<(u64, Hash)>,
+	/// Leaf data, (pos, data), in position order.
+	pub leaves: Vec<(u64, T)>,
+	/// Positions of the leaves provided above which are spent (not in the
+	/// leaf set anymore), for prunable MMRs.
+	pub spent: Vec<u64>,
+	/// Proof of the segment against the MMR root. For a full segment this is
+	/// the Merkle proof of its root, for a partial one the peaks on its left,
+	/// closest first.
+	pub proof: MerkleProof,
+}
+
+impl<T> Segment<T>
+where
+	T: PMMRable<E = T> + PMMRIndexHashable,
+{
+	/// Build a segment from a (possibly pruned) PMMR.
+	pub fn from_pmmr<B: Backend<T>>(
+		identifier: SegmentIdentifier,
+		pmmr: &ReadonlyPMMR<'_, T, B>,
+		prunable: bool,
+	) -> Result<Segment<T>, SegmentError> {
+		if identifier.height > MAX_SEGMENT_HEIGHT {
+			return Err(SegmentError::InvalidHeight(identifier.height));
+		}
+		let mmr_size = pmmr.unpruned_size();
+		let (first, _) = identifier
+			.segment_pos_range(mmr_size)
+			.ok_or(SegmentError::NonExistent)?;
+
+		let mut segment = Segment {
+			identifier,
+			hashes: vec![],
+			leaves: vec![],
+			spent: vec![],
+			proof: MerkleProof {
+				mmr_size,
+				path: vec![],
+			},
+		};
+
+		let roots = identifier.subtree_roots(mmr_size);
+		if identifier.is_full(mmr_size) {
+			let mut root = roots[0];
+			if pmmr.get_from_file(root).is_none() {
+				// The whole segment has been compacted, find the pruned root
+				// covering it.
+				loop {
+					root = pmmr::family(root).0;
+					if root > mmr_size {
+						return Err(SegmentError::MissingHash(roots[0]));
+					}
+					if let Some(hash) = pmmr.get_from_file(root) {
+						segment.hashes.push((root, hash));
+						break;
+					}
+				}
+			} else {
+				segment.collect(pmmr, root, prunable)?;
+			}
+			segment.proof.path = node_proof_path(pmmr, root)?;
+		} else {
+			for root in roots {
+				segment.collect(pmmr, root, prunable)?;
+			}
+			let mut path = vec![];
+			for pos in pmmr::peaks(mmr_size).into_iter().filter(|x| *x < first) {
+				path.push(
+					pmmr.get_from_file(pos)
+						.ok_or(SegmentError::MissingHash(pos))?,
+				);
+			}
+			path.reverse();
+			segment.proof.path = path;
+		}
+		Ok(segment)
+	}
+
+	// Walks down the subtree rooted at pos, collecting the leaves and the
+	// roots of the pruned subtrees, left to right.
+	fn collect<B: Backend<T>>(
+		&mut self,
+		pmmr: &ReadonlyPMMR<'_, T, B>,
+		pos: u64,
+		prunable: bool,
+	) -> Result<(), SegmentError> {
+		let height = pmmr::bintree_postorder_height(pos);
+		if height == 0 {
+			let data = pmmr
+				.get_data_from_file(pos)
+				.ok_or(SegmentError::MissingLeaf(pos))?;
+			if prunable && pmmr.get_hash(pos).is_none() {
+				self.spent.push(pos);
+			}
+			self.leaves.push((pos, data));
+			return Ok(());
+		}
+
+		let left = pos - (1 << height);
+		let right = pos - 1;
+		if pmmr.get_from_file(left).is_none() || pmmr.get_from_file(right).is_none() {
+			let hash = pmmr
+				.get_from_file(pos)
+				.ok_or(SegmentError::MissingHash(pos))?;
+			self.hashes.push((pos, hash));
+			return Ok(());
+		}
+		self.collect(pmmr, left, prunable)?;
+		self.collect(pmmr, right, prunable)
+	}
+
+	/// Validates the segment against the root of a MMR of the provided size.
+	/// The root commits to the leaves and pruned hashes but not to the leaf
+	/// set, the spent flags are only checked to refer to leaves of the segment.
+	pub fn validate(&self, mmr_size: u64, root: Hash) -> Result<(), SegmentError> {
+		let id = self.identifier;
+		if id.height > MAX_SEGMENT_HEIGHT {
+			return Err(SegmentError::InvalidHeight(id.height));
+		}
+		if self.proof.mmr_size != mmr_size {
+			return Err(SegmentError::RootMismatch);
+		}
+		let (first, last) = id
+			.segment_pos_range(mmr_size)
+			.ok_or(SegmentError::NonExistent)?;
+
+		// The whole segment is covered by a single pruned subtree.
+		if self.leaves.is_empty() && self.hashes.len() == 1 && self.hashes[0].0 > last {
+			let (pos, hash) = self.hashes[0];
+			if !id.is_full(mmr_size) || pmmr::bintree_leftmost(pos) > first || pos > mmr_size {
+				return Err(SegmentError::UnexpectedNode(pos));
+			}
+			return self
+				.proof
+				.verify_hash(root, hash, pos)
+				.map_err(|_| SegmentError::RootMismatch);
+		}
+
+		let mut nodes = BTreeMap::new();
+		for (pos, hash) in &self.hashes {
+			if *pos < first || *pos > last || nodes.insert(*pos, *hash).is_some() {
+				return Err(SegmentError::UnexpectedNode(*pos));
+			}
+		}
+		let mut prev = 0;
+		for (pos, data) in &self.leaves {
+			if *pos <= prev || *pos > last || !pmmr::is_leaf(*pos) {
+				return Err(SegmentError::UnexpectedNode(*pos));
+			}
+			prev = *pos;
+			if *pos < first {
+				return Err(SegmentError::UnexpectedNode(*pos));
+			}
+			if nodes.insert(*pos, data.hash_with_index(*pos - 1)).is_some() {
+				return Err(SegmentError::UnexpectedNode(*pos));
+			}
+		}
+		for pos in &self.spent {
+			if self.leaves.binary_search_by_key(pos, |(p, _)| *p).is_err() {
+				return Err(SegmentError::UnexpectedNode(*pos));
+			}
+		}
+
+		let mut used = 0;
+		let mut roots = vec![];
+		for pos in id.subtree_roots(mmr_size) {
+			roots.push(subtree_hash(&nodes, pos, &mut used)?);
+		}
+		if used != nodes.len() {
+			let unused = nodes.keys().next().cloned().unwrap_or(0);
+			return Err(SegmentError::UnexpectedNode(unused));
+		}
+
+		if id.is_full(mmr_size) {
+			self.proof
+				.verify_hash(root, roots[0], last)
+				.map_err(|_| SegmentError::RootMismatch)
+		} else {
+			// bag the peaks, right to left, as the MMR root does
+			let mut res: Option<Hash> = None;
+			for peak in roots.iter().rev().chain(self.proof.path.iter()) {
+				res = match res {
+					None => Some(*peak),
+					Some(rhash) => Some((*peak, rhash).hash_with_index(mmr_size)),
+				};
+			}
+			if res == Some(root) {
+				Ok(())
+			} else {
+				Err(SegmentError::RootMismatch)
+			}
+		}
+	}
+
+	/// Is the leaf at this position spent (not in the leaf set)?
+	pub fn is_spent(&self, pos: u64) -> bool {
+		self.spent.contains(&pos)
+	}
+
+	/// Positions of the leaves of the segment still in the leaf set. Pruned
+	/// subtrees only ever hold spent leaves, so two copies of a segment at
+	/// the same MMR size agree on these, however much they've been compacted.
+	pub fn unspent(&self) -> Vec<u64> {
+		self.leaves
+			.iter()
+			.map(|(pos, _)| *pos)
+			.filter(|pos| !self.is_spent(*pos))
+			.collect()
+	}
+}
+
+// Hash of the node at pos, from the leaves and pruned roots we have.
+fn subtree_hash(
+	nodes: &BTreeMap<u64, Hash>,
+	pos: u64,
+	used: &mut usize,
+) -> Result<Hash, SegmentError> {
+	if let Some(hash) = nodes.get(&pos) {
+		*used += 1;
+		return Ok(*hash);
+	}
+	let height = pmmr::bintree_postorder_height(pos);
+	if height == 0 {
+		return Err(SegmentError::MissingLeaf(pos));
+	}
+	let left = subtree_hash(nodes, pos - (1 << height), used)?;
+	let right = subtree_hash(nodes, pos - 1, used)?;
+	Ok((left, right).hash_with_index(pos - 1))
+}
+
+// Merkle proof path from the node at pos up to the MMR root: siblings up to
+// its peak, then the bagged peaks on its right and the peaks on its left.
+fn node_proof_path<T, B>(pmmr: &ReadonlyPMMR<'_, T, B>, pos: u64) -> Result<Vec<Hash>, SegmentError>
+where
+	T: PMMRable,
+	B: Backend<T>,
+{
+	let last_pos = pmmr.unpruned_size();
+	let branch = pmmr::family_branch(pos, last_pos);
+	let mut path = vec![];
+	for (_, sibling) in &branch {
+		path.push(
+			pmmr.get_from_file(*sibling)
+				.ok_or(SegmentError::MissingHash(*sibling))?,
+		);
+	}
+	let peak_pos = branch.last().map(|x| x.0).unwrap_or(pos);
+
+	let peak_hash = |x: u64| pmmr.get_from_file(x).ok_or(SegmentError::MissingHash(x));
+	let peaks = pmmr::peaks(last_pos);
+	let mut rhs: Option<Hash> = None;
+	for x in peaks.iter().filter(|x| **x > peak_pos).rev() {
+		let peak = peak_hash(*x)?;
+		rhs = match rhs {
+			None => Some(peak),
+			Some(rhash) => Some((peak, rhash).hash_with_index(last_pos)),
+		};
+	}
+	if let Some(rhs) = rhs {
+		path.push(rhs);
+	}
+	for x in peaks.iter().filter(|x| **x < peak_pos).rev() {
+		path.push(peak_hash(*x)?);
+	}
+	Ok(path)
+}
+
+impl<T: Writeable> Writeable for Segment<T> {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.identifier.write(writer)?;
+		writer.write_u64(self.hashes.len() as u64)?;
+		for (pos, hash) in &self.hashes {
+			writer.write_u64(*pos)?;
+			hash.write(writer)?;
+		}
+		writer.write_u64(self.leaves.len() as u64)?;
+		for (pos, data) in &self.leaves {
+			writer.write_u64(*pos)?;
+			data.write(writer)?;
+		}
+		writer.write_u64(self.spent.len() as u64)?;
+		for pos in &self.spent {
+			writer.write_u64(*pos)?;
+		}
+		self.proof.write(writer)
+	}
+}
+
+impl<T: Readable> Readable for Segment<T> {
+	fn read(reader: &mut dyn Reader) -> Result<Segment<T>, ser::Error> {
+		let identifier = SegmentIdentifier::read(reader)?;
+		let capacity = identifier.capacity();
+
+		let count = reader.read_u64()?;
+		if count > capacity {
+			return Err(ser::Error::TooLargeReadErr);
+		}
+		let mut hashes = Vec::with_capacity(count as usize);
+		for _ in 0..count {
+			let pos = reader.read_u64()?;
+			hashes.push((pos, Hash::read(reader)?));
+		}
+
+		let count = reader.read_u64()?;
+		if count > capacity {
+			return Err(ser::Error::TooLargeReadErr);
+		}
+		let mut leaves = Vec::with_capacity(count as usize);
+		for _ in 0..count {
+			let pos = reader.read_u64()?;
+			leaves.push((pos, T::read(reader)?));
+		}
+
+		let count = reader.read_u64()?;
+		if count > leaves.len() as u64 {
+			return Err(ser::Error::TooLargeReadErr);
+		}
+		let mut spent = Vec::with_capacity(count as usize);
+		for _ in 0..count {
+			spent.push(reader.read_u64()?);
+		}
+
+		let proof = MerkleProof::read(reader)?;
+		Ok(Segment {
+			identifier,
+			hashes,
+			leaves,
+			spent,
+			proof,
+		})
+	}
+}
@@ -0,0 +1,85 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod vec_backend;
+
+use self::core::core::pmmr::{Segment, SegmentError, SegmentIdentifier, PMMR};
+use self::core::ser;
+use crate::vec_backend::{TestElem, VecBackend};
+use gotts_core as core;
+
+#[test]
+fn segments_validate_against_root() {
+	let mut ba = VecBackend::new();
+	let mut pmmr = PMMR::new(&mut ba);
+	for x in 0..21 {
+		pmmr.push(&TestElem([0, 0, 0, x])).unwrap();
+	}
+	pmmr.prune(4).unwrap();
+	let root = pmmr.root().unwrap();
+	let mmr_size = pmmr.unpruned_size();
+
+	for height in 0..6 {
+		let ids = SegmentIdentifier::all_segments(mmr_size, height);
+		assert_eq!(ids.len() as u64, (21 + (1 << height) - 1) >> height);
+
+		let mut leaves = 0;
+		for id in ids {
+			let segment = Segment::from_pmmr(id, &pmmr.readonly_pmmr(), true).unwrap();
+			segment.validate(mmr_size, root).unwrap();
+			leaves += segment.leaves.len();
+			assert_eq!(segment.is_spent(4), segment.leaves.iter().any(|x| x.0 == 4));
+		}
+		assert_eq!(leaves, 21);
+	}
+
+	let beyond = SegmentIdentifier { height: 2, idx: 6 };
+	assert_eq!(
+		Segment::from_pmmr(beyond, &pmmr.readonly_pmmr(), true),
+		Err(SegmentError::NonExistent)
+	);
+}
+
+#[test]
+fn segment_tampered() {
+	let mut ba = VecBackend::new();
+	let mut pmmr = PMMR::new(&mut ba);
+	for x in 0..13 {
+		pmmr.push(&TestElem([0, 0, 0, x])).unwrap();
+	}
+	let root = pmmr.root().unwrap();
+	let mmr_size = pmmr.unpruned_size();
+
+	// full segment and last (partial) segment
+	for idx in 0..2 {
+		let id = SegmentIdentifier { height: 3, idx };
+		let segment = Segment::from_pmmr(id, &pmmr.readonly_pmmr(), true).unwrap();
+
+		let mut vec = Vec::new();
+		ser::serialize_default(&mut vec, &segment).expect("serialization failed");
+		let segment_2: Segment<TestElem> = ser::deserialize_default(&mut &vec[..]).unwrap();
+		assert_eq!(segment, segment_2);
+
+		let mut tampered = segment.clone();
+		tampered.leaves[1].1 = TestElem([1, 0, 0, 0]);
+		assert_eq!(
+			tampered.validate(mmr_size, root),
+			Err(SegmentError::RootMismatch)
+		);
+
+		let mut missing = segment.clone();
+		missing.leaves.remove(0);
+		assert!(missing.validate(mmr_size, root).is_err());
+	}
+}
@@ -0,0 +1,472 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Address manager deciding which addresses we open outbound connections to,
+//! modelled on the "new" and "tried" tables of Bitcoin Core.
+//!
+//! Addresses we only heard about go in the new table, bucketed by their /16
+//! and the /16 of the peer that told us about them, so a single source can
+//! only ever fill a small part of the table. Addresses we successfully
+//! connected to move to the tried table, bucketed by their own /16. Outbound
+//! candidates are picked evenly from both tables, which makes it expensive
+//! for an attacker to fill all our outbound slots (eclipse attack) by
+//! flooding us with addresses it controls.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+
+use rand::seq::SliceRandom;
+use rand::{thread_rng, Rng};
+
+use crate::core::ser::{self, Readable, Reader, Writeable, Writer};
+use crate::types::{PeerAddr, Subnet};
+
+/// Number of buckets in the new table
+pub const NEW_BUCKET_COUNT: usize = 256;
+
+/// Number of buckets in the tried table
+pub const TRIED_BUCKET_COUNT: usize = 64;
+
+/// Max number of addresses in a bucket
+pub const BUCKET_SIZE: usize = 64;
+
+/// Number of new buckets the addresses from a single source subnet can land in
+const NEW_BUCKETS_PER_SOURCE_GROUP: u64 = 32;
+
+/// Number of tried buckets the addresses of a single subnet can land in
+const TRIED_BUCKETS_PER_GROUP: u64 = 8;
+
+/// Failed attempts after which an address that never worked is dropped first
+const MAX_FAILED_ATTEMPTS: u32 = 5;
+
+/// An address known to the address manager.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KnownAddr {
+	pub addr: PeerAddr,
+	/// The peer that told us about this address (the address itself if we
+	/// found it on our own, from seeds or an inbound connection).
+	pub source: PeerAddr,
+	/// Whether we successfully connected to this address.
+	pub tried: bool,
+	/// Connection attempts since the last success.
+	pub attempts: u32,
+	/// Time of the last connection attempt.
+	pub last_try: i64,
+	/// Time of the last successful connection.
+	pub last_success: i64,
+	/// Time we first heard about this address.
+	pub added: i64,
+}
+
+impl KnownAddr {
+	/// Addresses we keep failing to connect to, evicted first.
+	fn is_terrible(&self) -> bool {
+		self.last_success == 0 && self.attempts >= MAX_FAILED_ATTEMPTS
+	}
+}
+
+impl Writeable for KnownAddr {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.addr.write(writer)?;
+		self.source.write(writer)?;
+		ser_multiwrite!(
+			writer,
+			[write_u8, self.tried as u8],
+			[write_u32, self.attempts],
+			[write_i64, self.last_try],
+			[write_i64, self.last_success],
+			[write_i64, self.added]
+		);
+		Ok(())
+	}
+}
+
+impl Readable for KnownAddr {
+	fn read(reader: &mut dyn Reader) -> Result<KnownAddr, ser::Error> {
+		let addr = PeerAddr::read(reader)?;
+		let source = PeerAddr::read(reader)?;
+		let (tried, attempts, last_try, last_success, added) =
+			ser_multiread!(reader, read_u8, read_u32, read_i64, read_i64, read_i64);
+		Ok(KnownAddr {
+			addr,
+			source,
+			tried: tried != 0,
+			attempts,
+			last_try,
+			last_success,
+			added,
+		})
+	}
+}
+
+/// The subnet peers are grouped by when bucketing addresses and limiting
+/// outbound connections: the /16 for IPv4 and the /32 for IPv6. Loopback
+/// addresses (local testing) don't belong to any group.
+pub fn addr_group(addr: &PeerAddr) -> Option<Subnet> {
+	let ip = addr.ip();
+	if ip.is_loopback() {
+		return None;
+	}
+	let prefix_len = match ip {
+		IpAddr::V4(_) => 16,
+		IpAddr::V6(_) => 32,
+	};
+	Subnet::new(ip, prefix_len).ok()
+}
+
+// Key used to hash an address into buckets, the address itself for loopback
+// addresses so local test nodes still spread over the tables.
+fn group_key(addr: &PeerAddr) -> String {
+	match addr_group(addr) {
+		Some(subnet) => subnet.to_string(),
+		None => addr.as_key(),
+	}
+}
+
+/// Tables of known addresses, see the module documentation.
+pub struct AddrManager {
+	// random key so other nodes can't predict our bucketing
+	key: u64,
+	addrs: HashMap<PeerAddr, KnownAddr>,
+	new_buckets: Vec<Vec<PeerAddr>>,
+	tried_buckets: Vec<Vec<PeerAddr>>,
+	// changes not persisted yet
+	dirty: HashSet<PeerAddr>,
+	removed: HashSet<PeerAddr>,
+}
+
+impl AddrManager {
+	pub fn new() -> AddrManager {
+		AddrManager {
+			key: thread_rng().gen(),
+			addrs: HashMap::new(),
+			new_buckets: vec![vec![]; NEW_BUCKET_COUNT],
+			tried_buckets: vec![vec![]; TRIED_BUCKET_COUNT],
+			dirty: HashSet::new(),
+			removed: HashSet::new(),
+		}
+	}
+
+	/// Rebuilds the tables from previously saved addresses.
+	pub fn load(known: Vec<KnownAddr>) -> AddrManager {
+		let mut addrman = AddrManager::new();
+		for ka in known {
+			if addrman.addrs.contains_key(&ka.addr) {
+				continue;
+			}
+			let addr = ka.addr;
+			let tried = ka.tried;
+			addrman.addrs.insert(addr, ka);
+			if !(tried && addrman.insert_tried(addr)) && !addrman.insert_new(addr) {
+				addrman.addrs.remove(&addr);
+				addrman.removed.insert(addr);
+			}
+		}
+		addrman
+	}
+
+	pub fn len(&self) -> usize {
+		self.addrs.len()
+	}
+
+	pub fn new_count(&self) -> usize {
+		self.new_buckets.iter().map(|b| b.len()).sum()
+	}
+
+	pub fn tried_count(&self) -> usize {
+		self.tried_buckets.iter().map(|b| b.len()).sum()
+	}
+
+	pub fn get(&self, addr: &PeerAddr) -> Option<&KnownAddr> {
+		self.addrs.get(addr)
+	}
+
+	/// Adds an address we heard about from `source`. Returns false if we
+	/// already knew it.
+	pub fn add(&mut self, addr: PeerAddr, source: PeerAddr, now: i64) -> bool {
+		if self.addrs.contains_key(&addr) {
+			return false;
+		}
+		self.addrs.insert(
+			addr,
+			KnownAddr {
+				addr,
+				source,
+				tried: false,
+				attempts: 0,
+				last_try: 0,
+				last_success: 0,
+				added: now,
+			},
+		);
+		if self.insert_new(addr) {
+			self.dirty.insert(addr);
+			true
+		} else {
+			self.addrs.remove(&addr);
+			false
+		}
+	}
+
+	/// Records a connection attempt to the address.
+	pub fn attempt(&mut self, addr: &PeerAddr, now: i64) {
+		if let Some(ka) = self.addrs.get_mut(addr) {
+			ka.attempts += 1;
+			ka.last_try = now;
+			self.dirty.insert(*addr);
+		}
+	}
+
+	/// Records a successful outbound connection, moving the address to the
+	/// tried table.
+	pub fn good(&mut self, addr: PeerAddr, now: i64) {
+		if !self.addrs.contains_key(&addr) {
+			self.add(addr, addr, now);
+		}
+		let tried = match self.addrs.get_mut(&addr) {
+			Some(ka) => {
+				ka.attempts = 0;
+				ka.last_try = now;
+				ka.last_success = now;
+				ka.tried
+			}
+			None => return,
+		};
+		self.dirty.insert(addr);
+		if tried {
+			return;
+		}
+
+		let bucket = self.new_bucket(&addr);
+		self.new_buckets[bucket].retain(|a| *a != addr);
+		if let Some(ka) = self.addrs.get_mut(&addr) {
+			ka.tried = true;
+		}
+		if !self.insert_tried(addr) {
+			// can't happen as insert_tried makes room, but stay consistent
+			self.remove(&addr);
+		}
+	}
+
+	/// Forgets about an address.
+	pub fn remove(&mut self, addr: &PeerAddr) {
+		if let Some(ka) = self.addrs.remove(addr) {
+			if ka.tried {
+				let bucket = self.tried_bucket(addr);
+				self.tried_buckets[bucket].retain(|a| a != addr);
+			} else {
+				let bucket = self.new_bucket_for(addr, &ka.source);
+				self.new_buckets[bucket].retain(|a| a != addr);
+			}
+			self.dirty.remove(addr);
+			self.removed.insert(*addr);
+		}
+	}
+
+	/// Picks a random address, from the tried or the new table with equal
+	/// probability (when both have some).
+	pub fn select(&self) -> Option<PeerAddr> {
+		let use_tried = match (self.tried_count(), self.new_count()) {
+			(0, 0) => return None,
+			(0, _) => false,
+			(_, 0) => true,
+			_ => thread_rng().gen(),
+		};
+		if use_tried {
+			AddrManager::select_from(&self.tried_buckets)
+		} else {
+			self.select_new()
+		}
+	}
+
+	/// Picks a random address from the new table, used for feeler
+	/// connections testing addresses we never connected to.
+	pub fn select_new(&self) -> Option<PeerAddr> {
+		let addr = AddrManager::select_from(&self.new_buckets)?;
+		// addresses we keep failing to reach are picked less often
+		if self.addrs.get(&addr).map(|ka| ka.is_terrible()) == Some(true) {
+			return AddrManager::select_from(&self.new_buckets);
+		}
+		Some(addr)
+	}
+
+	/// Returns the addresses changed and removed since the last call, to be
+	/// persisted.
+	pub fn take_changes(&mut self) -> (Vec<KnownAddr>, Vec<PeerAddr>) {
+		let changed = self
+			.dirty
+			.drain()
+			.filter_map(|a| self.addrs.get(&a).cloned())
+			.collect();
+		let removed = self.removed.drain().collect();
+		(changed, removed)
+	}
+
+	fn select_from(buckets: &[Vec<PeerAddr>]) -> Option<PeerAddr> {
+		let non_empty: Vec<&Vec<PeerAddr>> = buckets.iter().filter(|b| !b.is_empty()).collect();
+		let bucket = non_empty.choose(&mut thread_rng())?;
+		bucket.choose(&mut thread_rng()).cloned()
+	}
+
+	fn hash<T: Hash>(&self, t: T) -> u64 {
+		let mut hasher = DefaultHasher::new();
+		self.key.hash(&mut hasher);
+		t.hash(&mut hasher);
+		hasher.finish()
+	}
+
+	fn new_bucket(&self, addr: &PeerAddr) -> usize {
+		match self.addrs.get(addr) {
+			Some(ka) => self.new_bucket_for(addr, &ka.source),
+			None => self.new_bucket_for(addr, addr),
+		}
+	}
+
+	// The source subnet picks a limited set of buckets, the address subnet one
+	// bucket in that set.
+	fn new_bucket_for(&self, addr: &PeerAddr, source: &PeerAddr) -> usize {
+		let source_group = group_key(source);
+		let h = self.hash((group_key(addr), &source_group)) % NEW_BUCKETS_PER_SOURCE_GROUP;
+		(self.hash((source_group, h)) % NEW_BUCKET_COUNT as u64) as usize
+	}
+
+	fn tried_bucket(&self, addr: &PeerAddr) -> usize {
+		let h = self.hash(addr.as_key()) % TRIED_BUCKETS_PER_GROUP;
+		(self.hash((group_key(addr), h)) % TRIED_BUCKET_COUNT as u64) as usize
+	}
+
+	// Inserts an address (already in self.addrs) in its new bucket. A full
+	// bucket evicts its worst address: one we keep failing to reach, or else
+	// the one we heard about first.
+	fn insert_new(&mut self, addr: PeerAddr) -> bool {
+		let bucket = self.new_bucket(&addr);
+		if self.new_buckets[bucket].len() >= BUCKET_SIZE {
+			let worst = self.new_buckets[bucket]
+				.iter()
+				.filter_map(|a| self.addrs.get(a))
+				.min_by_key(|ka| (!ka.is_terrible(), ka.added))
+				.map(|ka| ka.addr);
+			match worst {
+				Some(worst) => self.remove(&worst),
+				None => return false,
+			}
+		}
+		self.new_buckets[bucket].push(addr);
+		true
+	}
+
+	// Inserts an address (already in self.addrs) in its tried bucket. A full
+	// bucket sends the address we least recently connected to back to the new
+	// table.
+	fn insert_tried(&mut self, addr: PeerAddr) -> bool {
+		let bucket = self.tried_bucket(&addr);
+		if self.tried_buckets[bucket].len() >= BUCKET_SIZE {
+			let oldest = self.tried_buckets[bucket]
+				.iter()
+				.filter_map(|a| self.addrs.get(a))
+				.min_by_key(|ka| ka.last_success)
+				.map(|ka| ka.addr);
+			if let Some(oldest) = oldest {
+				self.tried_buckets[bucket].retain(|a| *a != oldest);
+				if let Some(ka) = self.addrs.get_mut(&oldest) {
+					ka.tried = false;
+				}
+				self.dirty.insert(oldest);
+				if !self.insert_new(oldest) {
+					self.addrs.remove(&oldest);
+					self.dirty.remove(&oldest);
+					self.removed.insert(oldest);
+				}
+			}
+		}
+		self.tried_buckets[bucket].push(addr);
+		true
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn addr(s: &str) -> PeerAddr {
+		PeerAddr(s.parse().unwrap())
+	}
+
+	#[test]
+	fn groups() {
+		assert_eq!(
+			addr_group(&addr("10.12.34.56:3514")),
+			Some("10.12.0.0/16".parse().unwrap())
+		);
+		assert_eq!(
+			addr_group(&addr("[::ffff:10.12.34.56]:3514")),
+			addr_group(&addr("10.12.1.1:3514"))
+		);
+		assert_eq!(
+			addr_group(&addr("[2001:db8:1:2::1]:3514")),
+			Some("2001:db8::/32".parse().unwrap())
+		);
+		assert_eq!(addr_group(&addr("127.0.0.1:3514")), None);
+	}
+
+	#[test]
+	fn single_source_limited_to_few_buckets() {
+		let mut addrman = AddrManager::new();
+		let source = addr("10.0.0.1:3514");
+		for i in 0..5000u32 {
+			let a = addr(&format!(
+				"{}.{}.{}.1:3514",
+				20 + i / 256 % 200,
+				i % 256,
+				i / 51200
+			));
+			addrman.add(a, source, 1);
+		}
+		// all these addresses land in a few buckets, capping how much of the
+		// table a single source can fill
+		let used = addrman.new_buckets.iter().filter(|b| !b.is_empty()).count();
+		assert!(used <= NEW_BUCKETS_PER_SOURCE_GROUP as usize);
+		assert!(addrman.len() <= NEW_BUCKETS_PER_SOURCE_GROUP as usize * BUCKET_SIZE);
+		assert_eq!(addrman.len(), addrman.new_count());
+	}
+
+	#[test]
+	fn good_moves_to_tried() {
+		let mut addrman = AddrManager::new();
+		let a = addr("10.1.2.3:3514");
+		assert!(addrman.add(a, addr("10.9.9.9:3514"), 1));
+		assert!(!addrman.add(a, addr("10.8.8.8:3514"), 2));
+		assert_eq!((addrman.new_count(), addrman.tried_count()), (1, 0));
+
+		addrman.attempt(&a, 3);
+		assert_eq!(addrman.get(&a).unwrap().attempts, 1);
+		addrman.good(a, 4);
+		assert_eq!((addrman.new_count(), addrman.tried_count()), (0, 1));
+		assert_eq!(addrman.get(&a).unwrap().attempts, 0);
+		assert_eq!(addrman.select(), Some(a));
+
+		// persisted state rebuilds the same tables
+		let (changed, removed) = addrman.take_changes();
+		assert!(removed.is_empty());
+		let reloaded = AddrManager::load(changed);
+		assert_eq!((reloaded.new_count(), reloaded.tried_count()), (0, 1));
+
+		addrman.remove(&a);
+		assert_eq!(addrman.len(), 0);
+		assert_eq!(addrman.select(), None);
+		assert_eq!(addrman.take_changes().1, vec![a]);
+	}
+}
@@ -27,14 +27,16 @@ use crate::msg::{
 	read_body, read_discard, read_header, read_item, write_to_buf, MsgHeader, MsgHeaderWrapper,
 	Type,
 };
-use crate::types::Error;
-use crate::util::{RateCounter, RwLock};
+use crate::noise::{SecureStream, Session};
+use crate::types::{Error, P2PConfig};
+use crate::util::{Mutex, RateCounter, RwLock};
+use num::FromPrimitive;
 use std::fs::File;
 use std::io::{self, Read, Write};
 use std::net::{Shutdown, TcpStream};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{mpsc, Arc};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{
 	cmp,
 	thread::{self, JoinHandle},
@@ -64,7 +66,7 @@ macro_rules! try_break {
 				// to avoid the heavy polling which will consume CPU 100%
 				thread::sleep(Duration::from_millis(10));
 				None
-				}
+			}
 			Err(Error::Store(_))
 			| Err(Error::Chain(_))
 			| Err(Error::Internal)
@@ -72,8 +74,8 @@ macro_rules! try_break {
 			Err(ref e) => {
 				debug!("try_break: exit the loop: {:?}", e);
 				break;
-				}
 			}
+		}
 	};
 }
 
@@ -148,14 +150,18 @@ impl<'a> Response<'a> {
 		})
 	}
 
-	fn write(mut self, tracker: Arc<Tracker>) -> Result<(), Error> {
+	fn write(mut self, tracker: Arc<Tracker>, limits: &ConnLimits) -> Result<(), Error> {
 		let mut msg = ser::ser_vec(
 			&MsgHeader::new(self.resp_type, self.body.len() as u64),
 			self.version,
 		)?;
 		msg.append(&mut self.body);
+		limits.sending(self.resp_type, msg.len() as u64);
 		self.stream.write_all(&msg[..])?;
 		tracker.inc_sent(msg.len() as u64);
+		limits
+			.traffic()
+			.inc_sent(self.resp_type, 1, msg.len() as u64);
 
 		if let Some(mut file) = self.attachment {
 			let mut buf = [0u8; 8000];
@@ -163,10 +169,12 @@ impl<'a> Response<'a> {
 				match file.read(&mut buf[..]) {
 					Ok(0) => break,
 					Ok(n) => {
+						limits.sending(self.resp_type, n as u64);
 						self.stream.write_all(&buf[..n])?;
 						// Increase sent bytes "quietly" without incrementing the counter.
 						// (In a loop here for the single attachment).
 						tracker.inc_quiet_sent(n as u64);
+						limits.traffic().inc_sent(self.resp_type, 0, n as u64);
 					}
 					Err(e) => return Err(From::from(e)),
 				}
@@ -182,6 +190,232 @@ impl<'a> Response<'a> {
 
 pub const SEND_CHANNEL_CAP: usize = 100;
 
+/// A token bucket limiting a rate of bytes per second, a rate of 0 meaning
+/// unlimited. Allows bursts of up to a second worth of traffic, bigger
+/// amounts put the bucket in debt so the caller waits for as long as the
+/// amount takes to go through at the configured rate.
+pub struct RateLimiter {
+	rate: u64,
+	// available tokens (negative when in debt) and last refill time
+	bucket: Mutex<(f64, Instant)>,
+}
+
+impl RateLimiter {
+	pub fn new(rate: u64) -> RateLimiter {
+		RateLimiter {
+			rate,
+			bucket: Mutex::new((rate as f64, Instant::now())),
+		}
+	}
+
+	pub fn is_limited(&self) -> bool {
+		self.rate > 0
+	}
+
+	/// Takes tokens for `amount` bytes, returns how long the caller should
+	/// wait before sending or receiving more.
+	pub fn take(&self, amount: u64) -> Duration {
+		if !self.is_limited() {
+			return Duration::from_secs(0);
+		}
+		let mut bucket = self.bucket.lock();
+		let now = Instant::now();
+		let elapsed = now.duration_since(bucket.1);
+		let refill =
+			(elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9) * self.rate as f64;
+		bucket.0 = (bucket.0 + refill).min(self.rate as f64) - amount as f64;
+		bucket.1 = now;
+		if bucket.0 >= 0.0 {
+			Duration::from_secs(0)
+		} else {
+			Duration::from_millis((-bucket.0 * 1000.0 / self.rate as f64) as u64)
+		}
+	}
+}
+
+/// Number of message types we keep traffic counters for.
+const MSG_TYPES: usize = Type::KernelProof as usize + 1;
+
+/// Traffic of a single message type, cumulative since startup.
+#[derive(Clone, Debug)]
+pub struct MsgTraffic {
+	pub msg_type: Type,
+	pub sent_msgs: u64,
+	pub sent_bytes: u64,
+	pub received_msgs: u64,
+	pub received_bytes: u64,
+}
+
+/// Messages and bytes sent and received by the whole server, per message
+/// type. Counters only ever go up, even as peers come and go.
+pub struct TrafficStats {
+	// [sent msgs, sent bytes, received msgs, received bytes] per message type
+	counters: Vec<[AtomicU64; 4]>,
+}
+
+impl TrafficStats {
+	pub fn new() -> TrafficStats {
+		TrafficStats {
+			counters: (0..MSG_TYPES)
+				.map(|_| {
+					[
+						AtomicU64::new(0),
+						AtomicU64::new(0),
+						AtomicU64::new(0),
+						AtomicU64::new(0),
+					]
+				})
+				.collect(),
+		}
+	}
+
+	/// Accounts for a message sent, `msgs` being 0 for the extra bytes of an
+	/// attachment.
+	pub fn inc_sent(&self, msg_type: Type, msgs: u64, bytes: u64) {
+		let c = &self.counters[msg_type as usize];
+		c[0].fetch_add(msgs, Ordering::Relaxed);
+		c[1].fetch_add(bytes, Ordering::Relaxed);
+	}
+
+	/// Accounts for a message received.
+	pub fn inc_received(&self, msg_type: Type, bytes: u64) {
+		let c = &self.counters[msg_type as usize];
+		c[2].fetch_add(1, Ordering::Relaxed);
+		c[3].fetch_add(bytes, Ordering::Relaxed);
+	}
+
+	/// Traffic of all the message types we've seen at least once.
+	pub fn snapshot(&self) -> Vec<MsgTraffic> {
+		self.counters
+			.iter()
+			.enumerate()
+			.filter_map(|(i, c)| {
+				let msg_type = Type::from_u8(i as u8)?;
+				let traffic = MsgTraffic {
+					msg_type,
+					sent_msgs: c[0].load(Ordering::Relaxed),
+					sent_bytes: c[1].load(Ordering::Relaxed),
+					received_msgs: c[2].load(Ordering::Relaxed),
+					received_bytes: c[3].load(Ordering::Relaxed),
+				};
+				if traffic.sent_msgs + traffic.sent_bytes + traffic.received_msgs > 0 {
+					Some(traffic)
+				} else {
+					None
+				}
+			})
+			.collect()
+	}
+}
+
+/// Bandwidth limits of the whole server, shared by all its connections. Also
+/// holds the per-peer rates each connection gets its own buckets for, and
+/// the server wide traffic counters.
+pub struct BandwidthLimits {
+	upload: RateLimiter,
+	download: RateLimiter,
+	txhashset_upload: RateLimiter,
+	block_upload: RateLimiter,
+	peer_upload_rate: u64,
+	peer_download_rate: u64,
+	traffic: Arc<TrafficStats>,
+}
+
+impl BandwidthLimits {
+	pub fn new(config: &P2PConfig) -> BandwidthLimits {
+		BandwidthLimits {
+			upload: RateLimiter::new(config.max_upload_rate()),
+			download: RateLimiter::new(config.max_download_rate()),
+			txhashset_upload: RateLimiter::new(config.txhashset_max_upload_rate()),
+			block_upload: RateLimiter::new(config.block_max_upload_rate()),
+			peer_upload_rate: config.peer_max_upload_rate(),
+			peer_download_rate: config.peer_max_download_rate(),
+			traffic: Arc::new(TrafficStats::new()),
+		}
+	}
+
+	/// Traffic counters of all the connections sharing these limits.
+	pub fn traffic(&self) -> Arc<TrafficStats> {
+		self.traffic.clone()
+	}
+
+	/// No limit at all, mostly for tests.
+	pub fn unlimited() -> BandwidthLimits {
+		BandwidthLimits::new(&P2PConfig::default())
+	}
+}
+
+/// Bandwidth limits applying to a single connection, its own per-peer buckets
+/// plus the server wide ones.
+pub struct ConnLimits {
+	global: Arc<BandwidthLimits>,
+	upload: RateLimiter,
+	download: RateLimiter,
+	stopped: Arc<AtomicBool>,
+}
+
+impl ConnLimits {
+	fn new(global: Arc<BandwidthLimits>, stopped: Arc<AtomicBool>) -> ConnLimits {
+		ConnLimits {
+			upload: RateLimiter::new(global.peer_upload_rate),
+			download: RateLimiter::new(global.peer_download_rate),
+			global,
+			stopped,
+		}
+	}
+
+	/// Accounts for a message (or part of it) about to be sent, blocking
+	/// until all the buckets it draws from allow it. TxHashSet archives and
+	/// segments, and blocks, also draw from their own budget.
+	fn sending(&self, msg_type: Type, len: u64) {
+		let mut wait = cmp::max(self.upload.take(len), self.global.upload.take(len));
+		match msg_type {
+			Type::TxHashSetArchive | Type::Segment => {
+				wait = cmp::max(wait, self.global.txhashset_upload.take(len));
+			}
+			Type::Block | Type::CompactBlock | Type::BlockTxs => {
+				wait = cmp::max(wait, self.global.block_upload.take(len));
+			}
+			_ => {}
+		}
+		self.wait(wait);
+	}
+
+	/// Accounts for received bytes, blocking until the buckets allow reading
+	/// more.
+	fn received(&self, len: u64) {
+		let wait = cmp::max(self.download.take(len), self.global.download.take(len));
+		self.wait(wait);
+	}
+
+	fn traffic(&self) -> &TrafficStats {
+		&self.global.traffic
+	}
+
+	// Sleeps in small steps so a stopped connection doesn't hang around.
+	fn wait(&self, mut wait: Duration) {
+		while wait > Duration::from_secs(0) && !self.stopped.load(Ordering::Relaxed) {
+			let step = cmp::min(wait, IO_TIMEOUT);
+			thread::sleep(step);
+			wait -= step;
+		}
+	}
+}
+
+/// Reads from the connection within the download limits.
+struct LimitedReader {
+	stream: SecureStream,
+	limits: Arc<ConnLimits>,
+}
+
+impl Read for LimitedReader {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let n = self.stream.read(buf)?;
+		self.limits.received(n as u64);
+		Ok(n)
+	}
+}
+
 pub struct StopHandle {
 	/// Channel to close the connection
 	stopped: Arc<AtomicBool>,
@@ -221,9 +455,10 @@ impl StopHandle {
 	}
 }
 
+#[derive(Clone)]
 pub struct ConnHandle {
 	/// Channel to allow sending data through the connection
-	pub send_channel: mpsc::SyncSender<Vec<u8>>,
+	pub send_channel: mpsc::SyncSender<(Type, Vec<u8>)>,
 }
 
 impl ConnHandle {
@@ -233,7 +468,7 @@ impl ConnHandle {
 	{
 		let buf = write_to_buf(body, msg_type, version)?;
 		let buf_len = buf.len();
-		self.send_channel.try_send(buf)?;
+		self.send_channel.try_send((msg_type, buf))?;
 		Ok(buf_len as u64)
 	}
 }
@@ -274,11 +509,14 @@ impl Tracker {
 
 /// Start listening on the provided connection and wraps it. Does not hang
 /// the current thread, instead just returns a future and the Connection
-/// itself.
+/// itself. All traffic goes through the encrypted session if one was
+/// negotiated during the handshake, and within the bandwidth limits.
 pub fn listen<H>(
 	stream: TcpStream,
+	session: Option<Session>,
 	version: ProtocolVersion,
 	tracker: Arc<Tracker>,
+	limits: Arc<BandwidthLimits>,
 	handler: H,
 ) -> io::Result<(ConnHandle, StopHandle)>
 where
@@ -294,10 +532,19 @@ where
 		.set_write_timeout(Some(IO_TIMEOUT))
 		.expect("can't set read timeout");
 
+	let stream = SecureStream::new(stream, session);
 	let stopped = Arc::new(AtomicBool::new(false));
-
-	let (reader_thread, writer_thread) =
-		poll(stream, version, handler, send_rx, stopped.clone(), tracker)?;
+	let limits = Arc::new(ConnLimits::new(limits, stopped.clone()));
+
+	let (reader_thread, writer_thread) = poll(
+		stream,
+		version,
+		handler,
+		send_rx,
+		stopped.clone(),
+		tracker,
+		limits,
+	)?;
 
 	Ok((
 		ConnHandle {
@@ -312,21 +559,26 @@ where
 }
 
 fn poll<H>(
-	conn: TcpStream,
+	conn: SecureStream,
 	version: ProtocolVersion,
 	handler: H,
-	send_rx: mpsc::Receiver<Vec<u8>>,
+	send_rx: mpsc::Receiver<(Type, Vec<u8>)>,
 	stopped: Arc<AtomicBool>,
 	tracker: Arc<Tracker>,
+	limits: Arc<ConnLimits>,
 ) -> io::Result<(JoinHandle<()>, JoinHandle<()>)>
 where
 	H: MessageHandler,
 {
 	// Split out tcp stream out into separate reader/writer halves.
-	let mut reader = conn.try_clone().expect("clone conn for reader failed");
+	let mut reader = LimitedReader {
+		stream: conn.try_clone().expect("clone conn for reader failed"),
+		limits: limits.clone(),
+	};
 	let mut writer = conn.try_clone().expect("clone conn for writer failed");
 	let mut responder = conn.try_clone().expect("clone conn for writer failed");
 	let reader_stopped = stopped.clone();
+	let writer_limits = limits.clone();
 
 	let reader_thread = thread::Builder::new()
 		.name("peer_read".to_string())
@@ -345,11 +597,15 @@ where
 
 						// Increase received bytes counter
 						tracker.inc_received(MsgHeader::LEN as u64 + msg.header.msg_len);
+						limits.traffic().inc_received(
+							msg.header.msg_type,
+							MsgHeader::LEN as u64 + msg.header.msg_len,
+						);
 
 						if let Some(Some(resp)) =
 							try_break!(handler.consume(msg, &mut responder, tracker.clone()))
 						{
-							try_break!(resp.write(tracker.clone()));
+							try_break!(resp.write(tracker.clone(), &limits));
 						}
 					}
 					Some(MsgHeaderWrapper::Unknown(msg_len)) => {
@@ -375,11 +631,12 @@ where
 			debug!(
 				"Shutting down reader connection with {}",
 				reader
+					.stream
 					.peer_addr()
 					.map(|a| a.to_string())
 					.unwrap_or("?".to_owned())
 			);
-			let _ = reader.shutdown(Shutdown::Both);
+			let _ = reader.stream.shutdown(Shutdown::Both);
 		})?;
 
 	let writer_thread = thread::Builder::new()
@@ -390,10 +647,17 @@ where
 			loop {
 				let maybe_data = retry_send.or_else(|_| send_rx.recv_timeout(IO_TIMEOUT));
 				retry_send = Err(());
-				if let Ok(data) = maybe_data {
+				if let Ok((msg_type, data)) = maybe_data {
+					// only account for the data once, not on retries
+					if retry_count == 0 {
+						writer_limits.sending(msg_type, data.len() as u64);
+						writer_limits
+							.traffic()
+							.inc_sent(msg_type, 1, data.len() as u64);
+					}
 					let written = try_break!(writer.write_all(&data[..]).map_err(&From::from));
 					if written.is_none() {
-						retry_send = Ok(data);
+						retry_send = Ok((msg_type, data));
 						retry_count += 1;
 						// stop retrying if always fail on sending
 						if retry_count > 10 {
@@ -426,3 +690,37 @@ where
 		})?;
 	Ok((reader_thread, writer_thread))
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn conn_limits(txhashset_rate: u64) -> ConnLimits {
+		let config = P2PConfig {
+			txhashset_max_upload_rate: Some(txhashset_rate),
+			..P2PConfig::default()
+		};
+		ConnLimits::new(
+			Arc::new(BandwidthLimits::new(&config)),
+			Arc::new(AtomicBool::new(false)),
+		)
+	}
+
+	#[test]
+	fn segments_draw_from_txhashset_budget() {
+		let limits = conn_limits(1_000);
+		limits.sending(Type::Segment, 1_000);
+		assert!(limits.global.txhashset_upload.take(500) > Duration::from_millis(0));
+
+		let limits = conn_limits(1_000);
+		limits.sending(Type::TxHashSetArchive, 1_000);
+		assert!(limits.global.txhashset_upload.take(500) > Duration::from_millis(0));
+
+		let limits = conn_limits(1_000);
+		limits.sending(Type::Headers, 1_000);
+		assert_eq!(
+			limits.global.txhashset_upload.take(500),
+			Duration::from_millis(0)
+		);
+	}
+}
@@ -13,16 +13,20 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::conn::BandwidthLimits;
 use crate::core::core::hash::Hash;
 use crate::core::pow::Difficulty;
 use crate::core::ser::ProtocolVersion;
-use crate::msg::{read_message, write_message, Hand, Shake, Type, USER_AGENT};
+use crate::msg::{read_message_bytes, write_to_buf, Hand, Shake, Type, USER_AGENT};
+use crate::noise::{self, NodeKey, Session};
 use crate::peer::Peer;
 use crate::types::{Capabilities, Direction, Error, P2PConfig, PeerAddr, PeerInfo, PeerLiveInfo};
 use crate::util::RwLock;
 use rand::{thread_rng, Rng};
+use std::cmp;
 use std::collections::VecDeque;
-use std::net::{SocketAddr, TcpStream};
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream};
 use std::sync::Arc;
 
 /// Local generated nonce for peer connecting.
@@ -47,6 +51,13 @@ pub struct Handshake {
 	/// ok).
 	genesis: Hash,
 	config: P2PConfig,
+	/// Our static key for the encrypted transport, if enabled.
+	node_key: Option<Arc<NodeKey>>,
+	/// The protocol version we advertise, the version used on a connection is
+	/// the lower of ours and the one advertised by the peer.
+	protocol_version: ProtocolVersion,
+	/// Bandwidth limits shared by all the connections we handshake.
+	pub limits: Arc<BandwidthLimits>,
 }
 
 impl Handshake {
@@ -56,27 +67,56 @@ impl Handshake {
 			nonces: Arc::new(RwLock::new(VecDeque::with_capacity(NONCES_CAP))),
 			addrs: Arc::new(RwLock::new(VecDeque::with_capacity(ADDRS_CAP))),
 			genesis,
+			node_key: None,
+			protocol_version: ProtocolVersion::local(),
+			limits: Arc::new(BandwidthLimits::new(&config)),
 			config,
 		}
 	}
 
+	/// Overrides the protocol version we advertise, mainly for testing
+	/// compatibility between peers running different versions.
+	pub fn with_protocol_version(mut self, version: ProtocolVersion) -> Handshake {
+		self.protocol_version = version;
+		self
+	}
+
+	/// Enables the encrypted transport with peers also supporting it.
+	pub fn with_node_key(mut self, node_key: NodeKey) -> Handshake {
+		self.node_key = Some(Arc::new(node_key));
+		self
+	}
+
 	pub fn initiate(
 		&self,
 		capabilities: Capabilities,
 		total_difficulty: Difficulty,
 		height: u64,
 		self_addr: PeerAddr,
+		peer_addr: PeerAddr,
 		conn: &mut TcpStream,
-	) -> Result<PeerInfo, Error> {
-		// prepare the first part of the handshake
+	) -> Result<(PeerInfo, Option<Session>), Error> {
+		// prepare the first part of the handshake, the peer address is provided
+		// as our connection may go through a proxy
 		let nonce = self.next_nonce();
-		let peer_addr = match conn.peer_addr() {
-			Ok(pa) => PeerAddr(pa),
-			Err(e) => return Err(Error::Connection(e)),
-		};
+		let peer_addr = peer_addr.canonical();
+
+		// Hand and Shake are encoded the same way across protocol versions,
+		// we use our own version until the peer's one is known.
+		let version = self.protocol_version;
 
-		// Using our default "local" protocol version.
-		let version = ProtocolVersion::local();
+		// Not listening, or connecting through a proxy (e.g. Tor), we can't be
+		// reached anyway and must not reveal any address of ours.
+		let (sender_addr, external_addrs) =
+			if self.config.listen() && self.config.socks5_proxy.is_none() {
+				(
+					self_addr,
+					self.config.external_addrs.clone().unwrap_or(vec![]),
+				)
+			} else {
+				let unroutable = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+				(PeerAddr(unroutable), vec![])
+			};
 
 		let hand = Hand {
 			version,
@@ -85,28 +125,35 @@ impl Handshake {
 			genesis: self.genesis,
 			total_difficulty,
 			height,
-			sender_addr: self_addr,
+			sender_addr,
 			receiver_addr: peer_addr,
 			user_agent: USER_AGENT.to_string(),
+			external_addrs,
 		};
 
-		// write and read the handshake response
-		write_message(conn, hand, Type::Hand, version)?;
+		// write and read the handshake response, the Noise prologue is made of
+		// both as they were on the wire, so what we don't understand of them
+		// (e.g. newer capabilities) still ends up the same on both sides
+		let mut prologue = write_to_buf(hand, Type::Hand, version)?;
+		conn.write_all(&prologue)?;
 
 		// Note: We have to read the Shake message *before* we know which protocol
 		// version our peer supports (it is in the shake message itself).
-		let shake: Shake = read_message(conn, version, Type::Shake)?;
+		let (shake, mut shake_bytes): (Shake, _) = read_message_bytes(conn, version, Type::Shake)?;
 		if shake.genesis != self.genesis {
 			return Err(Error::GenesisMismatch {
 				us: self.genesis,
 				peer: shake.genesis,
 			});
 		}
+		prologue.append(&mut shake_bytes);
+		let negotiated_version = self.negotiate_protocol_version(shake.version);
 		let peer_info = PeerInfo {
 			capabilities: shake.capabilities,
 			user_agent: shake.user_agent,
 			addr: peer_addr,
-			version: shake.version,
+			external_addrs: vec![],
+			version: negotiated_version,
 			live_info: Arc::new(RwLock::new(PeerLiveInfo::new(
 				shake.total_difficulty,
 				shake.height,
@@ -120,16 +167,30 @@ impl Handshake {
 			return Err(Error::ConnectionClose);
 		}
 
+		let session = match self.encryption_key(capabilities, peer_info.capabilities) {
+			Some(key) => Some(noise::initiate(conn, &key, &prologue)?),
+			None if self.config.require_encryption() => {
+				debug!(
+					"Peer {} does not support encryption, closing.",
+					peer_info.addr
+				);
+				return Err(Error::ConnectionClose);
+			}
+			None => None,
+		};
+
 		debug!(
-			"Connected! Cumulative {}@{} offered from {:?} {:?} {:?}",
+			"Connected! Cumulative {}@{} offered from {:?} {:?} {:?}, version {} (peer {}), encrypted: {}",
 			shake.total_difficulty.to_num(),
 			shake.height,
 			peer_info.addr,
 			peer_info.user_agent,
-			peer_info.capabilities
+			peer_info.capabilities,
+			peer_info.version,
+			shake.version,
+			session.is_some(),
 		);
-		// when more than one protocol version is supported, choosing should go here
-		Ok(peer_info)
+		Ok((peer_info, session))
 	}
 
 	pub fn accept(
@@ -138,12 +199,12 @@ impl Handshake {
 		total_difficulty: Difficulty,
 		height: u64,
 		conn: &mut TcpStream,
-	) -> Result<PeerInfo, Error> {
+	) -> Result<(PeerInfo, Option<Session>), Error> {
 		// Note: We read the Hand message *before* we know which protocol version
 		// is supported by our peer (in the Hand message).
-		let version = ProtocolVersion::local();
+		let version = self.protocol_version;
 
-		let hand: Hand = read_message(conn, version, Type::Hand)?;
+		let (hand, mut prologue): (Hand, _) = read_message_bytes(conn, version, Type::Hand)?;
 
 		// all the reasons we could refuse this connection for
 		if hand.genesis != self.genesis {
@@ -171,7 +232,8 @@ impl Handshake {
 			capabilities: hand.capabilities,
 			user_agent: hand.user_agent,
 			addr: resolve_peer_addr(hand.sender_addr, &conn),
-			version: hand.version,
+			external_addrs: hand.external_addrs,
+			version: self.negotiate_protocol_version(hand.version),
 			live_info: Arc::new(RwLock::new(PeerLiveInfo::new(
 				hand.total_difficulty,
 				hand.height,
@@ -187,6 +249,15 @@ impl Handshake {
 			return Err(Error::ConnectionClose);
 		}
 
+		let node_key = self.encryption_key(capab, peer_info.capabilities);
+		if node_key.is_none() && self.config.require_encryption() {
+			debug!(
+				"Peer {} does not support encryption, closing.",
+				peer_info.addr
+			);
+			return Err(Error::ConnectionClose);
+		}
+
 		// send our reply with our info
 		let shake = Shake {
 			version,
@@ -197,10 +268,39 @@ impl Handshake {
 			user_agent: USER_AGENT.to_string(),
 		};
 
-		write_message(conn, shake, Type::Shake, version)?;
-		trace!("Success handshake with {}.", peer_info.addr);
+		let mut shake_bytes = write_to_buf(shake, Type::Shake, version)?;
+		conn.write_all(&shake_bytes)?;
+		prologue.append(&mut shake_bytes);
+
+		let session = match node_key {
+			Some(key) => Some(noise::respond(conn, &key, &prologue)?),
+			None => None,
+		};
+		trace!(
+			"Success handshake with {}, version {} (peer {}), encrypted: {}.",
+			peer_info.addr,
+			peer_info.version,
+			hand.version,
+			session.is_some()
+		);
 
-		Ok(peer_info)
+		Ok((peer_info, session))
+	}
+
+	/// The version used for all messages on a connection, the highest one
+	/// both ends understand.
+	fn negotiate_protocol_version(&self, other: ProtocolVersion) -> ProtocolVersion {
+		cmp::min(self.protocol_version, other)
+	}
+
+	/// Our node key, if both ends of the connection offer the encrypted
+	/// transport.
+	fn encryption_key(&self, ours: Capabilities, theirs: Capabilities) -> Option<Arc<NodeKey>> {
+		if ours.contains(Capabilities::ENCRYPTED) && theirs.contains(Capabilities::ENCRYPTED) {
+			self.node_key.clone()
+		} else {
+			None
+		}
 	}
 
 	/// Generate a new random nonce and store it in our ring buffer
@@ -217,10 +317,12 @@ impl Handshake {
 }
 
 /// Resolve the correct peer_addr based on the connection and the advertised port.
+/// A peer advertising no port (not listening) is known by its connection's.
 fn resolve_peer_addr(advertised: PeerAddr, conn: &TcpStream) -> PeerAddr {
 	let port = advertised.0.port();
 	if let Ok(addr) = conn.peer_addr() {
-		PeerAddr(SocketAddr::new(addr.ip(), port))
+		let port = if port == 0 { addr.port() } else { port };
+		PeerAddr(SocketAddr::new(addr.ip(), port)).canonical()
 	} else {
 		advertised
 	}
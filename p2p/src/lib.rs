@@ -37,22 +37,28 @@ extern crate serde_derive;
 #[macro_use]
 extern crate log;
 
+pub mod addrman;
 mod conn;
 pub mod handshake;
 pub mod msg;
+pub mod noise;
 mod peer;
 mod peers;
 mod protocol;
 mod serv;
+pub mod socks;
 mod store;
 pub mod types;
 
-pub use crate::conn::SEND_CHANNEL_CAP;
+pub use crate::conn::{
+	BandwidthLimits, MsgTraffic, RateLimiter, TrafficStats, SEND_CHANNEL_CAP,
+};
 pub use crate::peer::Peer;
 pub use crate::peers::Peers;
 pub use crate::serv::{DummyAdapter, Server};
-pub use crate::store::{PeerData, State};
+pub use crate::store::{BanEntry, PeerData, State};
 pub use crate::types::{
-	Capabilities, ChainAdapter, Direction, Error, P2PConfig, PeerAddr, PeerInfo, ReasonForBan,
-	Seeding, TxHashSetRead, MAX_BLOCK_HEADERS, MAX_LOCATORS, MAX_PEER_ADDRS,
+	Capabilities, ChainAdapter, Direction, Error, P2PConfig, PeerAddr, PeerInfo, PeerScore,
+	Penalty, ReasonForBan, Seeding, Subnet, TxHashSetRead, MAX_BLOCK_HEADERS, MAX_LOCATORS,
+	MAX_PEER_ADDRS,
 };
@@ -15,18 +15,26 @@
 
 //! Message types that transit over the network and related serialization code.
 
+use crate::chain::{
+	KernelProof, OutputProof, SegmentType, TxHashSetSegment, TXHASHSET_SEGMENT_HEIGHT,
+};
 use crate::core::core::hash::Hash;
-use crate::core::core::BlockHeader;
+use crate::core::core::id::ShortId;
+use crate::core::core::pmmr::SegmentIdentifier;
+use crate::core::core::{BlockHeader, Transaction};
 use crate::core::pow::Difficulty;
 use crate::core::ser::{
 	self, FixedLength, ProtocolVersion, Readable, Reader, StreamingReader, Writeable, Writer,
 };
 use crate::core::{consensus, global};
 use crate::types::{
-	Capabilities, Error, PeerAddr, ReasonForBan, MAX_BLOCK_HEADERS, MAX_LOCATORS, MAX_PEER_ADDRS,
+	Capabilities, Error, PeerAddr, ReasonForBan, MAX_ANNOUNCED_HEADERS, MAX_BLOCK_HEADERS,
+	MAX_EXTERNAL_ADDRS, MAX_LOCATORS, MAX_PEER_ADDRS, MAX_TX_PACKAGE_SIZE,
 };
+use crate::util::secp::pedersen::Commitment;
 use num::FromPrimitive;
-use std::io::{Read, Write};
+use std::cmp;
+use std::io::{self, Read, Write};
 
 /// Gotts's user agent with current version
 pub const USER_AGENT: &'static str = concat!("MW/Gotts ", env!("CARGO_PKG_VERSION"));
@@ -65,6 +73,17 @@ enum_from_primitive! {
 		TransactionKernel = 20,
 		KernelDataRequest = 21,
 		KernelDataResponse = 22,
+		TransactionPackage = 23,
+		GetBlockTxs = 24,
+		BlockTxs = 25,
+		SendHeaders = 26,
+		HeadersAnnouncement = 27,
+		GetSegment = 28,
+		Segment = 29,
+		GetOutputProof = 30,
+		OutputProof = 31,
+		GetKernelProof = 32,
+		KernelProof = 33,
 	}
 }
 
@@ -73,6 +92,18 @@ fn max_block_size() -> u64 {
 	(global::max_block_weight() / consensus::BLOCK_OUTPUT_WEIGHT * 708) as u64
 }
 
+/// Max size of a txhashset segment, all leaves (and as many hashes) present,
+/// with a merkle proof covering a MMR of up to 2^64 positions.
+fn max_segment_size() -> u64 {
+	let capacity = 1u64 << TXHASHSET_SEGMENT_HEIGHT;
+	32 + 1 + 9 + 3 * 8 + capacity * (8 + 32) + capacity * (8 + 256) + capacity * 8 + 16 + 32 * 64
+}
+
+/// Max size of a merkle proof, covering a MMR of up to 2^64 positions.
+fn max_merkle_proof_size() -> u64 {
+	8 + 8 + 32 * 64
+}
+
 // Max msg size when msg type is unknown.
 fn default_max_msg_size() -> u64 {
 	max_block_size()
@@ -82,7 +113,7 @@ fn default_max_msg_size() -> u64 {
 fn max_msg_size(msg_type: Type) -> u64 {
 	match msg_type {
 		Type::Error => 0,
-		Type::Hand => 128,
+		Type::Hand => 128 + 1 + (1 + 16 + 2) * MAX_EXTERNAL_ADDRS as u64,
 		Type::Shake => 88,
 		Type::Ping => 16,
 		Type::Pong => 16,
@@ -104,6 +135,17 @@ fn max_msg_size(msg_type: Type) -> u64 {
 		Type::TransactionKernel => 32,
 		Type::KernelDataRequest => 0,
 		Type::KernelDataResponse => 8,
+		Type::TransactionPackage => max_block_size(),
+		Type::GetBlockTxs => max_block_size() / 10,
+		Type::BlockTxs => max_block_size(),
+		Type::SendHeaders => 0,
+		Type::HeadersAnnouncement => 2 + 365 * MAX_ANNOUNCED_HEADERS as u64,
+		Type::GetSegment => 32 + 1 + 9,
+		Type::Segment => max_segment_size(),
+		Type::GetOutputProof => 33,
+		Type::OutputProof => 33 + 1 + 708 + 8 + 8 + 32 + max_merkle_proof_size(),
+		Type::GetKernelProof => 33,
+		Type::KernelProof => 33 + 1 + 128 + 8 + 8 + 32 + max_merkle_proof_size(),
 	}
 }
 
@@ -183,6 +225,33 @@ pub fn read_message<T: Readable>(
 	}
 }
 
+/// Reads a full message like `read_message`, also returning its bytes as
+/// they were on the wire, header included.
+pub fn read_message_bytes<T: Readable>(
+	stream: &mut dyn Read,
+	version: ProtocolVersion,
+	msg_type: Type,
+) -> Result<(T, Vec<u8>), Error> {
+	let mut bytes = vec![0u8; MsgHeader::LEN];
+	stream.read_exact(&mut bytes)?;
+	match ser::deserialize::<MsgHeaderWrapper>(&mut &bytes[..], version)? {
+		MsgHeaderWrapper::Known(header) => {
+			if header.msg_type != msg_type {
+				return Err(Error::BadMessage);
+			}
+			let mut body = vec![0u8; header.msg_len as usize];
+			stream.read_exact(&mut body)?;
+			let msg = ser::deserialize(&mut &body[..], version)?;
+			bytes.append(&mut body);
+			Ok((msg, bytes))
+		}
+		MsgHeaderWrapper::Unknown(msg_len) => {
+			read_discard(msg_len, stream)?;
+			Err(Error::BadMessage)
+		}
+	}
+}
+
 pub fn write_to_buf<T: Writeable>(
 	msg: T,
 	msg_type: Type,
@@ -335,6 +404,9 @@ pub struct Hand {
 	pub receiver_addr: PeerAddr,
 	/// name of version of the software
 	pub user_agent: String,
+	/// publicly reachable addresses of the sender, appended at the end so older
+	/// peers just ignore them
+	pub external_addrs: Vec<PeerAddr>,
 }
 
 impl Writeable for Hand {
@@ -351,6 +423,11 @@ impl Writeable for Hand {
 		self.receiver_addr.write(writer)?;
 		writer.write_bytes(&self.user_agent)?;
 		self.genesis.write(writer)?;
+		let count = cmp::min(self.external_addrs.len(), MAX_EXTERNAL_ADDRS as usize);
+		writer.write_u8(count as u8)?;
+		for addr in self.external_addrs.iter().take(count) {
+			addr.write(writer)?;
+		}
 		Ok(())
 	}
 }
@@ -367,6 +444,19 @@ impl Readable for Hand {
 		let ua = reader.read_bytes_len_prefix()?;
 		let user_agent = String::from_utf8(ua).map_err(|_| ser::Error::CorruptedData)?;
 		let genesis = Hash::read(reader)?;
+		// older peers don't send their external addresses
+		let count = match reader.read_u8() {
+			Ok(count) => count,
+			Err(ser::Error::IOErr(_, io::ErrorKind::UnexpectedEof)) => 0,
+			Err(e) => return Err(e),
+		};
+		if count as u32 > MAX_EXTERNAL_ADDRS {
+			return Err(ser::Error::TooLargeReadErr);
+		}
+		let mut external_addrs = Vec::with_capacity(count as usize);
+		for _ in 0..count {
+			external_addrs.push(PeerAddr::read(reader)?);
+		}
 		Ok(Hand {
 			version,
 			capabilities,
@@ -377,6 +467,7 @@ impl Readable for Hand {
 			sender_addr,
 			receiver_addr,
 			user_agent,
+			external_addrs,
 		})
 	}
 }
@@ -558,6 +649,20 @@ impl Writeable for Headers {
 	}
 }
 
+impl Readable for Headers {
+	fn read(reader: &mut dyn Reader) -> Result<Headers, ser::Error> {
+		let count = reader.read_u16()?;
+		if count as u32 > MAX_BLOCK_HEADERS {
+			return Err(ser::Error::TooLargeReadErr);
+		}
+		let mut headers = Vec::with_capacity(count as usize);
+		for _ in 0..count {
+			headers.push(BlockHeader::read(reader)?);
+		}
+		Ok(Headers { headers })
+	}
+}
+
 pub struct Ping {
 	/// total difficulty accumulated by the sender, used to check whether sync
 	/// may be needed
@@ -697,6 +802,172 @@ impl Readable for TxHashSetArchive {
 	}
 }
 
+/// Request for a segment of one of the txhashset MMRs, at the given (archive)
+/// block, to sync a new node from many peers in parallel.
+pub struct SegmentRequest {
+	/// Hash of the block the txhashset segment is requested at
+	pub block_hash: Hash,
+	/// Which MMR the segment is part of
+	pub segment_type: SegmentType,
+	/// Height and index of the segment
+	pub identifier: SegmentIdentifier,
+}
+
+impl Writeable for SegmentRequest {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.block_hash.write(writer)?;
+		self.segment_type.write(writer)?;
+		self.identifier.write(writer)?;
+		Ok(())
+	}
+}
+
+impl Readable for SegmentRequest {
+	fn read(reader: &mut dyn Reader) -> Result<SegmentRequest, ser::Error> {
+		Ok(SegmentRequest {
+			block_hash: Hash::read(reader)?,
+			segment_type: SegmentType::read(reader)?,
+			identifier: SegmentIdentifier::read(reader)?,
+		})
+	}
+}
+
+/// Response to a segment request, the segment with its merkle proof against
+/// the corresponding root of the block header.
+pub struct SegmentResponse {
+	/// Hash of the block the txhashset segment is at
+	pub block_hash: Hash,
+	/// The segment
+	pub segment: TxHashSetSegment,
+}
+
+impl Writeable for SegmentResponse {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.block_hash.write(writer)?;
+		self.segment.write(writer)?;
+		Ok(())
+	}
+}
+
+impl Readable for SegmentResponse {
+	fn read(reader: &mut dyn Reader) -> Result<SegmentResponse, ser::Error> {
+		Ok(SegmentResponse {
+			block_hash: Hash::read(reader)?,
+			segment: TxHashSetSegment::read(reader)?,
+		})
+	}
+}
+
+/// Request of an unspent output, with a merkle proof of its inclusion
+/// against the peer head header, from a light node.
+pub struct OutputProofRequest {
+	/// Commitment of the output
+	pub commit: Commitment,
+}
+
+impl Writeable for OutputProofRequest {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.commit.write(writer)
+	}
+}
+
+impl Readable for OutputProofRequest {
+	fn read(reader: &mut dyn Reader) -> Result<OutputProofRequest, ser::Error> {
+		Ok(OutputProofRequest {
+			commit: Commitment::read(reader)?,
+		})
+	}
+}
+
+/// Response to an output proof request, without proof if the output isn't
+/// in the peer UTXO set.
+pub struct OutputProofResponse {
+	/// Commitment of the requested output
+	pub commit: Commitment,
+	/// The output with its merkle proof, if unspent
+	pub proof: Option<OutputProof>,
+}
+
+impl Writeable for OutputProofResponse {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.commit.write(writer)?;
+		match self.proof {
+			Some(ref proof) => {
+				writer.write_u8(1)?;
+				proof.write(writer)
+			}
+			None => writer.write_u8(0),
+		}
+	}
+}
+
+impl Readable for OutputProofResponse {
+	fn read(reader: &mut dyn Reader) -> Result<OutputProofResponse, ser::Error> {
+		let commit = Commitment::read(reader)?;
+		let proof = match reader.read_u8()? {
+			0 => None,
+			1 => Some(OutputProof::read(reader)?),
+			_ => return Err(ser::Error::CorruptedData),
+		};
+		Ok(OutputProofResponse { commit, proof })
+	}
+}
+
+/// Request of a tx kernel, with a merkle proof of its inclusion against the
+/// peer head header, from a light node.
+pub struct KernelProofRequest {
+	/// Public excess of the kernel
+	pub excess: Commitment,
+}
+
+impl Writeable for KernelProofRequest {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.excess.write(writer)
+	}
+}
+
+impl Readable for KernelProofRequest {
+	fn read(reader: &mut dyn Reader) -> Result<KernelProofRequest, ser::Error> {
+		Ok(KernelProofRequest {
+			excess: Commitment::read(reader)?,
+		})
+	}
+}
+
+/// Response to a kernel proof request, without proof if the peer doesn't
+/// have the kernel.
+pub struct KernelProofResponse {
+	/// Public excess of the requested kernel
+	pub excess: Commitment,
+	/// The kernel with its merkle proof, if found
+	pub proof: Option<KernelProof>,
+}
+
+impl Writeable for KernelProofResponse {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.excess.write(writer)?;
+		match self.proof {
+			Some(ref proof) => {
+				writer.write_u8(1)?;
+				proof.write(writer)
+			}
+			None => writer.write_u8(0),
+		}
+	}
+}
+
+impl Readable for KernelProofResponse {
+	fn read(reader: &mut dyn Reader) -> Result<KernelProofResponse, ser::Error> {
+		let excess = Commitment::read(reader)?;
+		let proof = match reader.read_u8()? {
+			0 => None,
+			1 => Some(KernelProof::read(reader)?),
+			_ => return Err(ser::Error::CorruptedData),
+		};
+		Ok(KernelProofResponse { excess, proof })
+	}
+}
+
 pub struct KernelDataRequest {}
 
 impl Writeable for KernelDataRequest {
@@ -705,6 +976,16 @@ impl Writeable for KernelDataRequest {
 	}
 }
 
+/// Asks a peer to announce new blocks with their headers, if it supports it
+/// (Capabilities::HEADERS_ANNOUNCE).
+pub struct SendHeaders {}
+
+impl Writeable for SendHeaders {
+	fn write<W: Writer>(&self, _writer: &mut W) -> Result<(), ser::Error> {
+		Ok(())
+	}
+}
+
 pub struct KernelDataResponse {
 	/// Size in bytes of the attached kernel data file.
 	pub bytes: u64,
@@ -723,3 +1004,119 @@ impl Readable for KernelDataResponse {
 		Ok(KernelDataResponse { bytes })
 	}
 }
+
+/// A package of dependent transactions, topologically ordered (parents before
+/// children), to be accepted by the receiving pool as a unit.
+pub struct TransactionPackage {
+	/// Whether the package is relayed in Dandelion stem phase.
+	pub stem: bool,
+	/// Txs only spending outputs from the chain, the pool or a preceding tx.
+	pub txs: Vec<Transaction>,
+}
+
+impl Writeable for TransactionPackage {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		ser_multiwrite!(
+			writer,
+			[write_u8, self.stem as u8],
+			[write_u16, self.txs.len() as u16]
+		);
+		for tx in &self.txs {
+			tx.write(writer)?;
+		}
+		Ok(())
+	}
+}
+
+impl Readable for TransactionPackage {
+	fn read(reader: &mut dyn Reader) -> Result<TransactionPackage, ser::Error> {
+		let (stem, count) = ser_multiread!(reader, read_u8, read_u16);
+		if count > MAX_TX_PACKAGE_SIZE {
+			return Err(ser::Error::TooLargeReadErr);
+		} else if count == 0 {
+			return Err(ser::Error::CorruptedData);
+		}
+		let mut txs = Vec::with_capacity(count as usize);
+		for _ in 0..count {
+			txs.push(Transaction::read(reader)?);
+		}
+		Ok(TransactionPackage {
+			stem: stem != 0,
+			txs,
+		})
+	}
+}
+
+/// Request for the txs of a compact block we could not find in our pool,
+/// identified by their kernel short_ids (built from the block hash and nonce).
+pub struct GetBlockTxs {
+	/// Hash of the (compact) block.
+	pub hash: Hash,
+	/// Nonce of the compact block, used to build the kernel short_ids.
+	pub nonce: u64,
+	/// Kernel short_ids of the missing txs.
+	pub short_ids: Vec<ShortId>,
+}
+
+impl Writeable for GetBlockTxs {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.hash.write(writer)?;
+		ser_multiwrite!(
+			writer,
+			[write_u64, self.nonce],
+			[write_u16, self.short_ids.len() as u16]
+		);
+		for id in &self.short_ids {
+			id.write(writer)?;
+		}
+		Ok(())
+	}
+}
+
+impl Readable for GetBlockTxs {
+	fn read(reader: &mut dyn Reader) -> Result<GetBlockTxs, ser::Error> {
+		let hash = Hash::read(reader)?;
+		let (nonce, count) = ser_multiread!(reader, read_u64, read_u16);
+		let mut short_ids = Vec::with_capacity(count as usize);
+		for _ in 0..count {
+			short_ids.push(ShortId::read(reader)?);
+		}
+		Ok(GetBlockTxs {
+			hash,
+			nonce,
+			short_ids,
+		})
+	}
+}
+
+/// Txs of a compact block, in response to GetBlockTxs.
+/// May not contain all the requested txs if some are unknown to the sender.
+pub struct BlockTxs {
+	/// Hash of the (compact) block.
+	pub hash: Hash,
+	/// The requested txs the sender could find.
+	pub txs: Vec<Transaction>,
+}
+
+impl Writeable for BlockTxs {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.hash.write(writer)?;
+		writer.write_u16(self.txs.len() as u16)?;
+		for tx in &self.txs {
+			tx.write(writer)?;
+		}
+		Ok(())
+	}
+}
+
+impl Readable for BlockTxs {
+	fn read(reader: &mut dyn Reader) -> Result<BlockTxs, ser::Error> {
+		let hash = Hash::read(reader)?;
+		let count = reader.read_u16()?;
+		let mut txs = Vec::with_capacity(count as usize);
+		for _ in 0..count {
+			txs.push(Transaction::read(reader)?);
+		}
+		Ok(BlockTxs { hash, txs })
+	}
+}
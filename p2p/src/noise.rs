@@ -0,0 +1,470 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Optional encrypted and authenticated transport for peer connections.
+//!
+//! When both peers advertise `Capabilities::ENCRYPTED` in their Hand/Shake,
+//! a Noise XX handshake is run right after it, using the persistent node
+//! static key on each side. The Hand and Shake, byte for byte as sent and
+//! received, are used as the Noise prologue, so any tampering with the
+//! plaintext negotiation makes the Noise handshake fail, once it runs.
+//!
+//! It only runs if both sides see the other advertising the capability
+//! though. An active attacker stripping `ENCRYPTED` from both the Hand and
+//! the Shake downgrades the connection to plaintext, undetected. Nodes that
+//! can't accept this must set `require_encryption`, refusing any peer that
+//! does not advertise the encrypted transport.
+//!
+//! After the handshake all traffic is sent as length prefixed frames, each
+//! frame being a single Noise transport message.
+
+use crate::types::Error;
+use crate::util::{to_hex, Mutex};
+use snow::{Builder, HandshakeState, StatelessTransportState};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Noise protocol used for the encrypted transport.
+const NOISE_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+
+/// File (in the node data dir) the node static key is persisted to.
+const NODE_KEY_FILE: &str = "p2p_node.key";
+
+/// Length of the x25519 private and public keys.
+const KEY_LEN: usize = 32;
+
+/// Maximum size of a single Noise message, as defined by the spec.
+const MAX_NOISE_MSG_LEN: usize = 65535;
+
+/// Size of the authentication tag appended to every transport message.
+const TAG_LEN: usize = 16;
+
+/// Maximum plaintext carried by a single frame.
+const MAX_FRAME_PAYLOAD: usize = MAX_NOISE_MSG_LEN - TAG_LEN;
+
+/// How many read or write timeouts we tolerate in the middle of a frame
+/// before giving up on the connection. A partially transferred frame can't
+/// be resumed later without breaking the nonce sequence.
+const MAX_FRAME_TIMEOUTS: usize = 30;
+
+/// The static x25519 keypair identifying this node on encrypted connections.
+pub struct NodeKey {
+	private: Vec<u8>,
+	public: Vec<u8>,
+}
+
+impl NodeKey {
+	/// Generates a new random node key.
+	pub fn generate() -> Result<NodeKey, Error> {
+		let keypair = builder()?.generate_keypair().map_err(noise_err)?;
+		Ok(NodeKey {
+			private: keypair.private,
+			public: keypair.public,
+		})
+	}
+
+	/// Loads the node key persisted in the provided data dir, generating and
+	/// saving a new one on first use.
+	pub fn load_or_create(db_root: &str) -> Result<NodeKey, Error> {
+		let path = Path::new(db_root).join(NODE_KEY_FILE);
+		if path.exists() {
+			let mut bytes = vec![];
+			File::open(&path)?.read_to_end(&mut bytes)?;
+			if bytes.len() != 2 * KEY_LEN {
+				return Err(Error::Encryption(format!(
+					"invalid node key file {}",
+					path.display()
+				)));
+			}
+			let public = bytes.split_off(KEY_LEN);
+			return Ok(NodeKey {
+				private: bytes,
+				public,
+			});
+		}
+
+		let key = NodeKey::generate()?;
+		fs::create_dir_all(db_root)?;
+		let mut file = File::create(&path)?;
+		file.write_all(&key.private)?;
+		file.write_all(&key.public)?;
+		file.sync_all()?;
+		set_owner_only(&path)?;
+		info!("Generated new p2p node key at {}", path.display());
+		Ok(key)
+	}
+
+	/// Public key of this node, as seen by our peers.
+	pub fn public_key(&self) -> &[u8] {
+		&self.public
+	}
+}
+
+#[cfg(unix)]
+fn set_owner_only(path: &Path) -> io::Result<()> {
+	use std::os::unix::fs::PermissionsExt;
+	fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+}
+
+#[cfg(not(unix))]
+fn set_owner_only(_path: &Path) -> io::Result<()> {
+	Ok(())
+}
+
+/// An established encrypted session with a peer. Each direction keeps its
+/// own nonce, the send side lock also serializes frame writes on the socket.
+pub struct Session {
+	transport: StatelessTransportState,
+	send_nonce: Mutex<u64>,
+	recv_nonce: Mutex<u64>,
+}
+
+impl Session {
+	fn new(handshake: HandshakeState) -> Result<Session, Error> {
+		let transport = handshake
+			.into_stateless_transport_mode()
+			.map_err(noise_err)?;
+		Ok(Session {
+			transport,
+			send_nonce: Mutex::new(0),
+			recv_nonce: Mutex::new(0),
+		})
+	}
+
+	/// The authenticated static key of our peer.
+	pub fn remote_key(&self) -> Option<&[u8]> {
+		self.transport.get_remote_static()
+	}
+}
+
+/// Runs the initiator side of the Noise XX handshake.
+///   -> e
+///   <- e, ee, s, es
+///   -> s, se
+pub fn initiate(conn: &mut TcpStream, key: &NodeKey, prologue: &[u8]) -> Result<Session, Error> {
+	let mut hs = builder()?
+		.local_private_key(&key.private)
+		.prologue(prologue)
+		.build_initiator()
+		.map_err(noise_err)?;
+	write_handshake_msg(conn, &mut hs)?;
+	read_handshake_msg(conn, &mut hs)?;
+	write_handshake_msg(conn, &mut hs)?;
+	let session = Session::new(hs)?;
+	trace!(
+		"Encrypted session with {:?}, remote key {}",
+		conn.peer_addr(),
+		to_hex(session.remote_key().unwrap_or(&[]).to_vec())
+	);
+	Ok(session)
+}
+
+/// Runs the responder side of the Noise XX handshake.
+pub fn respond(conn: &mut TcpStream, key: &NodeKey, prologue: &[u8]) -> Result<Session, Error> {
+	let mut hs = builder()?
+		.local_private_key(&key.private)
+		.prologue(prologue)
+		.build_responder()
+		.map_err(noise_err)?;
+	read_handshake_msg(conn, &mut hs)?;
+	write_handshake_msg(conn, &mut hs)?;
+	read_handshake_msg(conn, &mut hs)?;
+	let session = Session::new(hs)?;
+	trace!(
+		"Encrypted session with {:?}, remote key {}",
+		conn.peer_addr(),
+		to_hex(session.remote_key().unwrap_or(&[]).to_vec())
+	);
+	Ok(session)
+}
+
+fn builder<'a>() -> Result<Builder<'a>, Error> {
+	let params = NOISE_PARAMS.parse().map_err(noise_err)?;
+	Ok(Builder::new(params))
+}
+
+fn noise_err(e: snow::Error) -> Error {
+	Error::Encryption(e.to_string())
+}
+
+fn write_handshake_msg(conn: &mut TcpStream, hs: &mut HandshakeState) -> Result<(), Error> {
+	let mut buf = vec![0u8; MAX_NOISE_MSG_LEN];
+	let len = hs.write_message(&[], &mut buf).map_err(noise_err)?;
+	conn.write_all(&(len as u16).to_be_bytes())?;
+	conn.write_all(&buf[..len])?;
+	Ok(())
+}
+
+fn read_handshake_msg(conn: &mut TcpStream, hs: &mut HandshakeState) -> Result<(), Error> {
+	let mut len = [0u8; 2];
+	conn.read_exact(&mut len)?;
+	let mut msg = vec![0u8; u16::from_be_bytes(len) as usize];
+	conn.read_exact(&mut msg)?;
+	let mut payload = vec![0u8; MAX_NOISE_MSG_LEN];
+	hs.read_message(&msg, &mut payload).map_err(noise_err)?;
+	Ok(())
+}
+
+/// A peer connection, either plain TCP or encrypted with the session
+/// negotiated during the handshake. Clones share the same session so the
+/// connection can be split into reader and writer halves.
+pub struct SecureStream {
+	stream: TcpStream,
+	session: Option<Arc<Session>>,
+	read_buf: Vec<u8>,
+	read_pos: usize,
+}
+
+impl SecureStream {
+	pub fn new(stream: TcpStream, session: Option<Session>) -> SecureStream {
+		SecureStream {
+			stream,
+			session: session.map(Arc::new),
+			read_buf: vec![],
+			read_pos: 0,
+		}
+	}
+
+	/// Whether the traffic on this stream is encrypted.
+	pub fn is_encrypted(&self) -> bool {
+		self.session.is_some()
+	}
+
+	pub fn try_clone(&self) -> io::Result<SecureStream> {
+		Ok(SecureStream {
+			stream: self.stream.try_clone()?,
+			session: self.session.clone(),
+			read_buf: vec![],
+			read_pos: 0,
+		})
+	}
+
+	pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+		self.stream.peer_addr()
+	}
+
+	pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+		self.stream.shutdown(how)
+	}
+
+	pub fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+		self.stream.set_read_timeout(dur)
+	}
+
+	pub fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+		self.stream.set_write_timeout(dur)
+	}
+
+	/// Reads and decrypts the next frame into our read buffer. Returns false
+	/// on a clean end of stream.
+	fn read_frame(&mut self, session: &Session) -> io::Result<bool> {
+		let mut len = [0u8; 2];
+		if !read_frame_bytes(&mut self.stream, &mut len)? {
+			return Ok(false);
+		}
+		let mut msg = vec![0u8; u16::from_be_bytes(len) as usize];
+		if msg.len() < TAG_LEN || !read_frame_bytes(&mut self.stream, &mut msg)? {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidData,
+				"truncated encrypted frame",
+			));
+		}
+
+		let mut nonce = session.recv_nonce.lock();
+		self.read_buf.resize(msg.len(), 0);
+		let len = session
+			.transport
+			.read_message(*nonce, &msg, &mut self.read_buf)
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+		*nonce += 1;
+		self.read_buf.truncate(len);
+		self.read_pos = 0;
+		Ok(true)
+	}
+
+	/// Encrypts and writes a single frame, returning the number of plaintext
+	/// bytes consumed.
+	fn write_frame(&mut self, session: &Session, buf: &[u8]) -> io::Result<usize> {
+		let payload = &buf[..buf.len().min(MAX_FRAME_PAYLOAD)];
+		let mut frame = vec![0u8; 2 + payload.len() + TAG_LEN];
+
+		// Hold the nonce for the whole frame so concurrent writers on clones
+		// of this stream can't interleave or reorder frames.
+		let mut nonce = session.send_nonce.lock();
+		let len = session
+			.transport
+			.write_message(*nonce, payload, &mut frame[2..])
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+		// A nonce must never be reused, even if the frame is not sent.
+		*nonce += 1;
+		frame[..2].copy_from_slice(&(len as u16).to_be_bytes());
+		write_frame_bytes(&mut self.stream, &frame[..2 + len])?;
+		Ok(payload.len())
+	}
+}
+
+impl Read for SecureStream {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let session = match self.session {
+			Some(ref session) => session.clone(),
+			None => return self.stream.read(buf),
+		};
+		while self.read_pos == self.read_buf.len() {
+			if !self.read_frame(&session)? {
+				return Ok(0);
+			}
+		}
+		let n = buf.len().min(self.read_buf.len() - self.read_pos);
+		buf[..n].copy_from_slice(&self.read_buf[self.read_pos..self.read_pos + n]);
+		self.read_pos += n;
+		Ok(n)
+	}
+}
+
+impl Write for SecureStream {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		match self.session {
+			Some(ref session) => {
+				let session = session.clone();
+				self.write_frame(&session, buf)
+			}
+			None => self.stream.write(buf),
+		}
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.stream.flush()
+	}
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+	e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock
+}
+
+/// Fills the buffer with frame bytes. A timeout before anything is read is
+/// returned as is (the connection is just idle), once a frame has started we
+/// keep waiting for the rest of it. Returns false on a clean end of stream.
+fn read_frame_bytes(stream: &mut TcpStream, buf: &mut [u8]) -> io::Result<bool> {
+	let mut read = 0;
+	let mut timeouts = 0;
+	while read < buf.len() {
+		match stream.read(&mut buf[read..]) {
+			Ok(0) if read == 0 => return Ok(false),
+			Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+			Ok(n) => read += n,
+			Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+			Err(e) => {
+				if !is_timeout(&e) || read == 0 {
+					return Err(e);
+				}
+				timeouts += 1;
+				if timeouts > MAX_FRAME_TIMEOUTS {
+					return Err(io::Error::new(
+						io::ErrorKind::Other,
+						"timed out in the middle of an encrypted frame",
+					));
+				}
+			}
+		}
+	}
+	Ok(true)
+}
+
+/// Writes a whole frame. As with reads, a timeout is only returned as is if
+/// nothing was written yet.
+fn write_frame_bytes(stream: &mut TcpStream, buf: &[u8]) -> io::Result<()> {
+	let mut written = 0;
+	let mut timeouts = 0;
+	while written < buf.len() {
+		match stream.write(&buf[written..]) {
+			Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+			Ok(n) => written += n,
+			Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+			Err(e) => {
+				if !is_timeout(&e) || written == 0 {
+					return Err(e);
+				}
+				timeouts += 1;
+				if timeouts > MAX_FRAME_TIMEOUTS {
+					return Err(io::Error::new(
+						io::ErrorKind::Other,
+						"timed out in the middle of an encrypted frame",
+					));
+				}
+			}
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use std::net::TcpListener;
+	use std::thread;
+
+	#[test]
+	fn encrypted_roundtrip() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let server_key = NodeKey::generate().unwrap();
+		let server_pub = server_key.public_key().to_vec();
+
+		let server = thread::spawn(move || {
+			let (mut conn, _) = listener.accept().unwrap();
+			let session = respond(&mut conn, &server_key, b"prologue").unwrap();
+			let mut stream = SecureStream::new(conn, Some(session));
+			let mut buf = vec![0u8; 100_000];
+			stream.read_exact(&mut buf).unwrap();
+			stream.write_all(&buf).unwrap();
+		});
+
+		let client_key = NodeKey::generate().unwrap();
+		let mut conn = TcpStream::connect(addr).unwrap();
+		let session = initiate(&mut conn, &client_key, b"prologue").unwrap();
+		assert_eq!(session.remote_key(), Some(&server_pub[..]));
+
+		// Larger than a single frame.
+		let data: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+		let mut stream = SecureStream::new(conn, Some(session));
+		assert!(stream.is_encrypted());
+		stream.write_all(&data).unwrap();
+		let mut echo = vec![0u8; data.len()];
+		stream.read_exact(&mut echo).unwrap();
+		assert_eq!(echo, data);
+		server.join().unwrap();
+	}
+
+	#[test]
+	fn prologue_mismatch() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let server_key = NodeKey::generate().unwrap();
+
+		let server = thread::spawn(move || {
+			let (mut conn, _) = listener.accept().unwrap();
+			respond(&mut conn, &server_key, b"hand and shake").is_err()
+		});
+
+		let client_key = NodeKey::generate().unwrap();
+		let mut conn = TcpStream::connect(addr).unwrap();
+		assert!(initiate(&mut conn, &client_key, b"tampered shake").is_err());
+		let _ = conn.shutdown(Shutdown::Both);
+		assert!(server.join().unwrap());
+	}
+}
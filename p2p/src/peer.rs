@@ -21,26 +21,38 @@ use std::net::{Shutdown, TcpStream};
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use crate::chain;
+use crate::chain::{KernelProof, OutputProof, SegmentType, TxHashSetSegment};
 use crate::conn;
 use crate::core::core::hash::{Hash, Hashed};
+use crate::core::core::id::ShortId;
+use crate::core::core::pmmr::SegmentIdentifier;
 use crate::core::pow::Difficulty;
-use crate::core::ser::Writeable;
+use crate::core::ser::{ProtocolVersion, Writeable};
 use crate::core::{core, global};
 use crate::handshake::Handshake;
 use crate::msg::{
-	self, BanReason, GetPeerAddrs, KernelDataRequest, Locator, Ping, TxHashSetRequest, Type,
+	self, BanReason, GetBlockTxs, GetPeerAddrs, Headers, KernelDataRequest, KernelProofRequest,
+	Locator, OutputProofRequest, Ping, SegmentRequest, SendHeaders, TransactionPackage,
+	TxHashSetRequest, Type,
 };
+use crate::noise::Session;
 use crate::protocol::Protocol;
 use crate::types::{
-	Capabilities, ChainAdapter, Error, NetAdapter, P2PConfig, PeerAddr, PeerInfo, ReasonForBan,
-	TxHashSetRead,
+	Capabilities, ChainAdapter, Error, NetAdapter, P2PConfig, PeerAddr, PeerInfo, Penalty,
+	ReasonForBan, TxHashSetRead, MAX_ANNOUNCED_HEADERS,
 };
+use crate::util::secp::pedersen::Commitment;
 use chrono::prelude::{DateTime, Utc};
 
 const MAX_TRACK_SIZE: usize = 30;
 const MAX_PEER_MSG_PER_MIN: u64 = 2500;
+/// After a header announcement, the headers following within this delay are
+/// batched and announced together.
+const HEADERS_ANNOUNCE_WINDOW: Duration = Duration::from_millis(500);
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 /// Remind: don't mix up this 'State' with that 'State' in p2p/src/store.rs,
@@ -64,6 +76,8 @@ pub struct Peer {
 	stop_handle: Mutex<conn::StopHandle>,
 	// Whether or not we requested a txhashset from this peer
 	state_sync_requested: Arc<AtomicBool>,
+	// Headers to announce to the peer, if it asked for it
+	announcer: Arc<Mutex<HeadersAnnouncer>>,
 }
 
 impl fmt::Debug for Peer {
@@ -74,7 +88,13 @@ impl fmt::Debug for Peer {
 
 impl Peer {
 	// Only accept and connect can be externally used to build a peer
-	fn new(info: PeerInfo, conn: TcpStream, adapter: Arc<dyn NetAdapter>) -> std::io::Result<Peer> {
+	fn new(
+		info: PeerInfo,
+		conn: TcpStream,
+		session: Option<Session>,
+		limits: Arc<conn::BandwidthLimits>,
+		adapter: Arc<dyn NetAdapter>,
+	) -> std::io::Result<Peer> {
 		let state = Arc::new(RwLock::new(State::Connected));
 		let state_sync_requested = Arc::new(AtomicBool::new(false));
 		let tracking_adapter = TrackingAdapter::new(adapter);
@@ -84,7 +104,14 @@ impl Peer {
 			state_sync_requested.clone(),
 		);
 		let tracker = Arc::new(conn::Tracker::new());
-		let (sendh, stoph) = conn::listen(conn, info.version, tracker.clone(), handler)?;
+		let (sendh, stoph) = conn::listen(
+			conn,
+			session,
+			info.version,
+			tracker.clone(),
+			limits,
+			handler,
+		)?;
 		let send_handle = Mutex::new(sendh);
 		let stop_handle = Mutex::new(stoph);
 		Ok(Peer {
@@ -95,6 +122,7 @@ impl Peer {
 			send_handle,
 			stop_handle,
 			state_sync_requested,
+			announcer: Arc::new(Mutex::new(HeadersAnnouncer::default())),
 		})
 	}
 
@@ -109,7 +137,7 @@ impl Peer {
 		debug!("accept: handshaking from {:?}", conn.peer_addr());
 		let info = hs.accept(capab, total_difficulty, height, &mut conn);
 		match info {
-			Ok(info) => Ok(Peer::new(info, conn, adapter)?),
+			Ok((info, session)) => Ok(Peer::new(info, conn, session, hs.limits.clone(), adapter)?),
 			Err(e) => {
 				debug!(
 					"accept: handshaking from {:?} failed with error: {:?}",
@@ -130,18 +158,25 @@ impl Peer {
 		total_difficulty: Difficulty,
 		height: u64,
 		self_addr: PeerAddr,
+		peer_addr: PeerAddr,
 		hs: &Handshake,
 		adapter: Arc<dyn NetAdapter>,
 	) -> Result<Peer, Error> {
-		debug!("connect: handshaking with {:?}", conn.peer_addr());
-		let info = hs.initiate(capab, total_difficulty, height, self_addr, &mut conn);
+		debug!("connect: handshaking with {}", peer_addr);
+		let info = hs.initiate(
+			capab,
+			total_difficulty,
+			height,
+			self_addr,
+			peer_addr,
+			&mut conn,
+		);
 		match info {
-			Ok(info) => Ok(Peer::new(info, conn, adapter)?),
+			Ok((info, session)) => Ok(Peer::new(info, conn, session, hs.limits.clone(), adapter)?),
 			Err(e) => {
 				debug!(
-					"connect: handshaking with {:?} failed with error: {:?}",
-					conn.peer_addr(),
-					e
+					"connect: handshaking with {} failed with error: {:?}",
+					peer_addr, e
 				);
 				if let Err(e) = conn.shutdown(Shutdown::Both) {
 					debug!("Error shutting down conn: {:?}", e);
@@ -307,6 +342,61 @@ impl Peer {
 		}
 	}
 
+	/// Whether the peer asked us to announce new blocks with their headers
+	/// (SendHeaders).
+	pub fn prefers_headers(&self) -> bool {
+		self.info.live_info.read().send_headers
+	}
+
+	/// Asks the peer to announce new blocks with their headers.
+	pub fn send_headers_request(&self) -> Result<(), Error> {
+		debug!("Asking {} for headers announcements", self.info.addr);
+		self.send(&SendHeaders {}, msg::Type::SendHeaders)
+	}
+
+	/// Announces a new block header to a peer having asked for it. The first
+	/// header is sent right away, the ones quickly following are batched into
+	/// a single message.
+	pub fn announce_header(&self, bh: &core::BlockHeader) -> Result<bool, Error> {
+		if self.tracking_adapter.has_recv(bh.hash()) {
+			debug!(
+				"Suppress header announcement {} to {} (already seen)",
+				bh.hash(),
+				self.info.addr,
+			);
+			return Ok(false);
+		}
+
+		let mut announcer = self.announcer.lock();
+		announcer.pending.push(bh.clone());
+		if announcer.flush_scheduled {
+			return Ok(true);
+		}
+		match announcer.last_sent.map(|t| t.elapsed()) {
+			Some(elapsed) if elapsed < HEADERS_ANNOUNCE_WINDOW => {
+				announcer.flush_scheduled = true;
+				let delay = HEADERS_ANNOUNCE_WINDOW - elapsed;
+				let announcer = self.announcer.clone();
+				let send_handle = self.send_handle.lock().clone();
+				let tracker = self.tracker.clone();
+				let version = self.info.version;
+				let addr = self.info.addr;
+				thread::Builder::new()
+					.name("announce_headers".to_string())
+					.spawn(move || {
+						thread::sleep(delay);
+						let mut announcer = announcer.lock();
+						announcer.flush_scheduled = false;
+						if let Err(e) = announcer.flush(&send_handle, version, &tracker) {
+							debug!("Failed to announce headers to {}: {:?}", addr, e);
+						}
+					})?;
+			}
+			_ => announcer.flush(&self.send_handle.lock(), self.info.version, &self.tracker)?,
+		}
+		Ok(true)
+	}
+
 	pub fn send_tx_kernel_hash(&self, h: Hash) -> Result<bool, Error> {
 		if !self.tracking_adapter.has_recv(h) {
 			debug!("Send tx kernel hash {} to {}", h, self.info.addr);
@@ -358,9 +448,76 @@ impl Peer {
 		self.send(tx, msg::Type::StemTransaction)
 	}
 
+	/// Sends the provided package of dependent transactions to the remote peer.
+	/// The package is always sent in full (never as kernel hashes) so the
+	/// remote pool can accept it as a unit. The request may be dropped if the
+	/// remote peer is known to already have all the transactions.
+	/// A peer not supporting packages gets the transactions one by one,
+	/// parents first.
+	pub fn send_transaction_package(&self, txs: &[core::Transaction]) -> Result<bool, Error> {
+		if !self.info.capabilities.contains(Capabilities::TX_PACKAGE) {
+			let mut sent = false;
+			for tx in txs {
+				sent |= self.send_transaction(tx)?;
+			}
+			return Ok(sent);
+		}
+
+		if txs
+			.iter()
+			.all(|tx| self.tracking_adapter.has_recv(tx.kernels()[0].hash()))
+		{
+			debug!(
+				"Not sending tx package ({} txs) to {} (already seen)",
+				txs.len(),
+				self.info.addr
+			);
+			return Ok(false);
+		}
+
+		debug!("Send tx package ({} txs) to {}", txs.len(), self.info.addr);
+		self.send(
+			&TransactionPackage {
+				stem: false,
+				txs: txs.to_vec(),
+			},
+			msg::Type::TransactionPackage,
+		)?;
+		Ok(true)
+	}
+
+	/// Sends the provided package of dependent stem transactions to the remote peer.
+	/// Note: tracking adapter is ignored for stem transactions (while under
+	/// embargo).
+	/// A peer not supporting packages gets the transactions one by one,
+	/// parents first.
+	pub fn send_stem_transaction_package(&self, txs: &[core::Transaction]) -> Result<(), Error> {
+		if !self.info.capabilities.contains(Capabilities::TX_PACKAGE) {
+			for tx in txs {
+				self.send_stem_transaction(tx)?;
+			}
+			return Ok(());
+		}
+
+		debug!(
+			"Send (stem) tx package ({} txs) to {}",
+			txs.len(),
+			self.info.addr
+		);
+		self.send(
+			&TransactionPackage {
+				stem: true,
+				txs: txs.to_vec(),
+			},
+			msg::Type::TransactionPackage,
+		)
+	}
+
 	/// Sends a request for block headers from the provided block locator
 	pub fn send_header_request(&self, locator: Vec<Hash>) -> Result<(), Error> {
-		self.send(&Locator { hashes: locator }, msg::Type::GetHeaders)
+		self.send(&Locator { hashes: locator }, msg::Type::GetHeaders)?;
+		self.info.header_requested();
+		Ok(())
 	}
 
 	pub fn send_tx_request(&self, h: Hash) -> Result<(), Error> {
@@ -384,6 +541,30 @@ impl Peer {
 		self.send(&h, msg::Type::GetCompactBlock)
 	}
 
+	/// Sends a request for the txs of a compact block we could not find in
+	/// our pool, identified by their kernel short_ids.
+	pub fn send_block_txs_request(
+		&self,
+		h: Hash,
+		nonce: u64,
+		short_ids: Vec<ShortId>,
+	) -> Result<(), Error> {
+		debug!(
+			"Requesting {} txs of compact block {} from {}",
+			short_ids.len(),
+			h,
+			self.info.addr
+		);
+		self.send(
+			&GetBlockTxs {
+				hash: h,
+				nonce,
+				short_ids,
+			},
+			msg::Type::GetBlockTxs,
+		)
+	}
+
 	pub fn send_peer_request(&self, capab: Capabilities) -> Result<(), Error> {
 		trace!("Asking {} for more peers {:?}", self.info.addr, capab);
 		self.send(
@@ -406,6 +587,43 @@ impl Peer {
 		)
 	}
 
+	/// Asks the peer for a segment of the txhashset at the given (archive)
+	/// block.
+	pub fn send_segment_request(
+		&self,
+		block_hash: Hash,
+		segment_type: SegmentType,
+		identifier: SegmentIdentifier,
+	) -> Result<(), Error> {
+		trace!(
+			"Asking {} for txhashset segment {:?} {:?} at {}",
+			self.info.addr,
+			segment_type,
+			identifier,
+			block_hash
+		);
+		self.send(
+			&SegmentRequest {
+				block_hash,
+				segment_type,
+				identifier,
+			},
+			msg::Type::GetSegment,
+		)
+	}
+
+	/// Asks the peer for an unspent output with its merkle proof.
+	pub fn send_output_proof_request(&self, commit: Commitment) -> Result<(), Error> {
+		trace!("Asking {} for output proof of {:?}", self.info.addr, commit);
+		self.send(&OutputProofRequest { commit }, msg::Type::GetOutputProof)
+	}
+
+	/// Asks the peer for a tx kernel with its merkle proof.
+	pub fn send_kernel_proof_request(&self, excess: Commitment) -> Result<(), Error> {
+		trace!("Asking {} for kernel proof of {:?}", self.info.addr, excess);
+		self.send(&KernelProofRequest { excess }, msg::Type::GetKernelProof)
+	}
+
 	pub fn send_kernel_data_request(&self) -> Result<(), Error> {
 		debug!("Asking {} for kernel data.", self.info.addr);
 		self.send(&KernelDataRequest {}, msg::Type::KernelDataRequest)
@@ -430,6 +648,42 @@ impl Peer {
 	}
 }
 
+/// New block headers waiting to be announced to a peer.
+#[derive(Default)]
+struct HeadersAnnouncer {
+	pending: Vec<core::BlockHeader>,
+	last_sent: Option<Instant>,
+	flush_scheduled: bool,
+}
+
+impl HeadersAnnouncer {
+	/// Sends all the pending headers, in a single message. If too many piled
+	/// up only the most recent ones are announced, the peer syncing the rest.
+	fn flush(
+		&mut self,
+		send_handle: &conn::ConnHandle,
+		version: ProtocolVersion,
+		tracker: &conn::Tracker,
+	) -> Result<(), Error> {
+		if self.pending.is_empty() {
+			return Ok(());
+		}
+		let mut headers: Vec<core::BlockHeader> = self.pending.drain(..).collect();
+		let max = MAX_ANNOUNCED_HEADERS as usize;
+		if headers.len() > max {
+			headers.drain(..headers.len() - max);
+		}
+		self.last_sent = Some(Instant::now());
+		let bytes = if headers.len() == 1 {
+			send_handle.send(&headers[0], Type::Header, version)?
+		} else {
+			send_handle.send(Headers { headers }, Type::HeadersAnnouncement, version)?
+		};
+		tracker.inc_sent(bytes);
+		Ok(())
+	}
+}
+
 /// Adapter implementation that forwards everything to an underlying adapter
 /// but keeps track of the block and transaction hashes that were requested or
 /// received.
@@ -497,6 +751,24 @@ impl ChainAdapter for TrackingAdapter {
 		self.adapter.get_transaction(kernel_hash)
 	}
 
+	fn get_block_txs(
+		&self,
+		hash: Hash,
+		nonce: u64,
+		short_ids: &[ShortId],
+	) -> Vec<core::Transaction> {
+		self.adapter.get_block_txs(hash, nonce, short_ids)
+	}
+
+	fn block_txs_received(
+		&self,
+		hash: Hash,
+		txs: Vec<core::Transaction>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		self.adapter.block_txs_received(hash, txs, peer_info)
+	}
+
 	fn tx_kernel_received(
 		&self,
 		kernel_hash: Hash,
@@ -509,6 +781,7 @@ impl ChainAdapter for TrackingAdapter {
 	fn transaction_received(
 		&self,
 		tx: core::Transaction,
+		peer_info: &PeerInfo,
 		stem: bool,
 	) -> Result<bool, chain::Error> {
 		// Do not track the tx hash for stem txs.
@@ -518,7 +791,23 @@ impl ChainAdapter for TrackingAdapter {
 			let kernel = &tx.kernels()[0];
 			self.push_recv(kernel.hash());
 		}
-		self.adapter.transaction_received(tx, stem)
+		self.adapter.transaction_received(tx, peer_info, stem)
+	}
+
+	fn transaction_package_received(
+		&self,
+		txs: Vec<core::Transaction>,
+		peer_info: &PeerInfo,
+		stem: bool,
+	) -> Result<bool, chain::Error> {
+		// Same as for individual txs, do not track stem txs.
+		if !stem {
+			for tx in &txs {
+				self.push_recv(tx.kernels()[0].hash());
+			}
+		}
+		self.adapter
+			.transaction_package_received(txs, peer_info, stem)
 	}
 
 	fn block_received(
@@ -609,10 +898,63 @@ impl ChainAdapter for TrackingAdapter {
 			.txhashset_download_update(start_time, downloaded_size, total_size)
 	}
 
+	fn get_txhashset_segment(
+		&self,
+		h: Hash,
+		segment_type: SegmentType,
+		id: SegmentIdentifier,
+	) -> Option<TxHashSetSegment> {
+		self.adapter.get_txhashset_segment(h, segment_type, id)
+	}
+
+	fn txhashset_segment_received(
+		&self,
+		h: Hash,
+		segment: TxHashSetSegment,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		self.adapter
+			.txhashset_segment_received(h, segment, peer_info)
+	}
+
+	fn get_output_proof(&self, commit: Commitment) -> Option<OutputProof> {
+		self.adapter.get_output_proof(commit)
+	}
+
+	fn output_proof_received(
+		&self,
+		commit: Commitment,
+		proof: Option<OutputProof>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		self.adapter.output_proof_received(commit, proof, peer_info)
+	}
+
+	fn get_kernel_proof(&self, excess: Commitment) -> Option<KernelProof> {
+		self.adapter.get_kernel_proof(excess)
+	}
+
+	fn kernel_proof_received(
+		&self,
+		excess: Commitment,
+		proof: Option<KernelProof>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		self.adapter.kernel_proof_received(excess, proof, peer_info)
+	}
+
 	fn get_tmp_dir(&self) -> PathBuf {
 		self.adapter.get_tmp_dir()
 	}
 
+	fn peer_connected(&self, peer_info: &PeerInfo) {
+		self.adapter.peer_connected(peer_info)
+	}
+
+	fn peer_disconnected(&self, addr: PeerAddr) {
+		self.adapter.peer_disconnected(addr)
+	}
+
 	fn get_tmpfile_pathname(&self, tmpfile_name: String) -> PathBuf {
 		self.adapter.get_tmpfile_pathname(tmpfile_name)
 	}
@@ -623,14 +965,18 @@ impl NetAdapter for TrackingAdapter {
 		self.adapter.find_peer_addrs(capab)
 	}
 
-	fn peer_addrs_received(&self, addrs: Vec<PeerAddr>) {
-		self.adapter.peer_addrs_received(addrs)
+	fn peer_addrs_received(&self, addrs: Vec<PeerAddr>, peer_info: &PeerInfo) {
+		self.adapter.peer_addrs_received(addrs, peer_info)
 	}
 
 	fn peer_difficulty(&self, addr: PeerAddr, diff: Difficulty, height: u64) {
 		self.adapter.peer_difficulty(addr, diff, height)
 	}
 
+	fn penalize_peer(&self, addr: PeerAddr, penalty: Penalty) {
+		self.adapter.penalize_peer(addr, penalty)
+	}
+
 	fn is_banned(&self, addr: PeerAddr) -> bool {
 		self.adapter.is_banned(addr)
 	}
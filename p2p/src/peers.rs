@@ -14,6 +14,7 @@
 // limitations under the License.
 
 use crate::util::RwLock;
+use std::cmp;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
@@ -23,36 +24,56 @@ use std::sync::Arc;
 use rand::seq::SliceRandom;
 use rand::thread_rng;
 
+use crate::addrman::{addr_group, AddrManager};
 use crate::chain;
+use crate::chain::{KernelProof, OutputProof, SegmentType, TxHashSetSegment};
 use crate::core::core;
 use crate::core::core::hash::{Hash, Hashed};
+use crate::core::core::id::ShortId;
+use crate::core::core::pmmr::SegmentIdentifier;
 use crate::core::global;
 use crate::core::pow::Difficulty;
 use crate::peer::Peer;
-use crate::store::{PeerData, PeerStore, State};
+use crate::store::{BanEntry, PeerData, PeerStore, State};
 use crate::types::{
-	Capabilities, ChainAdapter, Error, NetAdapter, P2PConfig, PeerAddr, PeerInfo, ReasonForBan,
-	TxHashSetRead, MAX_PEER_ADDRS,
+	Capabilities, ChainAdapter, Error, NetAdapter, P2PConfig, PeerAddr, PeerInfo, PeerScore,
+	Penalty, ReasonForBan, Subnet, TxHashSetRead, MAX_PEER_ADDRS, PEER_SCORE_BAN,
+	PEER_SCORE_BUCKET, PEER_SCORE_DISCONNECT,
 };
+use crate::util::secp::pedersen::Commitment;
 use chrono::prelude::*;
 use chrono::Duration;
 
 const LOCK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
 
+/// Number of outbound peers saved as anchors, the first peers we reconnect
+/// to on restart.
+const MAX_ANCHORS: usize = 2;
+
 pub struct Peers {
 	pub adapter: Arc<dyn ChainAdapter>,
 	store: PeerStore,
 	peers: RwLock<HashMap<PeerAddr, Arc<Peer>>>,
+	bans: RwLock<Vec<BanEntry>>,
+	addrs: RwLock<AddrManager>,
 	config: P2PConfig,
 }
 
 impl Peers {
 	pub fn new(store: PeerStore, adapter: Arc<dyn ChainAdapter>, config: P2PConfig) -> Peers {
+		// subnet bans are checked on every connection, keep them in memory
+		let bans = store.all_bans().unwrap_or_else(|e| {
+			error!("Couldn't load subnet bans: {:?}", e);
+			vec![]
+		});
+		let addrs = load_addrs(&store);
 		Peers {
 			adapter,
 			store,
 			config,
 			peers: RwLock::new(HashMap::new()),
+			bans: RwLock::new(bans),
+			addrs: RwLock::new(addrs),
 		}
 	}
 
@@ -66,6 +87,12 @@ impl Peers {
 				return Err(Error::Timeout);
 			}
 		};
+		// a reconnecting peer keeps its (decaying) misbehaviour score
+		let score = match self.store.get_peer(peer.info.addr) {
+			Ok(p) => p.score,
+			Err(_) => PeerScore::default(),
+		};
+		peer.info.live_info.write().score = score;
 		let peer_data = PeerData {
 			addr: peer.info.addr,
 			capabilities: peer.info.capabilities,
@@ -74,10 +101,40 @@ impl Peers {
 			last_banned: 0,
 			ban_reason: ReasonForBan::None,
 			last_connected: Utc::now().timestamp(),
+			score,
 		};
 		debug!("Saving newly connected peer {}.", peer_data.addr);
 		self.save_peer(&peer_data)?;
 		peers.insert(peer_data.addr, peer.clone());
+		self.adapter.peer_connected(&peer.info);
+		if self.config.headers_announce()
+			&& peer
+				.info
+				.capabilities
+				.contains(Capabilities::HEADERS_ANNOUNCE)
+		{
+			if let Err(e) = peer.send_headers_request() {
+				debug!(
+					"Failed to ask {} for headers announcements: {:?}",
+					peer_data.addr, e
+				);
+			}
+		}
+		if peer.info.is_outbound() {
+			self.addrs
+				.write()
+				.good(peer_data.addr, peer_data.last_connected);
+		} else {
+			// learn how the peer can be reached, possibly through another ip
+			// family than the one it connected from
+			let mut addrs = self.addrs.write();
+			for addr in &peer.info.external_addrs {
+				let ip = addr.ip();
+				if !ip.is_unspecified() && !ip.is_loopback() {
+					addrs.add(addr.canonical(), peer_data.addr, peer_data.last_connected);
+				}
+			}
+		}
 
 		Ok(())
 	}
@@ -93,6 +150,7 @@ impl Peers {
 			last_banned: Utc::now().timestamp(),
 			ban_reason,
 			last_connected: Utc::now().timestamp(),
+			score: PeerScore::default(),
 		};
 		debug!("Banning peer {}.", addr);
 		self.save_peer(&peer_data)
@@ -170,10 +228,12 @@ impl Peers {
 		self.incoming_connected_peers().len() as u32
 	}
 
-	// Return vec of connected peers that currently advertise more work
-	// (total_difficulty) than we do.
-	pub fn more_work_peers(&self) -> Result<Vec<Arc<Peer>>, chain::Error> {
-		let peers = self.connected_peers();
+	// Return vec of connected peers with the provided capabilities that
+	// currently advertise more work (total_difficulty) than we do. Light nodes
+	// advertise the work of their header chain, so only ask peers with the
+	// capabilities for what we need from them.
+	pub fn more_work_peers(&self, capab: Capabilities) -> Result<Vec<Arc<Peer>>, chain::Error> {
+		let peers = self.connected_peers_with(capab);
 		if peers.len() == 0 {
 			return Ok(vec![]);
 		}
@@ -185,7 +245,7 @@ impl Peers {
 			.filter(|x| x.info.total_difficulty() > total_difficulty)
 			.collect::<Vec<_>>();
 
-		max_peers.shuffle(&mut thread_rng());
+		sort_by_reliability(&mut max_peers);
 		Ok(max_peers)
 	}
 
@@ -205,10 +265,11 @@ impl Peers {
 			.count())
 	}
 
-	/// Returns single random peer with more work than us.
-	pub fn more_work_peer(&self) -> Option<Arc<Peer>> {
-		match self.more_work_peers() {
-			Ok(mut peers) => peers.pop(),
+	/// Returns single random peer with the provided capabilities and more
+	/// work than us, among the most reliable ones.
+	pub fn more_work_peer(&self, capab: Capabilities) -> Option<Arc<Peer>> {
+		match self.more_work_peers(capab) {
+			Ok(peers) => peers.into_iter().next(),
 			Err(e) => {
 				error!("failed to get more work peers: {:?}", e);
 				None
@@ -216,10 +277,11 @@ impl Peers {
 		}
 	}
 
-	/// Return vec of connected peers that currently have the most worked
-	/// branch, showing the highest total difficulty.
-	pub fn most_work_peers(&self) -> Vec<Arc<Peer>> {
-		let peers = self.connected_peers();
+	/// Return vec of connected peers with the provided capabilities that
+	/// currently have the most worked branch, showing the highest total
+	/// difficulty. The most reliable peers come first.
+	pub fn most_work_peers(&self, capab: Capabilities) -> Vec<Arc<Peer>> {
+		let peers = self.connected_peers_with(capab);
 		if peers.len() == 0 {
 			return vec![];
 		}
@@ -234,48 +296,332 @@ impl Peers {
 			.filter(|x| x.info.total_difficulty() == max_total_difficulty)
 			.collect::<Vec<_>>();
 
-		max_peers.shuffle(&mut thread_rng());
+		sort_by_reliability(&mut max_peers);
 		max_peers
 	}
 
-	/// Returns single random peer with the most worked branch, showing the
-	/// highest total difficulty.
-	pub fn most_work_peer(&self) -> Option<Arc<Peer>> {
-		self.most_work_peers().pop()
+	/// Returns single random peer with the provided capabilities and the
+	/// most worked branch, showing the highest total difficulty, among the
+	/// most reliable ones.
+	pub fn most_work_peer(&self, capab: Capabilities) -> Option<Arc<Peer>> {
+		self.most_work_peers(capab).into_iter().next()
 	}
 
+	// Connected peers with (at least) the provided capabilities.
+	fn connected_peers_with(&self, capab: Capabilities) -> Vec<Arc<Peer>> {
+		self.connected_peers()
+			.into_iter()
+			.filter(|p| p.info.capabilities.contains(capab))
+			.collect()
+	}
+
+	/// Whether the peer is banned, either on its own or as part of a banned
+	/// subnet.
 	pub fn is_banned(&self, peer_addr: PeerAddr) -> bool {
+		if self.is_subnet_banned(peer_addr) {
+			return true;
+		}
 		if let Ok(peer) = self.store.get_peer(peer_addr) {
 			return peer.flags == State::Banned;
 		}
 		false
 	}
 
+	fn is_subnet_banned(&self, peer_addr: PeerAddr) -> bool {
+		let now = Utc::now().timestamp();
+		self.bans
+			.read()
+			.iter()
+			.any(|b| !b.is_expired(now) && b.subnet.contains(peer_addr.0.ip()))
+	}
+
 	/// Ban a peer, disconnecting it if we're currently connected
 	pub fn ban_peer(&self, peer_addr: PeerAddr, ban_reason: ReasonForBan) {
-		if let Err(e) = self.update_state(peer_addr, State::Banned) {
+		if let Err(e) = self.store.ban_peer(peer_addr, ban_reason) {
 			error!("Couldn't ban {}: {:?}", peer_addr, e);
 			return;
 		}
 
 		if let Some(peer) = self.get_connected_peer(peer_addr) {
 			debug!("Banning peer {}", peer_addr);
-			// setting peer status will get it removed at the next clean_peer
-			match peer.send_ban_reason(ban_reason) {
-				Err(e) => error!("failed to send a ban reason to{}: {:?}", peer_addr, e),
-				Ok(_) => debug!("ban reason {:?} was sent to {}", ban_reason, peer_addr),
-			};
-			peer.set_banned();
+			self.disconnect_banned(&peer, ban_reason);
+		}
+	}
+
+	/// Ban a subnet (or a single address) until the ban expires, disconnecting
+	/// all the peers we're currently connected to in that subnet. Replaces any
+	/// previous ban on the same subnet.
+	pub fn ban_subnet(&self, ban: BanEntry) -> Result<(), Error> {
+		self.store.save_ban(&ban)?;
+		{
+			let mut bans = self.bans.write();
+			bans.retain(|b| b.subnet != ban.subnet);
+			bans.push(ban.clone());
+		}
+		info!(
+			"ban_subnet: {} banned ({:?}) until {:?}, note: {}",
+			ban.subnet, ban.reason, ban.expires, ban.note
+		);
+
+		for peer in self.connected_peers() {
+			if ban.subnet.contains(peer.info.addr.0.ip()) {
+				debug!("Banning peer {} in subnet {}", peer.info.addr, ban.subnet);
+				self.disconnect_banned(&peer, ban.reason);
+			}
+		}
+		Ok(())
+	}
+
+	/// Lift the ban on a subnet, the subnet must match the banned one exactly.
+	pub fn unban_subnet(&self, subnet: &Subnet) -> Result<(), Error> {
+		let found = {
+			let mut bans = self.bans.write();
+			let count = bans.len();
+			bans.retain(|b| b.subnet != *subnet);
+			bans.len() != count
+		};
+		if !found {
+			return Err(Error::Store(gotts_store::Error::NotFoundErr(format!(
+				"Ban on subnet: {}",
+				subnet
+			))));
+		}
+		debug!("unban_subnet: {}", subnet);
+		self.store.delete_ban(subnet).map_err(From::from)
+	}
+
+	/// How long a ban lasts unless specified otherwise, in seconds.
+	pub fn ban_window(&self) -> i64 {
+		self.config.ban_window()
+	}
+
+	/// All subnet bans currently in force.
+	pub fn bans(&self) -> Vec<BanEntry> {
+		let now = Utc::now().timestamp();
+		self.bans
+			.read()
+			.iter()
+			.filter(|b| !b.is_expired(now))
+			.cloned()
+			.collect()
+	}
+
+	/// Adds a list of subnet bans, usually exported from another node. Bans
+	/// that already expired are skipped. Returns the number of bans added.
+	pub fn import_bans(&self, bans: Vec<BanEntry>) -> Result<usize, Error> {
+		let now = Utc::now().timestamp();
+		let mut count = 0;
+		for mut ban in bans {
+			if ban.is_expired(now) {
+				continue;
+			}
+			if ban.banned_at == 0 {
+				ban.banned_at = now;
+			}
+			self.ban_subnet(ban)?;
+			count += 1;
+		}
+		Ok(count)
+	}
+
+	fn disconnect_banned(&self, peer: &Arc<Peer>, ban_reason: ReasonForBan) {
+		let peer_addr = peer.info.addr;
+		// setting peer status will get it removed at the next clean_peer
+		match peer.send_ban_reason(ban_reason) {
+			Err(e) => error!("failed to send a ban reason to{}: {:?}", peer_addr, e),
+			Ok(_) => debug!("ban reason {:?} was sent to {}", ban_reason, peer_addr),
+		};
+		peer.set_banned();
+		peer.stop();
+
+		let mut peers = match self.peers.try_write_for(LOCK_TIMEOUT) {
+			Some(peers) => peers,
+			None => {
+				error!("ban_peer: failed to get peers lock");
+				return;
+			}
+		};
+		if peers.remove(&peer_addr).is_some() {
+			self.adapter.peer_disconnected(peer_addr);
+		}
+	}
+
+	/// Picks up to `count` addresses to open outbound connections to from the
+	/// address manager, skipping peers we're connected to or banned. Also
+	/// keeps the number of outbound peers per subnet under the configured
+	/// limit, current outbound peers included.
+	pub fn select_outbound(&self, count: usize) -> Vec<PeerAddr> {
+		let mut subnets = self.outbound_per_subnet();
+
+		let candidates: Vec<PeerAddr> = {
+			let addrs = self.addrs.read();
+			let max_tries = cmp::min(count * 4, addrs.len() * 2);
+			(0..max_tries).filter_map(|_| addrs.select()).collect()
+		};
+
+		let mut selected = vec![];
+		for addr in candidates {
+			if selected.len() >= count {
+				break;
+			}
+			if selected.contains(&addr) || self.is_known(addr) || self.is_banned(addr) {
+				continue;
+			}
+			if self.take_subnet_slot(&mut subnets, &addr) {
+				selected.push(addr);
+			}
+		}
+		selected
+	}
+
+	/// Picks an address we never connected to, to test it with a short lived
+	/// feeler connection. Subject to the outbound limit per subnet too.
+	pub fn select_feeler(&self) -> Option<PeerAddr> {
+		let addr = self.addrs.read().select_new()?;
+		if self.is_known(addr) || self.is_banned(addr) {
+			return None;
+		}
+		if !self.take_subnet_slot(&mut self.outbound_per_subnet(), &addr) {
+			return None;
+		}
+		Some(addr)
+	}
+
+	// Number of outbound peers we're connected to in each subnet.
+	fn outbound_per_subnet(&self) -> HashMap<Subnet, usize> {
+		let mut subnets: HashMap<Subnet, usize> = HashMap::new();
+		for p in self.outgoing_connected_peers() {
+			if let Some(subnet) = addr_group(&p.info.addr) {
+				*subnets.entry(subnet).or_insert(0) += 1;
+			}
+		}
+		subnets
+	}
+
+	// Counts an outbound connection to the address in its subnet, false if
+	// the subnet is already at the limit.
+	fn take_subnet_slot(&self, subnets: &mut HashMap<Subnet, usize>, addr: &PeerAddr) -> bool {
+		let max_per_subnet = self.config.peer_max_outbound_per_subnet() as usize;
+		match addr_group(addr) {
+			Some(subnet) => {
+				let n = subnets.entry(subnet).or_insert(0);
+				if *n >= max_per_subnet {
+					return false;
+				}
+				*n += 1;
+				true
+			}
+			None => true,
+		}
+	}
+
+	/// Records an outbound connection attempt in the address manager.
+	pub fn mark_attempt(&self, addr: PeerAddr) {
+		self.addrs.write().attempt(&addr, Utc::now().timestamp());
+	}
+
+	/// Persists the address manager changes.
+	pub fn save_addrs(&self) {
+		let (changed, removed) = self.addrs.write().take_changes();
+		if changed.is_empty() && removed.is_empty() {
+			return;
+		}
+		if let Err(e) = self.store.save_known_addrs(&changed, &removed) {
+			error!("Couldn't save known addresses: {:?}", e);
+		}
+	}
+
+	/// Saves our most reliable outbound peers as anchors, so we reconnect to
+	/// them first on restart instead of relying on addresses an attacker
+	/// could have fed us in the meantime.
+	pub fn save_anchors(&self) {
+		let mut outbound = self.outgoing_connected_peers();
+		if outbound.is_empty() {
+			return;
+		}
+		sort_by_reliability(&mut outbound);
+		let anchors: Vec<PeerAddr> = outbound
+			.iter()
+			.take(MAX_ANCHORS)
+			.map(|p| p.info.addr)
+			.collect();
+		if let Err(e) = self.store.save_anchors(&anchors) {
+			error!("Couldn't save anchors: {:?}", e);
+		}
+	}
+
+	/// The anchors saved when we last ran, within the outbound limit per
+	/// subnet.
+	pub fn anchors(&self) -> Vec<PeerAddr> {
+		let anchors = self.store.anchors().unwrap_or_else(|e| {
+			error!("Couldn't load anchors: {:?}", e);
+			vec![]
+		});
+		let mut subnets = self.outbound_per_subnet();
+		anchors
+			.into_iter()
+			.filter(|addr| self.take_subnet_slot(&mut subnets, addr))
+			.collect()
+	}
+
+	/// Stops and forgets a connected peer.
+	pub fn disconnect_peer(&self, peer_addr: PeerAddr) {
+		if let Some(peer) = self.get_connected_peer(peer_addr) {
 			peer.stop();
+			match self.peers.try_write_for(LOCK_TIMEOUT) {
+				Some(mut peers) => {
+					if peers.remove(&peer_addr).is_some() {
+						self.adapter.peer_disconnected(peer_addr);
+					}
+				}
+				None => error!("disconnect_peer: failed to get peers lock"),
+			}
+		}
+	}
 
-			let mut peers = match self.peers.try_write_for(LOCK_TIMEOUT) {
-				Some(peers) => peers,
-				None => {
-					error!("ban_peer: failed to get peers lock");
-					return;
+	/// Adds a penalty to the misbehaviour score of a peer, disconnecting it
+	/// once the score reaches PEER_SCORE_DISCONNECT and banning it at
+	/// PEER_SCORE_BAN.
+	pub fn penalize(&self, peer_addr: PeerAddr, penalty: Penalty) {
+		let now = Utc::now().timestamp();
+		let peer = self.get_connected_peer(peer_addr);
+		let score = match peer {
+			Some(ref peer) => {
+				let mut live_info = peer.info.live_info.write();
+				live_info.score.penalize(penalty, now);
+				live_info.score
+			}
+			None => match self.store.get_peer(peer_addr) {
+				Ok(p) => {
+					let mut score = p.score;
+					score.penalize(penalty, now);
+					score
 				}
-			};
-			peers.remove(&peer.info.addr);
+				Err(_) => return,
+			},
+		};
+		debug!(
+			"penalize: peer {} for {:?}, score now {}",
+			peer_addr, penalty, score.value
+		);
+		if let Err(e) = self.store.update_score(peer_addr, score) {
+			error!("Couldn't save score of {}: {:?}", peer_addr, e);
+		}
+
+		if score.value >= PEER_SCORE_BAN {
+			info!(
+				"penalize: banning peer {}, misbehaviour score {}",
+				peer_addr, score.value
+			);
+			self.ban_peer(peer_addr, ReasonForBan::Misbehaviour);
+		} else if score.value >= PEER_SCORE_DISCONNECT {
+			if peer.is_some() {
+				info!(
+					"penalize: disconnecting peer {}, misbehaviour score {}",
+					peer_addr, score.value
+				);
+				self.disconnect_peer(peer_addr);
+			}
 		}
 	}
 
@@ -320,7 +666,9 @@ impl Peers {
 						}
 					};
 					p.stop();
-					peers.remove(&p.info.addr);
+					if peers.remove(&p.info.addr).is_some() {
+						self.adapter.peer_disconnected(p.info.addr);
+					}
 				}
 			}
 		}
@@ -329,8 +677,17 @@ impl Peers {
 
 	/// Broadcast a compact block to all our connected peers.
 	/// This is only used when initially broadcasting a newly mined block.
+	/// Peers having asked for headers announcements (SendHeaders) get the
+	/// header instead, and only request the compact block if it extends their
+	/// best chain.
 	pub fn broadcast_compact_block(&self, b: &core::CompactBlock) {
-		let count = self.broadcast("compact block", |p| p.send_compact_block(b));
+		let count = self.broadcast("compact block", |p| {
+			if self.announce_headers_to(p) {
+				p.announce_header(&b.header)
+			} else {
+				p.send_compact_block(b)
+			}
+		});
 		debug!(
 			"broadcast_compact_block: {}, {} at {}, to {} peers, done.",
 			b.hash(),
@@ -343,8 +700,16 @@ impl Peers {
 	/// Broadcast a block header to all our connected peers.
 	/// A peer implementation may drop the broadcast request
 	/// if it knows the remote peer already has the header.
+	/// Headers announced in quick succession to the peers having asked for it
+	/// are batched.
 	pub fn broadcast_header(&self, bh: &core::BlockHeader) {
-		let count = self.broadcast("header", |p| p.send_header(bh));
+		let count = self.broadcast("header", |p| {
+			if self.announce_headers_to(p) {
+				p.announce_header(bh)
+			} else {
+				p.send_header(bh)
+			}
+		});
 		debug!(
 			"broadcast_header: {}, {} at {}, to {} peers, done.",
 			bh.hash(),
@@ -354,6 +719,11 @@ impl Peers {
 		);
 	}
 
+	/// Whether new blocks are announced to this peer with their headers.
+	fn announce_headers_to(&self, peer: &Peer) -> bool {
+		self.config.headers_announce() && peer.prefers_headers()
+	}
+
 	/// Broadcasts the provided transaction to all our connected peers.
 	/// A peer implementation may drop the broadcast request
 	/// if it knows the remote peer already has the transaction.
@@ -366,6 +736,18 @@ impl Peers {
 		);
 	}
 
+	/// Broadcasts the provided package of dependent transactions to all our
+	/// connected peers, as a single message so they are accepted as a unit,
+	/// or one by one to the peers not supporting packages.
+	pub fn broadcast_transaction_package(&self, txs: &[core::Transaction]) {
+		let count = self.broadcast("transaction package", |p| p.send_transaction_package(txs));
+		debug!(
+			"broadcast_transaction_package: {} txs to {} peers, done.",
+			txs.len(),
+			count,
+		);
+	}
+
 	/// Ping all our connected peers. Always automatically expects a pong back
 	/// or disconnects. This acts as a liveness test.
 	pub fn check_all(&self, total_difficulty: Difficulty, height: u64) {
@@ -383,7 +765,9 @@ impl Peers {
 					}
 				};
 				p.stop();
-				peers.remove(&p.info.addr);
+				if peers.remove(&p.info.addr).is_some() {
+					self.adapter.peer_disconnected(p.info.addr);
+				}
 			}
 		}
 	}
@@ -516,12 +900,16 @@ impl Peers {
 			};
 			for addr in rm {
 				let _ = peers.get(&addr).map(|peer| peer.stop());
-				peers.remove(&addr);
+				if peers.remove(&addr).is_some() {
+					self.adapter.peer_disconnected(addr);
+				}
 			}
 		}
 	}
 
 	pub fn stop(&self) {
+		self.save_anchors();
+		self.save_addrs();
 		let mut peers = self.peers.write();
 		for peer in peers.values() {
 			peer.stop();
@@ -559,6 +947,22 @@ impl Peers {
 
 			should_remove
 		});
+
+		// Lift the subnet bans that expired
+		let now = now.timestamp();
+		let expired: Vec<BanEntry> = self
+			.bans
+			.read()
+			.iter()
+			.filter(|b| b.is_expired(now))
+			.cloned()
+			.collect();
+		for ban in expired {
+			debug!("removing expired ban on {}", ban.subnet);
+			if let Err(e) = self.unban_subnet(&ban.subnet) {
+				error!("Couldn't remove expired ban on {}: {:?}", ban.subnet, e);
+			}
+		}
 	}
 }
 
@@ -575,6 +979,24 @@ impl ChainAdapter for Peers {
 		self.adapter.get_transaction(kernel_hash)
 	}
 
+	fn get_block_txs(
+		&self,
+		hash: Hash,
+		nonce: u64,
+		short_ids: &[ShortId],
+	) -> Vec<core::Transaction> {
+		self.adapter.get_block_txs(hash, nonce, short_ids)
+	}
+
+	fn block_txs_received(
+		&self,
+		hash: Hash,
+		txs: Vec<core::Transaction>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		self.adapter.block_txs_received(hash, txs, peer_info)
+	}
+
 	fn tx_kernel_received(
 		&self,
 		kernel_hash: Hash,
@@ -586,9 +1008,20 @@ impl ChainAdapter for Peers {
 	fn transaction_received(
 		&self,
 		tx: core::Transaction,
+		peer_info: &PeerInfo,
+		stem: bool,
+	) -> Result<bool, chain::Error> {
+		self.adapter.transaction_received(tx, peer_info, stem)
+	}
+
+	fn transaction_package_received(
+		&self,
+		txs: Vec<core::Transaction>,
+		peer_info: &PeerInfo,
 		stem: bool,
 	) -> Result<bool, chain::Error> {
-		self.adapter.transaction_received(tx, stem)
+		self.adapter
+			.transaction_package_received(txs, peer_info, stem)
 	}
 
 	fn block_received(
@@ -708,6 +1141,56 @@ impl ChainAdapter for Peers {
 		}
 	}
 
+	fn get_output_proof(&self, commit: Commitment) -> Option<OutputProof> {
+		self.adapter.get_output_proof(commit)
+	}
+
+	fn output_proof_received(
+		&self,
+		commit: Commitment,
+		proof: Option<OutputProof>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		if self
+			.adapter
+			.output_proof_received(commit, proof, peer_info)?
+		{
+			debug!(
+				"Received a bad output proof from {}, the peer will be banned",
+				peer_info.addr
+			);
+			self.ban_peer(peer_info.addr, ReasonForBan::BadTxHashSet);
+			Ok(true)
+		} else {
+			Ok(false)
+		}
+	}
+
+	fn get_kernel_proof(&self, excess: Commitment) -> Option<KernelProof> {
+		self.adapter.get_kernel_proof(excess)
+	}
+
+	fn kernel_proof_received(
+		&self,
+		excess: Commitment,
+		proof: Option<KernelProof>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		if self
+			.adapter
+			.kernel_proof_received(excess, proof, peer_info)?
+		{
+			debug!(
+				"Received a bad kernel proof from {}, the peer will be banned",
+				peer_info.addr
+			);
+			self.ban_peer(peer_info.addr, ReasonForBan::BadTxHashSet);
+			Ok(true)
+		} else {
+			Ok(false)
+		}
+	}
+
 	fn txhashset_download_fail(&self, fail_reason: String) {
 		self.adapter.txhashset_download_fail(fail_reason);
 	}
@@ -722,10 +1205,48 @@ impl ChainAdapter for Peers {
 			.txhashset_download_update(start_time, downloaded_size, total_size)
 	}
 
+	fn get_txhashset_segment(
+		&self,
+		h: Hash,
+		segment_type: SegmentType,
+		id: SegmentIdentifier,
+	) -> Option<TxHashSetSegment> {
+		self.adapter.get_txhashset_segment(h, segment_type, id)
+	}
+
+	fn txhashset_segment_received(
+		&self,
+		h: Hash,
+		segment: TxHashSetSegment,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		if self
+			.adapter
+			.txhashset_segment_received(h, segment, peer_info)?
+		{
+			debug!(
+				"Received a bad txhashset segment from {}, the peer will be banned",
+				peer_info.addr
+			);
+			self.ban_peer(peer_info.addr, ReasonForBan::BadTxHashSet);
+			Ok(true)
+		} else {
+			Ok(false)
+		}
+	}
+
 	fn get_tmp_dir(&self) -> PathBuf {
 		self.adapter.get_tmp_dir()
 	}
 
+	fn peer_connected(&self, peer_info: &PeerInfo) {
+		self.adapter.peer_connected(peer_info)
+	}
+
+	fn peer_disconnected(&self, addr: PeerAddr) {
+		self.adapter.peer_disconnected(addr)
+	}
+
 	fn get_tmpfile_pathname(&self, tmpfile_name: String) -> PathBuf {
 		self.adapter.get_tmpfile_pathname(tmpfile_name)
 	}
@@ -741,8 +1262,15 @@ impl NetAdapter for Peers {
 	}
 
 	/// A list of peers has been received from one of our peers.
-	fn peer_addrs_received(&self, peer_addrs: Vec<PeerAddr>) {
+	fn peer_addrs_received(&self, peer_addrs: Vec<PeerAddr>, peer_info: &PeerInfo) {
 		trace!("Received {} peer addrs, saving.", peer_addrs.len());
+		{
+			let now = Utc::now().timestamp();
+			let mut addrs = self.addrs.write();
+			for pa in &peer_addrs {
+				addrs.add(*pa, peer_info.addr, now);
+			}
+		}
 		for pa in peer_addrs {
 			if let Ok(e) = self.exists_peer(pa) {
 				if e {
@@ -757,6 +1285,7 @@ impl NetAdapter for Peers {
 				last_banned: 0,
 				ban_reason: ReasonForBan::None,
 				last_connected: Utc::now().timestamp(),
+				score: PeerScore::default(),
 			};
 			if let Err(e) = self.save_peer(&peer) {
 				error!("Could not save received peer address: {:?}", e);
@@ -771,10 +1300,38 @@ impl NetAdapter for Peers {
 	}
 
 	fn is_banned(&self, addr: PeerAddr) -> bool {
-		if let Ok(peer) = self.get_peer(addr) {
-			peer.flags == State::Banned
-		} else {
-			false
+		Peers::is_banned(self, addr)
+	}
+
+	fn penalize_peer(&self, addr: PeerAddr, penalty: Penalty) {
+		self.penalize(addr, penalty)
+	}
+}
+
+/// Orders peers from the most to the least reliable, based on their
+/// misbehaviour score. Peers within the same score bucket stay in random
+/// order so we still spread our requests among them.
+fn sort_by_reliability(peers: &mut Vec<Arc<Peer>>) {
+	peers.shuffle(&mut thread_rng());
+	peers.sort_by_cached_key(|p| p.info.score() / PEER_SCORE_BUCKET);
+}
+
+// Loads the address manager tables, starting from the healthy peers we know
+// of the first time.
+fn load_addrs(store: &PeerStore) -> AddrManager {
+	match store.all_known_addrs() {
+		Ok(known) => {
+			if !known.is_empty() {
+				return AddrManager::load(known);
+			}
+		}
+		Err(e) => error!("Couldn't load known addresses: {:?}", e),
+	}
+	let mut addrs = AddrManager::new();
+	for p in store.all_peers().unwrap_or(vec![]) {
+		if p.flags == State::Healthy {
+			addrs.add(p.addr, p.addr, p.last_connected);
 		}
 	}
+	addrs
 }
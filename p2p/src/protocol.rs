@@ -16,16 +16,19 @@
 use crate::conn::{Message, MessageHandler, Response, Tracker};
 use crate::core::core::{self, hash::Hash, hash::Hashed, CompactBlock};
 
+use crate::core::ser;
 use crate::msg::{
-	BanReason, GetPeerAddrs, Headers, KernelDataResponse, Locator, PeerAddrs, Ping, Pong,
-	TxHashSetArchive, TxHashSetRequest, Type,
+	BanReason, BlockTxs, GetBlockTxs, GetPeerAddrs, Headers, KernelDataResponse,
+	KernelProofRequest, KernelProofResponse, Locator, OutputProofRequest, OutputProofResponse,
+	PeerAddrs, Ping, Pong, SegmentRequest, SegmentResponse, TransactionPackage, TxHashSetArchive,
+	TxHashSetRequest, Type,
 };
-use crate::types::{Error, NetAdapter, PeerInfo};
+use crate::types::{Error, NetAdapter, PeerInfo, Penalty, MAX_ANNOUNCED_HEADERS};
 use chrono::prelude::Utc;
 use rand::{thread_rng, Rng};
 use std::cmp;
 use std::fs::{self, File, OpenOptions};
-use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Instant;
@@ -51,8 +54,8 @@ impl Protocol {
 	}
 }
 
-impl MessageHandler for Protocol {
-	fn consume<'a>(
+impl Protocol {
+	fn handle_msg<'a>(
 		&self,
 		mut msg: Message<'a>,
 		writer: &'a mut dyn Write,
@@ -134,7 +137,7 @@ impl MessageHandler for Protocol {
 					msg.header.msg_len
 				);
 				let tx: core::Transaction = msg.body()?;
-				adapter.transaction_received(tx, false)?;
+				adapter.transaction_received(tx, &self.peer_info, false)?;
 				Ok(None)
 			}
 
@@ -144,7 +147,49 @@ impl MessageHandler for Protocol {
 					msg.header.msg_len
 				);
 				let tx: core::Transaction = msg.body()?;
-				adapter.transaction_received(tx, true)?;
+				adapter.transaction_received(tx, &self.peer_info, true)?;
+				Ok(None)
+			}
+
+			Type::TransactionPackage => {
+				debug!(
+					"handle_payload: received tx package: msg_len: {}",
+					msg.header.msg_len
+				);
+				let package: TransactionPackage = msg.body()?;
+				adapter.transaction_package_received(package.txs, &self.peer_info, package.stem)?;
+				Ok(None)
+			}
+
+			Type::GetBlockTxs => {
+				let req: GetBlockTxs = msg.body()?;
+				debug!(
+					"handle_payload: GetBlockTxs: {}, {} short_ids, msg_len: {}",
+					req.hash,
+					req.short_ids.len(),
+					msg.header.msg_len,
+				);
+				let txs = adapter.get_block_txs(req.hash, req.nonce, &req.short_ids);
+				Ok(Some(Response::new(
+					Type::BlockTxs,
+					self.peer_info.version,
+					BlockTxs {
+						hash: req.hash,
+						txs,
+					},
+					writer,
+				)?))
+			}
+
+			Type::BlockTxs => {
+				let res: BlockTxs = msg.body()?;
+				debug!(
+					"handle_payload: BlockTxs: {}, {} txs, msg_len: {}",
+					res.hash,
+					res.txs.len(),
+					msg.header.msg_len,
+				);
+				adapter.block_txs_received(res.hash, res.txs, &self.peer_info)?;
 				Ok(None)
 			}
 
@@ -229,6 +274,25 @@ impl MessageHandler for Protocol {
 				Ok(None)
 			}
 
+			// the peer wants new blocks announced with their headers
+			Type::SendHeaders => {
+				debug!("handle_payload: SendHeaders from {}", self.peer_info.addr);
+				self.peer_info.live_info.write().send_headers = true;
+				Ok(None)
+			}
+
+			// several new block headers in quick succession, in order
+			Type::HeadersAnnouncement => {
+				let headers: Headers = msg.body()?;
+				if headers.headers.len() > MAX_ANNOUNCED_HEADERS as usize {
+					return Err(Error::BadMessage);
+				}
+				for header in headers.headers {
+					adapter.header_received(header, &self.peer_info)?;
+				}
+				Ok(None)
+			}
+
 			Type::Headers => {
 				let mut total_bytes_read = 0;
 
@@ -252,6 +316,7 @@ impl MessageHandler for Protocol {
 				if total_bytes_read != msg.header.msg_len {
 					return Err(Error::MsgLen);
 				}
+				self.peer_info.header_request_answered();
 
 				Ok(None)
 			}
@@ -269,7 +334,7 @@ impl MessageHandler for Protocol {
 
 			Type::PeerAddrs => {
 				let peer_addrs: PeerAddrs = msg.body()?;
-				adapter.peer_addrs_received(peer_addrs.peers);
+				adapter.peer_addrs_received(peer_addrs.peers, &self.peer_info);
 				Ok(None)
 			}
 
@@ -365,10 +430,14 @@ impl MessageHandler for Protocol {
 					error!(
 						"handle_payload: txhashset archive received but SyncStatus not on TxHashsetDownload",
 					);
+					self.adapter
+						.penalize_peer(self.peer_info.addr, Penalty::UnsolicitedData);
 					return Err(Error::BadMessage);
 				}
 				if !self.state_sync_requested.load(Ordering::Relaxed) {
 					error!("handle_payload: txhashset archive received but from the wrong peer",);
+					self.adapter
+						.penalize_peer(self.peer_info.addr, Penalty::UnsolicitedData);
 					return Err(Error::BadMessage);
 				}
 				// Update the sync state requested status
@@ -452,6 +521,113 @@ impl MessageHandler for Protocol {
 
 				Ok(None)
 			}
+
+			Type::GetSegment => {
+				let req: SegmentRequest = msg.body()?;
+				trace!(
+					"handle_payload: GetSegment: {:?} {:?} at {}",
+					req.segment_type,
+					req.identifier,
+					req.block_hash,
+				);
+				let segment = self.adapter.get_txhashset_segment(
+					req.block_hash,
+					req.segment_type,
+					req.identifier,
+				);
+				if let Some(segment) = segment {
+					return Ok(Some(Response::new(
+						Type::Segment,
+						self.peer_info.version,
+						SegmentResponse {
+							block_hash: req.block_hash,
+							segment,
+						},
+						writer,
+					)?));
+				}
+				Ok(None)
+			}
+
+			Type::Segment => {
+				if !self.adapter.txhashset_receive_ready() {
+					// most likely a late answer to a request we gave up on
+					debug!(
+						"handle_payload: txhashset segment from {} received but SyncStatus not on TxHashsetDownload, ignored",
+						self.peer_info.addr,
+					);
+					return Ok(None);
+				}
+				let res: SegmentResponse = msg.body()?;
+				trace!(
+					"handle_payload: Segment: {:?} {:?} at {}, msg_len: {}",
+					res.segment.segment_type(),
+					res.segment.identifier(),
+					res.block_hash,
+					msg.header.msg_len,
+				);
+				self.adapter.txhashset_segment_received(
+					res.block_hash,
+					res.segment,
+					&self.peer_info,
+				)?;
+				Ok(None)
+			}
+
+			Type::GetOutputProof => {
+				let req: OutputProofRequest = msg.body()?;
+				trace!("handle_payload: GetOutputProof: {:?}", req.commit);
+				let proof = self.adapter.get_output_proof(req.commit);
+				Ok(Some(Response::new(
+					Type::OutputProof,
+					self.peer_info.version,
+					OutputProofResponse {
+						commit: req.commit,
+						proof,
+					},
+					writer,
+				)?))
+			}
+
+			Type::OutputProof => {
+				let res: OutputProofResponse = msg.body()?;
+				trace!(
+					"handle_payload: OutputProof: {:?}, found: {}",
+					res.commit,
+					res.proof.is_some(),
+				);
+				self.adapter
+					.output_proof_received(res.commit, res.proof, &self.peer_info)?;
+				Ok(None)
+			}
+
+			Type::GetKernelProof => {
+				let req: KernelProofRequest = msg.body()?;
+				trace!("handle_payload: GetKernelProof: {:?}", req.excess);
+				let proof = self.adapter.get_kernel_proof(req.excess);
+				Ok(Some(Response::new(
+					Type::KernelProof,
+					self.peer_info.version,
+					KernelProofResponse {
+						excess: req.excess,
+						proof,
+					},
+					writer,
+				)?))
+			}
+
+			Type::KernelProof => {
+				let res: KernelProofResponse = msg.body()?;
+				trace!(
+					"handle_payload: KernelProof: {:?}, found: {}",
+					res.excess,
+					res.proof.is_some(),
+				);
+				self.adapter
+					.kernel_proof_received(res.excess, res.proof, &self.peer_info)?;
+				Ok(None)
+			}
+
 			Type::Error | Type::Hand | Type::Shake => {
 				debug!("Received an unexpected msg: {:?}", msg.header.msg_type);
 				Ok(None)
@@ -459,3 +635,34 @@ impl MessageHandler for Protocol {
 		}
 	}
 }
+
+impl MessageHandler for Protocol {
+	fn consume<'a>(
+		&self,
+		msg: Message<'a>,
+		writer: &'a mut dyn Write,
+		tracker: Arc<Tracker>,
+	) -> Result<Option<Response<'a>>, Error> {
+		let res = self.handle_msg(msg, writer, tracker);
+
+		// Penalize the peer for messages we can't make sense of, but not for
+		// a slow connection timing out in the middle of a message.
+		let invalid = match res {
+			Err(Error::Serialization(ser::Error::IOErr(_, kind))) => {
+				kind != io::ErrorKind::TimedOut && kind != io::ErrorKind::WouldBlock
+			}
+			Err(Error::Serialization(_)) | Err(Error::MsgLen) => true,
+			_ => false,
+		};
+		if invalid {
+			debug!(
+				"handle_payload: invalid msg from {}: {:?}",
+				self.peer_info.addr,
+				res.as_ref().err()
+			);
+			self.adapter
+				.penalize_peer(self.peer_info.addr, Penalty::InvalidMessage);
+		}
+		res
+	}
+}
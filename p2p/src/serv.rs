@@ -22,20 +22,28 @@ use std::thread;
 use std::time::Duration;
 
 use crate::chain;
+use crate::chain::{KernelProof, OutputProof, SegmentType, TxHashSetSegment};
 use crate::core::core;
 use crate::core::core::hash::Hash;
+use crate::core::core::id::ShortId;
+use crate::core::core::pmmr::SegmentIdentifier;
 use crate::core::global;
 use crate::core::pow::Difficulty;
+use crate::conn::TrafficStats;
 use crate::handshake::Handshake;
+use crate::noise::NodeKey;
 use crate::peer::Peer;
 use crate::peers::Peers;
+use crate::socks;
 use crate::store::PeerStore;
 use crate::types::{
-	Capabilities, ChainAdapter, Error, NetAdapter, P2PConfig, PeerAddr, PeerInfo, ReasonForBan,
-	TxHashSetRead,
+	Capabilities, ChainAdapter, Error, NetAdapter, P2PConfig, PeerAddr, PeerInfo, Penalty,
+	ReasonForBan, TxHashSetRead,
 };
-use crate::util::StopState;
+use crate::util::secp::pedersen::Commitment;
+use crate::util::{to_hex, StopState};
 use chrono::prelude::{DateTime, Utc};
+use net2::TcpBuilder;
 
 /// P2P server implementation, handling bootstrapping to find and connect to
 /// peers, receiving connections from other peers and keep track of all of them.
@@ -58,22 +66,54 @@ impl Server {
 		genesis: Hash,
 		stop_state: Arc<StopState>,
 	) -> Result<Server, Error> {
+		// light nodes (headers only) have no txhashset to serve
+		let mut capab = capab;
+		if capab.contains(Capabilities::TXHASHSET_HIST) {
+			capab |= Capabilities::TXHASHSET_SEGMENTS
+				| Capabilities::MERKLE_PROOFS
+				| Capabilities::BLOCK_TXS
+				| Capabilities::TX_PACKAGE;
+		}
+		if config.headers_announce() {
+			capab |= Capabilities::HEADERS_ANNOUNCE;
+		}
+		let mut handshake = Handshake::new(genesis, config.clone());
+		if config.encryption() {
+			let node_key = NodeKey::load_or_create(db_root)?;
+			info!(
+				"P2P encrypted transport enabled (required: {}), node key {}",
+				config.require_encryption(),
+				to_hex(node_key.public_key().to_vec())
+			);
+			capab |= Capabilities::ENCRYPTED;
+			handshake = handshake.with_node_key(node_key);
+		}
+
 		Ok(Server {
 			config: config.clone(),
 			capabilities: capab,
-			handshake: Arc::new(Handshake::new(genesis, config.clone())),
+			handshake: Arc::new(handshake),
 			peers: Arc::new(Peers::new(PeerStore::new(db_root)?, adapter, config)),
 			stop_state,
 		})
 	}
 
-	/// Starts a new TCP server and listen to incoming connections. This is a
-	/// blocking call until the TCP server stops.
+	/// Starts a new TCP server and listen to incoming connections, on all our
+	/// configured addresses. This is a blocking call until the TCP server stops.
 	pub fn listen(&self) -> Result<(), Error> {
-		// start TCP listener and handle incoming connections
-		let addr = SocketAddr::new(self.config.host, self.config.port);
-		let listener = TcpListener::bind(addr)?;
-		listener.set_nonblocking(true)?;
+		if !self.config.listen() {
+			info!("P2P server not listening, incoming connections disabled");
+			return Ok(());
+		}
+
+		// start TCP listeners and handle incoming connections
+		let mut listeners = vec![];
+		for addr in self.config.listen_addrs() {
+			let listener = bind(addr)?;
+			listener.set_nonblocking(true)?;
+			info!("P2P server listening on {}", addr);
+			listeners.push(listener);
+		}
 
 		let sleep_time = Duration::from_millis(5);
 		loop {
@@ -83,34 +123,39 @@ impl Server {
 				continue;
 			}
 
-			match listener.accept() {
-				Ok((stream, peer_addr)) => {
-					// Explicitly move the accepted TCP stream into blocking mode.
-					stream.set_nonblocking(false)?;
+			for listener in &listeners {
+				match listener.accept() {
+					Ok((stream, peer_addr)) => {
+						// Explicitly move the accepted TCP stream into blocking mode.
+						stream.set_nonblocking(false)?;
 
-					let peer_addr = PeerAddr(peer_addr);
+						let peer_addr = PeerAddr(peer_addr).canonical();
 
-					if self.check_undesirable(&stream) {
-						// Shutdown the incoming TCP connection if it is not desired
-						if let Err(e) = stream.shutdown(Shutdown::Both) {
-							debug!("Error shutting down conn: {:?}", e);
+						if self.check_undesirable(&stream) {
+							// Shutdown the incoming TCP connection if it is not desired
+							if let Err(e) = stream.shutdown(Shutdown::Both) {
+								debug!("Error shutting down conn: {:?}", e);
+							}
+							continue;
 						}
-						continue;
-					}
-					match self.handle_new_peer(stream) {
-						Err(Error::ConnectionClose) => debug!("shutting down, ignoring a new peer"),
-						Err(e) => {
-							debug!("Error accepting peer {}: {:?}", peer_addr.to_string(), e);
-							let _ = self.peers.add_banned(peer_addr, ReasonForBan::BadHandshake);
+						match self.handle_new_peer(stream) {
+							Err(Error::ConnectionClose) => {
+								debug!("shutting down, ignoring a new peer")
+							}
+							Err(e) => {
+								debug!("Error accepting peer {}: {:?}", peer_addr.to_string(), e);
+								let _ =
+									self.peers.add_banned(peer_addr, ReasonForBan::BadHandshake);
+							}
+							Ok(_) => {}
 						}
-						Ok(_) => {}
 					}
-				}
-				Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-					// nothing to do, will retry in next iteration
-				}
-				Err(e) => {
-					debug!("Couldn't establish new client connection: {:?}", e);
+					Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+						// nothing to do, will retry in next iteration
+					}
+					Err(e) => {
+						debug!("Couldn't establish new client connection: {:?}", e);
+					}
 				}
 			}
 			if self.stop_state.is_stopped() {
@@ -127,16 +172,23 @@ impl Server {
 		if self.stop_state.is_stopped() {
 			return Err(Error::ConnectionClose);
 		}
+		let addr = addr.canonical();
 
 		if Peer::is_denied(&self.config, addr) {
 			debug!("connect_peer: peer {} denied, not connecting.", addr);
 			return Err(Error::ConnectionClose);
 		}
 
+		if self.peers.is_banned(addr) {
+			debug!("connect_peer: peer {} banned, not connecting.", addr);
+			return Err(Error::Banned);
+		}
+
 		if global::is_production_mode() {
 			let hs = self.handshake.clone();
 			let addrs = hs.addrs.read();
-			if addrs.contains(&addr) {
+			let external = self.config.external_addrs.iter().flatten();
+			if addrs.contains(&addr) || external.map(|a| a.canonical()).any(|a| a == addr) {
 				debug!("connect: ignore connecting to PeerWithSelf, addr: {}", addr);
 				return Err(Error::PeerWithSelf);
 			}
@@ -148,15 +200,22 @@ impl Server {
 			return Ok(p);
 		}
 
+		self.peers.mark_attempt(addr);
 		trace!(
 			"connect_peer: on {}:{}. connecting to {}",
 			self.config.host,
 			self.config.port,
 			addr
 		);
-		match TcpStream::connect_timeout(&addr.0, Duration::from_secs(10)) {
+		let stream = match self.config.socks5_proxy {
+			Some(proxy) => socks::connect(proxy, addr.0, Duration::from_secs(10)),
+			None => TcpStream::connect_timeout(&addr.0, Duration::from_secs(10)),
+		};
+		match stream {
 			Ok(stream) => {
-				let addr = SocketAddr::new(self.config.host, self.config.port);
+				// the peer only keeps the port of our advertised address, using
+				// the ip it sees our connection coming from
+				let self_addr = self.config.external_addrs()[0];
 				let total_diff = self.peers.total_difficulty()?;
 				let height = self.peers.total_height()?;
 
@@ -165,7 +224,8 @@ impl Server {
 					self.capabilities,
 					total_diff,
 					height,
-					PeerAddr(addr),
+					self_addr,
+					addr,
 					&self.handshake,
 					self.peers.clone(),
 				)?;
@@ -227,7 +287,7 @@ impl Server {
 			return true;
 		}
 		if let Ok(peer_addr) = stream.peer_addr() {
-			let peer_addr = PeerAddr(peer_addr);
+			let peer_addr = PeerAddr(peer_addr).canonical();
 			if self.peers.is_banned(peer_addr) {
 				debug!("Peer {} banned, refusing connection.", peer_addr);
 				return true;
@@ -240,6 +300,12 @@ impl Server {
 		false
 	}
 
+	/// Messages and bytes exchanged with all our peers since startup, per
+	/// message type.
+	pub fn traffic_stats(&self) -> Arc<TrafficStats> {
+		self.handshake.limits.traffic()
+	}
+
 	pub fn stop(&self) {
 		self.stop_state.stop();
 		self.peers.stop();
@@ -254,6 +320,25 @@ impl Server {
 	}
 }
 
+/// Binds a listening socket. IPv6 sockets only accept IPv6 connections, so the
+/// same port can be bound on both an IPv4 and an IPv6 address.
+fn bind(addr: SocketAddr) -> io::Result<TcpListener> {
+	let builder = match addr {
+		SocketAddr::V4(_) => TcpBuilder::new_v4()?,
+		SocketAddr::V6(_) => {
+			let builder = TcpBuilder::new_v6()?;
+			builder.only_v6(true)?;
+			builder
+		}
+	};
+	// same as std TcpListener::bind
+	if cfg!(unix) {
+		builder.reuse_address(true)?;
+	}
+	builder.bind(addr)?;
+	builder.listen(128)
+}
+
 /// A no-op network adapter used for testing.
 pub struct DummyAdapter {}
 
@@ -268,12 +353,39 @@ impl ChainAdapter for DummyAdapter {
 		None
 	}
 
+	fn get_block_txs(
+		&self,
+		_h: Hash,
+		_nonce: u64,
+		_short_ids: &[ShortId],
+	) -> Vec<core::Transaction> {
+		vec![]
+	}
+
+	fn block_txs_received(
+		&self,
+		_h: Hash,
+		_txs: Vec<core::Transaction>,
+		_peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		Ok(true)
+	}
+
 	fn tx_kernel_received(&self, _h: Hash, _peer_info: &PeerInfo) -> Result<bool, chain::Error> {
 		Ok(true)
 	}
 	fn transaction_received(
 		&self,
 		_: core::Transaction,
+		_peer_info: &PeerInfo,
+		_stem: bool,
+	) -> Result<bool, chain::Error> {
+		Ok(true)
+	}
+	fn transaction_package_received(
+		&self,
+		_: Vec<core::Transaction>,
+		_peer_info: &PeerInfo,
 		_stem: bool,
 	) -> Result<bool, chain::Error> {
 		Ok(true)
@@ -348,10 +460,58 @@ impl ChainAdapter for DummyAdapter {
 		false
 	}
 
+	fn get_txhashset_segment(
+		&self,
+		_h: Hash,
+		_segment_type: SegmentType,
+		_id: SegmentIdentifier,
+	) -> Option<TxHashSetSegment> {
+		None
+	}
+
+	fn txhashset_segment_received(
+		&self,
+		_h: Hash,
+		_segment: TxHashSetSegment,
+		_peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		Ok(false)
+	}
+
+	fn get_output_proof(&self, _commit: Commitment) -> Option<OutputProof> {
+		None
+	}
+
+	fn output_proof_received(
+		&self,
+		_commit: Commitment,
+		_proof: Option<OutputProof>,
+		_peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		Ok(false)
+	}
+
+	fn get_kernel_proof(&self, _excess: Commitment) -> Option<KernelProof> {
+		None
+	}
+
+	fn kernel_proof_received(
+		&self,
+		_excess: Commitment,
+		_proof: Option<KernelProof>,
+		_peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		Ok(false)
+	}
+
 	fn get_tmp_dir(&self) -> PathBuf {
 		unimplemented!()
 	}
 
+	fn peer_connected(&self, _peer_info: &PeerInfo) {}
+
+	fn peer_disconnected(&self, _addr: PeerAddr) {}
+
 	fn get_tmpfile_pathname(&self, _tmpfile_name: String) -> PathBuf {
 		unimplemented!()
 	}
@@ -361,9 +521,10 @@ impl NetAdapter for DummyAdapter {
 	fn find_peer_addrs(&self, _: Capabilities) -> Vec<PeerAddr> {
 		vec![]
 	}
-	fn peer_addrs_received(&self, _: Vec<PeerAddr>) {}
+	fn peer_addrs_received(&self, _: Vec<PeerAddr>, _: &PeerInfo) {}
 	fn peer_difficulty(&self, _: PeerAddr, _: Difficulty, _: u64) {}
 	fn is_banned(&self, _: PeerAddr) -> bool {
 		false
 	}
+	fn penalize_peer(&self, _: PeerAddr, _: Penalty) {}
 }
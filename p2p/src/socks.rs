@@ -0,0 +1,160 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Minimal SOCKS5 client (RFC 1928) used to route our outbound connections
+//! through a proxy. Only the "no authentication" method is supported, which
+//! is what local proxies (Tor, ssh -D...) offer.
+//!
+//! Hostnames can also be resolved through the proxy with the RESOLVE command,
+//! a Tor extension, so that looking up our DNS seeds doesn't leak to the local
+//! resolver.
+
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream};
+use std::time::Duration;
+
+pub const SOCKS_VERSION: u8 = 5;
+pub const NO_AUTH: u8 = 0;
+pub const CMD_CONNECT: u8 = 1;
+/// Tor extension, resolves a hostname and returns the address in the reply.
+pub const CMD_RESOLVE: u8 = 0xf0;
+pub const ATYP_IPV4: u8 = 1;
+pub const ATYP_DOMAIN: u8 = 3;
+pub const ATYP_IPV6: u8 = 4;
+
+/// Destination of a proxy request.
+enum Target<'a> {
+	Addr(SocketAddr),
+	Domain(&'a str, u16),
+}
+
+/// Opens a connection to `addr` through the proxy. The stream is ready to use
+/// once returned, the proxy just relaying bytes from then on.
+pub fn connect(proxy: SocketAddr, addr: SocketAddr, timeout: Duration) -> io::Result<TcpStream> {
+	let mut stream = open(proxy, timeout)?;
+	request(&mut stream, CMD_CONNECT, Target::Addr(addr))?;
+	stream.set_read_timeout(None)?;
+	stream.set_write_timeout(None)?;
+	Ok(stream)
+}
+
+/// Resolves a hostname through the proxy, which has to support the Tor
+/// RESOLVE extension.
+pub fn resolve(proxy: SocketAddr, host: &str, timeout: Duration) -> io::Result<IpAddr> {
+	let mut stream = open(proxy, timeout)?;
+	let addr = request(&mut stream, CMD_RESOLVE, Target::Domain(host, 0))?;
+	Ok(addr.ip())
+}
+
+/// Connects to the proxy and negotiates the (lack of) authentication.
+fn open(proxy: SocketAddr, timeout: Duration) -> io::Result<TcpStream> {
+	let mut stream = TcpStream::connect_timeout(&proxy, timeout)?;
+	stream.set_read_timeout(Some(timeout))?;
+	stream.set_write_timeout(Some(timeout))?;
+
+	stream.write_all(&[SOCKS_VERSION, 1, NO_AUTH])?;
+	let mut reply = [0u8; 2];
+	stream.read_exact(&mut reply)?;
+	if reply[0] != SOCKS_VERSION {
+		return Err(error("not a SOCKS5 proxy".to_string()));
+	}
+	if reply[1] != NO_AUTH {
+		return Err(error("SOCKS5 proxy requires authentication".to_string()));
+	}
+	Ok(stream)
+}
+
+/// Sends a request and reads the reply, returning the address it holds.
+fn request(stream: &mut TcpStream, cmd: u8, target: Target) -> io::Result<SocketAddr> {
+	let mut req = vec![SOCKS_VERSION, cmd, 0];
+	let port = match target {
+		Target::Addr(addr) => {
+			match addr.ip() {
+				IpAddr::V4(ip) => {
+					req.push(ATYP_IPV4);
+					req.extend_from_slice(&ip.octets());
+				}
+				IpAddr::V6(ip) => {
+					req.push(ATYP_IPV6);
+					req.extend_from_slice(&ip.octets());
+				}
+			}
+			addr.port()
+		}
+		Target::Domain(host, port) => {
+			if host.is_empty() || host.len() > 255 {
+				return Err(error(format!("invalid hostname {}", host)));
+			}
+			req.push(ATYP_DOMAIN);
+			req.push(host.len() as u8);
+			req.extend_from_slice(host.as_bytes());
+			port
+		}
+	};
+	req.extend_from_slice(&port.to_be_bytes());
+	stream.write_all(&req)?;
+
+	let mut head = [0u8; 4];
+	stream.read_exact(&mut head)?;
+	if head[0] != SOCKS_VERSION {
+		return Err(error("not a SOCKS5 proxy".to_string()));
+	}
+	if head[1] != 0 {
+		return Err(error(format!(
+			"SOCKS5 request failed: {}",
+			reply_message(head[1])
+		)));
+	}
+	let ip = match head[3] {
+		ATYP_IPV4 => {
+			let mut buf = [0u8; 4];
+			stream.read_exact(&mut buf)?;
+			IpAddr::from(buf)
+		}
+		ATYP_IPV6 => {
+			let mut buf = [0u8; 16];
+			stream.read_exact(&mut buf)?;
+			IpAddr::from(buf)
+		}
+		ATYP_DOMAIN => {
+			let mut len = [0u8; 1];
+			stream.read_exact(&mut len)?;
+			let mut buf = vec![0u8; len[0] as usize];
+			stream.read_exact(&mut buf)?;
+			IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+		}
+		atyp => return Err(error(format!("unknown SOCKS5 address type {}", atyp))),
+	};
+	let mut port = [0u8; 2];
+	stream.read_exact(&mut port)?;
+	Ok(SocketAddr::new(ip, u16::from_be_bytes(port)))
+}
+
+fn reply_message(code: u8) -> &'static str {
+	match code {
+		1 => "general failure",
+		2 => "connection not allowed by ruleset",
+		3 => "network unreachable",
+		4 => "host unreachable",
+		5 => "connection refused",
+		6 => "TTL expired",
+		7 => "command not supported",
+		8 => "address type not supported",
+		_ => "unknown error",
+	}
+}
+
+fn error(msg: String) -> io::Error {
+	io::Error::new(io::ErrorKind::Other, msg)
+}
@@ -20,14 +20,18 @@ use num::FromPrimitive;
 use rand::seq::SliceRandom;
 use rand::thread_rng;
 
+use crate::addrman::KnownAddr;
 use crate::core::ser::{self, Readable, Reader, Writeable, Writer};
-use crate::types::{Capabilities, PeerAddr, ReasonForBan};
+use crate::types::{Capabilities, PeerAddr, PeerScore, ReasonForBan, Subnet};
 use gotts_store::{self, option_to_not_found, to_key, Error};
 
 const DB_NAME: &'static str = "peer";
 const STORE_SUBPATH: &'static str = "peers";
 
 const PEER_PREFIX: u8 = 'P' as u8;
+const BAN_PREFIX: u8 = 'B' as u8;
+const KNOWN_ADDR_PREFIX: u8 = 'A' as u8;
+const ANCHORS_PREFIX: u8 = 'N' as u8;
 
 // Types of messages
 enum_from_primitive! {
@@ -57,6 +61,8 @@ pub struct PeerData {
 	pub ban_reason: ReasonForBan,
 	/// Time when we last connected to this peer.
 	pub last_connected: i64,
+	/// Misbehaviour score of the peer.
+	pub score: PeerScore,
 }
 
 impl Writeable for PeerData {
@@ -69,7 +75,9 @@ impl Writeable for PeerData {
 			[write_u8, self.flags as u8],
 			[write_i64, self.last_banned],
 			[write_i32, self.ban_reason as i32],
-			[write_i64, self.last_connected]
+			[write_i64, self.last_connected],
+			[write_i32, self.score.value],
+			[write_i64, self.score.updated]
 		);
 		Ok(())
 	}
@@ -83,12 +91,16 @@ impl Readable for PeerData {
 		let (fl, lb, br) = ser_multiread!(reader, read_u8, read_i64, read_i32);
 
 		let lc = reader.read_i64();
-		// this only works because each PeerData is read in its own vector and this
-		// is the last data element
+		// this only works because each PeerData is read in its own vector and these
+		// are the last data elements
 		let last_connected = match lc {
 			Err(_) => Utc::now().timestamp(),
 			Ok(lc) => lc,
 		};
+		let score = match (reader.read_i32(), reader.read_i64()) {
+			(Ok(value), Ok(updated)) => PeerScore { value, updated },
+			_ => PeerScore::default(),
+		};
 
 		let user_agent = String::from_utf8(ua).map_err(|_| ser::Error::CorruptedData)?;
 		let capabilities = Capabilities::from_bits_truncate(capab);
@@ -103,12 +115,90 @@ impl Readable for PeerData {
 				last_banned: lb,
 				ban_reason,
 				last_connected,
+				score,
 			}),
 			None => Err(ser::Error::CorruptedData),
 		}
 	}
 }
 
+/// A ban on a subnet, or on a single address, independent of any peer we may
+/// know of in that range.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BanEntry {
+	/// The banned address range.
+	pub subnet: Subnet,
+	/// The reason for the ban
+	pub reason: ReasonForBan,
+	/// Free form note about the ban, e.g. the incident it relates to.
+	#[serde(default)]
+	pub note: String,
+	/// The time the ban was put in place.
+	#[serde(default)]
+	pub banned_at: i64,
+	/// The time the ban is lifted, never if none.
+	#[serde(default)]
+	pub expires: Option<i64>,
+}
+
+impl BanEntry {
+	/// A ban starting now and lasting `duration` seconds, forever if none.
+	pub fn new(
+		subnet: Subnet,
+		reason: ReasonForBan,
+		note: String,
+		duration: Option<i64>,
+	) -> BanEntry {
+		let now = Utc::now().timestamp();
+		BanEntry {
+			subnet,
+			reason,
+			note,
+			banned_at: now,
+			expires: duration.map(|d| now + d),
+		}
+	}
+
+	/// Whether the ban has been lifted at the provided time.
+	pub fn is_expired(&self, now: i64) -> bool {
+		match self.expires {
+			Some(expires) => expires <= now,
+			None => false,
+		}
+	}
+}
+
+impl Writeable for BanEntry {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.subnet.write(writer)?;
+		ser_multiwrite!(
+			writer,
+			[write_i32, self.reason as i32],
+			[write_bytes, &self.note],
+			[write_i64, self.banned_at],
+			[write_i64, self.expires.unwrap_or(0)]
+		);
+		Ok(())
+	}
+}
+
+impl Readable for BanEntry {
+	fn read(reader: &mut dyn Reader) -> Result<BanEntry, ser::Error> {
+		let subnet = Subnet::read(reader)?;
+		let reason = reader.read_i32()?;
+		let note = reader.read_bytes_len_prefix()?;
+		let (banned_at, expires) = ser_multiread!(reader, read_i64, read_i64);
+
+		Ok(BanEntry {
+			subnet,
+			reason: ReasonForBan::from_i32(reason).ok_or(ser::Error::CorruptedData)?,
+			note: String::from_utf8(note).map_err(|_| ser::Error::CorruptedData)?,
+			banned_at,
+			expires: if expires == 0 { None } else { Some(expires) },
+		})
+	}
+}
+
 /// Storage facility for peer data.
 pub struct PeerStore {
 	db: gotts_store::Store,
@@ -192,6 +282,38 @@ impl PeerStore {
 		batch.commit()
 	}
 
+	/// Convenience method to load a peer data, mark it as banned for the
+	/// provided reason and save it back.
+	pub fn ban_peer(&self, peer_addr: PeerAddr, ban_reason: ReasonForBan) -> Result<(), Error> {
+		let batch = self.db.batch()?;
+
+		let mut peer =
+			option_to_not_found(batch.get_ser::<PeerData>(&peer_key(peer_addr)[..]), || {
+				format!("Peer at address: {}", peer_addr)
+			})?;
+		peer.flags = State::Banned;
+		peer.last_banned = Utc::now().timestamp();
+		peer.ban_reason = ban_reason;
+
+		batch.put_ser(&peer_key(peer_addr)[..], &peer)?;
+		batch.commit()
+	}
+
+	/// Convenience method to load a peer data, update its score and save it
+	/// back.
+	pub fn update_score(&self, peer_addr: PeerAddr, score: PeerScore) -> Result<(), Error> {
+		let batch = self.db.batch()?;
+
+		let mut peer =
+			option_to_not_found(batch.get_ser::<PeerData>(&peer_key(peer_addr)[..]), || {
+				format!("Peer at address: {}", peer_addr)
+			})?;
+		peer.score = score;
+
+		batch.put_ser(&peer_key(peer_addr)[..], &peer)?;
+		batch.commit()
+	}
+
 	/// Deletes peers from the storage that satisfy some condition `predicate`
 	pub fn delete_peers<F>(&self, predicate: F) -> Result<(), Error>
 	where
@@ -218,6 +340,83 @@ impl PeerStore {
 
 		Ok(())
 	}
+
+	/// Saves a subnet ban, replacing any existing ban on the same subnet.
+	pub fn save_ban(&self, ban: &BanEntry) -> Result<(), Error> {
+		debug!("save_ban: {} {:?}", ban.subnet, ban.reason);
+
+		let batch = self.db.batch()?;
+		batch.put_ser(&ban_key(&ban.subnet)[..], ban)?;
+		batch.commit()
+	}
+
+	pub fn delete_ban(&self, subnet: &Subnet) -> Result<(), Error> {
+		let batch = self.db.batch()?;
+		batch.delete(&ban_key(subnet)[..])?;
+		batch.commit()
+	}
+
+	/// Saves the address manager changes in a single batch.
+	pub fn save_known_addrs(
+		&self,
+		changed: &[KnownAddr],
+		removed: &[PeerAddr],
+	) -> Result<(), Error> {
+		let batch = self.db.batch()?;
+		for ka in changed {
+			batch.put_ser(&known_addr_key(ka.addr)[..], ka)?;
+		}
+		for addr in removed {
+			batch.delete(&known_addr_key(*addr)[..])?;
+		}
+		batch.commit()
+	}
+
+	/// All the addresses known to the address manager.
+	pub fn all_known_addrs(&self) -> Result<Vec<KnownAddr>, Error> {
+		let key = to_key(KNOWN_ADDR_PREFIX, &mut "".to_string().into_bytes());
+		Ok(self
+			.db
+			.iter::<KnownAddr>(&key)?
+			.map(|(_, v)| v)
+			.collect::<Vec<_>>())
+	}
+
+	/// Saves the outbound peers we reconnect to first on restart.
+	pub fn save_anchors(&self, anchors: &[PeerAddr]) -> Result<(), Error> {
+		let batch = self.db.batch()?;
+		batch.put_ser(&anchors_key()[..], &anchors.to_vec())?;
+		batch.commit()
+	}
+
+	pub fn anchors(&self) -> Result<Vec<PeerAddr>, Error> {
+		Ok(self
+			.db
+			.get_ser::<Vec<PeerAddr>>(&anchors_key()[..])?
+			.unwrap_or(vec![]))
+	}
+
+	/// List all subnet bans, including expired ones not pruned yet.
+	pub fn all_bans(&self) -> Result<Vec<BanEntry>, Error> {
+		let key = to_key(BAN_PREFIX, &mut "".to_string().into_bytes());
+		Ok(self
+			.db
+			.iter::<BanEntry>(&key)?
+			.map(|(_, v)| v)
+			.collect::<Vec<_>>())
+	}
+}
+
+fn known_addr_key(addr: PeerAddr) -> Vec<u8> {
+	to_key(KNOWN_ADDR_PREFIX, &mut addr.as_key().into_bytes())
+}
+
+fn anchors_key() -> Vec<u8> {
+	to_key(ANCHORS_PREFIX, &mut "".to_string().into_bytes())
+}
+
+fn ban_key(subnet: &Subnet) -> Vec<u8> {
+	to_key(BAN_PREFIX, &mut subnet.to_string().into_bytes())
 }
 
 // Ignore the port unless ip is loopback address.
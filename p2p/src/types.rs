@@ -26,11 +26,15 @@ use std::sync::Arc;
 use chrono::prelude::*;
 
 use crate::chain;
+use crate::chain::{KernelProof, OutputProof, SegmentType, TxHashSetSegment};
 use crate::core::core;
 use crate::core::core::hash::Hash;
+use crate::core::core::id::ShortId;
+use crate::core::core::pmmr::SegmentIdentifier;
 use crate::core::global;
 use crate::core::pow::Difficulty;
 use crate::core::ser::{self, ProtocolVersion, Readable, Reader, Writeable, Writer};
+use crate::util::secp::pedersen::Commitment;
 use gotts_store;
 
 /// Maximum number of block headers a peer should ever send
@@ -43,12 +47,34 @@ pub const MAX_BLOCK_BODIES: u32 = 16;
 /// Maximum number of peer addresses a peer should ever send
 pub const MAX_PEER_ADDRS: u32 = 256;
 
+/// Maximum number of external addresses a peer advertises in its handshake
+pub const MAX_EXTERNAL_ADDRS: u32 = 8;
+
+/// Maximum number of new block headers announced in a single message
+pub const MAX_ANNOUNCED_HEADERS: u32 = 8;
+
 /// Maximum number of block header hashes to send as part of a locator
 pub const MAX_LOCATORS: u32 = 20;
 
+/// Maximum number of dependent transactions a peer should ever send in a package
+pub const MAX_TX_PACKAGE_SIZE: u16 = 25;
+
 /// How long a banned peer should be banned for
 const BAN_WINDOW: i64 = 10800;
 
+/// Misbehaviour score at which we disconnect from a peer
+pub const PEER_SCORE_DISCONNECT: i32 = 50;
+
+/// Misbehaviour score at which we ban a peer
+pub const PEER_SCORE_BAN: i32 = 100;
+
+/// Time (in seconds) for a misbehaviour score to decay by half
+pub const PEER_SCORE_HALF_LIFE: i64 = 1800;
+
+/// Peers whose scores fall in the same bucket are considered equally reliable
+/// when picking a peer to sync from
+pub const PEER_SCORE_BUCKET: i32 = 10;
+
 /// The max inbound peer count
 const PEER_MAX_INBOUND_COUNT: u32 = 128;
 
@@ -58,10 +84,23 @@ const PEER_MAX_OUTBOUND_COUNT: u32 = 8;
 /// The min preferred outbound peer count
 const PEER_MIN_PREFERRED_OUTBOUND_COUNT: u32 = 8;
 
+/// The max number of outbound peers in the same /16 (IPv4) or /32 (IPv6)
+const PEER_MAX_OUTBOUND_PER_SUBNET: u32 = 1;
+
 /// The peer listener buffer count. Allows temporarily accepting more connections
 /// than allowed by PEER_MAX_INBOUND_COUNT to encourage network bootstrapping.
 const PEER_LISTENER_BUFFER_COUNT: u32 = 8;
 
+/// How far ahead of our head we request blocks during body sync, kept under
+/// the orphan pool size as blocks may arrive out of order.
+const BLOCK_DOWNLOAD_WINDOW: u64 = 128;
+
+/// Max number of block requests in flight with a single peer.
+const PEER_MAX_BLOCK_REQUESTS: u32 = 16;
+
+/// Seconds to wait for a requested block before asking another peer.
+const BLOCK_REQUEST_TIMEOUT: u64 = 15;
+
 #[derive(Debug)]
 pub enum Error {
 	Serialization(ser::Error),
@@ -83,6 +122,8 @@ pub enum Error {
 	Send(String),
 	PeerException,
 	Internal,
+	/// Encrypted transport negotiation or key handling failed.
+	Encryption(String),
 }
 
 impl From<ser::Error> for Error {
@@ -164,10 +205,10 @@ impl std::hash::Hash for PeerAddr {
 	/// If loopback address then we care about ip and port.
 	/// If regular address then we only care about the ip and ignore the port.
 	fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-		if self.0.ip().is_loopback() {
-			self.0.hash(state);
-		} else {
-			self.0.ip().hash(state);
+		let ip = self.ip();
+		ip.hash(state);
+		if ip.is_loopback() {
+			self.0.port().hash(state);
 		}
 	}
 }
@@ -176,11 +217,11 @@ impl PartialEq for PeerAddr {
 	/// If loopback address then we care about ip and port.
 	/// If regular address then we only care about the ip and ignore the port.
 	fn eq(&self, other: &PeerAddr) -> bool {
-		if self.0.ip().is_loopback() {
-			self.0 == other.0
-		} else {
-			self.0.ip() == other.0.ip()
+		let ip = self.ip();
+		if ip != other.ip() {
+			return false;
 		}
+		!ip.is_loopback() || self.0.port() == other.0.port()
 	}
 }
 
@@ -200,23 +241,214 @@ impl PeerAddr {
 		PeerAddr(SocketAddr::new(addr, port))
 	}
 
-	/// If the ip is loopback then our key is "ip:port" (mainly for local usernet testing).
+	/// If the ip is loopback then our key is "ip:port" (mainly for local usernet testing),
+	/// "[ip]:port" for IPv6.
 	/// Otherwise we only care about the ip (we disallow multiple peers on the same ip address).
 	pub fn as_key(&self) -> String {
-		if self.0.ip().is_loopback() {
-			format!("{}:{}", self.0.ip(), self.0.port())
+		let ip = self.ip();
+		if ip.is_loopback() {
+			format!("{}", SocketAddr::new(ip, self.0.port()))
 		} else {
-			format!("{}", self.0.ip())
+			format!("{}", ip)
+		}
+	}
+
+	/// The ip of the peer, IPv4 addresses mapped in IPv6 (::ffff:a.b.c.d, as
+	/// reported by dual stack sockets) being converted back to IPv4.
+	pub fn ip(&self) -> IpAddr {
+		canonical_ip(self.0.ip())
+	}
+
+	/// The same address, with an IPv4 mapped ip converted back to IPv4. Also
+	/// drops the IPv6 flow info and scope id which aren't relevant to us.
+	pub fn canonical(&self) -> PeerAddr {
+		PeerAddr(SocketAddr::new(self.ip(), self.0.port()))
+	}
+}
+
+/// Converts an IPv4 mapped IPv6 address (::ffff:a.b.c.d) to its IPv4 address.
+/// Unlike `Ipv6Addr::to_ipv4` IPv4 compatible addresses (like ::1) are left
+/// untouched.
+pub fn canonical_ip(ip: IpAddr) -> IpAddr {
+	match ip {
+		IpAddr::V6(ip6) => match ip6.segments() {
+			[0, 0, 0, 0, 0, 0xffff, _, _] => IpAddr::V4(ip6.to_ipv4().unwrap()),
+			_ => ip,
+		},
+		IpAddr::V4(_) => ip,
+	}
+}
+
+/// A block of IP addresses in CIDR notation, e.g. "10.12.0.0/16". A single
+/// address is the subnet with the full prefix length (/32 or /128), so bans
+/// on individual peers and on whole ranges are handled the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Subnet {
+	addr: IpAddr,
+	prefix_len: u8,
+}
+
+impl Subnet {
+	/// Builds the subnet of the given prefix length containing `addr`, the host
+	/// bits of the address are cleared.
+	pub fn new(addr: IpAddr, prefix_len: u8) -> Result<Subnet, String> {
+		let max_len = match addr {
+			IpAddr::V4(_) => 32,
+			IpAddr::V6(_) => 128,
+		};
+		if prefix_len > max_len {
+			return Err(format!(
+				"invalid prefix length /{} for {}",
+				prefix_len, addr
+			));
+		}
+		let addr = match addr {
+			IpAddr::V4(ip) => {
+				let mask = (!0u32).checked_shl(32 - prefix_len as u32).unwrap_or(0);
+				IpAddr::V4(Ipv4Addr::from(u32::from(ip) & mask))
+			}
+			IpAddr::V6(ip) => {
+				let mask = (!0u128).checked_shl(128 - prefix_len as u32).unwrap_or(0);
+				IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask))
+			}
+		};
+		Ok(Subnet { addr, prefix_len })
+	}
+
+	/// The subnet made of this single address.
+	pub fn from_ip(addr: IpAddr) -> Subnet {
+		let prefix_len = match addr {
+			IpAddr::V4(_) => 32,
+			IpAddr::V6(_) => 128,
+		};
+		Subnet { addr, prefix_len }
+	}
+
+	/// The network address (host bits cleared).
+	pub fn addr(&self) -> IpAddr {
+		self.addr
+	}
+
+	pub fn prefix_len(&self) -> u8 {
+		self.prefix_len
+	}
+
+	/// Whether this subnet is a single address.
+	pub fn is_single(&self) -> bool {
+		self.prefix_len == Subnet::from_ip(self.addr).prefix_len
+	}
+
+	/// Whether the address is part of this subnet. Addresses of the other
+	/// family never are.
+	pub fn contains(&self, ip: IpAddr) -> bool {
+		let ip = canonical_ip(ip);
+		match (self.addr, ip) {
+			(IpAddr::V4(_), IpAddr::V4(_)) | (IpAddr::V6(_), IpAddr::V6(_)) => {
+				match Subnet::new(ip, self.prefix_len) {
+					Ok(other) => other.addr == self.addr,
+					Err(_) => false,
+				}
+			}
+			_ => false,
+		}
+	}
+}
+
+impl std::fmt::Display for Subnet {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(f, "{}/{}", self.addr, self.prefix_len)
+	}
+}
+
+impl std::str::FromStr for Subnet {
+	type Err = String;
+
+	/// Parses "ip/prefix_len", or a bare ip as a single address subnet.
+	fn from_str(s: &str) -> Result<Subnet, String> {
+		let mut parts = s.trim().splitn(2, '/');
+		let addr: IpAddr = parts
+			.next()
+			.unwrap_or("")
+			.parse()
+			.map_err(|_| format!("invalid subnet address: {}", s))?;
+		match parts.next() {
+			None => Ok(Subnet::from_ip(addr)),
+			Some(len) => {
+				let prefix_len = len
+					.parse()
+					.map_err(|_| format!("invalid subnet prefix length: {}", s))?;
+				Subnet::new(addr, prefix_len)
+			}
 		}
 	}
 }
 
+impl serde::Serialize for Subnet {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.collect_str(self)
+	}
+}
+
+impl<'de> serde::Deserialize<'de> for Subnet {
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Subnet, D::Error> {
+		let s = String::deserialize(deserializer)?;
+		s.parse().map_err(serde::de::Error::custom)
+	}
+}
+
+impl Writeable for Subnet {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		match self.addr {
+			IpAddr::V4(ip) => {
+				writer.write_u8(0)?;
+				writer.write_fixed_bytes(&ip.octets().to_vec())?;
+			}
+			IpAddr::V6(ip) => {
+				writer.write_u8(1)?;
+				writer.write_fixed_bytes(&ip.octets().to_vec())?;
+			}
+		}
+		writer.write_u8(self.prefix_len)
+	}
+}
+
+impl Readable for Subnet {
+	fn read(reader: &mut dyn Reader) -> Result<Subnet, ser::Error> {
+		let addr = if reader.read_u8()? == 0 {
+			let ip = reader.read_fixed_bytes(4)?;
+			IpAddr::V4(Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3]))
+		} else {
+			let ip = reader.read_fixed_bytes(16)?;
+			let mut octets = [0u8; 16];
+			octets.copy_from_slice(&ip);
+			IpAddr::V6(Ipv6Addr::from(octets))
+		};
+		let prefix_len = reader.read_u8()?;
+		Subnet::new(addr, prefix_len).map_err(|_| ser::Error::CorruptedData)
+	}
+}
+
 /// Configuration for the peer-to-peer server.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct P2PConfig {
 	pub host: IpAddr,
 	pub port: u16,
 
+	/// Additional addresses to listen on besides host:port, i.e. "[::]:3514"
+	/// to accept IPv6 connections as well.
+	pub listen_addrs: Option<Vec<SocketAddr>>,
+
+	/// Publicly reachable addresses advertised to our peers, when they differ
+	/// from the ones we listen on (NAT, port forwarding...).
+	pub external_addrs: Option<Vec<PeerAddr>>,
+
+	/// Accept incoming connections, true by default.
+	pub listen: Option<bool>,
+
+	/// SOCKS5 proxy all our outbound connections and DNS seed lookups go
+	/// through.
+	pub socks5_proxy: Option<SocketAddr>,
+
 	/// Method used to get the list of seed nodes for initial bootstrap.
 	#[serde(default)]
 	pub seeding_type: Seeding,
@@ -245,7 +477,47 @@ pub struct P2PConfig {
 
 	pub peer_listener_buffer_count: Option<u32>,
 
+	/// Max number of outbound peers in the same /16 (IPv4) or /32 (IPv6).
+	pub peer_max_outbound_per_subnet: Option<u32>,
+
 	pub dandelion_peer: Option<PeerAddr>,
+
+	/// Offer the encrypted transport to peers supporting it.
+	pub encryption: Option<bool>,
+
+	/// Only accept encrypted connections, refusing peers without support.
+	pub require_encryption: Option<bool>,
+
+	/// Negotiate "headers first" announcements of new blocks with our peers.
+	pub headers_announce: Option<bool>,
+
+	/// Maximum upload rate of the whole node, in bytes per second.
+	pub max_upload_rate: Option<u64>,
+
+	/// Maximum download rate of the whole node, in bytes per second.
+	pub max_download_rate: Option<u64>,
+
+	/// Maximum upload rate to a single peer, in bytes per second.
+	pub peer_max_upload_rate: Option<u64>,
+
+	/// Maximum download rate from a single peer, in bytes per second.
+	pub peer_max_download_rate: Option<u64>,
+
+	/// Upload budget for serving txhashset archives, in bytes per second.
+	pub txhashset_max_upload_rate: Option<u64>,
+
+	/// Upload budget for relaying blocks, in bytes per second.
+	pub block_max_upload_rate: Option<u64>,
+
+	/// How far ahead of our head we request blocks during body sync.
+	pub block_download_window: Option<u64>,
+
+	/// Max number of block requests in flight with a single peer during
+	/// body sync.
+	pub peer_max_block_requests: Option<u32>,
+
+	/// Seconds to wait for a requested block before asking another peer.
+	pub block_request_timeout: Option<u64>,
 }
 
 /// Default address for peer-to-peer connections.
@@ -255,6 +527,10 @@ impl Default for P2PConfig {
 		P2PConfig {
 			host: ipaddr,
 			port: 3514,
+			listen_addrs: None,
+			external_addrs: None,
+			listen: None,
+			socks5_proxy: None,
 			capabilities: Capabilities::FULL_NODE,
 			seeding_type: Seeding::default(),
 			seeds: None,
@@ -266,7 +542,20 @@ impl Default for P2PConfig {
 			peer_max_outbound_count: None,
 			peer_min_preferred_outbound_count: None,
 			peer_listener_buffer_count: None,
+			peer_max_outbound_per_subnet: None,
 			dandelion_peer: None,
+			encryption: None,
+			require_encryption: None,
+			headers_announce: None,
+			max_upload_rate: None,
+			max_download_rate: None,
+			peer_max_upload_rate: None,
+			peer_max_download_rate: None,
+			txhashset_max_upload_rate: None,
+			block_max_upload_rate: None,
+			block_download_window: None,
+			peer_max_block_requests: None,
+			block_request_timeout: None,
 		}
 	}
 }
@@ -274,6 +563,32 @@ impl Default for P2PConfig {
 /// Note certain fields are options just so they don't have to be
 /// included in gotts-server.toml, but we don't want them to ever return none
 impl P2PConfig {
+	/// All the addresses to listen on, host:port first.
+	pub fn listen_addrs(&self) -> Vec<SocketAddr> {
+		let mut addrs = vec![SocketAddr::new(self.host, self.port)];
+		for addr in self.listen_addrs.iter().flatten() {
+			if !addrs.contains(addr) {
+				addrs.push(*addr);
+			}
+		}
+		addrs
+	}
+
+	/// whether we accept incoming connections
+	pub fn listen(&self) -> bool {
+		self.listen.unwrap_or(true)
+	}
+
+	/// The addresses we advertise to our peers, the external ones if configured.
+	/// Falls back to host:port otherwise, the receiving peer only trusting the
+	/// port anyway and using the ip it sees the connection coming from.
+	pub fn external_addrs(&self) -> Vec<PeerAddr> {
+		match self.external_addrs {
+			Some(ref addrs) if !addrs.is_empty() => addrs.clone(),
+			_ => vec![PeerAddr(SocketAddr::new(self.host, self.port))],
+		}
+	}
+
 	/// return ban window
 	pub fn ban_window(&self) -> i64 {
 		match self.ban_window {
@@ -313,6 +628,76 @@ impl P2PConfig {
 			None => PEER_LISTENER_BUFFER_COUNT,
 		}
 	}
+
+	/// return maximum outbound peer count per subnet
+	pub fn peer_max_outbound_per_subnet(&self) -> u32 {
+		match self.peer_max_outbound_per_subnet {
+			Some(n) => n,
+			None => PEER_MAX_OUTBOUND_PER_SUBNET,
+		}
+	}
+
+	/// whether the encrypted transport is enabled, implied by requiring it
+	pub fn encryption(&self) -> bool {
+		self.encryption.unwrap_or(false) || self.require_encryption()
+	}
+
+	/// whether connections without encryption are refused
+	pub fn require_encryption(&self) -> bool {
+		self.require_encryption.unwrap_or(false)
+	}
+
+	/// whether new blocks are announced with their headers to the peers
+	/// asking for it, true by default
+	pub fn headers_announce(&self) -> bool {
+		self.headers_announce.unwrap_or(true)
+	}
+
+	/// return the node upload rate limit, 0 if unlimited
+	pub fn max_upload_rate(&self) -> u64 {
+		self.max_upload_rate.unwrap_or(0)
+	}
+
+	/// return the node download rate limit, 0 if unlimited
+	pub fn max_download_rate(&self) -> u64 {
+		self.max_download_rate.unwrap_or(0)
+	}
+
+	/// return the per-peer upload rate limit, 0 if unlimited
+	pub fn peer_max_upload_rate(&self) -> u64 {
+		self.peer_max_upload_rate.unwrap_or(0)
+	}
+
+	/// return the per-peer download rate limit, 0 if unlimited
+	pub fn peer_max_download_rate(&self) -> u64 {
+		self.peer_max_download_rate.unwrap_or(0)
+	}
+
+	/// return the txhashset archive upload rate limit, 0 if unlimited
+	pub fn txhashset_max_upload_rate(&self) -> u64 {
+		self.txhashset_max_upload_rate.unwrap_or(0)
+	}
+
+	/// return the block relay upload rate limit, 0 if unlimited
+	pub fn block_max_upload_rate(&self) -> u64 {
+		self.block_max_upload_rate.unwrap_or(0)
+	}
+
+	/// return the body sync download window, in blocks
+	pub fn block_download_window(&self) -> u64 {
+		self.block_download_window.unwrap_or(BLOCK_DOWNLOAD_WINDOW)
+	}
+
+	/// return the max number of block requests in flight with a peer
+	pub fn peer_max_block_requests(&self) -> u32 {
+		self.peer_max_block_requests
+			.unwrap_or(PEER_MAX_BLOCK_REQUESTS)
+	}
+
+	/// return the block request timeout, in seconds
+	pub fn block_request_timeout(&self) -> u64 {
+		self.block_request_timeout.unwrap_or(BLOCK_REQUEST_TIMEOUT)
+	}
 }
 
 /// Type of seeding the server will use to find other peers on the network.
@@ -350,6 +735,28 @@ bitflags! {
 		const PEER_LIST = 0b00000100;
 		/// Can broadcast and request txs by kernel hash.
 		const TX_KERNEL_HASH = 0b00001000;
+		/// Can provide the txs of a compact block by kernel short_id.
+		/// Not part of FULL_NODE so older full nodes still match it.
+		const BLOCK_TXS = 0b00010000;
+		/// Can run the Noise handshake and talk over the encrypted transport.
+		/// Only advertised when enabled in the config, so not part of FULL_NODE.
+		const ENCRYPTED = 0b00100000;
+		/// Can announce new blocks with their headers first, to the peers
+		/// asking for it (SendHeaders), and request them in compact form.
+		/// Advertised unless disabled in the config.
+		const HEADERS_ANNOUNCE = 0b01000000;
+		/// Can provide segments of the txhashset (with merkle proofs) at the
+		/// archive header, for a new node to download them in parallel.
+		/// Not part of FULL_NODE so older full nodes still match it.
+		const TXHASHSET_SEGMENTS = 0b10000000;
+		/// Can provide unspent outputs and tx kernels with merkle proofs
+		/// against its head header, for light nodes.
+		/// Not part of FULL_NODE so older full nodes still match it.
+		const MERKLE_PROOFS = 0b100000000;
+		/// Can receive dependent transactions as a single TransactionPackage.
+		/// Older peers get the transactions one by one, parents first.
+		/// Not part of FULL_NODE so older full nodes still match it.
+		const TX_PACKAGE = 0b1000000000;
 
 		/// All nodes right now are "full nodes".
 		/// Some nodes internally may maintain longer block histories (archival_mode)
@@ -383,6 +790,67 @@ enum_from_primitive! {
 		ManualBan = 5,
 		FraudHeight = 6,
 		BadHandshake = 7,
+		Misbehaviour = 8,
+	}
+}
+
+/// The kinds of misbehaviour a peer is penalized for, see `PeerScore`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Penalty {
+	/// A message we could not deserialize or with an inconsistent length.
+	InvalidMessage,
+	/// Data we did not ask for.
+	UnsolicitedData,
+	/// A request left unanswered for too long.
+	SlowResponse,
+	/// Headers we already had, not moving our header chain forward.
+	StaleHeaders,
+}
+
+impl Penalty {
+	/// Points added to the peer score.
+	pub fn points(&self) -> i32 {
+		match self {
+			Penalty::InvalidMessage => 25,
+			Penalty::UnsolicitedData => 10,
+			Penalty::SlowResponse => 10,
+			Penalty::StaleHeaders => 2,
+		}
+	}
+}
+
+/// Misbehaviour score of a peer. Penalties accumulate and decay exponentially
+/// over time (see `PEER_SCORE_HALF_LIFE`), so a well behaved peer is back to
+/// zero after a while. Crossing `PEER_SCORE_DISCONNECT` gets the peer
+/// disconnected and `PEER_SCORE_BAN` banned.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct PeerScore {
+	/// Score as of the last update.
+	pub value: i32,
+	/// Time of the last update.
+	pub updated: i64,
+}
+
+impl PeerScore {
+	/// The score decayed up to the provided time.
+	pub fn at(&self, now: i64) -> i32 {
+		if self.value <= 0 || now <= self.updated {
+			return self.value;
+		}
+		let half_lives = (now - self.updated) as f64 / PEER_SCORE_HALF_LIFE as f64;
+		(self.value as f64 * 0.5f64.powf(half_lives)).round() as i32
+	}
+
+	/// The current decayed score.
+	pub fn current(&self) -> i32 {
+		self.at(Utc::now().timestamp())
+	}
+
+	/// Adds a penalty at the provided time, returning the new score.
+	pub fn penalize(&mut self, penalty: Penalty, now: i64) -> i32 {
+		self.value = self.at(now).saturating_add(penalty.points());
+		self.updated = now;
+		self.value
 	}
 }
 
@@ -393,6 +861,11 @@ pub struct PeerLiveInfo {
 	pub last_seen: DateTime<Utc>,
 	pub stuck_detector: DateTime<Utc>,
 	pub first_seen: DateTime<Utc>,
+	pub score: PeerScore,
+	/// The peer asked us to announce new blocks with their headers (SendHeaders).
+	pub send_headers: bool,
+	/// Header requests sent to the peer it did not answer yet.
+	pub header_requests: u32,
 }
 
 /// General information about a connected peer that's useful to other modules.
@@ -400,8 +873,12 @@ pub struct PeerLiveInfo {
 pub struct PeerInfo {
 	pub capabilities: Capabilities,
 	pub user_agent: String,
+	/// Protocol version negotiated with this peer, min(ours, theirs), used to
+	/// serialize and deserialize every message on the connection.
 	pub version: ProtocolVersion,
 	pub addr: PeerAddr,
+	/// Publicly reachable addresses the peer advertised in its handshake.
+	pub external_addrs: Vec<PeerAddr>,
 	pub direction: Direction,
 	pub live_info: Arc<RwLock<PeerLiveInfo>>,
 }
@@ -414,6 +891,9 @@ impl PeerLiveInfo {
 			first_seen: Utc::now(),
 			last_seen: Utc::now(),
 			stuck_detector: Utc::now(),
+			score: PeerScore::default(),
+			send_headers: false,
+			header_requests: 0,
 		}
 	}
 }
@@ -424,6 +904,11 @@ impl PeerInfo {
 		self.live_info.read().total_difficulty
 	}
 
+	/// The current misbehaviour score of the peer.
+	pub fn score(&self) -> i32 {
+		self.live_info.read().score.current()
+	}
+
 	pub fn is_outbound(&self) -> bool {
 		self.direction == Direction::Outbound
 	}
@@ -447,6 +932,23 @@ impl PeerInfo {
 		self.live_info.read().first_seen
 	}
 
+	/// Whether we asked the peer for headers it did not send yet.
+	pub fn awaits_headers(&self) -> bool {
+		self.live_info.read().header_requests > 0
+	}
+
+	/// Counts a header request sent to the peer.
+	pub fn header_requested(&self) {
+		let mut live_info = self.live_info.write();
+		live_info.header_requests = live_info.header_requests.saturating_add(1);
+	}
+
+	/// Counts a header request answered by the peer, late answers included.
+	pub fn header_request_answered(&self) {
+		let mut live_info = self.live_info.write();
+		live_info.header_requests = live_info.header_requests.saturating_sub(1);
+	}
+
 	/// Update the total_difficulty, height and last_seen of the peer.
 	/// Takes a write lock on the live_info.
 	pub fn update(&self, height: u64, total_difficulty: Difficulty) {
@@ -511,11 +1013,41 @@ pub trait ChainAdapter: Sync + Send {
 	fn total_height(&self) -> Result<u64, chain::Error>;
 
 	/// A valid transaction has been received from one of our peers
-	fn transaction_received(&self, tx: core::Transaction, stem: bool)
-		-> Result<bool, chain::Error>;
+	fn transaction_received(
+		&self,
+		tx: core::Transaction,
+		peer_info: &PeerInfo,
+		stem: bool,
+	) -> Result<bool, chain::Error>;
+
+	/// A package of dependent transactions (topologically ordered) has been
+	/// received from one of our peers, to be accepted as a unit.
+	fn transaction_package_received(
+		&self,
+		txs: Vec<core::Transaction>,
+		peer_info: &PeerInfo,
+		stem: bool,
+	) -> Result<bool, chain::Error>;
 
 	fn get_transaction(&self, kernel_hash: Hash) -> Option<core::Transaction>;
 
+	/// Find the txs matching the provided kernel short_ids of a compact block
+	/// (built from the block hash and nonce), to serve a GetBlockTxs request.
+	fn get_block_txs(
+		&self,
+		hash: Hash,
+		nonce: u64,
+		short_ids: &[ShortId],
+	) -> Vec<core::Transaction>;
+
+	/// The txs we requested to hydrate a compact block have been received.
+	fn block_txs_received(
+		&self,
+		hash: Hash,
+		txs: Vec<core::Transaction>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error>;
+
 	fn tx_kernel_received(
 		&self,
 		kernel_hash: Hash,
@@ -603,6 +1135,58 @@ pub trait ChainAdapter: Sync + Send {
 		peer_peer_info: &PeerInfo,
 	) -> Result<bool, chain::Error>;
 
+	/// Provides a segment of the txhashset at the given (archive) header, with
+	/// its merkle proof, if that's the txhashset we're serving.
+	fn get_txhashset_segment(
+		&self,
+		h: Hash,
+		segment_type: SegmentType,
+		id: SegmentIdentifier,
+	) -> Option<TxHashSetSegment>;
+
+	/// A txhashset segment we requested has been received. Returns true if
+	/// the segment doesn't match the header roots, so the peer can be banned.
+	fn txhashset_segment_received(
+		&self,
+		h: Hash,
+		segment: TxHashSetSegment,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error>;
+
+	/// Provides an unspent output with a merkle proof of its inclusion against
+	/// our head header, for a light node.
+	fn get_output_proof(&self, commit: Commitment) -> Option<OutputProof>;
+
+	/// An output proof we requested has been received, without proof if the
+	/// peer doesn't have that output. Returns true if the proof is invalid,
+	/// so the peer can be banned.
+	fn output_proof_received(
+		&self,
+		commit: Commitment,
+		proof: Option<OutputProof>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error>;
+
+	/// Provides a tx kernel with a merkle proof of its inclusion against our
+	/// head header, for a light node.
+	fn get_kernel_proof(&self, excess: Commitment) -> Option<KernelProof>;
+
+	/// A kernel proof we requested has been received, without proof if the
+	/// peer doesn't have that kernel. Returns true if the proof is invalid,
+	/// so the peer can be banned.
+	fn kernel_proof_received(
+		&self,
+		excess: Commitment,
+		proof: Option<KernelProof>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error>;
+
+	/// A peer is now connected, after a successful handshake.
+	fn peer_connected(&self, peer_info: &PeerInfo);
+
+	/// A connected peer has been dropped.
+	fn peer_disconnected(&self, addr: PeerAddr);
+
 	/// Get the Gotts specific tmp dir
 	fn get_tmp_dir(&self) -> PathBuf;
 
@@ -619,11 +1203,14 @@ pub trait NetAdapter: ChainAdapter {
 	fn find_peer_addrs(&self, capab: Capabilities) -> Vec<PeerAddr>;
 
 	/// A list of peers has been received from one of our peers.
-	fn peer_addrs_received(&self, _: Vec<PeerAddr>);
+	fn peer_addrs_received(&self, _: Vec<PeerAddr>, peer_info: &PeerInfo);
 
 	/// Heard total_difficulty from a connected peer (via ping/pong).
 	fn peer_difficulty(&self, _: PeerAddr, _: Difficulty, _: u64);
 
 	/// Is this peer currently banned?
 	fn is_banned(&self, addr: PeerAddr) -> bool;
+
+	/// A peer misbehaved, add the penalty to its score.
+	fn penalize_peer(&self, addr: PeerAddr, penalty: Penalty);
 }
@@ -0,0 +1,99 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use gotts_core as core;
+use gotts_p2p as p2p;
+use gotts_util as util;
+
+use std::collections::HashSet;
+use std::fs;
+use std::sync::Arc;
+
+use self::core::core::hash::Hash;
+use self::p2p::addrman::addr_group;
+use self::p2p::{Capabilities, PeerAddr, PeerData, PeerScore, ReasonForBan, State};
+use self::util::StopState;
+
+fn clean_output_dir(test_dir: &str) {
+	let _ = fs::remove_dir_all(test_dir);
+}
+
+fn addr(s: &str) -> PeerAddr {
+	PeerAddr(s.parse().unwrap())
+}
+
+// Outbound candidates never include two addresses of the same /16, and the
+// address manager tables survive a restart.
+#[test]
+fn select_outbound_per_subnet() {
+	util::init_test_logger();
+	let test_dir = ".gotts_addrman";
+	clean_output_dir(test_dir);
+
+	let new_server = || {
+		p2p::Server::new(
+			test_dir,
+			Capabilities::UNKNOWN,
+			p2p::P2PConfig::default(),
+			Arc::new(p2p::DummyAdapter {}),
+			Hash::from_vec(&vec![]),
+			Arc::new(StopState::new()),
+		)
+		.unwrap()
+	};
+
+	{
+		let server = new_server();
+		for a in &[
+			"10.1.0.1:13514",
+			"10.1.0.2:13514",
+			"10.1.7.3:13514",
+			"10.2.0.1:13514",
+			"10.2.9.2:13514",
+			"10.3.0.1:13514",
+		] {
+			server
+				.peers
+				.save_peer(&PeerData {
+					addr: addr(a),
+					capabilities: Capabilities::UNKNOWN,
+					user_agent: "".to_string(),
+					flags: State::Healthy,
+					last_banned: 0,
+					ban_reason: ReasonForBan::None,
+					last_connected: 0,
+					score: PeerScore::default(),
+				})
+				.unwrap();
+		}
+		server.stop();
+	}
+
+	// first restart imports the healthy peers, second one reads the
+	// address manager tables saved on stop
+	for _ in 0..2 {
+		let server = new_server();
+		let selected = server.peers.select_outbound(10);
+		assert!(!selected.is_empty() && selected.len() <= 3);
+		let groups: HashSet<_> = selected.iter().filter_map(addr_group).collect();
+		assert_eq!(groups.len(), selected.len());
+
+		// loopback addresses are not grouped
+		assert!(addr_group(&addr("127.0.0.1:13514")).is_none());
+		assert!(server.peers.anchors().is_empty());
+		server.stop();
+	}
+
+	clean_output_dir(test_dir);
+}
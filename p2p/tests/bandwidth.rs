@@ -0,0 +1,54 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use gotts_p2p as p2p;
+
+use std::thread;
+use std::time::Duration;
+
+use self::p2p::{P2PConfig, RateLimiter};
+
+#[test]
+fn unlimited_rate() {
+	let limiter = RateLimiter::new(0);
+	assert!(!limiter.is_limited());
+	assert_eq!(limiter.take(1_000_000_000), Duration::from_secs(0));
+
+	let config = P2PConfig::default();
+	assert_eq!(config.max_upload_rate(), 0);
+	assert_eq!(config.peer_max_download_rate(), 0);
+	assert_eq!(config.txhashset_max_upload_rate(), 0);
+}
+
+#[test]
+fn token_bucket() {
+	let limiter = RateLimiter::new(10_000);
+	assert!(limiter.is_limited());
+
+	// a second worth of traffic goes through right away
+	assert_eq!(limiter.take(10_000), Duration::from_secs(0));
+
+	// then we're in debt and have to wait for the bucket to refill
+	let wait = limiter.take(5_000);
+	assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500));
+	let wait = limiter.take(5_000);
+	assert!(wait > Duration::from_millis(900) && wait <= Duration::from_millis(1000));
+
+	// tokens come back over time, but never more than a second worth
+	thread::sleep(Duration::from_millis(1500));
+	assert!(limiter.take(0) < Duration::from_millis(100));
+	thread::sleep(Duration::from_millis(1500));
+	assert_eq!(limiter.take(10_000), Duration::from_secs(0));
+	assert!(limiter.take(1_000) > Duration::from_millis(0));
+}
@@ -0,0 +1,164 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use gotts_core as core;
+use gotts_p2p as p2p;
+use gotts_util as util;
+
+use std::fs;
+use std::sync::Arc;
+
+use self::core::core::hash::Hash;
+use self::core::ser::{self, ProtocolVersion};
+use self::p2p::{BanEntry, Capabilities, PeerAddr, ReasonForBan, Subnet};
+use self::util::StopState;
+
+fn clean_output_dir(test_dir: &str) {
+	let _ = fs::remove_dir_all(test_dir);
+}
+
+fn addr(s: &str) -> PeerAddr {
+	PeerAddr(s.parse().unwrap())
+}
+
+#[test]
+fn subnet_parse_and_contains() {
+	let subnet: Subnet = "10.12.34.56/16".parse().unwrap();
+	assert_eq!(subnet.to_string(), "10.12.0.0/16");
+	assert!(subnet.contains("10.12.200.1".parse().unwrap()));
+	assert!(!subnet.contains("10.13.0.1".parse().unwrap()));
+	assert!(!subnet.contains("::1".parse().unwrap()));
+
+	let single: Subnet = "10.12.34.56".parse().unwrap();
+	assert!(single.is_single());
+	assert_eq!(single.to_string(), "10.12.34.56/32");
+	assert!(!single.contains("10.12.34.57".parse().unwrap()));
+
+	let v6: Subnet = "2001:db8:1234::1/48".parse().unwrap();
+	assert_eq!(v6.to_string(), "2001:db8:1234::/48");
+	assert!(v6.contains("2001:db8:1234:ffff::2".parse().unwrap()));
+	assert!(!v6.contains("2001:db8:1235::2".parse().unwrap()));
+
+	let all: Subnet = "0.0.0.0/0".parse().unwrap();
+	assert!(all.contains("192.168.1.1".parse().unwrap()));
+
+	assert!("10.0.0.0/33".parse::<Subnet>().is_err());
+	assert!("10.0.0/8".parse::<Subnet>().is_err());
+	assert!("10.0.0.0/x".parse::<Subnet>().is_err());
+}
+
+#[test]
+fn ban_entry_ser_and_json() {
+	let ban = BanEntry::new(
+		"172.16.0.0/12".parse().unwrap(),
+		ReasonForBan::ManualBan,
+		"sybil wave".to_string(),
+		Some(3600),
+	);
+	let bytes = ser::ser_vec(&ban, ProtocolVersion::local()).unwrap();
+	let read: BanEntry = ser::deserialize(&mut &bytes[..], ProtocolVersion::local()).unwrap();
+	assert_eq!(read, ban);
+
+	// exported lists can be hand written, only the subnet and reason are needed
+	let json = r#"[{"subnet": "192.168.0.0/24", "reason": "ManualBan"}]"#;
+	let bans: Vec<BanEntry> = serde_json::from_str(json).unwrap();
+	assert_eq!(bans[0].subnet.to_string(), "192.168.0.0/24");
+	assert_eq!(bans[0].expires, None);
+	assert_eq!(
+		serde_json::from_str::<Vec<BanEntry>>(&serde_json::to_string(&bans).unwrap()).unwrap(),
+		bans
+	);
+}
+
+#[test]
+fn subnet_bans() {
+	util::init_test_logger();
+	let test_dir = ".gotts_bans";
+	clean_output_dir(test_dir);
+
+	let new_server = || {
+		p2p::Server::new(
+			test_dir,
+			Capabilities::UNKNOWN,
+			p2p::P2PConfig::default(),
+			Arc::new(p2p::DummyAdapter {}),
+			Hash::from_vec(&vec![]),
+			Arc::new(StopState::new()),
+		)
+		.unwrap()
+	};
+
+	{
+		let server = new_server();
+		let peers = &server.peers;
+		let subnet: Subnet = "10.12.0.0/16".parse().unwrap();
+		peers
+			.ban_subnet(BanEntry::new(
+				subnet,
+				ReasonForBan::ManualBan,
+				"floonet sybils".to_string(),
+				Some(3600),
+			))
+			.unwrap();
+		assert!(peers.is_banned(addr("10.12.1.2:13514")));
+		assert!(peers.is_banned(addr("10.12.200.3:13514")));
+		assert!(!peers.is_banned(addr("10.13.1.2:13514")));
+
+		// already expired bans are not enforced, nor imported
+		let expired = BanEntry {
+			expires: Some(1),
+			..BanEntry::new(
+				"10.20.0.0/16".parse().unwrap(),
+				ReasonForBan::ManualBan,
+				String::new(),
+				None,
+			)
+		};
+		let permanent = BanEntry::new(
+			"10.30.0.1".parse().unwrap(),
+			ReasonForBan::ManualBan,
+			String::new(),
+			None,
+		);
+		assert_eq!(peers.import_bans(vec![expired, permanent]).unwrap(), 1);
+		assert!(!peers.is_banned(addr("10.20.0.1:13514")));
+		assert!(peers.is_banned(addr("10.30.0.1:13514")));
+		assert!(!peers.is_banned(addr("10.30.0.2:13514")));
+		assert_eq!(peers.bans().len(), 2);
+
+		server.stop();
+	}
+
+	// bans survive a restart
+	{
+		let server = new_server();
+		let peers = &server.peers;
+		assert_eq!(peers.bans().len(), 2);
+		assert!(peers.is_banned(addr("10.12.1.2:13514")));
+
+		// only the exact subnet can be unbanned
+		assert!(peers
+			.unban_subnet(&"10.12.1.0/24".parse().unwrap())
+			.is_err());
+		peers
+			.unban_subnet(&"10.12.0.0/16".parse().unwrap())
+			.unwrap();
+		assert!(!peers.is_banned(addr("10.12.1.2:13514")));
+		assert_eq!(peers.bans().len(), 1);
+
+		server.stop();
+	}
+
+	clean_output_dir(test_dir);
+}
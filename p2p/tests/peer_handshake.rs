@@ -26,6 +26,7 @@ use std::{thread, time};
 
 use crate::core::core::hash::Hash;
 use crate::core::pow::Difficulty;
+use crate::p2p::noise::NodeKey;
 use crate::p2p::types::PeerAddr;
 use crate::p2p::Peer;
 
@@ -84,6 +85,7 @@ fn peer_handshake() {
 		Difficulty::min(),
 		0,
 		my_addr,
+		PeerAddr(addr),
 		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), p2p_config.clone()),
 		net_adapter,
 	)
@@ -101,3 +103,215 @@ fn peer_handshake() {
 	assert!(server.peers.peer_count() > 0);
 	clean_output_dir(test_dir);
 }
+
+// Same as above, but with both ends negotiating the encrypted transport and
+// the server refusing plaintext peers.
+#[test]
+fn peer_handshake_encrypted() {
+	util::init_test_logger();
+	let test_dir = ".gotts_encrypted";
+	clean_output_dir(test_dir);
+
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		peers_allow: None,
+		peers_deny: None,
+		require_encryption: Some(true),
+		..p2p::P2PConfig::default()
+	};
+	let net_adapter = Arc::new(p2p::DummyAdapter {});
+	let server = Arc::new(
+		p2p::Server::new(
+			test_dir,
+			p2p::Capabilities::UNKNOWN,
+			p2p_config.clone(),
+			net_adapter.clone(),
+			Hash::from_vec(&vec![]),
+			Arc::new(StopState::new()),
+		)
+		.unwrap(),
+	);
+
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen());
+
+	thread::sleep(time::Duration::from_secs(1));
+
+	let addr = SocketAddr::new(p2p_config.host, p2p_config.port);
+	let client_config = p2p::P2PConfig {
+		require_encryption: None,
+		..p2p_config.clone()
+	};
+
+	// A plaintext peer is refused.
+	let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	assert!(Peer::connect(
+		socket,
+		p2p::Capabilities::UNKNOWN,
+		Difficulty::min(),
+		0,
+		PeerAddr("127.0.0.1:5001".parse().unwrap()),
+		PeerAddr(addr),
+		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), client_config.clone()),
+		net_adapter.clone(),
+	)
+	.is_err());
+
+	let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	let my_addr = PeerAddr("127.0.0.1:5000".parse().unwrap());
+	let peer = Peer::connect(
+		socket,
+		p2p::Capabilities::ENCRYPTED,
+		Difficulty::min(),
+		0,
+		my_addr,
+		PeerAddr(addr),
+		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), client_config)
+			.with_node_key(NodeKey::generate().unwrap()),
+		net_adapter,
+	)
+	.unwrap();
+
+	assert!(peer
+		.info
+		.capabilities
+		.contains(p2p::Capabilities::ENCRYPTED));
+
+	thread::sleep(time::Duration::from_secs(1));
+
+	peer.send_ping(Difficulty::min(), 0).unwrap();
+	thread::sleep(time::Duration::from_secs(1));
+
+	let server_peer = server.peers.get_connected_peer(my_addr).unwrap();
+	assert_eq!(server_peer.info.total_difficulty(), Difficulty::min());
+	clean_output_dir(test_dir);
+}
+
+// A server listening on both IPv4 and IPv6 loopbacks, accepting a peer over
+// IPv6 and learning the external addresses it advertises.
+#[test]
+fn peer_handshake_ipv6() {
+	util::init_test_logger();
+	let test_dir = ".gotts_ipv6";
+	clean_output_dir(test_dir);
+
+	let port = open_port();
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port,
+		listen_addrs: Some(vec![SocketAddr::new("::1".parse().unwrap(), port)]),
+		..p2p::P2PConfig::default()
+	};
+	let net_adapter = Arc::new(p2p::DummyAdapter {});
+	let server = Arc::new(
+		p2p::Server::new(
+			test_dir,
+			p2p::Capabilities::UNKNOWN,
+			p2p_config.clone(),
+			net_adapter.clone(),
+			Hash::from_vec(&vec![]),
+			Arc::new(StopState::new()),
+		)
+		.unwrap(),
+	);
+
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen());
+
+	thread::sleep(time::Duration::from_secs(1));
+
+	// both addresses are listened on
+	let addr = SocketAddr::new(p2p_config.host, port);
+	assert!(TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).is_ok());
+	let addr = SocketAddr::new("::1".parse().unwrap(), port);
+	let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+
+	let external = PeerAddr("[2001:db8::7]:3514".parse().unwrap());
+	let client_config = p2p::P2PConfig {
+		external_addrs: Some(vec![external]),
+		..p2p_config.clone()
+	};
+	let my_addr = PeerAddr("[::1]:5000".parse().unwrap());
+	let _peer = Peer::connect(
+		socket,
+		p2p::Capabilities::UNKNOWN,
+		Difficulty::min(),
+		0,
+		my_addr,
+		PeerAddr(addr),
+		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), client_config),
+		net_adapter,
+	)
+	.unwrap();
+
+	thread::sleep(time::Duration::from_secs(1));
+
+	let server_peer = server.peers.get_connected_peer(my_addr).unwrap();
+	assert_eq!(server_peer.info.external_addrs, vec![external]);
+
+	server.stop();
+	clean_output_dir(test_dir);
+}
+
+// Peers advertising HEADERS_ANNOUNCE are asked for headers announcements
+// (SendHeaders) once connected, others aren't.
+#[test]
+fn peer_handshake_send_headers() {
+	util::init_test_logger();
+	let test_dir = ".gotts_send_headers";
+	clean_output_dir(test_dir);
+
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		..p2p::P2PConfig::default()
+	};
+	let net_adapter = Arc::new(p2p::DummyAdapter {});
+	let server = Arc::new(
+		p2p::Server::new(
+			test_dir,
+			p2p::Capabilities::UNKNOWN,
+			p2p_config.clone(),
+			net_adapter.clone(),
+			Hash::from_vec(&vec![]),
+			Arc::new(StopState::new()),
+		)
+		.unwrap(),
+	);
+
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen());
+
+	thread::sleep(time::Duration::from_secs(1));
+
+	let addr = SocketAddr::new(p2p_config.host, p2p_config.port);
+	let connect = |capab: p2p::Capabilities, port: u16| {
+		let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+		Peer::connect(
+			socket,
+			capab,
+			Difficulty::min(),
+			0,
+			PeerAddr(SocketAddr::new(p2p_config.host, port)),
+			PeerAddr(addr),
+			&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), p2p_config.clone()),
+			net_adapter.clone(),
+		)
+		.unwrap()
+	};
+	let peer = connect(p2p::Capabilities::HEADERS_ANNOUNCE, 5000);
+	let legacy_peer = connect(p2p::Capabilities::UNKNOWN, 5001);
+	assert!(peer
+		.info
+		.capabilities
+		.contains(p2p::Capabilities::HEADERS_ANNOUNCE));
+
+	thread::sleep(time::Duration::from_secs(1));
+
+	assert!(peer.prefers_headers());
+	assert!(!legacy_peer.prefers_headers());
+
+	server.stop();
+	clean_output_dir(test_dir);
+}
@@ -0,0 +1,114 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use gotts_core as core;
+use gotts_p2p as p2p;
+use gotts_util as util;
+
+use std::fs;
+use std::sync::Arc;
+
+use self::core::core::hash::Hash;
+use self::core::ser::{self, ProtocolVersion};
+use self::p2p::types::{PEER_SCORE_BAN, PEER_SCORE_HALF_LIFE};
+use self::p2p::{Capabilities, PeerAddr, PeerData, PeerScore, Penalty, ReasonForBan, State};
+use self::util::StopState;
+
+fn clean_output_dir(test_dir: &str) {
+	let _ = fs::remove_dir_all(test_dir);
+}
+
+fn peer_data(addr: PeerAddr) -> PeerData {
+	PeerData {
+		addr,
+		capabilities: Capabilities::FULL_NODE,
+		user_agent: "gotts test".to_string(),
+		flags: State::Healthy,
+		last_banned: 0,
+		ban_reason: ReasonForBan::None,
+		last_connected: 0,
+		score: PeerScore::default(),
+	}
+}
+
+#[test]
+fn peer_score_decay() {
+	let mut score = PeerScore::default();
+	assert_eq!(score.penalize(Penalty::InvalidMessage, 1000), 25);
+	assert_eq!(score.penalize(Penalty::InvalidMessage, 1000), 50);
+
+	// halves every PEER_SCORE_HALF_LIFE
+	assert_eq!(score.at(1000 + PEER_SCORE_HALF_LIFE), 25);
+	assert_eq!(score.at(1000 + 2 * PEER_SCORE_HALF_LIFE), 13);
+	assert_eq!(score.at(1000 + 20 * PEER_SCORE_HALF_LIFE), 0);
+
+	// new penalties add up to the decayed score
+	assert_eq!(
+		score.penalize(Penalty::SlowResponse, 1000 + PEER_SCORE_HALF_LIFE),
+		35
+	);
+}
+
+#[test]
+fn peer_data_legacy_score() {
+	let mut data = peer_data(PeerAddr("10.0.0.1:3414".parse().unwrap()));
+	data.score.penalize(Penalty::StaleHeaders, 1000);
+	let bytes = ser::ser_vec(&data, ProtocolVersion::local()).unwrap();
+
+	let read: PeerData = ser::deserialize(&mut &bytes[..], ProtocolVersion::local()).unwrap();
+	assert_eq!(read.score, data.score);
+
+	// entries saved before scores were introduced get a clean score
+	let legacy = &bytes[..bytes.len() - 12];
+	let read: PeerData = ser::deserialize(&mut &legacy[..], ProtocolVersion::local()).unwrap();
+	assert_eq!(read.score, PeerScore::default());
+	assert_eq!(read.last_connected, data.last_connected);
+}
+
+#[test]
+fn misbehaving_peer_banned() {
+	util::init_test_logger();
+	let test_dir = ".gotts_peer_score";
+	clean_output_dir(test_dir);
+
+	let server = p2p::Server::new(
+		test_dir,
+		Capabilities::UNKNOWN,
+		p2p::P2PConfig::default(),
+		Arc::new(p2p::DummyAdapter {}),
+		Hash::from_vec(&vec![]),
+		Arc::new(StopState::new()),
+	)
+	.unwrap();
+
+	let addr = PeerAddr("10.0.0.1:3414".parse().unwrap());
+	server.peers.save_peer(&peer_data(addr)).unwrap();
+
+	let penalties = PEER_SCORE_BAN / Penalty::InvalidMessage.points();
+	for _ in 0..penalties - 1 {
+		server.peers.penalize(addr, Penalty::InvalidMessage);
+	}
+	assert!(!server.peers.is_banned(addr));
+	assert!(server.peers.get_peer(addr).unwrap().score.value < PEER_SCORE_BAN);
+
+	server.peers.penalize(addr, Penalty::InvalidMessage);
+	assert!(server.peers.is_banned(addr));
+	assert!(server.peers.get_peer(addr).unwrap().score.value >= PEER_SCORE_BAN);
+	assert_eq!(
+		server.peers.get_peer(addr).unwrap().ban_reason,
+		ReasonForBan::Misbehaviour
+	);
+
+	clean_output_dir(test_dir);
+}
@@ -0,0 +1,189 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compatibility between peers advertising different protocol versions.
+
+use gotts_core as core;
+use gotts_p2p as p2p;
+use gotts_util as util;
+
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use self::core::core::hash::Hash;
+use self::core::pow::Difficulty;
+use self::core::ser::{self, ProtocolVersion};
+use self::p2p::handshake::Handshake;
+use self::p2p::msg::{Hand, Ping, Shake};
+use self::p2p::types::PeerAddr;
+use self::p2p::{Capabilities, DummyAdapter, P2PConfig, Peer};
+
+const V1: ProtocolVersion = ProtocolVersion(1);
+
+// The versions we may meet: ours and all the older ones.
+fn known_versions() -> Vec<ProtocolVersion> {
+	(V1.0..=ProtocolVersion::local().0)
+		.map(ProtocolVersion)
+		.collect()
+}
+
+// A version no node speaks yet.
+fn next_version() -> ProtocolVersion {
+	ProtocolVersion(ProtocolVersion::local().0 + 1)
+}
+
+// Waits for the condition to hold, a few seconds at most.
+fn wait_until<F: Fn() -> bool>(cond: F) -> bool {
+	let deadline = Instant::now() + Duration::from_secs(5);
+	while !cond() {
+		if Instant::now() > deadline {
+			return false;
+		}
+		thread::sleep(Duration::from_millis(10));
+	}
+	true
+}
+
+// Connects a client advertising one version to a server advertising another,
+// returning both ends of the connection.
+fn connect(client_version: ProtocolVersion, server_version: ProtocolVersion) -> (Peer, Peer) {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let addr = listener.local_addr().unwrap();
+	let config = P2PConfig {
+		host: addr.ip(),
+		port: addr.port(),
+		..P2PConfig::default()
+	};
+
+	let server_config = config.clone();
+	let server = thread::spawn(move || {
+		let (conn, _) = listener.accept().unwrap();
+		let hs = Handshake::new(Hash::from_vec(&vec![]), server_config)
+			.with_protocol_version(server_version);
+		Peer::accept(
+			conn,
+			Capabilities::UNKNOWN,
+			Difficulty::min(),
+			0,
+			&hs,
+			Arc::new(DummyAdapter {}),
+		)
+		.unwrap()
+	});
+
+	let conn = TcpStream::connect(addr).unwrap();
+	let hs = Handshake::new(Hash::from_vec(&vec![]), config).with_protocol_version(client_version);
+	let client = Peer::connect(
+		conn,
+		Capabilities::UNKNOWN,
+		Difficulty::min(),
+		0,
+		PeerAddr("127.0.0.1:5000".parse().unwrap()),
+		PeerAddr(addr),
+		&hs,
+		Arc::new(DummyAdapter {}),
+	)
+	.unwrap();
+
+	(client, server.join().unwrap())
+}
+
+// Both ends agree on the lower of the two versions, and can still exchange
+// messages (ping/pong) encoded with it.
+fn check_compat(client_version: ProtocolVersion, server_version: ProtocolVersion) {
+	let expected = std::cmp::min(client_version, server_version);
+	let (client, server) = connect(client_version, server_version);
+	assert_eq!(client.info.version, expected);
+	assert_eq!(server.info.version, expected);
+
+	client.send_ping(Difficulty::min(), 0).unwrap();
+
+	// The server decoded our ping and we decoded its pong.
+	let received = |peer: &Peer| peer.last_min_message_counts().unwrap().1;
+	assert!(wait_until(|| received(&client) == 1));
+	assert_eq!(received(&server), 1);
+	assert!(client.is_connected());
+	assert!(server.is_connected());
+
+	client.stop();
+	server.stop();
+	client.wait();
+	server.wait();
+}
+
+#[test]
+fn version_negotiation() {
+	util::init_test_logger();
+	let local = ProtocolVersion::local();
+	for version in known_versions() {
+		check_compat(local, version);
+		check_compat(version, local);
+	}
+}
+
+// Hand and Shake are read before the peer version is known, so they must be
+// encoded the same way whatever the version, even a later one.
+#[test]
+fn handshake_msgs_version_independent() {
+	let next = next_version();
+	let hand = Hand {
+		version: next,
+		capabilities: Capabilities::FULL_NODE,
+		nonce: 42,
+		genesis: Hash::from_vec(&vec![]),
+		total_difficulty: Difficulty::min(),
+		height: 1,
+		sender_addr: PeerAddr("127.0.0.1:5000".parse().unwrap()),
+		receiver_addr: PeerAddr("127.0.0.1:5001".parse().unwrap()),
+		user_agent: "gotts test".to_string(),
+		external_addrs: vec![
+			PeerAddr("203.0.113.7:3514".parse().unwrap()),
+			PeerAddr("[2001:db8::7]:3514".parse().unwrap()),
+		],
+	};
+	let v1_bytes = ser::ser_vec(&hand, V1).unwrap();
+	assert_eq!(v1_bytes, ser::ser_vec(&hand, next).unwrap());
+	let read: Hand = ser::deserialize(&mut &v1_bytes[..], V1).unwrap();
+	assert_eq!(read.version, next);
+	assert_eq!(read.nonce, 42);
+	assert_eq!(read.external_addrs, hand.external_addrs);
+	let read: Hand = ser::deserialize(&mut &v1_bytes[..], next).unwrap();
+	assert_eq!(read.external_addrs, hand.external_addrs);
+
+	let shake = Shake {
+		version: next,
+		capabilities: Capabilities::FULL_NODE,
+		genesis: Hash::from_vec(&vec![]),
+		total_difficulty: Difficulty::min(),
+		height: 1,
+		user_agent: "gotts test".to_string(),
+	};
+	let v1_bytes = ser::ser_vec(&shake, V1).unwrap();
+	assert_eq!(v1_bytes, ser::ser_vec(&shake, next).unwrap());
+	let read: Shake = ser::deserialize(&mut &v1_bytes[..], next).unwrap();
+	assert_eq!(read.version, next);
+	assert_eq!(read.capabilities, Capabilities::FULL_NODE);
+
+	let ping = Ping {
+		total_difficulty: Difficulty::min(),
+		height: 1,
+	};
+	for version in &[V1, next] {
+		let bytes = ser::ser_vec(&ping, *version).unwrap();
+		let read: Ping = ser::deserialize(&mut &bytes[..], *version).unwrap();
+		assert_eq!(read.height, 1);
+	}
+}
@@ -13,8 +13,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use gotts_core as core;
 use gotts_p2p as p2p;
 
+use self::core::ser::{self, ProtocolVersion};
 use num::FromPrimitive;
 
 // Test that Healthy == 0.
@@ -48,10 +50,19 @@ fn test_capabilities() {
 		p2p::types::Capabilities::UNKNOWN
 	);
 	assert_eq!(
-		p2p::types::Capabilities::from_bits_truncate(0b10000000 as u32),
+		p2p::types::Capabilities::from_bits_truncate(0b10000000000 as u32),
 		p2p::types::Capabilities::UNKNOWN
 	);
+	assert_eq!(
+		p2p::types::Capabilities::from_bits_truncate(0b100000000 as u32),
+		p2p::types::Capabilities::MERKLE_PROOFS
+	);
+	assert_eq!(
+		p2p::types::Capabilities::from_bits_truncate(0b1000000000 as u32),
+		p2p::types::Capabilities::TX_PACKAGE
+	);
 
+	// FULL_NODE is unchanged, older full nodes still match it
 	assert_eq!(
 		p2p::types::Capabilities::from_bits_truncate(0b1111 as u32),
 		p2p::types::Capabilities::FULL_NODE
@@ -63,19 +74,179 @@ fn test_capabilities() {
 	assert_eq!(
 		p2p::types::Capabilities::from_bits_truncate(0b11111111 as u32),
 		p2p::types::Capabilities::FULL_NODE
+			| p2p::types::Capabilities::BLOCK_TXS
+			| p2p::types::Capabilities::ENCRYPTED
+			| p2p::types::Capabilities::HEADERS_ANNOUNCE
+			| p2p::types::Capabilities::TXHASHSET_SEGMENTS
 	);
 	assert_eq!(
-		p2p::types::Capabilities::from_bits_truncate(0b00101111 as u32),
+		p2p::types::Capabilities::from_bits_truncate(0b00111111 as u32),
 		p2p::types::Capabilities::FULL_NODE
+			| p2p::types::Capabilities::BLOCK_TXS
+			| p2p::types::Capabilities::ENCRYPTED
 	);
+	assert!(!p2p::types::Capabilities::FULL_NODE.contains(p2p::types::Capabilities::ENCRYPTED));
+	assert!(!p2p::types::Capabilities::FULL_NODE.contains(p2p::types::Capabilities::BLOCK_TXS));
 
 	assert!(
-		p2p::types::Capabilities::from_bits_truncate(0b00101111 as u32)
+		p2p::types::Capabilities::from_bits_truncate(0b00111111 as u32)
 			.contains(p2p::types::Capabilities::FULL_NODE)
 	);
 
 	assert!(
-		p2p::types::Capabilities::from_bits_truncate(0b00101111 as u32)
+		p2p::types::Capabilities::from_bits_truncate(0b00111111 as u32)
 			.contains(p2p::types::Capabilities::TX_KERNEL_HASH)
 	);
+
+	assert!(
+		p2p::types::Capabilities::from_bits_truncate(0b00111111 as u32)
+			.contains(p2p::types::Capabilities::BLOCK_TXS)
+	);
+
+	assert!(
+		p2p::types::Capabilities::from_bits_truncate(0b00111111 as u32)
+			.contains(p2p::types::Capabilities::ENCRYPTED)
+	);
+}
+
+#[test]
+fn test_transaction_package_limits() {
+	// An empty package is not a package.
+	let bytes = vec![0u8, 0, 0];
+	let res: Result<p2p::msg::TransactionPackage, _> =
+		ser::deserialize(&mut &bytes[..], ProtocolVersion::local());
+	assert_eq!(res.err(), Some(ser::Error::CorruptedData));
+
+	// Packages larger than the max are rejected before reading any tx.
+	let count = p2p::types::MAX_TX_PACKAGE_SIZE + 1;
+	let bytes = vec![1u8, (count >> 8) as u8, count as u8];
+	let res: Result<p2p::msg::TransactionPackage, _> =
+		ser::deserialize(&mut &bytes[..], ProtocolVersion::local());
+	assert_eq!(res.err(), Some(ser::Error::TooLargeReadErr));
+}
+
+#[test]
+fn test_get_block_txs_ser_deser() {
+	let req = p2p::msg::GetBlockTxs {
+		hash: core::core::hash::ZERO_HASH,
+		nonce: 42,
+		short_ids: vec![
+			core::core::id::ShortId::from_bytes(&[1, 2, 3, 4, 5, 6]),
+			core::core::id::ShortId::from_bytes(&[6, 5, 4, 3, 2, 1]),
+		],
+	};
+	let bytes = ser::ser_vec(&req, ProtocolVersion::local()).unwrap();
+	assert_eq!(bytes.len(), 32 + 8 + 2 + 2 * 6);
+
+	let res: p2p::msg::GetBlockTxs =
+		ser::deserialize(&mut &bytes[..], ProtocolVersion::local()).unwrap();
+	assert_eq!(res.hash, req.hash);
+	assert_eq!(res.nonce, req.nonce);
+	assert_eq!(res.short_ids, req.short_ids);
+}
+
+#[test]
+fn test_hand_external_addrs() {
+	let addr = |s: &str| p2p::types::PeerAddr(s.parse().unwrap());
+	let hand = p2p::msg::Hand {
+		version: ProtocolVersion::local(),
+		capabilities: p2p::types::Capabilities::FULL_NODE,
+		nonce: 42,
+		genesis: core::core::hash::ZERO_HASH,
+		total_difficulty: core::pow::Difficulty::min(),
+		height: 0,
+		sender_addr: addr("0.0.0.0:3514"),
+		receiver_addr: addr("10.0.0.1:3514"),
+		user_agent: p2p::msg::USER_AGENT.to_string(),
+		external_addrs: vec![addr("203.0.113.7:3514"), addr("[2001:db8::7]:3514")],
+	};
+	let bytes = ser::ser_vec(&hand, ProtocolVersion::local()).unwrap();
+	let res: p2p::msg::Hand = ser::deserialize(&mut &bytes[..], ProtocolVersion::local()).unwrap();
+	assert_eq!(res.external_addrs, hand.external_addrs);
+
+	// a Hand from an older peer simply ends with the genesis hash
+	let hand = p2p::msg::Hand {
+		external_addrs: vec![],
+		..hand
+	};
+	let bytes = ser::ser_vec(&hand, ProtocolVersion::local()).unwrap();
+	let res: p2p::msg::Hand =
+		ser::deserialize(&mut &bytes[..bytes.len() - 1], ProtocolVersion::local()).unwrap();
+	assert!(res.external_addrs.is_empty());
+	assert_eq!(res.nonce, 42);
+}
+
+// The Noise prologue is made of the Hand and Shake as received, capability
+// bits we don't know of included, which we would lose re-serializing them.
+#[test]
+fn test_hand_wire_bytes() {
+	let addr = |s: &str| p2p::types::PeerAddr(s.parse().unwrap());
+	let hand = p2p::msg::Hand {
+		version: ProtocolVersion::local(),
+		capabilities: p2p::types::Capabilities::FULL_NODE,
+		nonce: 42,
+		genesis: core::core::hash::ZERO_HASH,
+		total_difficulty: core::pow::Difficulty::min(),
+		height: 0,
+		sender_addr: addr("0.0.0.0:3514"),
+		receiver_addr: addr("10.0.0.1:3514"),
+		user_agent: p2p::msg::USER_AGENT.to_string(),
+		external_addrs: vec![],
+	};
+	let body = ser::ser_vec(&hand, ProtocolVersion::local()).unwrap();
+	let mut bytes =
+		p2p::msg::write_to_buf(hand, p2p::msg::Type::Hand, ProtocolVersion::local()).unwrap();
+	let header_len = bytes.len() - body.len();
+	// a capability from a newer peer, right after the version
+	bytes[header_len + 4] |= 0x80;
+
+	let (res, wire): (p2p::msg::Hand, _) = p2p::msg::read_message_bytes(
+		&mut &bytes[..],
+		ProtocolVersion::local(),
+		p2p::msg::Type::Hand,
+	)
+	.unwrap();
+	assert_eq!(wire, bytes);
+	assert_eq!(res.nonce, 42);
+	let reser = ser::ser_vec(&res, ProtocolVersion::local()).unwrap();
+	assert_eq!(reser, body);
+	assert_ne!(&reser[..], &wire[header_len..]);
+}
+
+#[test]
+fn test_peer_addr_ipv6() {
+	let addr = |s: &str| p2p::types::PeerAddr(s.parse().unwrap());
+
+	// dual stack sockets report IPv4 peers as IPv4 mapped IPv6 addresses
+	let mapped = addr("[::ffff:10.0.0.1]:3514");
+	assert_eq!(mapped, addr("10.0.0.1:13514"));
+	assert_eq!(mapped.as_key(), "10.0.0.1");
+	assert_eq!(mapped.canonical().to_string(), "10.0.0.1:3514");
+
+	// IPv4 compatible addresses, like the loopback, are not mapped ones
+	assert_eq!(addr("[::1]:3514").ip().to_string(), "::1");
+	assert_eq!(addr("[::1]:3514").as_key(), "[::1]:3514");
+	assert_ne!(addr("[::1]:3514"), addr("[::1]:3515"));
+	assert_eq!(addr("[2001:db8::7]:3514").as_key(), "2001:db8::7");
+	assert_eq!(addr("[2001:db8::7]:3514"), addr("[2001:db8::7]:3515"));
+
+	let mut config = p2p::P2PConfig::default();
+	config.peers_deny = Some(vec![addr("10.0.0.1:3514"), addr("[2001:db8::7]:3514")]);
+	assert!(p2p::Peer::is_denied(&config, mapped));
+	assert!(p2p::Peer::is_denied(&config, addr("[2001:db8::7]:3515")));
+	assert!(!p2p::Peer::is_denied(&config, addr("[2001:db8::8]:3514")));
+}
+
+#[test]
+fn test_headers_limits() {
+	let count = p2p::types::MAX_BLOCK_HEADERS + 1;
+	let bytes = vec![(count >> 8) as u8, count as u8];
+	let res: Result<p2p::msg::Headers, _> =
+		ser::deserialize(&mut &bytes[..], ProtocolVersion::local());
+	assert_eq!(res.err(), Some(ser::Error::TooLargeReadErr));
+
+	let bytes = vec![0u8, 0];
+	let res: p2p::msg::Headers =
+		ser::deserialize(&mut &bytes[..], ProtocolVersion::local()).unwrap();
+	assert!(res.headers.is_empty());
 }
@@ -0,0 +1,182 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use gotts_core as core;
+use gotts_p2p as p2p;
+use gotts_util as util;
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::{thread, time};
+
+use self::core::core::hash::Hash;
+use self::p2p::socks;
+use self::p2p::PeerAddr;
+use self::util::StopState;
+
+fn clean_output_dir(test_dir: &str) {
+	let _ = fs::remove_dir_all(test_dir);
+}
+
+fn open_port() -> u16 {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	listener.local_addr().unwrap().port()
+}
+
+// Local stand-in for a SOCKS5 proxy, supporting CONNECT to IPv4 addresses and
+// RESOLVE of "localhost". Counts the requests it served.
+fn start_proxy() -> (SocketAddr, Arc<AtomicUsize>) {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let addr = listener.local_addr().unwrap();
+	let served = Arc::new(AtomicUsize::new(0));
+	let counter = served.clone();
+	thread::spawn(move || {
+		for stream in listener.incoming() {
+			if let Ok(stream) = stream {
+				let counter = counter.clone();
+				thread::spawn(move || serve(stream, counter));
+			}
+		}
+	});
+	(addr, served)
+}
+
+fn serve(mut client: TcpStream, served: Arc<AtomicUsize>) -> io::Result<()> {
+	let mut greeting = [0u8; 3];
+	client.read_exact(&mut greeting)?;
+	assert_eq!(greeting, [socks::SOCKS_VERSION, 1, socks::NO_AUTH]);
+	client.write_all(&[socks::SOCKS_VERSION, socks::NO_AUTH])?;
+
+	let mut head = [0u8; 4];
+	client.read_exact(&mut head)?;
+	let ip = match head[3] {
+		socks::ATYP_IPV4 => {
+			let mut buf = [0u8; 4];
+			client.read_exact(&mut buf)?;
+			IpAddr::from(buf)
+		}
+		socks::ATYP_DOMAIN => {
+			let mut len = [0u8; 1];
+			client.read_exact(&mut len)?;
+			let mut name = vec![0u8; len[0] as usize];
+			client.read_exact(&mut name)?;
+			assert_eq!(name, b"localhost");
+			IpAddr::V4(Ipv4Addr::LOCALHOST)
+		}
+		atyp => panic!("unexpected address type {}", atyp),
+	};
+	let mut port = [0u8; 2];
+	client.read_exact(&mut port)?;
+	let target = SocketAddr::new(ip, u16::from_be_bytes(port));
+	served.fetch_add(1, Ordering::SeqCst);
+
+	let reply = |client: &mut TcpStream, addr: SocketAddr| {
+		let mut buf = vec![socks::SOCKS_VERSION, 0, 0, socks::ATYP_IPV4];
+		if let IpAddr::V4(ip) = addr.ip() {
+			buf.extend_from_slice(&ip.octets());
+		}
+		buf.extend_from_slice(&addr.port().to_be_bytes());
+		client.write_all(&buf)
+	};
+
+	match head[1] {
+		socks::CMD_RESOLVE => reply(&mut client, target),
+		socks::CMD_CONNECT => {
+			let mut upstream = TcpStream::connect(target)?;
+			reply(&mut client, "0.0.0.0:0".parse().unwrap())?;
+			let mut client_read = client.try_clone()?;
+			let mut upstream_write = upstream.try_clone()?;
+			thread::spawn(move || {
+				let _ = io::copy(&mut client_read, &mut upstream_write);
+				let _ = upstream_write.shutdown(Shutdown::Write);
+			});
+			io::copy(&mut upstream, &mut client)?;
+			client.shutdown(Shutdown::Write)
+		}
+		cmd => panic!("unexpected command {}", cmd),
+	}
+}
+
+#[test]
+fn socks5_resolve() {
+	let (proxy, served) = start_proxy();
+	let ip = socks::resolve(proxy, "localhost", time::Duration::from_secs(10)).unwrap();
+	assert_eq!(ip, IpAddr::V4(Ipv4Addr::LOCALHOST));
+	assert_eq!(served.load(Ordering::SeqCst), 1);
+}
+
+// A node not accepting incoming connections and dialing out through the
+// proxy only.
+#[test]
+fn socks5_outbound_only() {
+	util::init_test_logger();
+	let test_dir = ".gotts_socks";
+	clean_output_dir(test_dir);
+
+	let (proxy, served) = start_proxy();
+
+	let new_server = |dir: &str, config: p2p::P2PConfig| {
+		Arc::new(
+			p2p::Server::new(
+				dir,
+				p2p::Capabilities::UNKNOWN,
+				config,
+				Arc::new(p2p::DummyAdapter {}),
+				Hash::from_vec(&vec![]),
+				Arc::new(StopState::new()),
+			)
+			.unwrap(),
+		)
+	};
+
+	let server_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		..p2p::P2PConfig::default()
+	};
+	let server = new_server(&format!("{}/server", test_dir), server_config.clone());
+	let server_inner = server.clone();
+	let _ = thread::spawn(move || server_inner.listen());
+
+	let client_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		listen: Some(false),
+		socks5_proxy: Some(proxy),
+		..p2p::P2PConfig::default()
+	};
+	let client = new_server(&format!("{}/client", test_dir), client_config.clone());
+	client.listen().unwrap();
+
+	thread::sleep(time::Duration::from_secs(1));
+
+	// nobody listens on the client port
+	let client_addr = SocketAddr::new(client_config.host, client_config.port);
+	assert!(TcpStream::connect(client_addr).is_err());
+
+	let server_addr = PeerAddr(SocketAddr::new(server_config.host, server_config.port));
+	let peer = client.connect(server_addr).unwrap();
+	assert_eq!(peer.info.addr, server_addr);
+	assert_eq!(served.load(Ordering::SeqCst), 1);
+
+	thread::sleep(time::Duration::from_secs(1));
+	assert_eq!(server.peers.peer_count(), 1);
+
+	client.stop();
+	server.stop();
+	clean_output_dir(test_dir);
+}
@@ -38,5 +38,6 @@ pub mod types;
 pub use crate::pool::Pool;
 pub use crate::transaction_pool::TransactionPool;
 pub use crate::types::{
-	BlockChain, DandelionConfig, PoolAdapter, PoolConfig, PoolEntry, PoolError, TxSource,
+	BlockChain, DandelionConfig, MinedBlock, PoolAdapter, PoolConfig, PoolEntry, PoolError,
+	RejectedTx, TxRule, TxRuleCheck, TxRuleStatus, TxSource,
 };
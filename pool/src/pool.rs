@@ -23,6 +23,7 @@ use self::core::core::verifier_cache::VerifierCache;
 use self::core::core::{
 	Block, BlockHeader, BlockSums, Committed, Input, Output, Transaction, TxKernel, Weighting,
 };
+use self::util::secp::pedersen::Commitment;
 use self::util::RwLock;
 use crate::types::{BlockChain, PoolEntry, PoolError};
 use gotts_core as core;
@@ -59,6 +60,28 @@ impl Pool {
 		self.entries.iter().any(|x| x.tx.hash() == hash)
 	}
 
+	/// Does the pool contain a tx with the given kernel excess?
+	pub fn contains_kernel(&self, excess: &Commitment) -> bool {
+		self.entries
+			.iter()
+			.any(|x| x.tx.kernels().iter().any(|k| k.excess == *excess))
+	}
+
+	/// The inputs of a tx already spent by txs in the pool, along with the
+	/// hash of the tx spending each.
+	pub fn spent_inputs(&self, tx: &Transaction) -> Vec<(Commitment, Hash)> {
+		let commits: HashSet<Commitment> = tx.inputs().iter().map(|i| i.commit).collect();
+		let mut spent = vec![];
+		for x in &self.entries {
+			for input in x.tx.inputs() {
+				if commits.contains(&input.commit) {
+					spent.push((input.commit, x.tx.hash()));
+				}
+			}
+		}
+		spent
+	}
+
 	pub fn get_tx(&self, hash: Hash) -> Option<Transaction> {
 		self.entries
 			.iter()
@@ -216,6 +239,24 @@ impl Pool {
 		Ok(())
 	}
 
+	/// Validate a package of dependent txs as a unit, aggregated with all txs
+	/// currently in the pool (and any extra txs provided).
+	/// Nothing is added to the pool here.
+	pub fn validate_package(
+		&self,
+		txs: &[Transaction],
+		extra_txs: Vec<Transaction>,
+		header: &BlockHeader,
+	) -> Result<(), PoolError> {
+		let mut all_txs = self.all_transactions();
+		all_txs.extend(extra_txs);
+		all_txs.extend_from_slice(txs);
+
+		let agg_tx = transaction::aggregate(all_txs)?;
+		self.validate_raw_tx(&agg_tx, header, Weighting::NoLimit)?;
+		Ok(())
+	}
+
 	fn log_pool_add(&self, entry: &PoolEntry, header: &BlockHeader) {
 		debug!(
 			"add_to_pool [{}]: {} ({:?}) [in/out/kern: {}/{}/{}] pool: {} (at block {})",
@@ -297,11 +338,13 @@ impl Pool {
 		})
 	}
 
+	/// Revalidates every entry against the current chain state, dropping the
+	/// ones no longer valid. Returns the dropped entries, with the reason why.
 	pub fn reconcile(
 		&mut self,
 		extra_tx: Option<Transaction>,
 		header: &BlockHeader,
-	) -> Result<(), PoolError> {
+	) -> Result<Vec<(PoolEntry, PoolError)>, PoolError> {
 		let existing_entries = self.entries.clone();
 		self.entries.clear();
 
@@ -310,11 +353,14 @@ impl Pool {
 			extra_txs.push(extra_tx);
 		}
 
+		let mut dropped = vec![];
 		for x in existing_entries {
-			let _ = self.add_to_pool(x, extra_txs.clone(), header);
+			if let Err(e) = self.add_to_pool(x.clone(), extra_txs.clone(), header) {
+				dropped.push((x, e));
+			}
 		}
 
-		Ok(())
+		Ok(dropped)
 	}
 
 	/// Buckets consist of a vec of txs and track the aggregate fee_to_weight.
@@ -446,17 +492,19 @@ impl Pool {
 	}
 
 	/// Quick reconciliation step - we can evict any txs in the pool where
-	/// inputs or kernels intersect with the block.
-	pub fn reconcile_block(&mut self, block: &Block) {
+	/// inputs or kernels intersect with the block. Returns the evicted entries.
+	pub fn reconcile_block(&mut self, block: &Block) -> Vec<PoolEntry> {
 		// Filter txs in the pool based on the latest block.
 		// Reject any txs where we see a matching tx kernel in the block.
 		// Also reject any txs where we see a conflicting tx,
 		// where an input is spent in a different tx.
 		let block_inputs = block.inputs();
-		self.entries.retain(|x| {
-			!x.tx.kernels().iter().any(|y| block.kernels().contains(y))
-				&& !x.tx.inputs().iter().any(|y| block_inputs.contains(y))
+		let (evicted, kept): (Vec<_>, Vec<_>) = self.entries.drain(..).partition(|x| {
+			x.tx.kernels().iter().any(|y| block.kernels().contains(y))
+				|| x.tx.inputs().iter().any(|y| block_inputs.contains(y))
 		});
+		self.entries = kept;
+		evicted
 	}
 
 	/// Size of the pool.
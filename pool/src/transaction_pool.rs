@@ -19,13 +19,18 @@
 //! valid chain state.
 
 use self::core::core::hash::{Hash, Hashed};
-use self::core::core::id::ShortId;
+use self::core::core::id::{ShortId, ShortIdentifiable};
 use self::core::core::verifier_cache::VerifierCache;
-use self::core::core::{transaction, Block, BlockHeader, Input, OutputEx, Transaction, Weighting};
+use self::core::core::{
+	transaction, Block, BlockHeader, Input, Output, OutputEx, Transaction, Weighting,
+};
 use self::util::secp::pedersen::Commitment;
 use self::util::RwLock;
 use crate::pool::Pool;
-use crate::types::{BlockChain, PoolAdapter, PoolConfig, PoolEntry, PoolError, TxSource};
+use crate::types::{
+	BlockChain, MinedBlock, PoolAdapter, PoolConfig, PoolEntry, PoolError, RejectedTx, TxRule,
+	TxRuleCheck, TxSource,
+};
 use chrono::prelude::*;
 use gotts_core as core;
 use gotts_util as util;
@@ -42,6 +47,10 @@ pub struct TransactionPool {
 	pub stempool: Pool,
 	/// Cache of previous txs in case of a re-org.
 	pub reorg_cache: Arc<RwLock<VecDeque<PoolEntry>>>,
+	/// Recently rejected (or evicted) txs, oldest first.
+	pub rejected: VecDeque<RejectedTx>,
+	/// Blocks recently reconciled against, oldest first.
+	pub mined: VecDeque<MinedBlock>,
 	/// The blockchain
 	pub blockchain: Arc<dyn BlockChain>,
 	pub verifier_cache: Arc<RwLock<dyn VerifierCache>>,
@@ -66,6 +75,8 @@ impl TransactionPool {
 				"stempool".to_string(),
 			),
 			reorg_cache: Arc::new(RwLock::new(VecDeque::new())),
+			rejected: VecDeque::new(),
+			mined: VecDeque::new(),
 			blockchain: chain,
 			verifier_cache,
 			adapter,
@@ -95,6 +106,40 @@ impl TransactionPool {
 		debug!("added tx to reorg_cache: size now {}", cache.len());
 	}
 
+	// Remember why a tx was rejected (or evicted), up to a limit.
+	fn add_to_rejected(&mut self, kernels: Vec<Commitment>, error: String) {
+		if kernels.is_empty() {
+			return;
+		}
+		self.rejected.push_back(RejectedTx {
+			kernels,
+			error,
+			rejected_at: Utc::now(),
+		});
+		while self.rejected.len() > self.config.max_rejected_cache_size {
+			let _ = self.rejected.pop_front();
+		}
+	}
+
+	/// The latest rejection of the tx with the given kernel excess, if it was
+	/// recently rejected (or evicted).
+	pub fn rejection(&self, excess: &Commitment) -> Option<&RejectedTx> {
+		self.rejected
+			.iter()
+			.rev()
+			.find(|x| x.kernels.contains(excess))
+	}
+
+	/// The latest block we reconciled against which mined the tx with the
+	/// given kernel excess, if any recently did.
+	pub fn mined_in(&self, excess: &Commitment) -> Option<Hash> {
+		self.mined
+			.iter()
+			.rev()
+			.find(|x| x.kernels.contains(excess))
+			.map(|x| x.hash)
+	}
+
 	/// Find the complete input/s info, use chain database data according to inputs
 	pub fn get_complete_inputs(
 		&self,
@@ -134,6 +179,34 @@ impl TransactionPool {
 		Ok(complete_inputs)
 	}
 
+	// Find the complete input/s info for a tx in a package, resolving inputs
+	// spending outputs of preceding txs in the same package first.
+	fn get_package_inputs(
+		&self,
+		inputs: &Vec<Input>,
+		package_outputs: &HashMap<Commitment, Output>,
+	) -> Result<HashMap<Commitment, OutputEx>, PoolError> {
+		let (in_package, others): (Vec<Input>, Vec<Input>) = inputs
+			.iter()
+			.cloned()
+			.partition(|x| package_outputs.contains_key(&x.commit));
+
+		let mut complete_inputs = self.get_complete_inputs(&others)?;
+		for input in in_package {
+			if let Some(o) = package_outputs.get(&input.commit) {
+				complete_inputs.insert(
+					input.commit.clone(),
+					OutputEx {
+						output: o.clone(),
+						height: 0,
+						mmr_index: 0,
+					},
+				);
+			}
+		}
+		Ok(complete_inputs)
+	}
+
 	fn add_to_txpool(
 		&mut self,
 		mut entry: PoolEntry,
@@ -170,13 +243,30 @@ impl TransactionPool {
 	}
 
 	/// Add the given tx to the pool, directing it to either the stempool or
-	/// txpool based on stem flag provided.
+	/// txpool based on stem flag provided. Remembers why if it's rejected.
 	pub fn add_to_pool(
 		&mut self,
 		src: TxSource,
 		tx: Transaction,
 		stem: bool,
 		header: &BlockHeader,
+	) -> Result<(), PoolError> {
+		let kernels = tx.kernels().iter().map(|k| k.excess).collect();
+		let res = self.try_add_to_pool(src, tx, stem, header);
+		if let Err(ref e) = res {
+			if *e != PoolError::DuplicateTx {
+				self.add_to_rejected(kernels, e.to_string());
+			}
+		}
+		res
+	}
+
+	fn try_add_to_pool(
+		&mut self,
+		src: TxSource,
+		tx: Transaction,
+		stem: bool,
+		header: &BlockHeader,
 	) -> Result<(), PoolError> {
 		// Quick check to deal with common case of seeing the *same* tx
 		// broadcast from multiple peers simultaneously.
@@ -240,6 +330,150 @@ impl TransactionPool {
 		Ok(())
 	}
 
+	/// Add a package of dependent txs to the pool as a unit, directing it to
+	/// either the stempool or txpool based on stem flag provided.
+	/// Txs must be topologically ordered, each tx only spending outputs from
+	/// the chain, the pool or a preceding tx in the package.
+	/// Either all the txs (we do not already have) are accepted or none of them.
+	/// Remembers why if they're rejected.
+	pub fn add_package_to_pool(
+		&mut self,
+		src: TxSource,
+		txs: Vec<Transaction>,
+		stem: bool,
+		header: &BlockHeader,
+	) -> Result<(), PoolError> {
+		let kernels = txs
+			.iter()
+			.flat_map(|tx| tx.kernels().iter().map(|k| k.excess))
+			.collect();
+		let res = self.try_add_package_to_pool(src, txs, stem, header);
+		if let Err(ref e) = res {
+			if *e != PoolError::DuplicateTx {
+				self.add_to_rejected(kernels, e.to_string());
+			}
+		}
+		res
+	}
+
+	fn try_add_package_to_pool(
+		&mut self,
+		src: TxSource,
+		txs: Vec<Transaction>,
+		stem: bool,
+		header: &BlockHeader,
+	) -> Result<(), PoolError> {
+		// Skip the txs we already have, typically parents we received earlier.
+		let mut txs: Vec<_> = txs
+			.into_iter()
+			.filter(|x| !self.txpool.contains_tx(x.hash()) && !self.stempool.contains_tx(x.hash()))
+			.collect();
+		if txs.is_empty() {
+			return Err(PoolError::DuplicateTx);
+		} else if txs.len() == 1 {
+			return self.try_add_to_pool(src, txs.remove(0), stem, header);
+		}
+
+		// A package is never a reason to evict other txs, it simply needs to fit.
+		if (!stem && self.total_size() + txs.len() > self.config.max_pool_size)
+			|| (stem && self.stempool.size() + txs.len() > self.config.max_stempool_size)
+		{
+			return Err(PoolError::OverCapacity);
+		}
+
+		// Check the package is topologically ordered, no tx can spend an output
+		// of itself or of a subsequent tx in the package.
+		for (i, tx) in txs.iter().enumerate() {
+			for input in tx.inputs() {
+				if txs[i..]
+					.iter()
+					.any(|x| x.find_output_by_commit(&input.commit).is_some())
+				{
+					return Err(PoolError::InvalidPackage(format!(
+						"tx {} spends an output of a subsequent tx",
+						tx.hash()
+					)));
+				}
+			}
+		}
+
+		// Validate each tx individually (fee, weight, lock height, maturity),
+		// in order, resolving inputs against the outputs of the preceding txs.
+		let mut package_outputs: HashMap<Commitment, Output> = HashMap::new();
+		for tx in &txs {
+			self.is_acceptable(tx, stem)?;
+
+			let complete_inputs = self.get_package_inputs(&tx.inputs(), &package_outputs)?;
+			tx.validate(
+				Weighting::AsTransaction,
+				self.verifier_cache.clone(),
+				Some(&complete_inputs),
+				header.height,
+			)
+			.map_err(PoolError::InvalidTx)?;
+
+			self.blockchain.verify_tx_lock_height(tx)?;
+			self.blockchain.verify_coinbase_maturity(tx)?;
+
+			for output in tx.outputs() {
+				package_outputs.insert(output.commitment(), output.clone());
+			}
+		}
+
+		// Now validate the package as a unit, against the current pool state.
+		if stem {
+			self.stempool
+				.validate_package(&txs, self.txpool.all_transactions(), header)?;
+		} else {
+			self.txpool.validate_package(&txs, vec![], header)?;
+		}
+
+		let tx_at = Utc::now();
+		let entries: Vec<PoolEntry> = txs
+			.into_iter()
+			.map(|tx| PoolEntry {
+				src: src.clone(),
+				tx_at,
+				tx,
+			})
+			.collect();
+
+		// Same as for a single tx, any problems during stem, fallback to fluff.
+		// Either way the package goes in as a whole or not at all, the pools
+		// being restored if any of its txs fails to go in.
+		let stempool_entries = self.stempool.entries.clone();
+		if stem {
+			let res = entries
+				.iter()
+				.try_for_each(|x| self.add_to_stempool(x.clone(), header))
+				.and_then(|_| self.adapter.stem_tx_package_accepted(&entries));
+			if res.is_ok() {
+				return Ok(());
+			}
+			self.stempool.entries = stempool_entries.clone();
+
+			// Only validated against the stempool so far.
+			let txs: Vec<_> = entries.iter().map(|x| x.tx.clone()).collect();
+			self.txpool.validate_package(&txs, vec![], header)?;
+		}
+
+		let txpool_entries = self.txpool.entries.clone();
+		let res = entries
+			.iter()
+			.try_for_each(|x| self.add_to_txpool(x.clone(), header));
+		if let Err(e) = res {
+			self.txpool.entries = txpool_entries;
+			self.stempool.entries = stempool_entries;
+			return Err(e);
+		}
+		for entry in &entries {
+			self.add_to_reorg_cache(entry.clone());
+		}
+		self.adapter.tx_package_accepted(&entries);
+
+		Ok(())
+	}
+
 	// Remove the last transaction from the flattened bucket transactions.
 	// No other tx depends on it, it has low fee_to_weight and is unlikely to participate in any cut-through.
 	pub fn evict_from_txpool(&mut self) {
@@ -249,21 +483,59 @@ impl TransactionPool {
 		// Get last transaction and remove it
 		match bucket_transactions.last() {
 			Some(evictable_transaction) => {
+				self.add_to_rejected(
+					evictable_transaction
+						.kernels()
+						.iter()
+						.map(|k| k.excess)
+						.collect(),
+					"Evicted, pool over capacity".to_owned(),
+				);
 				// Remove transaction
-				self.txpool.entries = self
+				let (evicted, kept): (Vec<_>, Vec<_>) = self
 					.txpool
 					.entries
-					.iter()
-					.filter(|x| x.tx != *evictable_transaction)
-					.map(|x| x.clone())
-					.collect::<Vec<_>>();
+					.drain(..)
+					.partition(|x| x.tx == *evictable_transaction);
+				self.txpool.entries = kept;
+				if !evicted.is_empty() {
+					self.adapter.txs_evicted(&evicted, None);
+				}
 			}
 			None => (),
 		}
 	}
 
-	// Old txs will "age out" after 30 mins.
+	/// Remove every tx from the txpool and the stempool, and empty the reorg
+	/// cache so none of them comes back on a reorg. Returns the removed txs.
+	pub fn clear(&mut self) -> Vec<Transaction> {
+		let mut entries = self.txpool.entries.split_off(0);
+		// Stem txs are under embargo, only the txpool ones are announced.
+		if !entries.is_empty() {
+			self.adapter.txs_evicted(&entries, None);
+		}
+		entries.append(&mut self.stempool.entries);
+		self.reorg_cache.write().clear();
+		for entry in &entries {
+			self.add_to_rejected(
+				entry.tx.kernels().iter().map(|k| k.excess).collect(),
+				"Removed, pool cleared by the node owner".to_owned(),
+			);
+		}
+		entries.into_iter().map(|x| x.tx).collect()
+	}
+
+	// Old txs (and mined blocks) will "age out" after 30 mins.
 	pub fn truncate_reorg_cache(&mut self, cutoff: DateTime<Utc>) {
+		while self
+			.mined
+			.front()
+			.map(|x| x.seen_at < cutoff)
+			.unwrap_or(false)
+		{
+			let _ = self.mined.pop_front();
+		}
+
 		let mut cache = self.reorg_cache.write();
 
 		while cache.front().map(|x| x.tx_at < cutoff).unwrap_or(false) {
@@ -281,7 +553,16 @@ impl TransactionPool {
 			header.hash(),
 		);
 		for entry in entries {
-			let _ = &self.add_to_txpool(entry.clone(), header);
+			let kernels: Vec<Commitment> = entry.tx.kernels().iter().map(|k| k.excess).collect();
+			if kernels.iter().any(|x| self.txpool.contains_kernel(x)) {
+				continue;
+			}
+			if let Err(e) = self.add_to_txpool(entry.clone(), header) {
+				self.add_to_rejected(
+					kernels,
+					format!("Not back in the pool after a reorg: {}", e),
+				);
+			}
 		}
 		debug!(
 			"reconcile_reorg_cache: block: {:?} ... done.",
@@ -291,11 +572,40 @@ impl TransactionPool {
 	}
 
 	/// Reconcile the transaction pool (both txpool and stempool) against the
-	/// provided block.
+	/// provided block. The txs leaving the txpool, mined, spending the same
+	/// inputs as the block or no longer valid (after a reorg), are passed to
+	/// the adapter.
 	pub fn reconcile_block(&mut self, block: &Block) -> Result<(), PoolError> {
+		self.mined.push_back(MinedBlock {
+			hash: block.hash(),
+			kernels: block.kernels().iter().map(|k| k.excess).collect(),
+			seen_at: Utc::now(),
+		});
+
 		// First reconcile the txpool.
-		self.txpool.reconcile_block(block);
-		self.txpool.reconcile(None, &block.header)?;
+		let mut evicted = self.txpool.reconcile_block(block);
+		let dropped = self.txpool.reconcile(None, &block.header)?;
+		for (entry, e) in &dropped {
+			self.add_to_rejected(
+				entry.tx.kernels().iter().map(|k| k.excess).collect(),
+				format!("Evicted, no longer valid at block {}: {}", block.hash(), e),
+			);
+		}
+		evicted.extend(dropped.into_iter().map(|(entry, _)| entry));
+
+		// Txs evicted but not mined in the block were double spent by it.
+		for entry in &evicted {
+			let kernels: Vec<Commitment> = entry.tx.kernels().iter().map(|k| k.excess).collect();
+			if !kernels
+				.iter()
+				.any(|x| block.kernels().iter().any(|k| k.excess == *x))
+			{
+				self.add_to_rejected(
+					kernels,
+					format!("Evicted, conflicting with block {}", block.hash()),
+				);
+			}
+		}
 
 		// Now reconcile our stempool, accounting for the updated txpool txs.
 		self.stempool.reconcile_block(block);
@@ -304,6 +614,9 @@ impl TransactionPool {
 			self.stempool.reconcile(txpool_tx, &block.header)?;
 		}
 
+		if !evicted.is_empty() {
+			self.adapter.txs_evicted(&evicted, Some(block));
+		}
 		Ok(())
 	}
 
@@ -324,9 +637,44 @@ impl TransactionPool {
 		self.txpool.retrieve_transactions(hash, nonce, kern_ids)
 	}
 
+	/// Retrieve the txs matching the provided kernel short_ids of a compact block,
+	/// to serve a peer that could not hydrate it from its own pool.
+	/// We look in the txpool and in the reorg cache, as txs of a block we just
+	/// accepted (and relayed) are no longer in the txpool.
+	/// Note: we never look in the stempool (under embargo).
+	pub fn retrieve_block_txs(
+		&self,
+		hash: Hash,
+		nonce: u64,
+		kern_ids: &[ShortId],
+	) -> Vec<Transaction> {
+		let (mut txs, missing_ids) = self.txpool.retrieve_transactions(hash, nonce, kern_ids);
+		if missing_ids.is_empty() {
+			return txs;
+		}
+
+		for entry in self.reorg_cache.read().iter() {
+			if entry
+				.tx
+				.kernels()
+				.iter()
+				.any(|k| missing_ids.contains(&k.short_id(&hash, nonce)))
+				&& !txs.contains(&entry.tx)
+			{
+				txs.push(entry.tx.clone());
+			}
+		}
+		txs
+	}
+
 	/// Whether the transaction is acceptable to the pool, given both how
 	/// full the pool is and the transaction weight.
 	fn is_acceptable(&self, tx: &Transaction, stem: bool) -> Result<(), PoolError> {
+		self.check_capacity(stem)?;
+		self.check_fee(tx)
+	}
+
+	fn check_capacity(&self, stem: bool) -> Result<(), PoolError> {
 		if self.total_size() > self.config.max_pool_size {
 			return Err(PoolError::OverCapacity);
 		}
@@ -334,10 +682,11 @@ impl TransactionPool {
 		// Check that the stempool can accept this transaction
 		if stem && self.stempool.size() > self.config.max_stempool_size {
 			return Err(PoolError::OverCapacity);
-		} else if self.total_size() > self.config.max_pool_size {
-			return Err(PoolError::OverCapacity);
 		}
+		Ok(())
+	}
 
+	fn check_fee(&self, tx: &Transaction) -> Result<(), PoolError> {
 		// for a basic transaction (1 input, 2 outputs) -
 		// (-1 * 1) + (4 * 2) + 1 = 8
 		// 8 * 10 = 80
@@ -350,6 +699,104 @@ impl TransactionPool {
 		Ok(())
 	}
 
+	/// Checks the given tx against all the rules it has to pass to be added to
+	/// the pool, reporting each of them, without adding nor relaying it.
+	/// Without `with_stem` the stempool is left out of the checks, so they
+	/// tell nothing about the txs still under embargo.
+	pub fn dry_run(
+		&self,
+		tx: &Transaction,
+		stem: bool,
+		with_stem: bool,
+		header: &BlockHeader,
+	) -> Vec<TxRuleCheck> {
+		let mut checks = vec![];
+
+		let duplicate = self.txpool.contains_tx(tx.hash())
+			|| (with_stem && self.stempool.contains_tx(tx.hash()));
+		checks.push(TxRuleCheck::new(
+			TxRule::NotDuplicate,
+			if duplicate {
+				Err(PoolError::DuplicateTx)
+			} else {
+				Ok(())
+			},
+		));
+
+		// a fluffed tx makes room for itself by evicting others
+		let capacity = match self.check_capacity(stem) {
+			Err(PoolError::OverCapacity) if !stem => Ok(()),
+			res => res,
+		};
+		checks.push(TxRuleCheck::new(TxRule::Capacity, capacity));
+		checks.push(TxRuleCheck::new(TxRule::Fee, self.check_fee(tx)));
+
+		let complete_inputs = self.get_complete_inputs(&tx.inputs());
+		match complete_inputs {
+			Ok(ref complete_inputs) => {
+				checks.push(TxRuleCheck::new(TxRule::InputsExist, Ok(())));
+				let res = tx
+					.validate(
+						Weighting::AsTransaction,
+						self.verifier_cache.clone(),
+						Some(complete_inputs),
+						header.height,
+					)
+					.map_err(PoolError::InvalidTx);
+				checks.push(TxRuleCheck::new(TxRule::TxValid, res));
+			}
+			Err(e) => {
+				checks.push(TxRuleCheck::new(TxRule::InputsExist, Err(e)));
+				checks.push(TxRuleCheck::skipped(TxRule::TxValid));
+			}
+		}
+
+		checks.push(TxRuleCheck::new(
+			TxRule::LockHeight,
+			self.blockchain.verify_tx_lock_height(tx),
+		));
+		checks.push(TxRuleCheck::new(
+			TxRule::CoinbaseMaturity,
+			self.blockchain.verify_coinbase_maturity(tx),
+		));
+
+		let mut spent = self.txpool.spent_inputs(tx);
+		if stem && with_stem {
+			spent.extend(self.stempool.spent_inputs(tx));
+		}
+		let double_spend = if spent.is_empty() {
+			Ok(())
+		} else {
+			let spent: Vec<String> = spent
+				.iter()
+				.map(|(commit, hash)| format!("{:?} spent by {}", commit, hash))
+				.collect();
+			Err(PoolError::Other(format!(
+				"double spend: {}",
+				spent.join(", ")
+			)))
+		};
+		checks.push(TxRuleCheck::new(TxRule::NoPoolDoubleSpend, double_spend));
+
+		// against the pool the tx would go to, the stempool being on top of
+		// the txpool (the txpool alone without with_stem), a duplicate tx
+		// being in there already
+		if duplicate {
+			checks.push(TxRuleCheck::skipped(TxRule::ChainState));
+		} else {
+			let res = if stem && with_stem {
+				let txpool_txs = self.txpool.all_transactions();
+				self.stempool
+					.validate_package(&[tx.clone()], txpool_txs, header)
+			} else {
+				self.txpool.validate_package(&[tx.clone()], vec![], header)
+			};
+			checks.push(TxRuleCheck::new(TxRule::ChainState, res));
+		}
+
+		checks
+	}
+
 	/// Get the total size of the pool.
 	/// Note: we only consider the txpool here as stempool is under embargo.
 	pub fn total_size(&self) -> usize {
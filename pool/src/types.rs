@@ -22,7 +22,7 @@ use self::core::core::block;
 use self::core::core::committed;
 use self::core::core::hash::Hash;
 use self::core::core::transaction::{self, Input, OutputEx, Transaction};
-use self::core::core::{BlockHeader, BlockSums};
+use self::core::core::{Block, BlockHeader, BlockSums};
 use self::core::{consensus, global};
 use self::util::secp::pedersen::Commitment;
 use failure::Fail;
@@ -30,6 +30,7 @@ use gotts_core as core;
 use gotts_keychain as keychain;
 use gotts_util as util;
 use std::collections::HashMap;
+use std::net::SocketAddr;
 
 /// Dandelion "epoch" length.
 const DANDELION_EPOCH_SECS: u16 = 600;
@@ -49,6 +50,14 @@ const DANDELION_STEM_PROBABILITY: u8 = 90;
 /// If set to false we will stem/fluff our txs as per current epoch.
 const DANDELION_ALWAYS_STEM_OUR_TXS: bool = true;
 
+/// Dandelion++ number of outbound relays selected per epoch.
+/// Inbound stem txs are routed (pseudo-randomly, fixed for the epoch) to one of these.
+const DANDELION_RELAY_COUNT: usize = 2;
+
+/// Dandelion embargo jitter, the mean (in secs) of the exponentially distributed
+/// random delay added to the embargo timer of each individual stem tx.
+const DANDELION_EMBARGO_JITTER_SECS: u16 = 30;
+
 /// Configuration for "Dandelion".
 /// Note: shared between p2p and pool.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -69,6 +78,12 @@ pub struct DandelionConfig {
 	/// Default to always stem our txs as described in Dandelion++ paper.
 	#[serde(default = "default_dandelion_always_stem_our_txs")]
 	pub always_stem_our_txs: bool,
+	/// Number of outbound relay peers selected for each epoch.
+	#[serde(default = "default_dandelion_relay_count")]
+	pub relay_count: usize,
+	/// Mean of the randomized (exponential) delay added to the embargo timer per tx.
+	#[serde(default = "default_dandelion_embargo_jitter_secs")]
+	pub embargo_jitter_secs: u16,
 }
 
 impl Default for DandelionConfig {
@@ -79,6 +94,8 @@ impl Default for DandelionConfig {
 			aggregation_secs: default_dandelion_aggregation_secs(),
 			stem_probability: default_dandelion_stem_probability(),
 			always_stem_our_txs: default_dandelion_always_stem_our_txs(),
+			relay_count: default_dandelion_relay_count(),
+			embargo_jitter_secs: default_dandelion_embargo_jitter_secs(),
 		}
 	}
 }
@@ -103,6 +120,14 @@ fn default_dandelion_always_stem_our_txs() -> bool {
 	DANDELION_ALWAYS_STEM_OUR_TXS
 }
 
+fn default_dandelion_relay_count() -> usize {
+	DANDELION_RELAY_COUNT
+}
+
+fn default_dandelion_embargo_jitter_secs() -> u16 {
+	DANDELION_EMBARGO_JITTER_SECS
+}
+
 /// Transaction pool configuration
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct PoolConfig {
@@ -125,6 +150,11 @@ pub struct PoolConfig {
 	/// blocks.
 	#[serde(default = "default_mineable_max_weight")]
 	pub mineable_max_weight: usize,
+
+	/// Maximum number of recently rejected (or evicted) transactions we
+	/// remember the reason of, for transaction status queries.
+	#[serde(default = "default_max_rejected_cache_size")]
+	pub max_rejected_cache_size: usize,
 }
 
 impl Default for PoolConfig {
@@ -134,6 +164,7 @@ impl Default for PoolConfig {
 			max_pool_size: default_max_pool_size(),
 			max_stempool_size: default_max_stempool_size(),
 			mineable_max_weight: default_mineable_max_weight(),
+			max_rejected_cache_size: default_max_rejected_cache_size(),
 		}
 	}
 }
@@ -150,6 +181,9 @@ fn default_max_stempool_size() -> usize {
 fn default_mineable_max_weight() -> usize {
 	global::max_block_weight()
 }
+fn default_max_rejected_cache_size() -> usize {
+	1_000
+}
 
 /// Represents a single entry in the pool.
 /// A single (possibly aggregated) transaction.
@@ -163,6 +197,106 @@ pub struct PoolEntry {
 	pub tx: Transaction,
 }
 
+/// A transaction the pool rejected, or evicted after accepting it, along with
+/// the reason why.
+#[derive(Clone, Debug)]
+pub struct RejectedTx {
+	/// Kernel excesses of the transaction.
+	pub kernels: Vec<Commitment>,
+	/// Why the transaction was rejected.
+	pub error: String,
+	/// When the transaction was rejected.
+	pub rejected_at: DateTime<Utc>,
+}
+
+/// The kernels of a block the pool was reconciled against, to tell the txs
+/// mined in a block that later got reorged out.
+#[derive(Clone, Debug)]
+pub struct MinedBlock {
+	/// Hash of the block.
+	pub hash: Hash,
+	/// Kernel excesses of the block.
+	pub kernels: Vec<Commitment>,
+	/// When the pool was reconciled against the block.
+	pub seen_at: DateTime<Utc>,
+}
+
+/// A rule a transaction is checked against before being accepted in the pool.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxRule {
+	/// Not already in the pool.
+	NotDuplicate,
+	/// Room in the pool (a fluffed tx evicts others instead of failing).
+	Capacity,
+	/// Paying at least the pool minimum fee for its weight.
+	Fee,
+	/// All the inputs in the UTXO set or created by pool txs.
+	InputsExist,
+	/// `Transaction::validate`: weight, sorting, cut-through, signatures,
+	/// range proofs and kernel sums.
+	TxValid,
+	/// Lock height reached by the chain.
+	LockHeight,
+	/// Spent coinbase outputs mature.
+	CoinbaseMaturity,
+	/// No input already spent by another pool tx.
+	NoPoolDoubleSpend,
+	/// `Chain::validate_tx` and the kernel sums, aggregated with the pool txs.
+	ChainState,
+}
+
+/// Whether a transaction passes a rule.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxRuleStatus {
+	Passed,
+	Failed,
+	/// Not checked, an earlier rule it depends on failed.
+	Skipped,
+}
+
+/// Outcome of checking a transaction against one of the pool rules.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TxRuleCheck {
+	pub rule: TxRule,
+	pub status: TxRuleStatus,
+	/// Why the transaction fails the rule.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub error: Option<String>,
+}
+
+impl TxRuleCheck {
+	/// Check of a rule from its result.
+	pub fn new(rule: TxRule, res: Result<(), PoolError>) -> TxRuleCheck {
+		match res {
+			Ok(()) => TxRuleCheck {
+				rule,
+				status: TxRuleStatus::Passed,
+				error: None,
+			},
+			Err(e) => TxRuleCheck {
+				rule,
+				status: TxRuleStatus::Failed,
+				error: Some(e.to_string()),
+			},
+		}
+	}
+
+	/// Rule not checked.
+	pub fn skipped(rule: TxRule) -> TxRuleCheck {
+		TxRuleCheck {
+			rule,
+			status: TxRuleStatus::Skipped,
+			error: None,
+		}
+	}
+
+	pub fn passed(&self) -> bool {
+		self.status == TxRuleStatus::Passed
+	}
+}
+
 /// Used to make decisions based on transaction acceptance priority from
 /// various sources. For example, a node may want to bypass pool size
 /// restrictions when accepting a transaction from a local wallet.
@@ -173,6 +307,8 @@ pub struct PoolEntry {
 pub enum TxSource {
 	PushApi,
 	Broadcast,
+	/// Stem tx relayed to us by the peer at this address.
+	Stem(SocketAddr),
 	Fluff,
 	EmbargoExpired,
 	Deaggregate,
@@ -186,6 +322,14 @@ impl TxSource {
 			_ => false,
 		}
 	}
+
+	/// The peer address this tx was stemmed from, if relayed to us in stem phase.
+	pub fn stem_peer(&self) -> Option<SocketAddr> {
+		match self {
+			TxSource::Stem(addr) => Some(*addr),
+			_ => None,
+		}
+	}
 }
 
 /// Possible errors when interacting with the transaction pool.
@@ -225,6 +369,9 @@ pub enum PoolError {
 	/// Attempt to add a duplicate tx to the pool.
 	#[fail(display = "Duplicate tx")]
 	DuplicateTx,
+	/// A package of dependent txs that cannot be accepted as a unit.
+	#[fail(display = "Invalid tx package {}", _0)]
+	InvalidPackage(String),
 	/// Other kinds of error (not yet pulled out into meaningful errors).
 	#[fail(display = "General pool error {}", _0)]
 	Other(String),
@@ -287,6 +434,17 @@ pub trait PoolAdapter: Send + Sync {
 
 	/// The stem transaction pool has accepted this transactions as valid.
 	fn stem_tx_accepted(&self, entry: &PoolEntry) -> Result<(), PoolError>;
+
+	/// The transaction pool has accepted this package of dependent txs as valid.
+	fn tx_package_accepted(&self, entries: &[PoolEntry]);
+
+	/// The stem transaction pool has accepted this package of dependent txs as valid.
+	fn stem_tx_package_accepted(&self, entries: &[PoolEntry]) -> Result<(), PoolError>;
+
+	/// These txs left the transaction pool, mined or conflicting with a block,
+	/// no longer valid after a reorg, evicted or removed. The block is the one
+	/// the pool was reconciled against, if any.
+	fn txs_evicted(&self, entries: &[PoolEntry], block: Option<&Block>);
 }
 
 /// Dummy adapter used as a placeholder for real implementations
@@ -298,4 +456,9 @@ impl PoolAdapter for NoopAdapter {
 	fn stem_tx_accepted(&self, _entry: &PoolEntry) -> Result<(), PoolError> {
 		Ok(())
 	}
+	fn tx_package_accepted(&self, _entries: &[PoolEntry]) {}
+	fn stem_tx_package_accepted(&self, _entries: &[PoolEntry]) -> Result<(), PoolError> {
+		Ok(())
+	}
+	fn txs_evicted(&self, _entries: &[PoolEntry], _block: Option<&Block>) {}
 }
@@ -135,7 +135,7 @@ fn test_transaction_pool_block_reconciliation() {
 			valid_transaction.clone(),
 			block_child,
 			pool_child.clone(),
-			conflict_child,
+			conflict_child.clone(),
 			conflict_valid_child.clone(),
 			valid_child_conflict.clone(),
 			valid_child_valid.clone(),
@@ -207,6 +207,14 @@ fn test_transaction_pool_block_reconciliation() {
 			assert_eq!(write_pool.txpool.entries[1].tx, pool_child);
 			assert_eq!(write_pool.txpool.entries[2].tx, conflict_valid_child);
 			assert_eq!(write_pool.txpool.entries[3].tx, valid_child_valid);
+
+			// We remember the kernels mined in the block, and why the orphaned
+			// txs were dropped.
+			let mined = block.kernels()[0].excess;
+			assert_eq!(write_pool.mined_in(&mined), Some(block.hash()));
+			let orphaned = conflict_child.kernels()[0].excess;
+			assert!(write_pool.mined_in(&orphaned).is_none());
+			assert!(write_pool.rejection(&orphaned).is_some());
 		}
 	}
 	// Cleanup db directory
@@ -201,6 +201,7 @@ pub fn test_setup(
 			max_pool_size: 50,
 			max_stempool_size: 50,
 			mineable_max_weight: 10_000,
+			max_rejected_cache_size: 10,
 		},
 		chain.clone(),
 		verifier_cache.clone(),
@@ -0,0 +1,182 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub mod common;
+
+use self::core::core::hash::Hashed;
+use self::core::core::verifier_cache::LruVerifierCache;
+use self::core::core::{Block, BlockHeader, Transaction};
+use self::core::libtx;
+use self::core::pow::Difficulty;
+use self::keychain::{ExtKeychain, Identifier, Keychain};
+use self::pool::types::PoolError;
+use self::util::RwLock;
+use crate::common::*;
+use gotts_core as core;
+use gotts_keychain as keychain;
+use gotts_pool as pool;
+use gotts_util as util;
+use std::sync::Arc;
+
+#[test]
+fn test_transaction_package() {
+	util::init_test_logger();
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let db_root = ".gotts_transaction_package".to_string();
+	clean_output_dir(db_root.clone());
+
+	{
+		let mut chain = ChainAdapter::init(db_root.clone()).unwrap();
+
+		let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+		let add_block =
+			|prev_header: BlockHeader, txs: Vec<Transaction>, chain: &mut ChainAdapter| {
+				let height = prev_header.height + 1;
+				let key_id = ExtKeychain::derive_key_id(1, height as u32, 0, 0, 0);
+				let fee = txs.iter().map(|x| x.fee()).sum();
+				let reward = libtx::reward::output(
+					&keychain,
+					&libtx::ProofBuilder::new(&keychain, &Identifier::zero()),
+					&key_id,
+					fee,
+					false,
+				)
+				.unwrap();
+				let mut block = Block::new(&prev_header, txs, Difficulty::min(), reward).unwrap();
+
+				// Set the prev_root to the prev hash for testing purposes (no MMR to obtain a root from).
+				block.header.prev_root = prev_header.hash();
+
+				chain.update_db_for_block(&block);
+				block
+			};
+
+		let block = add_block(BlockHeader::default(), vec![], &mut chain);
+		let header = block.header;
+
+		// Now create tx to spend that first coinbase (now matured).
+		// Provides us with some useful outputs to test with.
+		let initial_tx = test_transaction_spending_coinbase(
+			&keychain,
+			&header,
+			vec![10, 20, 30, 40, 59_000_000_000],
+		);
+
+		// Mine that initial tx so we can spend it with multiple txs
+		let block = add_block(header, vec![initial_tx], &mut chain);
+		let header = block.header;
+
+		// Initialize a new pool with our chain adapter.
+		let pool = RwLock::new(test_setup(Arc::new(chain.clone()), verifier_cache));
+
+		let root_tx_1 = test_transaction(&keychain, vec![10, 20], vec![24]);
+		let child_tx_1 = test_transaction(&keychain, vec![24], vec![22]);
+		let grandchild_tx_1 = test_transaction(&keychain, vec![22], vec![19]);
+
+		let root_tx_2 = test_transaction(&keychain, vec![30], vec![28]);
+		let bad_child_tx_2 = test_bad_transaction(&keychain, vec![28], vec![25]);
+
+		{
+			let mut write_pool = pool.write();
+
+			// A child on its own is rejected, its parent is not known yet.
+			assert!(write_pool
+				.add_to_pool(test_source(), child_tx_1.clone(), false, &header)
+				.is_err());
+			assert_eq!(write_pool.total_size(), 0);
+
+			// Out of order package is rejected.
+			match write_pool.add_package_to_pool(
+				test_source(),
+				vec![child_tx_1.clone(), root_tx_1.clone()],
+				false,
+				&header,
+			) {
+				Err(PoolError::InvalidPackage(_)) => {}
+				res => panic!("unexpected result: {:?}", res),
+			}
+			assert_eq!(write_pool.total_size(), 0);
+
+			// Package with a bad child is rejected as a unit, parent included.
+			assert_eq!(
+				write_pool.add_package_to_pool(
+					test_source(),
+					vec![root_tx_2.clone(), bad_child_tx_2.clone()],
+					false,
+					&header,
+				),
+				Err(PoolError::InvalidTx(
+					core::core::transaction::Error::TransactionSumMismatch
+				)),
+			);
+			assert_eq!(write_pool.total_size(), 0);
+
+			// Ordered package is accepted as a unit.
+			write_pool
+				.add_package_to_pool(
+					test_source(),
+					vec![root_tx_1.clone(), child_tx_1.clone()],
+					false,
+					&header,
+				)
+				.unwrap();
+			assert_eq!(write_pool.total_size(), 2);
+
+			// Txs we already have are skipped, only the new one is added.
+			write_pool
+				.add_package_to_pool(
+					test_source(),
+					vec![
+						root_tx_1.clone(),
+						child_tx_1.clone(),
+						grandchild_tx_1.clone(),
+					],
+					false,
+					&header,
+				)
+				.unwrap();
+			assert_eq!(write_pool.total_size(), 3);
+
+			// Nothing new in this package.
+			assert_eq!(
+				write_pool.add_package_to_pool(
+					test_source(),
+					vec![root_tx_1.clone(), child_tx_1.clone()],
+					false,
+					&header,
+				),
+				Err(PoolError::DuplicateTx),
+			);
+			assert_eq!(write_pool.total_size(), 3);
+
+			// A stem package goes to the stempool as a unit.
+			let root_tx_3 = test_transaction(&keychain, vec![40], vec![38]);
+			let child_tx_3 = test_transaction(&keychain, vec![38], vec![36]);
+			write_pool
+				.add_package_to_pool(
+					test_source(),
+					vec![root_tx_3.clone(), child_tx_3.clone()],
+					true,
+					&header,
+				)
+				.unwrap();
+			assert_eq!(write_pool.stempool.size(), 2);
+			assert_eq!(write_pool.total_size(), 3);
+		}
+	}
+	// Cleanup db directory
+	clean_output_dir(db_root.clone());
+}
@@ -20,7 +20,7 @@ use self::core::core::{transaction, Block, BlockHeader, Weighting};
 use self::core::libtx;
 use self::core::pow::Difficulty;
 use self::keychain::{ExtKeychain, Identifier, Keychain};
-use self::pool::TxSource;
+use self::pool::{TxRule, TxRuleStatus, TxSource};
 use self::util::RwLock;
 use crate::common::*;
 use gotts_core as core;
@@ -98,10 +98,28 @@ fn test_the_transaction_pool() {
 	// already in the txpool. In this case we attempt to spend the original coinbase twice.
 	{
 		let tx = test_transaction_spending_coinbase(&keychain, &header, vec![501]);
+		let excess = tx.kernels()[0].excess;
 		let mut write_pool = pool.write();
+		assert!(write_pool.rejection(&excess).is_none());
+
+		// A dry run tells which rules it breaks, without remembering it.
+		let failed: Vec<TxRule> = write_pool
+			.dry_run(&tx, false, true, &header)
+			.into_iter()
+			.filter(|c| c.status == TxRuleStatus::Failed)
+			.map(|c| c.rule)
+			.collect();
+		assert_eq!(failed, vec![TxRule::NoPoolDoubleSpend, TxRule::ChainState]);
+		assert!(write_pool.rejection(&excess).is_none());
+		assert_eq!(write_pool.total_size(), 1);
+
 		assert!(write_pool
 			.add_to_pool(test_source(), tx, false, &header)
 			.is_err());
+
+		// We remember why it was rejected.
+		assert!(write_pool.rejection(&excess).is_some());
+		assert!(!write_pool.txpool.contains_kernel(&excess));
 	}
 
 	// tx1 spends some outputs from the initial test tx.
@@ -122,6 +140,15 @@ fn test_the_transaction_pool() {
 			.unwrap();
 		assert_eq!(write_pool.total_size(), 2);
 
+		let checks = write_pool.dry_run(&tx1, false, true, &header);
+		assert_eq!(checks[0].rule, TxRule::NotDuplicate);
+		assert_eq!(checks[0].status, TxRuleStatus::Failed);
+		assert!(write_pool
+			.dry_run(&tx2, false, true, &header)
+			.iter()
+			.all(|c| c.passed()));
+		assert_eq!(write_pool.total_size(), 2);
+
 		// Add another tx spending outputs from the previous tx.
 		write_pool
 			.add_to_pool(test_source(), tx2.clone(), false, &header)
@@ -223,6 +250,15 @@ fn test_the_transaction_pool() {
 		assert_eq!(write_pool.total_size(), 4);
 		assert_eq!(write_pool.stempool.size(), 1);
 
+		// A dry run leaving the stempool out tells nothing about the stem tx.
+		let checks = write_pool.dry_run(&tx, true, true, &header);
+		assert_eq!(checks[0].rule, TxRule::NotDuplicate);
+		assert_eq!(checks[0].status, TxRuleStatus::Failed);
+		assert!(write_pool
+			.dry_run(&tx, true, false, &header)
+			.iter()
+			.all(|c| c.passed()));
+
 		// Duplicate stem tx so fluff, adding it to txpool and removing it from stempool.
 		write_pool
 			.add_to_pool(test_source(), tx.clone(), true, &header)
@@ -506,6 +542,17 @@ fn test_the_transaction_pool() {
 				.add_to_pool(test_source(), double_spend_tx.clone(), false, &header)
 				.is_err());
 		}
+
+		// Clearing the pool removes every tx and remembers why.
+		{
+			let mut write_pool = pool.write();
+			let removed = write_pool.clear();
+			assert_eq!(removed.len(), 6);
+			assert_eq!(write_pool.total_size(), 0);
+			assert!(write_pool.reorg_cache.read().is_empty());
+			let excess = tx1.kernels()[0].excess;
+			assert!(write_pool.rejection(&excess).is_some());
+		}
 	}
 	// Cleanup db directory
 	clean_output_dir(db_root.clone());
@@ -17,5 +17,6 @@
 
 pub mod adapters;
 pub mod hooks;
+pub mod light;
 pub mod stats;
 pub mod types;
@@ -24,10 +24,17 @@ use std::sync::{Arc, Weak};
 use std::thread;
 use std::time::Instant;
 
-use crate::chain::{self, BlockStatus, ChainAdapter, Options, SyncState, SyncStatus};
+use crate::chain::{
+	self, BlockStatus, ChainAdapter, KernelProof, Options, OutputProof, SegmentType, SyncState,
+	SyncStatus, TxHashSetSegment,
+};
 use crate::common::hooks::{ChainEvents, NetEvents};
+use crate::common::light::LightClient;
+use crate::common::stats::{CompactBlockStats, DandelionStats};
 use crate::common::types::{ChainValidationMode, DandelionEpoch, ServerConfig};
 use crate::core::core::hash::{Hash, Hashed};
+use crate::core::core::id::ShortId;
+use crate::core::core::pmmr::SegmentIdentifier;
 use crate::core::core::transaction::Transaction;
 use crate::core::core::verifier_cache::VerifierCache;
 use crate::core::core::{BlockHeader, BlockSums, CompactBlock, Input, OutputEx};
@@ -54,28 +61,53 @@ pub struct NetToChainAdapter {
 	peers: OneTime<Weak<p2p::Peers>>,
 	config: ServerConfig,
 	hooks: Vec<Box<dyn NetEvents + Send + Sync>>,
+	compact_block_stats: Arc<RwLock<CompactBlockStats>>,
+	pending_block_txs: RwLock<HashMap<Hash, PendingBlockTxs>>,
+	light_client: Arc<LightClient>,
+}
+
+/// How long we wait for the missing txs of a compact block before falling
+/// back to requesting the full block.
+const BLOCK_TXS_TIMEOUT_SECS: i64 = 10;
+
+// A compact block waiting on its missing txs (requested via GetBlockTxs).
+struct PendingBlockTxs {
+	cb: core::CompactBlock,
+	peer_info: PeerInfo,
+	requested_at: DateTime<Utc>,
 }
 
 impl p2p::ChainAdapter for NetToChainAdapter {
 	fn total_difficulty(&self) -> Result<Difficulty, chain::Error> {
-		Ok(self.chain().head()?.total_difficulty)
+		Ok(self.local_head()?.total_difficulty)
 	}
 
 	fn total_height(&self) -> Result<u64, chain::Error> {
-		Ok(self.chain().head()?.height)
+		Ok(self.local_head()?.height)
 	}
 
 	fn get_transaction(&self, kernel_hash: Hash) -> Option<core::Transaction> {
 		self.tx_pool.read().retrieve_tx_by_kernel_hash(kernel_hash)
 	}
 
+	fn get_block_txs(
+		&self,
+		hash: Hash,
+		nonce: u64,
+		short_ids: &[ShortId],
+	) -> Vec<core::Transaction> {
+		self.tx_pool
+			.read()
+			.retrieve_block_txs(hash, nonce, short_ids)
+	}
+
 	fn tx_kernel_received(
 		&self,
 		kernel_hash: Hash,
 		peer_info: &PeerInfo,
 	) -> Result<bool, chain::Error> {
 		// nothing much we can do with a new transaction while syncing
-		if self.sync_state.is_syncing() {
+		if self.sync_state.is_syncing() || self.light_mode() {
 			debug!(
 				"tx kernel {} received but ignored while syncing",
 				kernel_hash
@@ -94,14 +126,22 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 	fn transaction_received(
 		&self,
 		tx: core::Transaction,
+		peer_info: &PeerInfo,
 		stem: bool,
 	) -> Result<bool, chain::Error> {
 		// nothing much we can do with a new transaction while syncing
-		if self.sync_state.is_syncing() {
+		// (or without the utxo set)
+		if self.sync_state.is_syncing() || self.light_mode() {
 			return Ok(true);
 		}
 
-		let source = pool::TxSource::Broadcast;
+		// Remember where a stem tx came from so Dandelion++ can route it
+		// to the outbound relay assigned to this inbound peer for the epoch.
+		let source = if stem {
+			pool::TxSource::Stem(peer_info.addr.0)
+		} else {
+			pool::TxSource::Broadcast
+		};
 
 		let header = self.chain().head_header()?;
 
@@ -121,6 +161,47 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 		}
 	}
 
+	fn transaction_package_received(
+		&self,
+		txs: Vec<core::Transaction>,
+		peer_info: &PeerInfo,
+		stem: bool,
+	) -> Result<bool, chain::Error> {
+		// nothing much we can do with new transactions while syncing
+		// (or without the utxo set)
+		if self.sync_state.is_syncing() || self.light_mode() {
+			return Ok(true);
+		}
+
+		let source = if stem {
+			pool::TxSource::Stem(peer_info.addr.0)
+		} else {
+			pool::TxSource::Broadcast
+		};
+
+		let header = self.chain().head_header()?;
+
+		for tx in &txs {
+			for hook in &self.hooks {
+				hook.on_transaction_received(tx);
+			}
+		}
+
+		let tx_count = txs.len();
+
+		let mut tx_pool = self.tx_pool.write();
+		match tx_pool.add_package_to_pool(source, txs, stem, &header) {
+			Ok(_) => Ok(true),
+			Err(e) => {
+				debug!(
+					"Transaction package ({} txs) from {} rejected: {:?}",
+					tx_count, peer_info.addr, e
+				);
+				Ok(false)
+			}
+		}
+	}
+
 	fn block_received(
 		&self,
 		b: core::Block,
@@ -136,6 +217,10 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 			b.outputs().len(),
 			b.kernels().len(),
 		);
+		// light nodes only keep the header chain
+		if self.light_mode() {
+			return self.header_received(b.header, peer_info);
+		}
 		self.process_block(b, peer_info, was_requested)
 	}
 
@@ -155,6 +240,11 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 			cb.kern_ids().len(),
 		);
 
+		// light nodes only keep the header chain
+		if self.light_mode() {
+			return self.header_received(cb.header, peer_info);
+		}
+
 		let cb_hash = cb.hash();
 		if cb.kern_ids().is_empty() {
 			// push the freshly hydrated block through the chain pipeline
@@ -194,49 +284,71 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 				missing_short_ids.len(),
 			);
 
-			// TODO - 3 scenarios here -
-			// 1) we hydrate a valid block (good to go)
-			// 2) we hydrate an invalid block (txs legit missing from our pool)
-			// 3) we hydrate an invalid block (peer sent us a "bad" compact block) - [TBD]
+			{
+				let mut stats = self.compact_block_stats.write();
+				stats.received += 1;
+				stats.txs_from_pool += txs.len() as u64;
+			}
 
-			let block = match core::Block::hydrate_from(cb.clone(), txs) {
-				Ok(block) => {
-					if !self.sync_state.is_syncing() {
-						for hook in &self.hooks {
-							hook.on_block_received(&block, &peer_info.addr);
-						}
-					}
-					block
-				}
-				Err(e) => {
-					debug!("Invalid hydrated block {}: {:?}", cb.hash(), e);
-					return Ok(false);
-				}
-			};
+			// If we are missing some txs then ask the peer for those txs only,
+			// hydrating the block when they come back (see block_txs_received).
+			// Fallback to hydrating with what we have (and requesting the full block
+			// if the block is invalid) if the peer cannot provide the missing txs.
+			if !missing_short_ids.is_empty()
+				&& self.sync_state.status() == SyncStatus::NoSync
+				&& self.request_block_txs(&cb, missing_short_ids, peer_info)
+			{
+				return Ok(true);
+			}
 
-			if let Ok(_prev) = self.chain().get_previous_header(&cb.header) {
-				let complete_inputs = self.chain().get_complete_inputs(&block.inputs())?;
-				if block
-					.validate(self.verifier_cache.clone(), Some(&complete_inputs))
-					.is_ok()
-				{
-					debug!("successfully hydrated block from tx pool!");
-					self.process_block(block, peer_info, false)
-				} else {
-					if self.sync_state.status() == SyncStatus::NoSync {
-						debug!("adapter: block invalid after hydration, requesting full block");
-						self.request_block(&cb.header, peer_info);
-						Ok(true)
-					} else {
-						debug!("block invalid after hydration, ignoring it, cause still syncing");
-						Ok(true)
-					}
+			self.hydrate_compact_block(cb, txs, peer_info, false)
+		}
+	}
+
+	fn block_txs_received(
+		&self,
+		hash: Hash,
+		txs: Vec<core::Transaction>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		self.check_pending_block_txs();
+
+		// Only the peer we asked can answer, the others are ignored.
+		let cb = {
+			let mut pending = self.pending_block_txs.write();
+			match pending.get(&hash) {
+				Some(x) if x.peer_info.addr == peer_info.addr => {
+					pending.remove(&hash).map(|x| x.cb)
 				}
-			} else {
-				debug!("failed to retrieve previous block header (still syncing?)");
-				Ok(true)
+				_ => None,
+			}
+		};
+		let cb = match cb {
+			Some(cb) => cb,
+			None => {
+				debug!(
+					"block_txs_received: {} txs for {} from {}, not (or no longer) requested",
+					txs.len(),
+					hash,
+					peer_info.addr
+				);
+				return Ok(true);
+			}
+		};
+
+		// Our pool may have changed since we requested the missing txs,
+		// so look again and complete with the txs the peer sent us.
+		let (mut block_txs, _) =
+			self.tx_pool
+				.read()
+				.retrieve_transactions(cb.hash(), cb.nonce, cb.kern_ids());
+		for tx in txs {
+			if !block_txs.contains(&tx) {
+				block_txs.push(tx);
 			}
 		}
+
+		self.hydrate_compact_block(cb, block_txs, peer_info, true)
 	}
 
 	fn header_received(
@@ -271,9 +383,22 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 			}
 		}
 
-		// we have successfully processed a block header
-		// so we can go request the block itself
-		self.request_compact_block(&bh, peer_info);
+		// we have successfully processed a block header so we can go request
+		// the block itself, only if it would improve our chain, no need to
+		// download the blocks of forks with less work
+		if self.light_mode() {
+			return Ok(true);
+		}
+		let head = self.chain().head()?;
+		if bh.total_difficulty() > head.total_difficulty {
+			self.request_compact_block(&bh, peer_info);
+		} else {
+			debug!(
+				"header_received: {} at {} doesn't extend our best chain, not requesting it",
+				bh.hash(),
+				bh.height
+			);
+		}
 
 		// done receiving the header
 		Ok(true)
@@ -294,6 +419,21 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 			return Ok(false);
 		}
 
+		// Headers we already have don't move our header chain forward. That's
+		// fine in answer to a request (maybe late, after we asked another
+		// peer), not otherwise.
+		if let Some(last) = bhs.last() {
+			if !peer_info.awaits_headers() && self.chain().get_block_header(&last.hash()).is_ok() {
+				debug!(
+					"headers_received: {} stale headers from {}",
+					bhs.len(),
+					peer_info.addr
+				);
+				self.peers()
+					.penalize(peer_info.addr, p2p::Penalty::StaleHeaders);
+			}
+		}
+
 		// try to add headers to our header chain
 		match self.chain().sync_block_headers(bhs, self.chain_opts(true)) {
 			Ok(_) => Ok(true),
@@ -466,10 +606,207 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 		}
 	}
 
+	fn get_txhashset_segment(
+		&self,
+		h: Hash,
+		segment_type: SegmentType,
+		id: SegmentIdentifier,
+	) -> Option<TxHashSetSegment> {
+		match self.chain().txhashset_segment(h, segment_type, id) {
+			Ok(segment) => Some(segment),
+			Err(e) => {
+				debug!(
+					"Couldn't provide txhashset segment {:?} {:?} at {}: {:?}",
+					segment_type, id, h, e
+				);
+				None
+			}
+		}
+	}
+
+	fn txhashset_segment_received(
+		&self,
+		h: Hash,
+		segment: TxHashSetSegment,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		// only the segments of the txhashset we're downloading
+		if self.sync_state.txhashset_segments_hash() != Some(h) {
+			debug!(
+				"Unexpected txhashset segment at {} from {}, ignored",
+				h, peer_info.addr
+			);
+			return Ok(false);
+		}
+
+		match self
+			.chain()
+			.txhashset_segment_write(h, &segment, peer_info.addr.0)
+		{
+			Ok(()) => Ok(false),
+			Err(e) => match e.kind() {
+				chain::ErrorKind::InvalidSegment(_) => {
+					error!(
+						"Invalid txhashset segment {:?} {:?} from {}: {}",
+						segment.segment_type(),
+						segment.identifier(),
+						peer_info.addr,
+						e
+					);
+					Ok(true)
+				}
+				_ => {
+					warn!("Failed to save txhashset segment: {}", e);
+					Ok(false)
+				}
+			},
+		}
+	}
+
+	fn get_output_proof(&self, commit: Commitment) -> Option<OutputProof> {
+		if self.light_mode() {
+			return None;
+		}
+		match self.chain().get_output_proof(&commit) {
+			Ok(proof) => Some(proof),
+			Err(e) => {
+				debug!("get_output_proof: {:?}: {}", commit, e);
+				None
+			}
+		}
+	}
+
+	fn output_proof_received(
+		&self,
+		commit: Commitment,
+		proof: Option<OutputProof>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		if !self
+			.light_client
+			.awaiting_output_proof(&commit, &peer_info.addr)
+		{
+			debug!(
+				"Unexpected output proof of {:?} from {}, ignored",
+				commit, peer_info.addr
+			);
+			return Ok(false);
+		}
+
+		let proof = match proof {
+			Some(proof) => {
+				if proof.output.commit != commit {
+					debug!(
+						"Output proof of {:?} from {} is for another output",
+						commit, peer_info.addr
+					);
+					self.light_client
+						.output_proof_answered(&commit, &peer_info.addr, None);
+					self.peers()
+						.penalize(peer_info.addr, p2p::Penalty::InvalidMessage);
+					return Ok(true);
+				}
+				match self.chain().verify_output_proof(&proof) {
+					Ok(header) => Some((proof, header.height)),
+					Err(e) => {
+						debug!(
+							"Output proof of {:?} from {} not verified: {}",
+							commit, peer_info.addr, e
+						);
+						self.light_client
+							.output_proof_answered(&commit, &peer_info.addr, None);
+						// the proof may be against a header we don't have yet
+						return Ok(e.kind() == chain::ErrorKind::MerkleProof);
+					}
+				}
+			}
+			None => None,
+		};
+		self.light_client
+			.output_proof_answered(&commit, &peer_info.addr, proof);
+		Ok(false)
+	}
+
+	fn get_kernel_proof(&self, excess: Commitment) -> Option<KernelProof> {
+		if self.light_mode() {
+			return None;
+		}
+		match self.chain().get_kernel_proof(&excess) {
+			Ok(proof) => Some(proof),
+			Err(e) => {
+				debug!("get_kernel_proof: {:?}: {}", excess, e);
+				None
+			}
+		}
+	}
+
+	fn kernel_proof_received(
+		&self,
+		excess: Commitment,
+		proof: Option<KernelProof>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		if !self
+			.light_client
+			.awaiting_kernel_proof(&excess, &peer_info.addr)
+		{
+			debug!(
+				"Unexpected kernel proof of {:?} from {}, ignored",
+				excess, peer_info.addr
+			);
+			return Ok(false);
+		}
+
+		let proof = match proof {
+			Some(proof) => {
+				if proof.kernel.excess != excess {
+					debug!(
+						"Kernel proof of {:?} from {} is for another kernel",
+						excess, peer_info.addr
+					);
+					self.light_client
+						.kernel_proof_answered(&excess, &peer_info.addr, None);
+					self.peers()
+						.penalize(peer_info.addr, p2p::Penalty::InvalidMessage);
+					return Ok(true);
+				}
+				match self.chain().verify_kernel_proof(&proof) {
+					Ok(header) => Some((proof, header.height)),
+					Err(e) => {
+						debug!(
+							"Kernel proof of {:?} from {} not verified: {}",
+							excess, peer_info.addr, e
+						);
+						self.light_client
+							.kernel_proof_answered(&excess, &peer_info.addr, None);
+						// the proof may be against a header we don't have yet
+						return Ok(e.kind() == chain::ErrorKind::MerkleProof);
+					}
+				}
+			}
+			None => None,
+		};
+		self.light_client
+			.kernel_proof_answered(&excess, &peer_info.addr, proof);
+		Ok(false)
+	}
+
 	fn get_tmp_dir(&self) -> PathBuf {
 		self.chain().get_tmp_dir()
 	}
 
+	fn peer_connected(&self, peer_info: &PeerInfo) {
+		for hook in &self.hooks {
+			hook.on_peer_connected(peer_info);
+		}
+	}
+
+	fn peer_disconnected(&self, addr: p2p::PeerAddr) {
+		for hook in &self.hooks {
+			hook.on_peer_disconnected(&addr);
+		}
+	}
+
 	fn get_tmpfile_pathname(&self, tmpfile_name: String) -> PathBuf {
 		self.chain().get_tmpfile_pathname(tmpfile_name)
 	}
@@ -484,6 +821,8 @@ impl NetToChainAdapter {
 		verifier_cache: Arc<RwLock<dyn VerifierCache>>,
 		config: ServerConfig,
 		hooks: Vec<Box<dyn NetEvents + Send + Sync>>,
+		compact_block_stats: Arc<RwLock<CompactBlockStats>>,
+		light_client: Arc<LightClient>,
 	) -> NetToChainAdapter {
 		NetToChainAdapter {
 			sync_state,
@@ -493,6 +832,9 @@ impl NetToChainAdapter {
 			peers: OneTime::new(),
 			config,
 			hooks,
+			compact_block_stats,
+			pending_block_txs: RwLock::new(HashMap::new()),
+			light_client,
 		}
 	}
 
@@ -515,6 +857,20 @@ impl NetToChainAdapter {
 			.expect("Failed to upgrade weak ref to our chain.")
 	}
 
+	// Light nodes only have the header chain, no blocks nor txhashset.
+	fn light_mode(&self) -> bool {
+		self.config.light_mode.unwrap_or(false)
+	}
+
+	// Our head to compare with the peers, the header head for a light node.
+	fn local_head(&self) -> Result<chain::Tip, chain::Error> {
+		if self.light_mode() {
+			self.chain().header_head()
+		} else {
+			self.chain().head()
+		}
+	}
+
 	// Find the first locator hash that refers to a known header on our main chain.
 	fn find_common_header(&self, locator: &[Hash]) -> Option<BlockHeader> {
 		let header_pmmr = self.chain().header_pmmr();
@@ -645,6 +1001,147 @@ impl NetToChainAdapter {
 		}
 	}
 
+	// Hydrate a compact block with the provided txs, and process the resulting
+	// block if valid. If not valid fallback to requesting the full block.
+	fn hydrate_compact_block(
+		&self,
+		cb: core::CompactBlock,
+		txs: Vec<core::Transaction>,
+		peer_info: &PeerInfo,
+		with_block_txs: bool,
+	) -> Result<bool, chain::Error> {
+		// TODO - 3 scenarios here -
+		// 1) we hydrate a valid block (good to go)
+		// 2) we hydrate an invalid block (txs legit missing from our pool)
+		// 3) we hydrate an invalid block (peer sent us a "bad" compact block) - [TBD]
+
+		let block = match core::Block::hydrate_from(cb.clone(), txs) {
+			Ok(block) => {
+				if !self.sync_state.is_syncing() {
+					for hook in &self.hooks {
+						hook.on_block_received(&block, &peer_info.addr);
+					}
+				}
+				block
+			}
+			Err(e) => {
+				debug!("Invalid hydrated block {}: {:?}", cb.hash(), e);
+				if !with_block_txs {
+					return Ok(false);
+				}
+				// The compact block may well be fine, the txs sent along not.
+				if self.sync_state.status() == SyncStatus::NoSync {
+					debug!("adapter: hydration with block txs failed, requesting full block");
+					self.compact_block_stats.write().full_block_fallbacks += 1;
+					self.peers()
+						.penalize(peer_info.addr, p2p::Penalty::InvalidMessage);
+					self.request_block(&cb.header, peer_info);
+				}
+				return Ok(true);
+			}
+		};
+
+		if let Ok(_prev) = self.chain().get_previous_header(&cb.header) {
+			let complete_inputs = self.chain().get_complete_inputs(&block.inputs())?;
+			if block
+				.validate(self.verifier_cache.clone(), Some(&complete_inputs))
+				.is_ok()
+			{
+				debug!("successfully hydrated block from tx pool!");
+				{
+					let mut stats = self.compact_block_stats.write();
+					if with_block_txs {
+						stats.hydrated_with_block_txs += 1;
+					} else {
+						stats.hydrated_from_pool += 1;
+					}
+				}
+				self.process_block(block, peer_info, false)
+			} else {
+				if self.sync_state.status() == SyncStatus::NoSync {
+					debug!("adapter: block invalid after hydration, requesting full block");
+					self.compact_block_stats.write().full_block_fallbacks += 1;
+					self.request_block(&cb.header, peer_info);
+					Ok(true)
+				} else {
+					debug!("block invalid after hydration, ignoring it, cause still syncing");
+					Ok(true)
+				}
+			}
+		} else {
+			debug!("failed to retrieve previous block header (still syncing?)");
+			Ok(true)
+		}
+	}
+
+	// Request the txs of a compact block we could not find in our pool from the
+	// peer that sent us the compact block. Returns false if the peer cannot
+	// provide them (not connected or not supported).
+	fn request_block_txs(
+		&self,
+		cb: &core::CompactBlock,
+		short_ids: Vec<ShortId>,
+		peer_info: &PeerInfo,
+	) -> bool {
+		self.check_pending_block_txs();
+
+		if !peer_info
+			.capabilities
+			.contains(p2p::Capabilities::BLOCK_TXS)
+		{
+			return false;
+		}
+		let peer = match self.peers().get_connected_peer(peer_info.addr) {
+			Some(peer) => peer,
+			None => return false,
+		};
+
+		let count = short_ids.len();
+		if let Err(e) = peer.send_block_txs_request(cb.hash(), cb.nonce, short_ids) {
+			error!("request_block_txs: failed: {:?}", e);
+			return false;
+		}
+
+		self.pending_block_txs.write().insert(
+			cb.hash(),
+			PendingBlockTxs {
+				cb: cb.clone(),
+				peer_info: peer_info.clone(),
+				requested_at: Utc::now(),
+			},
+		);
+		self.compact_block_stats.write().txs_requested += count as u64;
+		true
+	}
+
+	/// Fallback to requesting the full block for any compact block still
+	/// waiting on its missing txs after BLOCK_TXS_TIMEOUT_SECS. Called
+	/// periodically, as well as when txs are requested or received.
+	pub fn check_pending_block_txs(&self) {
+		let cutoff = Utc::now() - Duration::seconds(BLOCK_TXS_TIMEOUT_SECS);
+		let expired: Vec<PendingBlockTxs> = {
+			let mut pending = self.pending_block_txs.write();
+			let hashes: Vec<Hash> = pending
+				.iter()
+				.filter(|(_, x)| x.requested_at < cutoff)
+				.map(|(h, _)| *h)
+				.collect();
+			hashes.iter().filter_map(|h| pending.remove(h)).collect()
+		};
+
+		for x in expired {
+			debug!(
+				"check_pending_block_txs: no txs for {} from {}, requesting full block",
+				x.cb.hash(),
+				x.peer_info.addr
+			);
+			self.compact_block_stats.write().full_block_fallbacks += 1;
+			self.peers()
+				.penalize(x.peer_info.addr, p2p::Penalty::SlowResponse);
+			self.request_block(&x.cb.header, &x.peer_info);
+		}
+	}
+
 	fn request_transaction(&self, h: Hash, peer_info: &PeerInfo) {
 		self.send_tx_request_to_peer(h, peer_info, |peer, h| peer.send_tx_request(h))
 	}
@@ -807,6 +1304,7 @@ impl ChainToPoolAndNetAdapter {
 pub struct PoolToNetAdapter {
 	peers: OneTime<Weak<p2p::Peers>>,
 	dandelion_epoch: Arc<RwLock<DandelionEpoch>>,
+	hooks: Vec<Box<dyn NetEvents + Send + Sync>>,
 }
 
 /// Adapter between the Dandelion monitor and the current Dandelion "epoch".
@@ -838,31 +1336,96 @@ impl DandelionAdapter for PoolToNetAdapter {
 impl pool::PoolAdapter for PoolToNetAdapter {
 	fn tx_accepted(&self, entry: &pool::PoolEntry) {
 		self.peers().broadcast_transaction(&entry.tx);
+		for hook in &self.hooks {
+			hook.on_transaction_accepted(&entry.tx);
+		}
+	}
+
+	fn tx_package_accepted(&self, entries: &[pool::PoolEntry]) {
+		let txs: Vec<_> = entries.iter().map(|x| x.tx.clone()).collect();
+		self.peers().broadcast_transaction_package(&txs);
+		for hook in &self.hooks {
+			for tx in &txs {
+				hook.on_transaction_accepted(tx);
+			}
+		}
+	}
+
+	fn txs_evicted(&self, entries: &[pool::PoolEntry], block: Option<&core::Block>) {
+		let txs: Vec<_> = entries.iter().map(|x| x.tx.clone()).collect();
+		for hook in &self.hooks {
+			hook.on_transactions_evicted(&txs, block);
+		}
+	}
+
+	fn stem_tx_package_accepted(&self, entries: &[pool::PoolEntry]) -> Result<(), pool::PoolError> {
+		let src = match entries.first() {
+			Some(entry) => entry.src.clone(),
+			None => return Ok(()),
+		};
+
+		// Same rules as for a single stem tx, the whole package follows the
+		// route of the peer we received it from.
+		let mut epoch = self.dandelion_epoch.write();
+		if epoch.is_stem() || (src.is_pushed() && epoch.always_stem_our_txs()) {
+			if let Some(peer) = epoch.relay_peer(&src, &self.peers()) {
+				let txs: Vec<_> = entries.iter().map(|x| x.tx.clone()).collect();
+				match peer.send_stem_transaction_package(&txs) {
+					Ok(_) => {
+						info!(
+							"Stemming this epoch, relaying tx package to next peer {:?}.",
+							peer.info.addr
+						);
+						epoch.record_relay(true);
+						Ok(())
+					}
+					Err(e) => {
+						error!("Stemming tx package failed. Fluffing. {:?}", e);
+						epoch.record_relay(false);
+						Err(pool::PoolError::DandelionError)
+					}
+				}
+			} else {
+				error!("No relay peer. Fluffing tx package.");
+				epoch.record_relay(false);
+				Err(pool::PoolError::DandelionError)
+			}
+		} else {
+			info!("Fluff epoch. Aggregating stem tx package. Will fluff via Dandelion monitor.");
+			Ok(())
+		}
 	}
 
 	fn stem_tx_accepted(&self, entry: &pool::PoolEntry) -> Result<(), pool::PoolError> {
 		// Take write lock on the current epoch.
-		// We need to be able to update the current relay peer if not currently connected.
+		// We need to be able to update the current relay peers if not currently connected.
 		let mut epoch = self.dandelion_epoch.write();
 
-		// If "stem" epoch attempt to relay the tx to the next Dandelion relay.
+		// If "stem" epoch attempt to relay the tx to the Dandelion relay assigned
+		// to the peer we received it from (or to our local relay for our own txs).
 		// Fallback to immediately fluffing the tx if we cannot stem for any reason.
 		// If "fluff" epoch then nothing to do right now (fluff via Dandelion monitor).
 		// If node is configured to always stem our (pushed via api) txs then do so.
 		if epoch.is_stem() || (entry.src.is_pushed() && epoch.always_stem_our_txs()) {
-			if let Some(peer) = epoch.relay_peer(&self.peers()) {
+			if let Some(peer) = epoch.relay_peer(&entry.src, &self.peers()) {
 				match peer.send_stem_transaction(&entry.tx) {
 					Ok(_) => {
-						info!("Stemming this epoch, relaying to next peer.");
+						info!(
+							"Stemming this epoch, relaying to next peer {:?}.",
+							peer.info.addr
+						);
+						epoch.record_relay(true);
 						Ok(())
 					}
 					Err(e) => {
 						error!("Stemming tx failed. Fluffing. {:?}", e);
+						epoch.record_relay(false);
 						Err(pool::PoolError::DandelionError)
 					}
 				}
 			} else {
 				error!("No relay peer. Fluffing.");
+				epoch.record_relay(false);
 				Err(pool::PoolError::DandelionError)
 			}
 		} else {
@@ -874,10 +1437,15 @@ impl pool::PoolAdapter for PoolToNetAdapter {
 
 impl PoolToNetAdapter {
 	/// Create a new pool to net adapter
-	pub fn new(config: pool::DandelionConfig) -> PoolToNetAdapter {
+	pub fn new(
+		config: pool::DandelionConfig,
+		dandelion_stats: Arc<RwLock<DandelionStats>>,
+		hooks: Vec<Box<dyn NetEvents + Send + Sync>>,
+	) -> PoolToNetAdapter {
 		PoolToNetAdapter {
 			peers: OneTime::new(),
-			dandelion_epoch: Arc::new(RwLock::new(DandelionEpoch::new(config))),
+			dandelion_epoch: Arc::new(RwLock::new(DandelionEpoch::new(config, dandelion_stats))),
+			hooks,
 		}
 	}
 
@@ -20,11 +20,14 @@ extern crate hyper;
 extern crate hyper_rustls;
 extern crate tokio;
 
-use crate::chain::BlockStatus;
+use crate::api;
+use crate::api::ws::{BlockAcceptedEvent, EventHub, PeerDisconnectedEvent, PoolTxEvent, Topic};
+use crate::chain::{self, BlockStatus};
 use crate::common::types::{ServerConfig, WebHooksConfig};
 use crate::core::core;
 use crate::core::core::hash::Hashed;
-use crate::p2p::types::PeerAddr;
+use crate::p2p::types::{PeerAddr, PeerInfo, PeerInfoDisplay};
+use crate::util;
 use futures::future::Future;
 use hyper::client::HttpConnector;
 use hyper::header::HeaderValue;
@@ -33,11 +36,15 @@ use hyper::{Body, Method, Request};
 use hyper_rustls::HttpsConnector;
 use serde::Serialize;
 use serde_json::{json, to_string};
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::runtime::Runtime;
 
 /// Returns the list of event hooks that will be initialized for network events
-pub fn init_net_hooks(config: &ServerConfig) -> Vec<Box<dyn NetEvents + Send + Sync>> {
+pub fn init_net_hooks(
+	config: &ServerConfig,
+	event_hub: &Arc<EventHub>,
+) -> Vec<Box<dyn NetEvents + Send + Sync>> {
 	let mut list: Vec<Box<dyn NetEvents + Send + Sync>> = Vec::new();
 	list.push(Box::new(EventLogger));
 	if config.webhook_config.block_received_url.is_some()
@@ -46,16 +53,29 @@ pub fn init_net_hooks(config: &ServerConfig) -> Vec<Box<dyn NetEvents + Send + S
 	{
 		list.push(Box::new(WebHook::from_config(&config.webhook_config)));
 	}
+	list.push(Box::new(EventStream::new(event_hub)));
 	list
 }
 
 /// Returns the list of event hooks that will be initialized for chain events
-pub fn init_chain_hooks(config: &ServerConfig) -> Vec<Box<dyn ChainEvents + Send + Sync>> {
+pub fn init_chain_hooks(
+	config: &ServerConfig,
+	event_hub: &Arc<EventHub>,
+) -> Vec<Box<dyn ChainEvents + Send + Sync>> {
 	let mut list: Vec<Box<dyn ChainEvents + Send + Sync>> = Vec::new();
 	list.push(Box::new(EventLogger));
 	if config.webhook_config.block_accepted_url.is_some() {
 		list.push(Box::new(WebHook::from_config(&config.webhook_config)));
 	}
+	list.push(Box::new(EventStream::new(event_hub)));
+	list
+}
+
+/// Returns the list of event hooks that will be initialized for the txs
+/// entering and leaving the pool
+pub fn init_pool_hooks(event_hub: &Arc<EventHub>) -> Vec<Box<dyn NetEvents + Send + Sync>> {
+	let mut list: Vec<Box<dyn NetEvents + Send + Sync>> = Vec::new();
+	list.push(Box::new(EventStream::new(event_hub)));
 	list
 }
 
@@ -70,6 +90,21 @@ pub trait NetEvents {
 
 	/// Triggers when a new block header arrives
 	fn on_header_received(&self, header: &core::BlockHeader, addr: &PeerAddr) {}
+
+	/// Triggers when a transaction enters the pool (stem txs, under embargo,
+	/// excluded)
+	fn on_transaction_accepted(&self, tx: &core::Transaction) {}
+
+	/// Triggers when txs leave the pool, mined or conflicting with the block
+	/// the pool was reconciled against, no longer valid after a reorg, evicted
+	/// or removed (stem txs excluded)
+	fn on_transactions_evicted(&self, txs: &[core::Transaction], block: Option<&core::Block>) {}
+
+	/// Triggers when a peer is connected
+	fn on_peer_connected(&self, peer: &PeerInfo) {}
+
+	/// Triggers when a connected peer is dropped
+	fn on_peer_disconnected(&self, addr: &PeerAddr) {}
 }
 
 #[allow(unused_variables)]
@@ -113,6 +148,17 @@ impl NetEvents for EventLogger {
 			addr
 		);
 	}
+
+	fn on_peer_connected(&self, peer: &PeerInfo) {
+		debug!(
+			"Peer {} connected ({:?}, {}).",
+			peer.addr, peer.direction, peer.user_agent
+		);
+	}
+
+	fn on_peer_disconnected(&self, addr: &PeerAddr) {
+		debug!("Peer {} disconnected.", addr);
+	}
 }
 
 impl ChainEvents for EventLogger {
@@ -147,6 +193,77 @@ impl ChainEvents for EventLogger {
 	}
 }
 
+/// Publishes the events to the WebSocket subscribers of the API.
+struct EventStream {
+	hub: Arc<EventHub>,
+}
+
+impl EventStream {
+	fn new(hub: &Arc<EventHub>) -> EventStream {
+		EventStream { hub: hub.clone() }
+	}
+
+	fn publish_tx(&self, name: &str, tx: &core::Transaction, block: Option<&core::Block>) {
+		let event = PoolTxEvent {
+			hash: tx.hash().to_hex(),
+			kernels: tx
+				.kernels()
+				.iter()
+				.map(|k| util::to_hex(k.excess.0.to_vec()))
+				.collect(),
+			fee: tx.fee(),
+			block_hash: block.map(|b| b.hash().to_hex()),
+		};
+		self.hub.publish(Topic::Pool, name, &event);
+	}
+}
+
+impl ChainEvents for EventStream {
+	fn on_block_accepted(&self, block: &core::Block, status: &BlockStatus) {
+		let (status_str, reorg_depth) = match status {
+			BlockStatus::Reorg(depth) => ("reorg", Some(*depth)),
+			BlockStatus::Fork => ("fork", None),
+			BlockStatus::Next => ("head", None),
+		};
+		let event = BlockAcceptedEvent {
+			hash: block.hash().to_hex(),
+			height: block.header.height,
+			prev_hash: block.header.prev_hash.to_hex(),
+			status: status_str.to_owned(),
+			reorg_depth,
+		};
+		self.hub.publish(Topic::Block, "block_accepted", &event);
+
+		// a fork doesn't change our head
+		if *status != BlockStatus::Fork {
+			let tip = api::Tip::from_tip(chain::Tip::from_header(&block.header));
+			self.hub.publish(Topic::Tip, "new_tip", &tip);
+		}
+	}
+}
+
+impl NetEvents for EventStream {
+	fn on_transaction_accepted(&self, tx: &core::Transaction) {
+		self.publish_tx("tx_accepted", tx, None);
+	}
+
+	fn on_transactions_evicted(&self, txs: &[core::Transaction], block: Option<&core::Block>) {
+		for tx in txs {
+			self.publish_tx("tx_evicted", tx, block);
+		}
+	}
+
+	fn on_peer_connected(&self, peer: &PeerInfo) {
+		let peer: PeerInfoDisplay = peer.clone().into();
+		self.hub.publish(Topic::Peers, "peer_connected", &peer);
+	}
+
+	fn on_peer_disconnected(&self, addr: &PeerAddr) {
+		let event = PeerDisconnectedEvent { addr: *addr };
+		self.hub.publish(Topic::Peers, "peer_disconnected", &event);
+	}
+}
+
 fn parse_url(value: &Option<String>) -> Option<hyper::Uri> {
 	match value {
 		Some(url) => {
@@ -0,0 +1,360 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Queries of a light node (header chain only) for outputs and kernels, with
+//! merkle proofs against our headers, to the full nodes we're connected to,
+//! and the source of those proofs for our APIs.
+
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::api;
+use crate::chain::{self, KernelProof, OutputProof};
+use crate::common::types::Error;
+use crate::p2p::{self, Capabilities, Peer, PeerAddr};
+use crate::util::secp::pedersen::Commitment;
+use crate::util::{Condvar, Mutex};
+
+/// Number of peers a query is sent to.
+const LIGHT_QUERY_PEERS: usize = 3;
+
+/// How long we wait for the answers of the peers to a query.
+const LIGHT_QUERY_TIMEOUT_SECS: u64 = 10;
+
+// A query sent to some peers, waiting on their answers.
+struct PendingQuery<T> {
+	awaiting: Vec<PeerAddr>,
+	// our header head height when the query was sent
+	head: u64,
+	// the most recent valid proof so far, with the height of its header
+	proof: Option<(T, u64)>,
+	// API requests waiting on this query, the last one removes it
+	waiters: usize,
+}
+
+impl<T> PendingQuery<T> {
+	// Done once all the peers answered, or with a proof against our head that
+	// no other answer can beat.
+	fn is_done(&self) -> bool {
+		self.awaiting.is_empty()
+			|| self
+				.proof
+				.as_ref()
+				.map(|p| p.1 >= self.head)
+				.unwrap_or(false)
+	}
+}
+
+// The queries in flight, by output commitment or kernel excess. Concurrent
+// requests for the same key share the same query.
+struct PendingQueries<T> {
+	queries: Mutex<HashMap<Commitment, PendingQuery<T>>>,
+	answered: Condvar,
+}
+
+impl<T: Clone> PendingQueries<T> {
+	fn new() -> PendingQueries<T> {
+		PendingQueries {
+			queries: Mutex::new(HashMap::new()),
+			answered: Condvar::new(),
+		}
+	}
+
+	// Starts a query to these peers, or joins the one in flight for the same
+	// key. Returns whether the query is a new one, to be sent.
+	fn start(&self, key: Commitment, awaiting: Vec<PeerAddr>, head: u64) -> bool {
+		let mut queries = self.queries.lock();
+		if let Some(query) = queries.get_mut(&key) {
+			query.waiters += 1;
+			return false;
+		}
+		queries.insert(
+			key,
+			PendingQuery {
+				awaiting,
+				head,
+				proof: None,
+				waiters: 1,
+			},
+		);
+		true
+	}
+
+	fn is_awaiting(&self, key: &Commitment, peer: &PeerAddr) -> bool {
+		self.queries
+			.lock()
+			.get(key)
+			.map(|q| q.awaiting.contains(peer))
+			.unwrap_or(false)
+	}
+
+	// The most recent valid proof wins, an older one may be of an output
+	// spent since.
+	fn answered(&self, key: &Commitment, peer: &PeerAddr, proof: Option<(T, u64)>) {
+		if let Some(query) = self.queries.lock().get_mut(key) {
+			query.awaiting.retain(|p| p != peer);
+			if let Some((proof, height)) = proof {
+				if query.proof.as_ref().map(|p| p.1 < height).unwrap_or(true) {
+					query.proof = Some((proof, height));
+				}
+			}
+		}
+		self.answered.notify_all();
+	}
+
+	// Waits until the query is done or the timeout expires, returning the best
+	// proof received.
+	fn wait(&self, key: &Commitment, timeout: Duration) -> Option<T> {
+		let deadline = Instant::now() + timeout;
+		let mut queries = self.queries.lock();
+		while !queries.get(key).map(|q| q.is_done()).unwrap_or(true) {
+			if self.answered.wait_until(&mut queries, deadline).timed_out() {
+				break;
+			}
+		}
+		let query = queries.get_mut(key)?;
+		query.waiters -= 1;
+		let proof = query.proof.as_ref().map(|p| p.0.clone());
+		if query.waiters == 0 {
+			queries.remove(key);
+		}
+		proof
+	}
+}
+
+/// Light client, asking the full nodes we're connected to for unspent outputs
+/// and tx kernels with merkle proofs. The proofs are checked against our
+/// header chain by the adapter on reception.
+pub struct LightClient {
+	outputs: PendingQueries<OutputProof>,
+	kernels: PendingQueries<KernelProof>,
+}
+
+impl LightClient {
+	/// New light client, with no query in flight.
+	pub fn new() -> LightClient {
+		LightClient {
+			outputs: PendingQueries::new(),
+			kernels: PendingQueries::new(),
+		}
+	}
+
+	/// Asks some peers for the unspent output with that commitment, waiting on
+	/// their answers, the most recent proof winning. None if none of them has
+	/// it.
+	pub fn output_proof(
+		&self,
+		peers: &p2p::Peers,
+		commit: Commitment,
+		head: u64,
+	) -> Result<Option<OutputProof>, Error> {
+		query(&self.outputs, peers, commit, head, |peer, commit| {
+			peer.send_output_proof_request(commit)
+		})
+	}
+
+	/// Asks some peers for the tx kernel with that excess, waiting on their
+	/// answers, the most recent proof winning. None if none of them has it.
+	pub fn kernel_proof(
+		&self,
+		peers: &p2p::Peers,
+		excess: Commitment,
+		head: u64,
+	) -> Result<Option<KernelProof>, Error> {
+		query(&self.kernels, peers, excess, head, |peer, excess| {
+			peer.send_kernel_proof_request(excess)
+		})
+	}
+
+	/// Whether we're waiting on that peer for an output proof.
+	pub fn awaiting_output_proof(&self, commit: &Commitment, peer: &PeerAddr) -> bool {
+		self.outputs.is_awaiting(commit, peer)
+	}
+
+	/// A peer answered an output query, the proof being already verified,
+	/// with the height of the header it is against.
+	pub fn output_proof_answered(
+		&self,
+		commit: &Commitment,
+		peer: &PeerAddr,
+		proof: Option<(OutputProof, u64)>,
+	) {
+		self.outputs.answered(commit, peer, proof);
+	}
+
+	/// Whether we're waiting on that peer for a kernel proof.
+	pub fn awaiting_kernel_proof(&self, excess: &Commitment, peer: &PeerAddr) -> bool {
+		self.kernels.is_awaiting(excess, peer)
+	}
+
+	/// A peer answered a kernel query, the proof being already verified,
+	/// with the height of the header it is against.
+	pub fn kernel_proof_answered(
+		&self,
+		excess: &Commitment,
+		peer: &PeerAddr,
+		proof: Option<(KernelProof, u64)>,
+	) {
+		self.kernels.answered(excess, peer, proof);
+	}
+}
+
+/// Outputs and kernels with their merkle proofs, as served by our APIs:
+/// from our own chain, or from our peers in light mode.
+pub struct NodeProofs {
+	chain: Arc<chain::Chain>,
+	peers: Arc<p2p::Peers>,
+	light_client: Option<Arc<LightClient>>,
+}
+
+impl NodeProofs {
+	/// Proofs from our chain, or from our peers through the light client.
+	pub fn new(
+		chain: Arc<chain::Chain>,
+		peers: Arc<p2p::Peers>,
+		light_client: Option<Arc<LightClient>>,
+	) -> NodeProofs {
+		NodeProofs {
+			chain,
+			peers,
+			light_client,
+		}
+	}
+
+	/// Unspent output with that commitment and its merkle proof against our
+	/// header chain. None if the output isn't unspent (or no peer has it).
+	/// In light mode it is unspent as of the header of the proof, at most
+	/// `MAX_PROOF_AGE` blocks behind our header head.
+	pub fn output_proof(&self, commit: Commitment) -> Result<Option<OutputProof>, Error> {
+		if let Some(ref light_client) = self.light_client {
+			let head = self.chain.header_head()?.height;
+			return light_client.output_proof(&self.peers, commit, head);
+		}
+		match self.chain.get_output_proof(&commit) {
+			Ok(proof) => Ok(Some(proof)),
+			Err(e) => match e.kind() {
+				chain::ErrorKind::OutputNotFound => Ok(None),
+				_ => Err(e.into()),
+			},
+		}
+	}
+
+	/// Tx kernel with that excess and its merkle proof against our header
+	/// chain. None if the kernel isn't found.
+	pub fn kernel_proof(&self, excess: Commitment) -> Result<Option<KernelProof>, Error> {
+		if let Some(ref light_client) = self.light_client {
+			let head = self.chain.header_head()?.height;
+			return light_client.kernel_proof(&self.peers, excess, head);
+		}
+		match self.chain.get_kernel_proof(&excess) {
+			Ok(proof) => Ok(Some(proof)),
+			Err(e) => match e.kind() {
+				chain::ErrorKind::TxKernelNotFound => Ok(None),
+				_ => Err(e.into()),
+			},
+		}
+	}
+}
+
+impl api::ProofSource for NodeProofs {
+	fn output_proof(&self, commit: Commitment) -> Result<Option<OutputProof>, api::Error> {
+		NodeProofs::output_proof(self, commit)
+			.map_err(|e| api::ErrorKind::Internal(format!("{:?}", e)).into())
+	}
+
+	fn kernel_proof(&self, excess: Commitment) -> Result<Option<KernelProof>, api::Error> {
+		NodeProofs::kernel_proof(self, excess)
+			.map_err(|e| api::ErrorKind::Internal(format!("{:?}", e)).into())
+	}
+}
+
+fn query<T, F>(
+	pending: &PendingQueries<T>,
+	peers: &p2p::Peers,
+	key: Commitment,
+	head: u64,
+	send: F,
+) -> Result<Option<T>, Error>
+where
+	F: Fn(&Peer, Commitment) -> Result<(), p2p::Error>,
+{
+	let mut candidates: Vec<_> = peers
+		.connected_peers()
+		.into_iter()
+		.filter(|p| p.info.capabilities.contains(Capabilities::MERKLE_PROOFS))
+		.collect();
+	if candidates.is_empty() {
+		return Err(Error::General(
+			"no connected peer able to provide merkle proofs".to_owned(),
+		));
+	}
+	candidates.shuffle(&mut thread_rng());
+	candidates.truncate(LIGHT_QUERY_PEERS);
+
+	let awaiting = candidates.iter().map(|p| p.info.addr).collect();
+	if pending.start(key, awaiting, head) {
+		for peer in &candidates {
+			if let Err(e) = send(peer, key) {
+				debug!("light: failed to query {}: {:?}", peer.info.addr, e);
+				pending.answered(&key, &peer.info.addr, None);
+			}
+		}
+	}
+	Ok(pending.wait(&key, Duration::from_secs(LIGHT_QUERY_TIMEOUT_SECS)))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use std::thread;
+
+	fn addr(s: &str) -> PeerAddr {
+		PeerAddr(s.parse().unwrap())
+	}
+
+	#[test]
+	fn concurrent_queries_share_answers() {
+		let pending = Arc::new(PendingQueries::<u32>::new());
+		let key = Commitment::from_vec(vec![1; 33]);
+		let peers = vec![addr("10.0.0.1:3414"), addr("10.0.0.2:3414")];
+		assert!(pending.start(key, peers.clone(), 10));
+		// a second request for the same output joins the query
+		assert!(!pending.start(key, peers.clone(), 10));
+
+		let waiter = {
+			let pending = pending.clone();
+			thread::spawn(move || pending.wait(&key, Duration::from_secs(10)))
+		};
+		pending.answered(&key, &peers[1], Some((2, 9)));
+		pending.answered(&key, &peers[0], Some((1, 8)));
+		assert_eq!(pending.wait(&key, Duration::from_secs(10)), Some(2));
+		assert_eq!(waiter.join().unwrap(), Some(2));
+		assert!(pending.queries.lock().is_empty());
+	}
+
+	#[test]
+	fn query_timeout() {
+		let pending = PendingQueries::<u32>::new();
+		let key = Commitment::from_vec(vec![1; 33]);
+		let peers = vec![addr("10.0.0.1:3414"), addr("10.0.0.2:3414")];
+		assert!(pending.start(key, peers.clone(), 10));
+		pending.answered(&key, &peers[0], Some((1, 8)));
+		assert!(pending.is_awaiting(&key, &peers[1]));
+		assert_eq!(pending.wait(&key, Duration::from_millis(100)), Some(1));
+		assert!(!pending.is_awaiting(&key, &peers[1]));
+	}
+}
@@ -36,12 +36,18 @@ use crate::p2p;
 pub struct ServerStateInfo {
 	/// Stratum stats
 	pub stratum_stats: Arc<RwLock<StratumStats>>,
+	/// Dandelion stats
+	pub dandelion_stats: Arc<RwLock<DandelionStats>>,
+	/// Compact block reconstruction stats
+	pub compact_block_stats: Arc<RwLock<CompactBlockStats>>,
 }
 
 impl Default for ServerStateInfo {
 	fn default() -> ServerStateInfo {
 		ServerStateInfo {
 			stratum_stats: Arc::new(RwLock::new(StratumStats::default())),
+			dandelion_stats: Arc::new(RwLock::new(DandelionStats::default())),
+			compact_block_stats: Arc::new(RwLock::new(CompactBlockStats::default())),
 		}
 	}
 }
@@ -63,6 +69,10 @@ pub struct ServerStats {
 	pub peer_stats: Vec<PeerStats>,
 	/// Difficulty calculation statistics
 	pub diff_stats: DiffStats,
+	/// Dandelion stem/fluff statistics
+	pub dandelion_stats: DandelionStats,
+	/// Compact block reconstruction statistics
+	pub compact_block_stats: CompactBlockStats,
 }
 
 /// Struct to return relevant information about stratum workers
@@ -107,6 +117,58 @@ pub struct StratumStats {
 	pub worker_stats: Vec<WorkerStats>,
 }
 
+/// Struct to return relevant information about Dandelion++ stem/fluff relay
+#[derive(Clone, Serialize, Debug, Default)]
+pub struct DandelionStats {
+	/// Whether we are stemming (or fluffing) in the current epoch
+	pub is_stem: bool,
+	/// When the current epoch started
+	pub epoch_start: Option<DateTime<Utc>>,
+	/// Number of epochs since startup
+	pub epoch_count: u64,
+	/// Outbound relay peers selected for the current epoch
+	pub relay_peers: Vec<String>,
+	/// Number of inbound peers routed to one of our relays in the current epoch
+	pub inbound_routes: usize,
+	/// Number of stem txs successfully relayed to the next stem peer
+	pub stem_relayed: u64,
+	/// Number of stem txs we failed to relay (and fluffed instead)
+	pub stem_relay_failed: u64,
+	/// Number of stem txs currently waiting on their embargo timer
+	pub embargo_pending: usize,
+	/// Number of stem txs fluffed after their embargo timer expired
+	pub embargo_expired: u64,
+	/// Number of aggregated txs fluffed at the end of the aggregation period
+	pub fluffed: u64,
+}
+
+/// Struct to return relevant information about compact block reconstruction
+#[derive(Clone, Serialize, Debug, Default)]
+pub struct CompactBlockStats {
+	/// Number of compact blocks received with txs to find in our pool
+	pub received: u64,
+	/// Number of compact blocks hydrated from our pool only
+	pub hydrated_from_pool: u64,
+	/// Number of compact blocks hydrated after requesting missing txs from the peer
+	pub hydrated_with_block_txs: u64,
+	/// Number of compact blocks for which we had to request the full block
+	pub full_block_fallbacks: u64,
+	/// Number of txs found in our pool
+	pub txs_from_pool: u64,
+	/// Number of missing txs requested from peers
+	pub txs_requested: u64,
+}
+
+impl CompactBlockStats {
+	/// Ratio of compact blocks hydrated from our pool only, without any round trip
+	pub fn hit_rate(&self) -> f64 {
+		if self.received == 0 {
+			return 0.0;
+		}
+		self.hydrated_from_pool as f64 / self.received as f64
+	}
+}
+
 /// Stats on the last WINDOW blocks and the difficulty calculation
 #[derive(Clone)]
 pub struct DiffStats {
@@ -164,6 +226,8 @@ pub struct PeerStats {
 	pub sent_bytes_per_sec: u64,
 	/// Number of bytes we've received from the peer.
 	pub received_bytes_per_sec: u64,
+	/// Misbehaviour score, zero for a well behaved peer.
+	pub score: i32,
 }
 
 impl StratumStats {
@@ -201,6 +265,7 @@ impl PeerStats {
 			last_seen: peer.info.last_seen(),
 			sent_bytes_per_sec: peer.last_min_sent_bytes().unwrap_or(0) / 60,
 			received_bytes_per_sec: peer.last_min_received_bytes().unwrap_or(0) / 60,
+			score: peer.info.score(),
 		}
 	}
 }
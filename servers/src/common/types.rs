@@ -14,6 +14,8 @@
 // limitations under the License.
 
 //! Server types
+use std::cmp;
+use std::collections::HashMap;
 use std::convert::From;
 use std::sync::Arc;
 
@@ -22,6 +24,7 @@ use rand::prelude::*;
 
 use crate::api;
 use crate::chain;
+use crate::common::stats::DandelionStats;
 use crate::core::global::ChainTypes;
 use crate::core::{core, libtx, pow};
 use crate::keychain;
@@ -29,6 +32,7 @@ use crate::p2p;
 use crate::pool;
 use crate::pool::types::DandelionConfig;
 use crate::store;
+use crate::util::RwLock;
 
 /// Error type wrapping underlying module errors.
 #[derive(Debug)]
@@ -143,12 +147,32 @@ pub struct ServerConfig {
 	/// Directory under which the rocksdb stores will be created
 	pub db_root: String,
 
-	/// Network address for the Rest API HTTP server.
+	/// Network address for the foreign Rest API HTTP server (chain data and
+	/// pushing txs).
 	pub api_http_addr: String,
 
-	/// Location of secret for basic auth on Rest API HTTP server.
+	/// Location of secret for basic auth on the foreign Rest API HTTP server.
 	pub api_secret_path: Option<String>,
 
+	/// Network address for the owner Rest API HTTP server (peers, bans and
+	/// chain maintenance), not started if not set.
+	pub owner_api_http_addr: Option<String>,
+
+	/// Location of secret for basic auth on the owner Rest API HTTP server.
+	pub owner_api_secret_path: Option<String>,
+
+	/// Location of the API tokens file, each token with its scopes (read,
+	/// push_tx, admin). The api secrets alone are checked if not set.
+	pub api_tokens_path: Option<String>,
+
+	/// Requests per minute allowed to a client address on the Rest APIs, not
+	/// limited if not set.
+	pub api_rate_limit_per_ip: Option<u32>,
+
+	/// Requests per minute allowed to an API token without its own rate
+	/// limit, not limited if not set.
+	pub api_rate_limit_per_token: Option<u32>,
+
 	/// TLS certificate file
 	pub tls_certificate_file: Option<String>,
 	/// TLS certificate private key file
@@ -168,6 +192,10 @@ pub struct ServerConfig {
 	/// If pruned node, whether pruning the tx kernel mmr position index
 	pub pruning_kernel_index: Option<bool>,
 
+	/// Whether this node is a light node, syncing the header chain only and
+	/// asking its peers for outputs and kernels with merkle proofs
+	pub light_mode: Option<bool>,
+
 	/// Whether to skip the sync timeout on startup
 	/// (To assist testing on solo chains)
 	pub skip_sync_wait: Option<bool>,
@@ -208,6 +236,11 @@ impl Default for ServerConfig {
 			db_root: "gotts_chain".to_string(),
 			api_http_addr: "127.0.0.1:3513".to_string(),
 			api_secret_path: Some(".api_secret".to_string()),
+			owner_api_http_addr: Some("127.0.0.1:3517".to_string()),
+			owner_api_secret_path: Some(".owner_api_secret".to_string()),
+			api_tokens_path: None,
+			api_rate_limit_per_ip: None,
+			api_rate_limit_per_token: None,
 			tls_certificate_file: None,
 			tls_certificate_key: None,
 			p2p_config: p2p::P2PConfig::default(),
@@ -216,6 +249,7 @@ impl Default for ServerConfig {
 			chain_type: ChainTypes::default(),
 			archive_mode: Some(false),
 			pruning_kernel_index: Some(true),
+			light_mode: Some(false),
 			chain_validation_mode: ChainValidationMode::default(),
 			pool_config: pool::PoolConfig::default(),
 			skip_sync_wait: Some(false),
@@ -306,7 +340,10 @@ impl Default for WebHooksConfig {
 }
 
 /// A node is either "stem" of "fluff" for the duration of a single epoch.
-/// A node also maintains an outbound relay peer for the epoch.
+/// A node also maintains a small set of outbound relay peers for the epoch
+/// (Dandelion++). Each inbound peer is pseudo-randomly routed to one of these
+/// relays and the route is kept for the duration of the epoch, our own txs
+/// are all routed to a single relay.
 #[derive(Debug)]
 pub struct DandelionEpoch {
 	config: DandelionConfig,
@@ -314,18 +351,27 @@ pub struct DandelionEpoch {
 	start_time: Option<i64>,
 	// Are we in "stem" mode or "fluff" mode for this epoch?
 	is_stem: bool,
-	// Our current Dandelion relay peer (effective for this epoch).
-	relay_peer: Option<Arc<p2p::Peer>>,
+	// Our current Dandelion relay peers (effective for this epoch).
+	relay_peers: Vec<Arc<p2p::Peer>>,
+	// Inbound peer to relay index routing (effective for this epoch).
+	routes: HashMap<p2p::PeerAddr, usize>,
+	// Relay index used for our own (pushed via api) txs.
+	local_route: usize,
+	// Shared Dandelion stats, refreshed on every epoch and relay change.
+	stats: Arc<RwLock<DandelionStats>>,
 }
 
 impl DandelionEpoch {
-	/// Create a new Dandelion epoch, defaulting to "stem" and no outbound relay peer.
-	pub fn new(config: DandelionConfig) -> DandelionEpoch {
+	/// Create a new Dandelion epoch, defaulting to "stem" and no outbound relay peers.
+	pub fn new(config: DandelionConfig, stats: Arc<RwLock<DandelionStats>>) -> DandelionEpoch {
 		DandelionEpoch {
 			config,
 			start_time: None,
 			is_stem: true,
-			relay_peer: None,
+			relay_peers: vec![],
+			routes: HashMap::new(),
+			local_route: 0,
+			stats,
 		}
 	}
 
@@ -343,21 +389,34 @@ impl DandelionEpoch {
 
 	/// Transition to next Dandelion epoch.
 	/// Select stem/fluff based on configured stem_probability.
-	/// Choose a new outbound stem relay peer.
+	/// Choose new outbound stem relay peers and forget all inbound routes.
 	pub fn next_epoch(&mut self, peers: &Arc<p2p::Peers>) {
 		self.start_time = Some(Utc::now().timestamp());
-		self.relay_peer = peers.outgoing_connected_peers().first().cloned();
+		self.relay_peers = peers
+			.outgoing_connected_peers()
+			.into_iter()
+			.take(self.config.relay_count)
+			.collect();
+		self.routes.clear();
 
 		// If stem_probability == 90 then we stem 90% of the time.
 		let mut rng = rand::thread_rng();
 		let stem_probability = self.config.stem_probability;
 		self.is_stem = rng.gen_range(0, 100) < stem_probability;
+		self.local_route = rng.gen_range(0, cmp::max(self.config.relay_count, 1));
 
-		let addr = self.relay_peer.clone().map(|p| p.info.addr);
+		let addrs: Vec<_> = self.relay_peers.iter().map(|p| p.info.addr).collect();
 		info!(
-			"DandelionEpoch: next_epoch: is_stem: {} ({}%), relay: {:?}",
-			self.is_stem, stem_probability, addr
+			"DandelionEpoch: next_epoch: is_stem: {} ({}%), relays: {:?}",
+			self.is_stem, stem_probability, addrs
 		);
+
+		{
+			let mut stats = self.stats.write();
+			stats.epoch_start = Some(Utc::now());
+			stats.epoch_count += 1;
+		}
+		self.update_stats();
 	}
 
 	/// Are we stemming (or fluffing) transactions in this epoch?
@@ -370,30 +429,105 @@ impl DandelionEpoch {
 		self.config.always_stem_our_txs
 	}
 
-	/// What is our current relay peer?
-	/// If it is not connected then choose a new one.
-	pub fn relay_peer(&mut self, peers: &Arc<p2p::Peers>) -> Option<Arc<p2p::Peer>> {
-		let mut update_relay = false;
-		if let Some(peer) = &self.relay_peer {
-			if !peer.is_connected() {
+	/// What is our relay peer for a tx from the given source?
+	/// Stem txs from an inbound peer are always routed to the same relay
+	/// for the epoch, all other txs go to our "local" relay.
+	/// Disconnected relays are replaced with a new outbound peer.
+	pub fn relay_peer(
+		&mut self,
+		src: &pool::TxSource,
+		peers: &Arc<p2p::Peers>,
+	) -> Option<Arc<p2p::Peer>> {
+		self.refresh_relays(peers);
+		if self.relay_peers.is_empty() {
+			return None;
+		}
+
+		let count = self.relay_peers.len();
+		let index = match src.stem_peer() {
+			Some(addr) => *self
+				.routes
+				.entry(p2p::PeerAddr(addr))
+				.or_insert_with(|| rand::thread_rng().gen_range(0, count)),
+			None => self.local_route,
+		};
+		self.stats.write().inbound_routes = self.routes.len();
+
+		self.relay_peers.get(index % count).cloned()
+	}
+
+	/// Record the outcome of relaying a stem tx to the next relay peer.
+	pub fn record_relay(&self, success: bool) {
+		let mut stats = self.stats.write();
+		if success {
+			stats.stem_relayed += 1;
+		} else {
+			stats.stem_relay_failed += 1;
+		}
+	}
+
+	// Replace any disconnected relay peer (in place, to keep the inbound routes
+	// stable) with an outbound peer not already used as a relay.
+	fn refresh_relays(&mut self, peers: &Arc<p2p::Peers>) {
+		if self.relay_peers.iter().all(|p| p.is_connected())
+			&& self.relay_peers.len() >= self.config.relay_count
+		{
+			return;
+		}
+
+		let mut candidates: Vec<_> = peers
+			.outgoing_connected_peers()
+			.into_iter()
+			.filter(|p| !self.relay_peers.iter().any(|r| r.info.addr == p.info.addr))
+			.collect();
+
+		let mut changed = false;
+		for relay in self.relay_peers.iter_mut() {
+			if !relay.is_connected() {
 				info!(
 					"DandelionEpoch: relay_peer: {:?} not connected, choosing a new one.",
-					peer.info.addr
+					relay.info.addr
 				);
-				update_relay = true;
+				if let Some(peer) = candidates.pop() {
+					*relay = peer;
+					changed = true;
+				}
 			}
-		} else {
-			update_relay = true;
 		}
-
-		if update_relay {
-			self.relay_peer = peers.outgoing_connected_peers().first().cloned();
-			info!(
-				"DandelionEpoch: relay_peer: new peer chosen: {:?}",
-				self.relay_peer.clone().map(|p| p.info.addr)
-			);
+		let before = self.relay_peers.len();
+		self.relay_peers.retain(|p| p.is_connected());
+		changed |= before != self.relay_peers.len();
+		while self.relay_peers.len() < self.config.relay_count {
+			match candidates.pop() {
+				Some(peer) => {
+					self.relay_peers.push(peer);
+					changed = true;
+				}
+				None => break,
+			}
+		}
+		if !changed {
+			return;
 		}
 
-		self.relay_peer.clone()
+		info!(
+			"DandelionEpoch: relay_peer: relays now: {:?}",
+			self.relay_peers
+				.iter()
+				.map(|p| p.info.addr)
+				.collect::<Vec<_>>()
+		);
+		self.update_stats();
+	}
+
+	fn update_stats(&self) {
+		let mut stats = self.stats.write();
+		stats.is_stem = self.is_stem;
+		stats.relay_peers = self
+			.relay_peers
+			.iter()
+			.map(|p| p.info.addr.to_string())
+			.collect();
+		stats.inbound_routes = self.routes.len();
 	}
 }
@@ -15,12 +15,14 @@
 
 use chrono::prelude::Utc;
 use rand::{thread_rng, Rng};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
 
 use crate::common::adapters::DandelionAdapter;
-use crate::core::core::hash::Hashed;
+use crate::common::stats::DandelionStats;
+use crate::core::core::hash::{Hash, Hashed};
 use crate::core::core::transaction;
 use crate::core::core::verifier_cache::VerifierCache;
 use crate::pool::{DandelionConfig, Pool, PoolEntry, PoolError, TransactionPool, TxSource};
@@ -34,11 +36,14 @@ use crate::util::{RwLock, StopState};
 /// stempool and test if the timer is expired for each transaction. In that case
 /// the transaction will be sent in fluff phase (to multiple peers) instead of
 /// sending only to the peer relay.
+/// Each stem transaction gets its own randomized embargo timer (Dandelion++),
+/// the configured embargo_secs plus an exponentially distributed delay.
 pub fn monitor_transactions(
 	dandelion_config: DandelionConfig,
 	tx_pool: Arc<RwLock<TransactionPool>>,
 	adapter: Arc<dyn DandelionAdapter>,
 	verifier_cache: Arc<RwLock<dyn VerifierCache>>,
+	dandelion_stats: Arc<RwLock<DandelionStats>>,
 	stop_state: Arc<StopState>,
 ) -> std::io::Result<thread::JoinHandle<()>> {
 	debug!("Started Dandelion transaction monitor.");
@@ -46,6 +51,8 @@ pub fn monitor_transactions(
 	thread::Builder::new()
 		.name("dandelion".to_string())
 		.spawn(move || {
+			// Embargo expiry (timestamp) for each tx currently in the stempool.
+			let mut embargo: HashMap<Hash, i64> = HashMap::new();
 			let run_interval = Duration::from_secs(10);
 			let mut last_run = Instant::now()
 				.checked_sub(Duration::from_secs(20))
@@ -63,6 +70,7 @@ pub fn monitor_transactions(
 							&tx_pool,
 							&adapter,
 							&verifier_cache,
+							&dandelion_stats,
 						)
 						.map_err(|e| {
 							error!("dand_mon: Problem processing fluff phase. {:?}", e);
@@ -70,7 +78,13 @@ pub fn monitor_transactions(
 					}
 
 					// Now find all expired entries based on embargo timer.
-					let _ = process_expired_entries(&dandelion_config, &tx_pool).map_err(|e| {
+					let _ = process_expired_entries(
+						&dandelion_config,
+						&tx_pool,
+						&mut embargo,
+						&dandelion_stats,
+					)
+					.map_err(|e| {
 						error!("dand_mon: Problem processing expired entries. {:?}", e);
 					});
 
@@ -90,7 +104,7 @@ pub fn monitor_transactions(
 }
 
 // Query the pool for transactions older than the cutoff.
-// Used for both periodic fluffing and handling expired embargo timer.
+// Used for periodic fluffing (embargo timers are tracked per tx).
 fn select_txs_cutoff(pool: &Pool, cutoff_secs: u16) -> Vec<PoolEntry> {
 	let cutoff = Utc::now().timestamp() - cutoff_secs as i64;
 	pool.entries
@@ -105,6 +119,7 @@ fn process_fluff_phase(
 	tx_pool: &Arc<RwLock<TransactionPool>>,
 	adapter: &Arc<dyn DandelionAdapter>,
 	verifier_cache: &Arc<RwLock<dyn VerifierCache>>,
+	dandelion_stats: &Arc<RwLock<DandelionStats>>,
 ) -> Result<(), PoolError> {
 	// Take a write lock on the txpool for the duration of this processing.
 	let mut tx_pool = tx_pool.write();
@@ -142,6 +157,7 @@ fn process_fluff_phase(
 		fluffable_txs.len()
 	);
 
+	let fluffed_count = fluffable_txs.len() as u64;
 	let agg_tx = transaction::aggregate(fluffable_txs)?;
 	// todo: put 'complete_inputs' parameter as None at this moment, to check whether need so many
 	//  duplications on this validation, since 'tx_pool.stempool.validate_raw_txs' just did it.
@@ -153,18 +169,61 @@ fn process_fluff_phase(
 	)?;
 
 	tx_pool.add_to_pool(TxSource::Fluff, agg_tx, false, &header)?;
+	dandelion_stats.write().fluffed += fluffed_count;
 	Ok(())
 }
 
+// Randomized embargo timer (in secs) for a single stem tx.
+// The configured embargo plus an exponentially distributed delay with the
+// configured mean, so the first node to fluff on embargo expiry does not
+// reveal its position on the stem.
+fn embargo_timer<R: Rng>(embargo_secs: u16, jitter_secs: u16, rng: &mut R) -> i64 {
+	let u: f64 = rng.gen_range(std::f64::EPSILON, 1.0);
+	let jitter = -(jitter_secs as f64) * u.ln();
+	embargo_secs as i64 + jitter.round() as i64
+}
+
 fn process_expired_entries(
 	dandelion_config: &DandelionConfig,
 	tx_pool: &Arc<RwLock<TransactionPool>>,
+	embargo: &mut HashMap<Hash, i64>,
+	dandelion_stats: &Arc<RwLock<DandelionStats>>,
 ) -> Result<(), PoolError> {
 	// Take a write lock on the txpool for the duration of this processing.
 	let mut tx_pool = tx_pool.write();
 
-	let embargo_secs = dandelion_config.embargo_secs + thread_rng().gen_range(0, 31);
-	let expired_entries = select_txs_cutoff(&tx_pool.stempool, embargo_secs);
+	// Forget timers for txs no longer in the stempool (fluffed, mined or evicted)
+	// and start a new timer for every tx we have not seen before.
+	let stem_hashes: HashSet<Hash> = tx_pool
+		.stempool
+		.entries
+		.iter()
+		.map(|x| x.tx.hash())
+		.collect();
+	embargo.retain(|h, _| stem_hashes.contains(h));
+
+	let mut rng = thread_rng();
+	for entry in &tx_pool.stempool.entries {
+		embargo.entry(entry.tx.hash()).or_insert_with(|| {
+			entry.tx_at.timestamp()
+				+ embargo_timer(
+					dandelion_config.embargo_secs,
+					dandelion_config.embargo_jitter_secs,
+					&mut rng,
+				)
+		});
+	}
+
+	let now = Utc::now().timestamp();
+	let expired_entries: Vec<PoolEntry> = tx_pool
+		.stempool
+		.entries
+		.iter()
+		.filter(|x| embargo.get(&x.tx.hash()).map_or(false, |t| *t <= now))
+		.cloned()
+		.collect();
+
+	dandelion_stats.write().embargo_pending = embargo.len() - expired_entries.len();
 
 	if expired_entries.is_empty() {
 		return Ok(());
@@ -177,12 +236,39 @@ fn process_expired_entries(
 	for entry in expired_entries {
 		let txhash = entry.tx.hash();
 		match tx_pool.add_to_pool(TxSource::EmbargoExpired, entry.tx, false, &header) {
-			Ok(_) => info!(
-				"dand_mon: embargo expired for {}, fluffed successfully.",
-				txhash
-			),
+			Ok(_) => {
+				info!(
+					"dand_mon: embargo expired for {}, fluffed successfully.",
+					txhash
+				);
+				embargo.remove(&txhash);
+				dandelion_stats.write().embargo_expired += 1;
+			}
 			Err(e) => warn!("dand_mon: failed to fluff expired tx {}, {:?}", txhash, e),
 		};
 	}
 	Ok(())
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_embargo_timer() {
+		let mut rng = thread_rng();
+
+		// No jitter configured, timer is exactly the embargo.
+		for _ in 0..100 {
+			assert_eq!(embargo_timer(180, 0, &mut rng), 180);
+		}
+
+		// Jitter is never negative and averages out around the configured mean.
+		let samples: Vec<i64> = (0..10_000)
+			.map(|_| embargo_timer(180, 30, &mut rng))
+			.collect();
+		assert!(samples.iter().all(|x| *x >= 180));
+		let mean = samples.iter().map(|x| x - 180).sum::<i64>() as f64 / samples.len() as f64;
+		assert!(mean > 25.0 && mean < 35.0);
+	}
+}
@@ -23,7 +23,7 @@ use chrono::{Duration, MIN_DATE};
 use rand::seq::SliceRandom;
 use rand::thread_rng;
 use std::collections::HashMap;
-use std::net::ToSocketAddrs;
+use std::net::{SocketAddr, ToSocketAddrs};
 use std::sync::{mpsc, Arc};
 use std::{cmp, str, thread, time};
 
@@ -41,6 +41,10 @@ const FLOONET_DNS_SEEDS: &'static [&'static str] = &[
 	"floonet.seed.gotts.tech", // gotts.tech@protonmail.com
 ];
 
+// Interval (in seconds) between feeler connections, testing an address of the
+// address manager new table once our outbound slots are full
+const FEELER_INTERVAL: i64 = 120;
+
 pub fn connect_and_monitor(
 	p2p_server: Arc<p2p::Server>,
 	capabilities: p2p::Capabilities,
@@ -68,6 +72,7 @@ pub fn connect_and_monitor(
 			let mut prev = MIN_DATE.and_hms(0, 0, 0);
 			let mut prev_expire_check = MIN_DATE.and_hms(0, 0, 0);
 			let mut prev_ping = Utc::now();
+			let mut prev_feeler = Utc::now();
 			let mut start_attempt = 0;
 			let mut connecting_history: HashMap<PeerAddr, DateTime<Utc>> = HashMap::new();
 
@@ -113,6 +118,15 @@ pub fn connect_and_monitor(
 					start_attempt = cmp::min(6, start_attempt + 1);
 				}
 
+				// Open a feeler connection from time to time, moving the address to
+				// the tried table if it works
+				if Utc::now() - prev_feeler > Duration::seconds(FEELER_INTERVAL) {
+					if peers.enough_outbound_peers() {
+						connect_feeler(peers.clone(), p2p_server.clone());
+					}
+					prev_feeler = Utc::now();
+				}
+
 				// Ping connected peers on every 10s to monitor peers.
 				if Utc::now() - prev_ping > Duration::seconds(10) {
 					let total_diff = peers.total_difficulty();
@@ -169,7 +183,7 @@ fn monitor_peers(
 		config.host,
 		config.port,
 		peers.peer_count(),
-		peers.most_work_peers().len(),
+		peers.most_work_peers(p2p::Capabilities::UNKNOWN).len(),
 		total_count,
 		healthy_count,
 		banned_count,
@@ -182,6 +196,10 @@ fn monitor_peers(
 		config.peer_max_outbound_count() as usize,
 	);
 
+	// persist what we learnt about addresses and our current anchors
+	peers.save_addrs();
+	peers.save_anchors();
+
 	if peers.enough_outbound_peers() {
 		return;
 	}
@@ -220,45 +238,61 @@ fn monitor_peers(
 		let _ = peers.update_state(defuncts[0].addr, p2p::State::Healthy);
 	}
 
-	// find some peers from our db
+	// pick some peers from the address manager
 	// and queue them up for a connection attempt
 	// intentionally make too many attempts (2x) as some (most?) will fail
 	// as many nodes in our db are not publicly accessible
 	let max_peer_attempts = 128;
-	let new_peers = peers.find_peers(
-		p2p::State::Healthy,
-		p2p::Capabilities::UNKNOWN,
-		max_peer_attempts as usize,
-	);
+	let new_peers = peers.select_outbound(max_peer_attempts);
 
-	for p in new_peers.iter().filter(|p| !peers.is_known(p.addr)) {
+	for addr in new_peers {
 		trace!(
 			"monitor_peers: on {}:{}, queue to soon try {}",
 			config.host,
 			config.port,
-			p.addr,
+			addr,
 		);
-		tx.send(p.addr).unwrap();
+		tx.send(addr).unwrap();
 	}
 }
 
-// Check if we have any pre-existing peer in db. If so, start with those,
-// otherwise use the seeds provided.
+// Connects to an address we never connected to, just to check it works, and
+// disconnects right away.
+fn connect_feeler(peers: Arc<p2p::Peers>, p2p: Arc<p2p::Server>) {
+	let addr = match peers.select_feeler() {
+		Some(addr) => addr,
+		None => return,
+	};
+	debug!("connect_feeler: testing {}", addr);
+	let _ = thread::Builder::new()
+		.name("peer_feeler".to_string())
+		.spawn(move || match p2p.connect(addr) {
+			Ok(_) => {
+				debug!("connect_feeler: {} is reachable", addr);
+				peers.disconnect_peer(addr);
+			}
+			Err(e) => debug!("connect_feeler: {} unreachable: {:?}", addr, e),
+		});
+}
+
+// Reconnect to our anchors first. Then check if we have any pre-existing peer
+// in db. If so, start with those, otherwise use the seeds provided.
 fn connect_to_seeds_and_preferred_peers(
 	peers: Arc<p2p::Peers>,
 	tx: mpsc::Sender<PeerAddr>,
 	seed_list: Box<dyn Fn() -> Vec<PeerAddr>>,
 	peers_preferred_list: Option<Vec<PeerAddr>>,
 ) {
+	let mut peer_addrs = peers.anchors();
+
 	// check if we have some peers in db
-	// look for peers that are able to give us other peers (via PEER_LIST capability)
-	let peers = peers.find_peers(p2p::State::Healthy, p2p::Capabilities::PEER_LIST, 100);
+	let known_addrs = peers.select_outbound(100);
 
 	// if so, get their addresses, otherwise use our seeds
-	let mut peer_addrs = if peers.len() > 3 {
-		peers.iter().map(|p| p.addr).collect::<Vec<_>>()
+	if known_addrs.len() > 3 {
+		peer_addrs.extend(known_addrs.into_iter().filter(|a| !peer_addrs.contains(a)));
 	} else {
-		seed_list()
+		peer_addrs.append(&mut seed_list());
 	};
 
 	// If we have preferred peers add them to the connection
@@ -353,8 +387,10 @@ fn listen_for_addrs(
 	}
 }
 
-pub fn dns_seeds() -> Box<dyn Fn() -> Vec<PeerAddr> + Send> {
-	Box::new(|| {
+/// Resolves our DNS seeds, through the SOCKS5 proxy if one is provided (it
+/// then has to support the Tor RESOLVE extension).
+pub fn dns_seeds(proxy: Option<SocketAddr>) -> Box<dyn Fn() -> Vec<PeerAddr> + Send> {
+	Box::new(move || {
 		let mut addresses: Vec<PeerAddr> = vec![];
 		let net_seeds = if global::is_floonet() {
 			FLOONET_DNS_SEEDS
@@ -364,10 +400,18 @@ pub fn dns_seeds() -> Box<dyn Fn() -> Vec<PeerAddr> + Send> {
 		for dns_seed in net_seeds {
 			let temp_addresses = addresses.clone();
 			debug!("Retrieving seed nodes from dns {}", dns_seed);
-			match (dns_seed.to_owned(), 0).to_socket_addrs() {
+			let resolved = match proxy {
+				Some(proxy) => p2p::socks::resolve(proxy, dns_seed, time::Duration::from_secs(10))
+					.map(|ip| vec![SocketAddr::new(ip, 0)]),
+				None => (dns_seed.to_owned(), 0)
+					.to_socket_addrs()
+					.map(|addrs| addrs.collect()),
+			};
+			match resolved {
 				Ok(addrs) => addresses.append(
 					&mut (addrs
-						.map(|mut addr| {
+						.into_iter()
+						.map(|mut addr: SocketAddr| {
 							addr.set_port(if global::is_floonet() { 13514 } else { 3514 });
 							PeerAddr(addr)
 						})
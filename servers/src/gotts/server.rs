@@ -28,14 +28,17 @@ use std::{
 };
 
 use fs2::FileExt;
+use rand::distributions::{Alphanumeric, Distribution};
+use rand::thread_rng;
 
 use crate::api;
 use crate::api::TLSConfig;
-use crate::chain::{self, SyncState, SyncStatus};
+use crate::chain::{self, KernelProof, OutputProof, SyncState, SyncStatus};
 use crate::common::adapters::{
 	ChainToPoolAndNetAdapter, NetToChainAdapter, PoolToChainAdapter, PoolToNetAdapter,
 };
-use crate::common::hooks::{init_chain_hooks, init_net_hooks};
+use crate::common::hooks::{init_chain_hooks, init_net_hooks, init_pool_hooks};
+use crate::common::light::{LightClient, NodeProofs};
 use crate::common::stats::{DiffBlock, DiffStats, PeerStats, ServerStateInfo, ServerStats};
 use crate::common::types::{Error, ServerConfig, StratumServerConfig};
 use crate::core::core::hash::{Hashed, ZERO_HASH};
@@ -49,6 +52,7 @@ use crate::p2p;
 use crate::p2p::types::PeerAddr;
 use crate::pool;
 use crate::util::file::get_first_line;
+use crate::util::secp::pedersen::Commitment;
 use crate::util::{RwLock, StopState};
 
 /// Gotts server holding internal structures.
@@ -66,6 +70,9 @@ pub struct Server {
 	verifier_cache: Arc<RwLock<dyn VerifierCache>>,
 	/// Whether we're currently syncing
 	sync_state: Arc<SyncState>,
+	/// Outputs and kernels with merkle proofs, the APIs only holding a weak
+	/// reference
+	proofs: Arc<NodeProofs>,
 	/// To be passed around to collect stats and info
 	state_info: ServerStateInfo,
 	/// Stop flag
@@ -85,8 +92,14 @@ impl Server {
 	where
 		F: FnMut(Server),
 	{
-		let mining_config = config.stratum_mining_config.clone();
-		let enable_test_miner = config.run_test_miner;
+		// light nodes have no utxo set to mine on
+		let light_mode = config.light_mode.unwrap_or(false);
+		let mining_config = if light_mode {
+			None
+		} else {
+			config.stratum_mining_config.clone()
+		};
+		let enable_test_miner = config.run_test_miner.filter(|_| !light_mode);
 		let test_miner_wallet_url = config.test_miner_wallet_url.clone();
 		let serv = Server::new(config)?;
 
@@ -138,10 +151,37 @@ impl Server {
 		Ok(Arc::new(lock_file))
 	}
 
+	// The owner API fails closed: its secret file is generated when missing and
+	// the server refuses to start if no secret can be read from it.
+	fn owner_api_secret(config: &ServerConfig) -> Result<String, Error> {
+		let path = config.owner_api_secret_path.clone().ok_or_else(|| {
+			Error::Configuration("owner_api_secret_path is required by the owner API".to_owned())
+		})?;
+		if !Path::new(&path).exists() {
+			let secret: String = Alphanumeric
+				.sample_iter(&mut thread_rng())
+				.take(20)
+				.collect();
+			File::create(&path)?.write_all(secret.as_bytes())?;
+			info!("Generated owner API secret in {}", path);
+		}
+		match get_first_line(Some(path.clone())) {
+			Some(secret) if !secret.trim().is_empty() => Ok(secret),
+			_ => Err(Error::Configuration(format!(
+				"no owner API secret could be read from {}",
+				path
+			))),
+		}
+	}
+
 	/// Instantiates a new server associated with the provided future reactor.
 	pub fn new(config: ServerConfig) -> Result<Server, Error> {
 		// Obtain our lock_file or fail immediately with an error.
 		let lock_file = Server::one_gotts_at_a_time(&config)?;
+		let owner_api_secret = match config.owner_api_http_addr {
+			Some(_) => Some(Server::owner_api_secret(&config)?),
+			None => None,
+		};
 
 		// Defaults to None (optional) in config file.
 		// This translates to false here.
@@ -153,6 +193,10 @@ impl Server {
 			None => false,
 			Some(b) => b,
 		};
+		let light_mode = config.light_mode.unwrap_or(false);
+		if light_mode {
+			warn!("Light mode, only syncing the block headers");
+		}
 
 		let stop_state = Arc::new(StopState::new());
 
@@ -160,8 +204,20 @@ impl Server {
 		// We cache rangeproof verification and kernel signature verification.
 		let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
 
+		// To be passed around to collect stats and info.
+		let state_info = ServerStateInfo {
+			..Default::default()
+		};
+
+		// Node events, pushed to the WebSocket subscribers of the API.
+		let event_hub = Arc::new(api::ws::EventHub::new());
+
 		let pool_adapter = Arc::new(PoolToChainAdapter::new());
-		let pool_net_adapter = Arc::new(PoolToNetAdapter::new(config.dandelion_config.clone()));
+		let pool_net_adapter = Arc::new(PoolToNetAdapter::new(
+			config.dandelion_config.clone(),
+			state_info.dandelion_stats.clone(),
+			init_pool_hooks(&event_hub),
+		));
 		let tx_pool = Arc::new(RwLock::new(pool::TransactionPool::new(
 			config.pool_config.clone(),
 			pool_adapter.clone(),
@@ -173,7 +229,7 @@ impl Server {
 
 		let chain_adapter = Arc::new(ChainToPoolAndNetAdapter::new(
 			tx_pool.clone(),
-			init_chain_hooks(&config),
+			init_chain_hooks(&config, &event_hub),
 		));
 
 		let genesis = match config.chain_type {
@@ -201,18 +257,28 @@ impl Server {
 
 		pool_adapter.set_chain(shared_chain.clone());
 
+		let light_client = Arc::new(LightClient::new());
+
 		let net_adapter = Arc::new(NetToChainAdapter::new(
 			sync_state.clone(),
 			shared_chain.clone(),
 			tx_pool.clone(),
 			verifier_cache.clone(),
 			config.clone(),
-			init_net_hooks(&config),
+			init_net_hooks(&config, &event_hub),
+			state_info.compact_block_stats.clone(),
+			light_client.clone(),
 		));
 
+		// a light node can only provide the header chain (and peers)
+		let capabilities = if light_mode {
+			p2p::Capabilities::HEADER_HIST | p2p::Capabilities::PEER_LIST
+		} else {
+			config.p2p_config.capabilities
+		};
 		let p2p_server = Arc::new(p2p::Server::new(
 			&config.db_root,
-			config.p2p_config.capabilities,
+			capabilities,
 			config.p2p_config.clone(),
 			net_adapter.clone(),
 			genesis.hash(),
@@ -240,7 +306,7 @@ impl Server {
 						));
 					}
 				},
-				p2p::Seeding::DNSSeed => seed::dns_seeds(),
+				p2p::Seeding::DNSSeed => seed::dns_seeds(config.p2p_config.socks5_proxy),
 				_ => unreachable!(),
 			};
 
@@ -262,6 +328,8 @@ impl Server {
 			sync_state.clone(),
 			p2p_server.peers.clone(),
 			shared_chain.clone(),
+			config.p2p_config.clone(),
+			light_mode,
 			stop_state.clone(),
 		)?;
 
@@ -274,8 +342,27 @@ impl Server {
 				}
 			})?;
 
-		info!("Starting rest apis at: {}", &config.api_http_addr);
+		let block_txs_adapter = net_adapter.clone();
+		let block_txs_stop_state = stop_state.clone();
+		let _ = thread::Builder::new()
+			.name("block_txs".to_string())
+			.spawn(move || {
+				while !block_txs_stop_state.is_stopped() {
+					thread::sleep(time::Duration::from_secs(1));
+					block_txs_adapter.check_pending_block_txs();
+				}
+			})?;
+
+		info!("Starting foreign rest apis at: {}", &config.api_http_addr);
 		let api_secret = get_first_line(config.api_secret_path.clone());
+		let api_access = api::ApiAccessConfig {
+			tokens: match config.api_tokens_path {
+				Some(ref path) => Some(api::load_api_tokens(path)?),
+				None => None,
+			},
+			rate_limit_per_ip: config.api_rate_limit_per_ip,
+			rate_limit_per_token: config.api_rate_limit_per_token,
+		};
 
 		let tls_conf = match config.tls_certificate_file.clone() {
 			None => None,
@@ -291,22 +378,49 @@ impl Server {
 			}
 		};
 
+		// light nodes ask their peers for the outputs and kernels
+		let proofs = Arc::new(NodeProofs::new(
+			shared_chain.clone(),
+			p2p_server.peers.clone(),
+			Some(light_client).filter(|_| light_mode),
+		));
+
 		// TODO fix API shutdown and join this thread
 		api::start_rest_apis(
 			config.api_http_addr.clone(),
 			shared_chain.clone(),
 			tx_pool.clone(),
 			p2p_server.peers.clone(),
+			proofs.clone(),
+			event_hub.clone(),
 			api_secret,
-			tls_conf,
+			api_access.clone(),
+			tls_conf.clone(),
 		);
 
+		if let Some(ref owner_api_http_addr) = config.owner_api_http_addr {
+			info!("Starting owner rest apis at: {}", owner_api_http_addr);
+			api::start_owner_rest_apis(
+				owner_api_http_addr.clone(),
+				shared_chain.clone(),
+				tx_pool.clone(),
+				p2p_server.peers.clone(),
+				sync_state.clone(),
+				p2p_server.traffic_stats(),
+				event_hub.clone(),
+				owner_api_secret,
+				api_access,
+				tls_conf,
+			);
+		}
+
 		info!("Starting dandelion monitor: {}", &config.api_http_addr);
 		let dandelion_thread = dandelion_monitor::monitor_transactions(
 			config.dandelion_config.clone(),
 			tx_pool.clone(),
 			pool_net_adapter.clone(),
 			verifier_cache.clone(),
+			state_info.dandelion_stats.clone(),
 			stop_state.clone(),
 		)?;
 
@@ -318,9 +432,8 @@ impl Server {
 			tx_pool,
 			verifier_cache,
 			sync_state,
-			state_info: ServerStateInfo {
-				..Default::default()
-			},
+			proofs,
+			state_info,
 			stop_state,
 			lock_file,
 			connect_thread,
@@ -329,6 +442,21 @@ impl Server {
 		})
 	}
 
+	/// Unspent output with that commitment and its merkle proof against our
+	/// header chain. Light nodes ask their peers, verifying the proofs they
+	/// send back. None if the output isn't unspent (or no peer has it).
+	/// Also served on the foreign API.
+	pub fn get_output_proof(&self, commit: Commitment) -> Result<Option<OutputProof>, Error> {
+		self.proofs.output_proof(commit)
+	}
+
+	/// Tx kernel with that excess and its merkle proof against our header
+	/// chain. Light nodes ask their peers, verifying the proofs they send
+	/// back. None if the kernel isn't found. Also served on the foreign API.
+	pub fn get_kernel_proof(&self, excess: Commitment) -> Result<Option<KernelProof>, Error> {
+		self.proofs.kernel_proof(excess)
+	}
+
 	/// Asks the server to connect to a peer at the provided network address.
 	pub fn connect_peer(&self, addr: PeerAddr) -> Result<(), Error> {
 		self.p2p.connect(addr)?;
@@ -427,6 +555,8 @@ impl Server {
 	/// other consumers
 	pub fn get_server_stats(&self) -> Result<ServerStats, Error> {
 		let stratum_stats = self.state_info.stratum_stats.read().clone();
+		let dandelion_stats = self.state_info.dandelion_stats.read().clone();
+		let compact_block_stats = self.state_info.compact_block_stats.read().clone();
 
 		// Fill out stats on our current difficulty calculation
 		// TODO: check the overhead of calculating this again isn't too much
@@ -503,6 +633,8 @@ impl Server {
 			stratum_stats: stratum_stats,
 			peer_stats: peer_stats,
 			diff_stats: diff_stats,
+			dandelion_stats: dandelion_stats,
+			compact_block_stats: compact_block_stats,
 		})
 	}
 
@@ -15,6 +15,7 @@
 
 //! Syncing of the chain with the rest of the network
 
+mod block_download;
 mod body_sync;
 mod header_sync;
 mod state_sync;
@@ -0,0 +1,297 @@
+// Copyright 2019 The Gotts Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Book keeping of the block requests in flight during body sync: which peer
+//! was asked for which block and when, and how fast each peer delivers, so
+//! requests go to the fastest peers and the ones timing out are reassigned.
+
+use chrono::prelude::{DateTime, Utc};
+use chrono::Duration;
+use std::collections::HashMap;
+
+use crate::core::core::hash::Hash;
+use crate::p2p::PeerAddr;
+
+/// Latency assumed for a peer we haven't received any block from yet, in
+/// milliseconds. Optimistic enough for new peers to get some requests.
+const DEFAULT_LATENCY_MS: f64 = 1000.0;
+
+/// Weight of the latest block latency in the per peer moving average.
+const LATENCY_WEIGHT: f64 = 0.2;
+
+/// A block we asked a peer for.
+#[derive(Debug, Clone)]
+struct BlockRequest {
+	peer: PeerAddr,
+	height: u64,
+	time: DateTime<Utc>,
+}
+
+/// Download statistics of a peer.
+#[derive(Debug, Clone, Default)]
+pub struct PeerDownloadStats {
+	/// Requests currently in flight
+	pub in_flight: usize,
+	/// Blocks received from this peer
+	pub received: u64,
+	/// Requests which timed out, each block delivered since clearing one
+	pub timeouts: u64,
+	/// Moving average of the time to receive a block, in milliseconds
+	pub latency_ms: Option<f64>,
+}
+
+impl PeerDownloadStats {
+	/// Blocks per second we can expect from this peer.
+	pub fn throughput(&self) -> f64 {
+		1000.0 / self.latency_ms.unwrap_or(DEFAULT_LATENCY_MS)
+	}
+}
+
+/// Block download manager for body sync.
+pub struct BlockDownloader {
+	peer_max_requests: usize,
+	timeout: Duration,
+	requests: HashMap<Hash, BlockRequest>,
+	peers: HashMap<PeerAddr, PeerDownloadStats>,
+}
+
+impl BlockDownloader {
+	/// New download manager, with at most peer_max_requests in flight with
+	/// each peer and requests expiring after timeout_secs.
+	pub fn new(peer_max_requests: usize, timeout_secs: u64) -> BlockDownloader {
+		BlockDownloader {
+			peer_max_requests: peer_max_requests.max(1),
+			timeout: Duration::seconds(timeout_secs as i64),
+			requests: HashMap::new(),
+			peers: HashMap::new(),
+		}
+	}
+
+	/// Number of requests in flight.
+	pub fn in_flight(&self) -> usize {
+		self.requests.len()
+	}
+
+	/// Whether that block has been requested and not received yet.
+	pub fn is_requested(&self, hash: &Hash) -> bool {
+		self.requests.contains_key(hash)
+	}
+
+	/// The peer a block was requested from and when.
+	pub fn request(&self, hash: &Hash) -> Option<(PeerAddr, DateTime<Utc>)> {
+		self.requests.get(hash).map(|r| (r.peer, r.time))
+	}
+
+	/// The hashes of the blocks requested and not received yet, with their
+	/// heights.
+	pub fn requested(&self) -> Vec<(Hash, u64)> {
+		self.requests.iter().map(|(h, r)| (*h, r.height)).collect()
+	}
+
+	/// The peers we sent requests to.
+	pub fn peers(&self) -> Vec<PeerAddr> {
+		self.peers.keys().cloned().collect()
+	}
+
+	/// Download statistics of a peer.
+	pub fn peer_stats(&self, peer: &PeerAddr) -> Option<&PeerDownloadStats> {
+		self.peers.get(peer)
+	}
+
+	/// Number of requests we can still send to that peer. Peers timing out
+	/// get fewer requests, until they deliver again.
+	pub fn free_slots(&self, peer: &PeerAddr) -> usize {
+		let stats = self.peers.get(peer).cloned().unwrap_or_default();
+		let max = (self.peer_max_requests >> stats.timeouts.min(16) as usize).max(1);
+		max.saturating_sub(stats.in_flight)
+	}
+
+	/// Among the candidates, the peer with a free slot expected to deliver
+	/// first, considering its throughput and the requests it already has.
+	pub fn select_peer(
+		&self,
+		candidates: &[PeerAddr],
+		exclude: Option<PeerAddr>,
+	) -> Option<PeerAddr> {
+		candidates
+			.iter()
+			.filter(|p| Some(**p) != exclude && self.free_slots(p) > 0)
+			.map(|p| {
+				let stats = self.peers.get(p).cloned().unwrap_or_default();
+				let wait = (stats.in_flight + 1) as f64 / stats.throughput();
+				(*p, wait)
+			})
+			.min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+			.map(|(p, _)| p)
+	}
+
+	/// Records the request of a block to a peer. A previous request for the
+	/// same block is forgotten.
+	pub fn requested_from(&mut self, hash: Hash, height: u64, peer: PeerAddr, now: DateTime<Utc>) {
+		self.forget(&hash);
+		self.peers.entry(peer).or_default().in_flight += 1;
+		self.requests.insert(
+			hash,
+			BlockRequest {
+				peer,
+				height,
+				time: now,
+			},
+		);
+	}
+
+	/// The block has been received, the peer it was requested from is
+	/// credited with it and recovers from one of its timeouts.
+	pub fn received(&mut self, hash: &Hash, now: DateTime<Utc>) {
+		if let Some(req) = self.requests.remove(hash) {
+			let stats = self.peers.entry(req.peer).or_default();
+			stats.in_flight = stats.in_flight.saturating_sub(1);
+			stats.received += 1;
+			stats.timeouts = stats.timeouts.saturating_sub(1);
+			let latency = (now - req.time).num_milliseconds().max(1) as f64;
+			stats.latency_ms = Some(match stats.latency_ms {
+				Some(avg) => avg * (1.0 - LATENCY_WEIGHT) + latency * LATENCY_WEIGHT,
+				None => latency,
+			});
+		}
+	}
+
+	/// Removes the requests which timed out, returning their blocks to be
+	/// requested again from other peers.
+	pub fn expire(&mut self, now: DateTime<Utc>) -> Vec<(Hash, u64)> {
+		let expired: Vec<_> = self
+			.requests
+			.iter()
+			.filter(|(_, r)| now - r.time > self.timeout)
+			.map(|(h, r)| (*h, r.height, r.peer))
+			.collect();
+		for (hash, _, peer) in &expired {
+			self.forget(hash);
+			self.peers.entry(*peer).or_default().timeouts += 1;
+		}
+		expired
+			.into_iter()
+			.map(|(h, height, _)| (h, height))
+			.collect()
+	}
+
+	/// Marks the request of a block as stalling the download, the block
+	/// being requested again from another peer. The peer is penalized as if
+	/// the request timed out.
+	pub fn stalled(&mut self, hash: &Hash) {
+		if let Some(req) = self.requests.get(hash) {
+			let peer = req.peer;
+			self.forget(hash);
+			self.peers.entry(peer).or_default().timeouts += 1;
+		}
+	}
+
+	/// Forgets a peer (disconnected), returning the blocks it was asked for.
+	pub fn remove_peer(&mut self, peer: &PeerAddr) -> Vec<(Hash, u64)> {
+		let hashes: Vec<_> = self
+			.requests
+			.iter()
+			.filter(|(_, r)| r.peer == *peer)
+			.map(|(h, r)| (*h, r.height))
+			.collect();
+		for (hash, _) in &hashes {
+			self.requests.remove(hash);
+		}
+		self.peers.remove(peer);
+		hashes
+	}
+
+	/// Forgets all the requests, keeping the peer statistics.
+	pub fn clear(&mut self) {
+		self.requests.clear();
+		for stats in self.peers.values_mut() {
+			stats.in_flight = 0;
+		}
+	}
+
+	fn forget(&mut self, hash: &Hash) {
+		if let Some(req) = self.requests.remove(hash) {
+			if let Some(stats) = self.peers.get_mut(&req.peer) {
+				stats.in_flight = stats.in_flight.saturating_sub(1);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn hash(n: u8) -> Hash {
+		Hash::from_vec(&[n])
+	}
+
+	fn peer(port: u16) -> PeerAddr {
+		PeerAddr(format!("127.0.0.1:{}", port).parse().unwrap())
+	}
+
+	#[test]
+	fn fastest_peer_with_free_slots() {
+		let mut downloader = BlockDownloader::new(2, 10);
+		let (fast, slow) = (peer(1), peer(2));
+		let now = Utc::now();
+
+		downloader.requested_from(hash(1), 1, fast, now);
+		downloader.requested_from(hash(2), 2, slow, now);
+		downloader.received(&hash(1), now + Duration::milliseconds(100));
+		downloader.received(&hash(2), now + Duration::milliseconds(3000));
+		assert_eq!(downloader.peer_stats(&fast).unwrap().received, 1);
+		assert_eq!(downloader.select_peer(&[slow, fast], None), Some(fast));
+		assert_eq!(
+			downloader.select_peer(&[slow, fast], Some(fast)),
+			Some(slow)
+		);
+
+		downloader.requested_from(hash(3), 3, fast, now);
+		downloader.requested_from(hash(4), 4, fast, now);
+		assert_eq!(downloader.free_slots(&fast), 0);
+		assert_eq!(downloader.select_peer(&[slow, fast], None), Some(slow));
+		assert_eq!(downloader.in_flight(), 2);
+	}
+
+	#[test]
+	fn timed_out_requests_reassigned() {
+		let mut downloader = BlockDownloader::new(4, 10);
+		let (p1, p2) = (peer(1), peer(2));
+		let now = Utc::now();
+
+		downloader.requested_from(hash(1), 1, p1, now);
+		downloader.requested_from(hash(2), 2, p2, now + Duration::seconds(8));
+		let expired = downloader.expire(now + Duration::seconds(11));
+		assert_eq!(expired, vec![(hash(1), 1)]);
+		assert!(!downloader.is_requested(&hash(1)));
+		assert_eq!(downloader.peer_stats(&p1).unwrap().timeouts, 1);
+		assert_eq!(downloader.free_slots(&p1), 2);
+
+		// requesting it again elsewhere
+		downloader.requested_from(hash(1), 1, p2, now + Duration::seconds(11));
+		assert_eq!(downloader.request(&hash(1)).unwrap().0, p2);
+		downloader.stalled(&hash(1));
+		assert_eq!(downloader.peer_stats(&p2).unwrap().in_flight, 1);
+
+		assert_eq!(downloader.remove_peer(&p2), vec![(hash(2), 2)]);
+		assert_eq!(downloader.in_flight(), 0);
+
+		// delivering again lifts the penalty
+		downloader.requested_from(hash(3), 3, p1, now + Duration::seconds(12));
+		downloader.received(&hash(3), now + Duration::seconds(13));
+		assert_eq!(downloader.peer_stats(&p1).unwrap().timeouts, 0);
+		assert_eq!(downloader.free_slots(&p1), 4);
+	}
+}
@@ -15,22 +15,37 @@
 
 use chrono::prelude::{DateTime, Utc};
 use chrono::Duration;
-use std::cmp;
 use std::sync::Arc;
 
 use crate::chain::{self, SyncState, SyncStatus};
 use crate::core::core::hash::Hash;
-use crate::p2p;
+use crate::gotts::sync::block_download::BlockDownloader;
+use crate::p2p::{self, Capabilities, PeerAddr};
 
+/// How often we look for received blocks and send new requests.
+const BODY_SYNC_TICK_MS: i64 = 100;
+
+/// How often we refresh the list of blocks to download from the header chain.
+const BODY_SYNC_REFRESH_SECS: i64 = 2;
+
+/// Body sync requests the blocks we have the headers of in parallel from all
+/// the peers with more work, within a window ahead of our head. Each peer gets
+/// a limited number of requests in flight, the fastest peers first. Requests
+/// timing out are reassigned, as well as the request of the next block to
+/// apply when it holds back the whole window.
 pub struct BodySync {
 	chain: Arc<chain::Chain>,
 	peers: Arc<p2p::Peers>,
 	sync_state: Arc<SyncState>,
 
-	blocks_requested: u64,
+	window: u64,
+	downloader: BlockDownloader,
+	stall_timeout: Duration,
 
-	receive_timeout: DateTime<Utc>,
-	prev_blocks_received: u64,
+	// blocks to download (hash and height), by increasing height
+	to_download: Vec<(Hash, u64)>,
+	prev_refresh: Option<DateTime<Utc>>,
+	prev_tick: Option<DateTime<Utc>>,
 }
 
 impl BodySync {
@@ -38,14 +53,19 @@ impl BodySync {
 		sync_state: Arc<SyncState>,
 		peers: Arc<p2p::Peers>,
 		chain: Arc<chain::Chain>,
+		config: &p2p::P2PConfig,
 	) -> BodySync {
+		let timeout = config.block_request_timeout();
 		BodySync {
 			sync_state,
 			peers,
 			chain,
-			blocks_requested: 0,
-			receive_timeout: Utc::now(),
-			prev_blocks_received: 0,
+			window: config.block_download_window().max(1),
+			downloader: BlockDownloader::new(config.peer_max_block_requests() as usize, timeout),
+			stall_timeout: Duration::seconds((timeout / 3).max(1) as i64),
+			to_download: vec![],
+			prev_refresh: None,
+			prev_tick: None,
 		}
 	}
 
@@ -56,22 +76,39 @@ impl BodySync {
 		head: &chain::Tip,
 		highest_height: u64,
 	) -> Result<bool, chain::Error> {
-		// run the body_sync every 5s
-		if self.body_sync_due()? {
-			if self.body_sync()? {
-				return Ok(true);
+		let now = Utc::now();
+		if let Some(prev) = self.prev_tick {
+			if now - prev < Duration::milliseconds(BODY_SYNC_TICK_MS) {
+				return Ok(false);
 			}
+		}
+		self.prev_tick = Some(now);
 
-			self.sync_state.update(SyncStatus::BodySync {
-				current_height: head.height,
-				highest_height: highest_height,
-			});
+		let refresh_due = match self.prev_refresh {
+			Some(prev) => now - prev > Duration::seconds(BODY_SYNC_REFRESH_SECS),
+			None => true,
+		};
+		if refresh_due || self.to_download.is_empty() {
+			self.prev_refresh = Some(now);
+			self.log_peer_stats();
+			if self.refresh()? {
+				self.downloader.clear();
+				return Ok(true);
+			}
 		}
+
+		self.body_sync(now)?;
+
+		self.sync_state.update(SyncStatus::BodySync {
+			current_height: head.height,
+			highest_height: highest_height,
+		});
 		Ok(false)
 	}
 
+	/// Refreshes the list of blocks to download.
 	/// Return true if txhashset download is needed (when requested block is under the horizon).
-	fn body_sync(&mut self) -> Result<bool, chain::Error> {
+	fn refresh(&mut self) -> Result<bool, chain::Error> {
 		let mut hashes: Option<Vec<Hash>> = Some(vec![]);
 		let txhashset_needed = match self
 			.chain
@@ -98,99 +135,148 @@ impl BodySync {
 			}
 		};
 
+		// the hashes go down the header chain, one block at a time
 		hashes.reverse();
+		self.to_download = match hashes.first() {
+			Some(first) => {
+				let first_height = self.chain.get_block_header(first)?.height;
+				hashes
+					.into_iter()
+					.enumerate()
+					.map(|(i, h)| (h, first_height + i as u64))
+					.collect()
+			}
+			None => vec![],
+		};
+		Ok(false)
+	}
 
-		let peers = self.peers.more_work_peers()?;
-
-		// if we have 5 peers to sync from then ask for 50 blocks total (peer_count *
-		// 10) max will be 80 if all 8 peers are advertising more work
-		// also if the chain is already saturated with orphans, throttle
-		let block_count = cmp::min(
-			cmp::min(100, peers.len() * 10),
-			chain::MAX_ORPHAN_SIZE.saturating_sub(self.chain.orphans_len()) + 1,
-		);
-
-		let hashes_to_get = hashes
-			.iter()
-			.filter(|x| {
-				// only ask for blocks that we have not yet processed
-				// either successfully stored or in our orphan list
-				!self.chain.get_block(x).is_ok() && !self.chain.is_orphan(x)
-			})
-			.take(block_count)
-			.collect::<Vec<_>>();
-
-		if hashes_to_get.len() > 0 {
-			let body_head = self.chain.head()?;
-			let header_head = self.chain.header_head()?;
+	fn body_sync(&mut self, now: DateTime<Utc>) -> Result<(), chain::Error> {
+		// blocks received since last time
+		for (hash, _) in self.downloader.requested() {
+			if self.have_block(&hash) {
+				self.downloader.received(&hash, now);
+			}
+		}
 
+		// requests of the peers gone or timed out, to send again
+		for peer in self.downloader.peers() {
+			let connected = self
+				.peers
+				.get_connected_peer(peer)
+				.map(|p| p.is_connected())
+				.unwrap_or(false);
+			if !connected {
+				self.downloader.remove_peer(&peer);
+			}
+		}
+		for (hash, height) in self.downloader.expire(now) {
 			debug!(
-				"block_sync: {}/{} requesting blocks {:?} from {} peers",
-				body_head.height,
-				header_head.height,
-				hashes_to_get,
-				peers.len(),
+				"body_sync: request for block {} at {} timed out",
+				hash, height
 			);
+		}
+
+		let body_head = self.chain.head()?;
+		let max_height = body_head.height + self.window;
+		self.to_download
+			.retain(|(_, height)| *height > body_head.height);
+
+		let peers = self.peers.more_work_peers(Capabilities::FULL_NODE)?;
+		if peers.is_empty() {
+			return Ok(());
+		}
+		let candidates: Vec<PeerAddr> = peers.iter().map(|p| p.info.addr).collect();
+
+		// if the chain is already saturated with orphans, throttle
+		let mut budget = chain::MAX_ORPHAN_SIZE
+			.saturating_sub(self.chain.orphans_len() + self.downloader.in_flight())
+			+ 1;
+
+		let mut to_request = vec![];
+		let mut window_full = true;
+		for (hash, height) in self.to_download.iter().cloned() {
+			if height > max_height {
+				break;
+			}
+			if self.downloader.is_requested(&hash) || self.have_block(&hash) {
+				continue;
+			}
+			window_full = false;
+			if to_request.len() >= budget {
+				break;
+			}
+			to_request.push((hash, height, None));
+		}
 
-			// reinitialize download tracking state
-			self.blocks_requested = 0;
-			self.receive_timeout = Utc::now() + Duration::seconds(6);
-
-			let mut peers_iter = peers.iter().cycle();
-			for hash in hashes_to_get.clone() {
-				if let Some(peer) = peers_iter.next() {
-					if let Err(e) = peer.send_block_request(*hash) {
-						debug!("Skipped request to {}: {:?}", peer.info.addr, e);
-						peer.stop();
-					} else {
-						self.blocks_requested += 1;
+		// the next block to apply holding back the whole window, ask someone
+		// else than the (slow) peer we asked first
+		if window_full {
+			if let Some((hash, height)) = self.to_download.first().cloned() {
+				if let Some((peer, time)) = self.downloader.request(&hash) {
+					if now - time > self.stall_timeout && candidates.len() > 1 {
+						debug!(
+							"body_sync: block {} at {} from {} stalling the download",
+							hash, height, peer
+						);
+						self.downloader.stalled(&hash);
+						to_request.insert(0, (hash, height, Some(peer)));
+						budget += 1;
 					}
 				}
 			}
 		}
-		return Ok(false);
-	}
-
-	// Should we run block body sync and ask for more full blocks?
-	fn body_sync_due(&mut self) -> Result<bool, chain::Error> {
-		let blocks_received = self.blocks_received()?;
 
-		// some blocks have been requested
-		if self.blocks_requested > 0 {
-			// but none received since timeout, ask again
-			let timeout = Utc::now() > self.receive_timeout;
-			if timeout && blocks_received <= self.prev_blocks_received {
-				debug!(
-					"body_sync: expecting {} more blocks and none received for a while",
-					self.blocks_requested,
-				);
-				return Ok(true);
+		let mut requested = 0;
+		for (hash, height, exclude) in to_request.into_iter().take(budget) {
+			let addr = match self.downloader.select_peer(&candidates, exclude) {
+				Some(addr) => addr,
+				None => break,
+			};
+			let peer = match peers.iter().find(|p| p.info.addr == addr) {
+				Some(peer) => peer,
+				None => break,
+			};
+			if let Err(e) = peer.send_block_request(hash) {
+				debug!("Skipped request to {}: {:?}", peer.info.addr, e);
+				peer.stop();
+				break;
 			}
+			self.downloader.requested_from(hash, height, addr, now);
+			requested += 1;
 		}
 
-		if blocks_received > self.prev_blocks_received {
-			// some received, update for next check
-			self.receive_timeout = Utc::now() + Duration::seconds(1);
-			self.blocks_requested = self
-				.blocks_requested
-				.saturating_sub(blocks_received - self.prev_blocks_received);
-			self.prev_blocks_received = blocks_received;
+		if requested > 0 {
+			let header_head = self.chain.header_head()?;
+			debug!(
+				"body_sync: {}/{} requested {} blocks, {} in flight, from {} peers",
+				body_head.height,
+				header_head.height,
+				requested,
+				self.downloader.in_flight(),
+				peers.len(),
+			);
 		}
+		Ok(())
+	}
 
-		// off by one to account for broadcast adding a couple orphans
-		if self.blocks_requested < 2 {
-			// no pending block requests, ask more
-			debug!("body_sync: no pending block request, asking more");
-			return Ok(true);
+	fn log_peer_stats(&self) {
+		for peer in self.downloader.peers() {
+			if let Some(stats) = self.downloader.peer_stats(&peer) {
+				debug!(
+					"body_sync: peer {}: {} blocks received, {} in flight, {} timeouts, {:.1} blocks/s",
+					peer,
+					stats.received,
+					stats.in_flight,
+					stats.timeouts,
+					stats.throughput()
+				);
+			}
 		}
-
-		Ok(false)
 	}
 
-	// Total numbers received on this chain, including the head and orphans
-	fn blocks_received(&self) -> Result<u64, chain::Error> {
-		Ok((self.chain.head()?).height
-			+ self.chain.orphans_len() as u64
-			+ self.chain.orphans_evicted_len() as u64)
+	// Whether we have that block, on our chain or as an orphan
+	fn have_block(&self, hash: &Hash) -> bool {
+		self.chain.block_exists(*hash).unwrap_or(false) || self.chain.is_orphan(hash)
 	}
 }
@@ -20,7 +20,7 @@ use std::sync::Arc;
 use crate::chain::{self, SyncState, SyncStatus};
 use crate::common::types::Error;
 use crate::core::core::hash::{Hash, Hashed};
-use crate::p2p::{self, types::ReasonForBan, Peer};
+use crate::p2p::{self, types::ReasonForBan, Capabilities, Peer, Penalty};
 
 pub struct HeaderSync {
 	sync_state: Arc<SyncState>,
@@ -133,6 +133,10 @@ impl HeaderSync {
 				if self.stalling_ts.is_none() {
 					self.stalling_ts = Some(now);
 				}
+				// the peer we asked did not send us any header in time
+				if let Some(ref peer) = self.syncing_peer {
+					self.peers.penalize(peer.info.addr, Penalty::SlowResponse);
+				}
 			} else {
 				self.stalling_ts = None;
 			}
@@ -180,7 +184,7 @@ impl HeaderSync {
 		if let Ok(header_head) = self.chain.header_head() {
 			let difficulty = header_head.total_difficulty;
 
-			if let Some(peer) = self.peers.most_work_peer() {
+			if let Some(peer) = self.peers.most_work_peer(Capabilities::HEADER_HIST) {
 				if peer.info.total_difficulty() > difficulty {
 					return self.request_headers(peer);
 				}
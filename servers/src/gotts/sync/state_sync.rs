@@ -15,12 +15,21 @@
 
 use chrono::prelude::{DateTime, Utc};
 use chrono::Duration;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
-use crate::chain::{self, SyncState, SyncStatus};
-use crate::core::core::hash::Hashed;
+use crate::chain::{self, SegmentType, SyncState, SyncStatus};
+use crate::core::core::hash::{Hash, Hashed};
+use crate::core::core::pmmr::SegmentIdentifier;
+use crate::core::core::BlockHeader;
 use crate::core::global;
-use crate::p2p::{self, Peer};
+use crate::p2p::{self, Capabilities, Peer, PeerAddr, ReasonForBan};
+
+/// Max number of segment requests in flight with a single peer.
+const MAX_SEGMENT_REQUESTS_PER_PEER: usize = 4;
+
+/// Seconds to wait for a requested segment before asking another peer.
+const SEGMENT_REQUEST_TIMEOUT_SECS: i64 = 30;
 
 /// Fast sync has 3 "states":
 /// * syncing headers
@@ -28,6 +37,12 @@ use crate::p2p::{self, Peer};
 /// * once we have the state, get blocks after that
 ///
 /// The StateSync struct implements and monitors the middle step.
+///
+/// When some of our peers can provide txhashset segments, the state is
+/// downloaded in segments, requested in parallel from all of them. Each
+/// segment is validated and saved as soon as it's received, so the download
+/// resumes where it stopped after a restart. Otherwise we fall back to asking
+/// a single peer for the whole txhashset archive.
 pub struct StateSync {
 	sync_state: Arc<SyncState>,
 	peers: Arc<p2p::Peers>,
@@ -35,6 +50,14 @@ pub struct StateSync {
 
 	prev_state_sync: Option<DateTime<Utc>>,
 	state_sync_peer: Option<Arc<Peer>>,
+
+	// header we download the txhashset segments for, if segmented
+	segments_hash: Option<Hash>,
+	segment_requests: HashMap<(SegmentType, SegmentIdentifier), (Arc<Peer>, DateTime<Utc>)>,
+	// peers not answering our segment requests (likely not serving this header)
+	slow_peers: HashSet<PeerAddr>,
+	segments_downloaded: u64,
+	prev_segments_check: Option<DateTime<Utc>>,
 }
 
 impl StateSync {
@@ -49,6 +72,11 @@ impl StateSync {
 			chain,
 			prev_state_sync: None,
 			state_sync_peer: None,
+			segments_hash: None,
+			segment_requests: HashMap::new(),
+			slow_peers: HashSet::new(),
+			segments_downloaded: 0,
+			prev_segments_check: None,
 		}
 	}
 
@@ -112,6 +140,14 @@ impl StateSync {
 			return false;
 		}
 
+		if !sync_need_restart {
+			if let Some(hash) = self.segments_hash {
+				if let SyncStatus::TxHashsetDownload { .. } = self.sync_state.status() {
+					self.check_segments(hash);
+				}
+			}
+		}
+
 		// run fast sync if applicable, normally only run one-time, except restart in error
 		if sync_need_restart || header_head.height == highest_height {
 			let (go, download_timeout) = self.state_sync_due();
@@ -129,7 +165,7 @@ impl StateSync {
 				self.state_sync_peer = None;
 				match self.request_state(&header_head) {
 					Ok(peer) => {
-						self.state_sync_peer = Some(peer);
+						self.state_sync_peer = peer;
 					}
 					Err(e) => self
 						.sync_state
@@ -161,52 +197,251 @@ impl StateSync {
 		true
 	}
 
-	fn request_state(&self, header_head: &chain::Tip) -> Result<Arc<Peer>, p2p::Error> {
+	/// Starts the txhashset download, in segments if any of our peers can
+	/// provide them, otherwise asking the most work peer for the archive.
+	/// Returns the peer we asked for the archive, if any.
+	fn request_state(&mut self, header_head: &chain::Tip) -> Result<Option<Arc<Peer>>, p2p::Error> {
+		let txhashset_head = self.txhashset_head(header_head)?;
+		let bhash = txhashset_head.hash();
+		debug!(
+			"state_sync: before txhashset request, header head: {} / {}, txhashset_head: {} / {}",
+			header_head.height, header_head.last_block_h, txhashset_head.height, bhash
+		);
+
+		if !self.segment_peers().is_empty() {
+			info!(
+				"state_sync: downloading txhashset segments at {} / {}",
+				txhashset_head.height, bhash
+			);
+			self.segments_hash = Some(bhash);
+			self.sync_state.set_txhashset_segments_hash(Some(bhash));
+			self.prev_segments_check = None;
+			return Ok(None);
+		}
+
+		if let Some(peer) = self.peers.most_work_peer(Capabilities::TXHASHSET_HIST) {
+			// ask for txhashset at state_sync_threshold
+			if let Err(e) = peer.send_txhashset_request(txhashset_head.height, bhash) {
+				error!("state_sync: send_txhashset_request err! {:?}", e);
+				return Err(e);
+			}
+			return Ok(Some(peer.clone()));
+		}
+		Err(p2p::Error::PeerException)
+	}
+
+	/// The header at state_sync_threshold (rounded to the archive interval)
+	/// we download the txhashset for.
+	fn txhashset_head(&self, header_head: &chain::Tip) -> Result<BlockHeader, p2p::Error> {
 		let threshold = global::state_sync_threshold() as u64;
 		let archive_interval = global::txhashset_archive_interval();
 		let mut txhashset_height = header_head.height.saturating_sub(threshold);
 		txhashset_height = txhashset_height.saturating_sub(txhashset_height % archive_interval);
 
-		if let Some(peer) = self.peers.most_work_peer() {
-			// ask for txhashset at state_sync_threshold
-			let mut txhashset_head = self
+		let mut txhashset_head = self
+			.chain
+			.get_block_header(&header_head.prev_block_h)
+			.map_err(|e| {
+				error!(
+					"chain error during getting a block header {}: {:?}",
+					&header_head.prev_block_h, e
+				);
+				p2p::Error::Internal
+			})?;
+		while txhashset_head.height > txhashset_height {
+			txhashset_head = self
 				.chain
-				.get_block_header(&header_head.prev_block_h)
+				.get_previous_header(&txhashset_head)
 				.map_err(|e| {
 					error!(
-						"chain error during getting a block header {}: {:?}",
-						&header_head.prev_block_h, e
+						"chain error during getting a previous block header {}: {:?}",
+						txhashset_head.hash(),
+						e
 					);
 					p2p::Error::Internal
 				})?;
-			while txhashset_head.height > txhashset_height {
-				txhashset_head = self
-					.chain
-					.get_previous_header(&txhashset_head)
-					.map_err(|e| {
-						error!(
-							"chain error during getting a previous block header {}: {:?}",
-							txhashset_head.hash(),
-							e
+		}
+		Ok(txhashset_head)
+	}
+
+	/// Connected peers able to provide txhashset segments.
+	fn segment_peers(&self) -> Vec<Arc<Peer>> {
+		self.peers
+			.connected_peers()
+			.into_iter()
+			.filter(|p| {
+				p.info
+					.capabilities
+					.contains(Capabilities::TXHASHSET_SEGMENTS)
+			})
+			.collect()
+	}
+
+	/// Monitors the segments download: builds the txhashset once we have all
+	/// the segments, otherwise (re)requests the missing ones.
+	fn check_segments(&mut self, hash: Hash) {
+		// no need to look at the segments on disk at every run
+		let now = Utc::now();
+		if let Some(prev) = self.prev_segments_check {
+			if now - prev < Duration::seconds(1) {
+				return;
+			}
+		}
+		self.prev_segments_check = Some(now);
+
+		let missing = match self.chain.txhashset_segments_missing(hash) {
+			Ok(missing) => missing,
+			Err(e) => {
+				self.sync_state.set_sync_error(e);
+				return;
+			}
+		};
+
+		if missing.is_empty() {
+			info!("state_sync: all txhashset segments received for {}", hash);
+			self.segment_requests.clear();
+			match self
+				.chain
+				.txhashset_write_segments(hash, self.sync_state.as_ref())
+			{
+				Ok(dishonest) => {
+					info!("state_sync: txhashset rebuilt from segments for {}", hash);
+					for addr in dishonest {
+						warn!(
+							"state_sync: {} sent us a segment with a wrong leaf set, banning",
+							addr
 						);
-						p2p::Error::Internal
-					})?;
-			}
-			let bhash = txhashset_head.hash();
-			debug!(
-				"state_sync: before txhashset request, header head: {} / {}, txhashset_head: {} / {}",
-				header_head.height,
-				header_head.last_block_h,
-				txhashset_head.height,
-				bhash
-			);
-			if let Err(e) = peer.send_txhashset_request(txhashset_head.height, bhash) {
-				error!("state_sync: send_txhashset_request err! {:?}", e);
-				return Err(e);
+						self.peers
+							.ban_peer(PeerAddr(addr), ReasonForBan::BadTxHashSet);
+					}
+				}
+				Err(e) => {
+					self.chain.clean_txhashset_sandbox();
+					error!(
+						"state_sync: failed to rebuild txhashset from segments: {}",
+						e
+					);
+					self.sync_state.set_sync_error(e);
+				}
 			}
-			return Ok(peer.clone());
+			return;
+		}
+
+		self.update_segments_progress(hash);
+
+		// forget the requests answered, or timed out to ask another peer
+		let missing_set: HashSet<_> = missing.iter().cloned().collect();
+		let timeout = Duration::seconds(SEGMENT_REQUEST_TIMEOUT_SECS);
+		let slow_peers = &mut self.slow_peers;
+		self.segment_requests.retain(|key, (peer, time)| {
+			if !missing_set.contains(key) {
+				return false;
+			}
+			if now - *time > timeout {
+				debug!(
+					"state_sync: segment {:?} request to {} timed out",
+					key, peer.info.addr
+				);
+				slow_peers.insert(peer.info.addr);
+				return false;
+			}
+			peer.is_connected()
+		});
+
+		let mut peers = self.segment_peers();
+		peers.retain(|p| !self.slow_peers.contains(&p.info.addr));
+		if peers.is_empty() {
+			// give all the peers another chance
+			self.slow_peers.clear();
+			peers = self.segment_peers();
+		}
+
+		// prefer the peers which didn't send us a disputed copy of the segment
+		let disputed = self
+			.chain
+			.txhashset_segments_disputed(hash)
+			.unwrap_or_default();
+
+		let mut in_flight: HashMap<PeerAddr, usize> = HashMap::new();
+		for (peer, _) in self.segment_requests.values() {
+			*in_flight.entry(peer.info.addr).or_insert(0) += 1;
+		}
+
+		for (segment_type, id) in missing {
+			if self.segment_requests.contains_key(&(segment_type, id)) {
+				continue;
+			}
+			let disputed_senders = disputed.get(&(segment_type, id.idx));
+			let peer = peers
+				.iter()
+				.filter(|p| {
+					in_flight.get(&p.info.addr).cloned().unwrap_or(0)
+						< MAX_SEGMENT_REQUESTS_PER_PEER
+				})
+				.min_by_key(|p| {
+					let sent_disputed = disputed_senders
+						.map(|x| x.contains(&p.info.addr.0))
+						.unwrap_or(false);
+					(
+						sent_disputed,
+						in_flight.get(&p.info.addr).cloned().unwrap_or(0),
+					)
+				});
+			let peer = match peer {
+				Some(peer) => peer,
+				None => break,
+			};
+			match peer.send_segment_request(hash, segment_type, id) {
+				Ok(()) => {
+					*in_flight.entry(peer.info.addr).or_insert(0) += 1;
+					self.segment_requests
+						.insert((segment_type, id), (peer.clone(), now));
+				}
+				Err(e) => {
+					debug!(
+						"state_sync: segment request to {} failed: {:?}",
+						peer.info.addr, e
+					);
+					self.slow_peers.insert(peer.info.addr);
+				}
+			}
+		}
+	}
+
+	/// Reports the download progress, from the segments saved so far. The
+	/// total size is estimated from the average size of those segments.
+	fn update_segments_progress(&mut self, hash: Hash) {
+		let (count, bytes, total) = match self.chain.txhashset_segments_downloaded(hash) {
+			Ok(res) => res,
+			Err(e) => {
+				warn!("state_sync: failed to read segments progress: {}", e);
+				return;
+			}
+		};
+		if count > self.segments_downloaded {
+			// making progress, no need to time out the download
+			self.prev_state_sync = Some(Utc::now());
+		}
+		self.segments_downloaded = count;
+
+		if let SyncStatus::TxHashsetDownload {
+			start_time,
+			update_time,
+			downloaded_size,
+			..
+		} = self.sync_state.status()
+		{
+			let total_size = if count > 0 { bytes * total / count } else { 0 };
+			self.sync_state
+				.update_txhashset_download(SyncStatus::TxHashsetDownload {
+					start_time,
+					prev_update_time: update_time,
+					update_time: Utc::now(),
+					prev_downloaded_size: downloaded_size,
+					downloaded_size: bytes,
+					total_size,
+				});
 		}
-		Err(p2p::Error::PeerException)
 	}
 
 	// For now this is a one-time thing (it can be slow) at initial startup.
@@ -231,5 +466,11 @@ impl StateSync {
 	fn state_sync_reset(&mut self) {
 		self.prev_state_sync = None;
 		self.state_sync_peer = None;
+		// the segments received so far are kept on disk, for the next try
+		self.segments_hash = None;
+		self.segment_requests.clear();
+		self.slow_peers.clear();
+		self.segments_downloaded = 0;
+		self.sync_state.set_txhashset_segments_hash(None);
 	}
 }
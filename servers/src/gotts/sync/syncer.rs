@@ -30,12 +30,14 @@ pub fn run_sync(
 	sync_state: Arc<SyncState>,
 	peers: Arc<p2p::Peers>,
 	chain: Arc<chain::Chain>,
+	config: p2p::P2PConfig,
+	light_mode: bool,
 	stop_state: Arc<StopState>,
 ) -> std::io::Result<std::thread::JoinHandle<()>> {
 	thread::Builder::new()
 		.name("sync".to_string())
 		.spawn(move || {
-			let runner = SyncRunner::new(sync_state, peers, chain, stop_state);
+			let runner = SyncRunner::new(sync_state, peers, chain, config, light_mode, stop_state);
 			runner.sync_loop();
 		})
 }
@@ -44,6 +46,9 @@ pub struct SyncRunner {
 	sync_state: Arc<SyncState>,
 	peers: Arc<p2p::Peers>,
 	chain: Arc<chain::Chain>,
+	config: p2p::P2PConfig,
+	// only the header chain is synced, no blocks nor txhashset
+	light_mode: bool,
 	stop_state: Arc<StopState>,
 }
 
@@ -52,12 +57,16 @@ impl SyncRunner {
 		sync_state: Arc<SyncState>,
 		peers: Arc<p2p::Peers>,
 		chain: Arc<chain::Chain>,
+		config: p2p::P2PConfig,
+		light_mode: bool,
 		stop_state: Arc<StopState>,
 	) -> SyncRunner {
 		SyncRunner {
 			sync_state,
 			peers,
 			chain,
+			config,
+			light_mode,
 			stop_state,
 		}
 	}
@@ -72,7 +81,7 @@ impl SyncRunner {
 			3
 		};
 
-		let head = self.chain.head()?;
+		let head = self.local_head()?;
 
 		let mut n = 0;
 		const MIN_PEERS: usize = 3;
@@ -131,6 +140,7 @@ impl SyncRunner {
 			self.sync_state.clone(),
 			self.peers.clone(),
 			self.chain.clone(),
+			&self.config,
 		);
 		let mut state_sync = StateSync::new(
 			self.sync_state.clone(),
@@ -168,7 +178,9 @@ impl SyncRunner {
 					// This triggers a chain compaction to keep out local node tidy.
 					// Note: Chain compaction runs with an internal threshold
 					// so can be safely run even if the node is restarted frequently.
-					unwrap_or_restart_loop!(self.chain.compact());
+					if !self.light_mode {
+						unwrap_or_restart_loop!(self.chain.compact());
+					}
 				}
 
 				// sleep for 10 secs but check stop signal every second
@@ -190,6 +202,11 @@ impl SyncRunner {
 			// except for state sync that only runs if body sync return true (means txhashset is needed)
 			unwrap_or_restart_loop!(header_sync.check_run(&header_head, highest_height));
 
+			// light nodes stop at the header chain
+			if self.light_mode {
+				continue;
+			}
+
 			let mut check_state_sync = false;
 			match self.sync_state.status() {
 				SyncStatus::TxHashsetDownload { .. }
@@ -226,9 +243,15 @@ impl SyncRunner {
 	/// Whether we're currently syncing the chain or we're fully caught up and
 	/// just receiving blocks through gossip.
 	fn needs_syncing(&self) -> Result<(bool, u64), chain::Error> {
-		let local_diff = self.chain.head()?.total_difficulty;
+		let local_diff = self.local_head()?.total_difficulty;
 		let mut is_syncing = self.sync_state.is_syncing();
-		let peer = self.peers.most_work_peer();
+		// a light node syncs headers from any peer, a full node needs blocks
+		let capab = if self.light_mode {
+			p2p::Capabilities::HEADER_HIST
+		} else {
+			p2p::Capabilities::FULL_NODE
+		};
+		let peer = self.peers.most_work_peer(capab);
 
 		let peer_info = if let Some(p) = peer {
 			p.info.clone()
@@ -241,7 +264,7 @@ impl SyncRunner {
 		// difficulty than us
 		if is_syncing {
 			if peer_info.total_difficulty() <= local_diff {
-				let ch = self.chain.head()?;
+				let ch = self.local_head()?;
 				info!(
 					"synchronized at {} @ {} [{}]",
 					local_diff.to_num(),
@@ -280,4 +303,13 @@ impl SyncRunner {
 		}
 		Ok((is_syncing, peer_info.height()))
 	}
+
+	/// Our head to compare with the peers, the header head for a light node.
+	fn local_head(&self) -> Result<chain::Tip, chain::Error> {
+		if self.light_mode {
+			self.chain.header_head()
+		} else {
+			self.chain.head()
+		}
+	}
 }
@@ -21,6 +21,7 @@ use clap::ArgMatches;
 use crate::api;
 use crate::config::GlobalConfig;
 use crate::p2p;
+use crate::p2p::Subnet;
 use crate::servers::ServerConfig;
 use crate::util::file::get_first_line;
 use term;
@@ -29,31 +30,34 @@ pub fn client_command(client_args: &ArgMatches<'_>, global_config: GlobalConfig)
 	// just get defaults from the global config
 	let server_config = global_config.members.unwrap().server;
 	let api_secret = get_first_line(server_config.api_secret_path.clone());
+	let owner_api_secret = get_first_line(server_config.owner_api_secret_path.clone());
 
 	match client_args.subcommand() {
 		("status", Some(_)) => {
 			show_status(&server_config, api_secret);
 		}
 		("listconnectedpeers", Some(_)) => {
-			list_connected_peers(&server_config, api_secret);
+			list_connected_peers(&server_config, owner_api_secret);
 		}
-		("ban", Some(peer_args)) => {
-			let peer = peer_args.value_of("peer").unwrap();
-
-			if let Ok(addr) = peer.parse() {
-				ban_peer(&server_config, &addr, api_secret);
-			} else {
-				panic!("Invalid peer address format");
-			}
+		("ban", Some(ban_args)) => {
+			let subnet = subnet_arg(ban_args);
+			let duration = ban_args
+				.value_of("duration")
+				.map(|d| d.parse().expect("Invalid ban duration"));
+			let permanent = ban_args.is_present("permanent");
+			let note = ban_args.value_of("note").map(|n| n.to_owned());
+			ban_subnet(
+				&server_config,
+				&subnet,
+				duration,
+				permanent,
+				note,
+				owner_api_secret,
+			);
 		}
-		("unban", Some(peer_args)) => {
-			let peer = peer_args.value_of("peer").unwrap();
-
-			if let Ok(addr) = peer.parse() {
-				unban_peer(&server_config, &addr, api_secret);
-			} else {
-				panic!("Invalid peer address format");
-			}
+		("unban", Some(unban_args)) => {
+			let subnet = subnet_arg(unban_args);
+			unban_subnet(&server_config, &subnet, owner_api_secret);
 		}
 		_ => panic!("Unknown client command, use 'gotts help client' for details"),
 	}
@@ -93,47 +97,72 @@ pub fn show_status(config: &ServerConfig, api_secret: Option<String>) {
 	println!()
 }
 
-pub fn ban_peer(config: &ServerConfig, peer_addr: &SocketAddr, api_secret: Option<String>) {
-	let params = "";
+/// The subnet given by the "subnet" argument, or the single address subnet
+/// of the "peer" argument.
+fn subnet_arg(args: &ArgMatches<'_>) -> Subnet {
+	if let Some(subnet) = args.value_of("subnet") {
+		match subnet.parse() {
+			Ok(subnet) => subnet,
+			Err(e) => panic!("Invalid subnet format: {}", e),
+		}
+	} else {
+		let peer = args.value_of("peer").unwrap();
+		match peer.parse::<SocketAddr>() {
+			Ok(addr) => Subnet::from_ip(addr.ip()),
+			Err(_) => panic!("Invalid peer address format"),
+		}
+	}
+}
+
+pub fn ban_subnet(
+	config: &ServerConfig,
+	subnet: &Subnet,
+	duration: Option<i64>,
+	permanent: bool,
+	note: Option<String>,
+	api_secret: Option<String>,
+) {
+	let params = api::BanRequest {
+		subnet: *subnet,
+		duration,
+		permanent,
+		note,
+	};
 	let mut e = term::stdout().unwrap();
-	let url = format!(
-		"http://{}/v1/peers/{}/ban",
-		config.api_http_addr,
-		peer_addr.to_string()
-	);
-	match api::client::post_no_ret(url.as_str(), api_secret, &params).map_err(|e| Error::API(e)) {
-		Ok(_) => writeln!(e, "Successfully banned peer {}", peer_addr.to_string()).unwrap(),
-		Err(_) => writeln!(e, "Failed to ban peer {}", peer_addr).unwrap(),
+	let res = owner_api_url(config, "/v1/bans").and_then(|url| {
+		api::client::post_no_ret(url.as_str(), api_secret, &params).map_err(|e| Error::API(e))
+	});
+	match res {
+		Ok(_) => writeln!(e, "Successfully banned {}", subnet).unwrap(),
+		Err(Error::OwnerApiDisabled) => writeln!(e, "{}", OWNER_API_DISABLED).unwrap(),
+		Err(_) => writeln!(e, "Failed to ban {}", subnet).unwrap(),
 	};
 	e.reset().unwrap();
 }
 
-pub fn unban_peer(config: &ServerConfig, peer_addr: &SocketAddr, api_secret: Option<String>) {
-	let params = "";
+pub fn unban_subnet(config: &ServerConfig, subnet: &Subnet, api_secret: Option<String>) {
+	let params = api::UnbanRequest { subnet: *subnet };
 	let mut e = term::stdout().unwrap();
-	let url = format!(
-		"http://{}/v1/peers/{}/unban",
-		config.api_http_addr,
-		peer_addr.to_string()
-	);
-	let res: Result<(), api::Error>;
-	res = api::client::post_no_ret(url.as_str(), api_secret, &params);
-
-	match res.map_err(|e| Error::API(e)) {
-		Ok(_) => writeln!(e, "Successfully unbanned peer {}", peer_addr).unwrap(),
-		Err(_) => writeln!(e, "Failed to unban peer {}", peer_addr).unwrap(),
+	let res = owner_api_url(config, "/v1/bans/unban").and_then(|url| {
+		api::client::post_no_ret(url.as_str(), api_secret, &params).map_err(|e| Error::API(e))
+	});
+
+	match res {
+		Ok(_) => writeln!(e, "Successfully unbanned {}", subnet).unwrap(),
+		Err(Error::OwnerApiDisabled) => writeln!(e, "{}", OWNER_API_DISABLED).unwrap(),
+		Err(_) => writeln!(e, "Failed to unban {}", subnet).unwrap(),
 	};
 	e.reset().unwrap();
 }
 
 pub fn list_connected_peers(config: &ServerConfig, api_secret: Option<String>) {
 	let mut e = term::stdout().unwrap();
-	let url = format!("http://{}/v1/peers/connected", config.api_http_addr);
-	// let peers_info: Result<Vec<p2p::PeerInfoDisplay>, api::Error>;
+	let peers_info = owner_api_url(config, "/v1/peers/connected").and_then(|url| {
+		api::client::get::<Vec<p2p::types::PeerInfoDisplay>>(url.as_str(), api_secret)
+			.map_err(|e| Error::API(e))
+	});
 
-	let peers_info = api::client::get::<Vec<p2p::types::PeerInfoDisplay>>(url.as_str(), api_secret);
-
-	match peers_info.map_err(|e| Error::API(e)) {
+	match peers_info {
 		Ok(connected_peers) => {
 			let mut index = 0;
 			for connected_peer in connected_peers {
@@ -149,12 +178,24 @@ pub fn list_connected_peers(config: &ServerConfig, api_secret: Option<String>) {
 				index = index + 1;
 			}
 		}
+		Err(Error::OwnerApiDisabled) => writeln!(e, "{}", OWNER_API_DISABLED).unwrap(),
 		Err(_) => writeln!(e, "Failed to get connected peers").unwrap(),
 	};
 
 	e.reset().unwrap();
 }
 
+const OWNER_API_DISABLED: &str = "owner_api_http_addr not set, the owner API is disabled";
+
+// The peers and bans are only served by the owner API.
+fn owner_api_url(config: &ServerConfig, path: &str) -> Result<String, Error> {
+	config
+		.owner_api_http_addr
+		.as_ref()
+		.map(|a| format!("http://{}{}", a, path))
+		.ok_or(Error::OwnerApiDisabled)
+}
+
 fn get_status_from_node(
 	config: &ServerConfig,
 	api_secret: Option<String>,
@@ -168,4 +209,6 @@ fn get_status_from_node(
 enum Error {
 	/// Error originating from HTTP API calls.
 	API(api::Error),
+	/// The owner API address is not configured.
+	OwnerApiDisabled,
 }
@@ -14,6 +14,7 @@
 // limitations under the License.
 
 /// Gotts server commands processing
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::process::exit;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
@@ -103,6 +104,18 @@ pub fn server_command(
 			server_config.api_http_addr = format!("{}:{}", default_ip, api_port);
 		}
 
+		// the owner api keeps its (local by default) ip, only the port changes
+		if let Some(owner_api_port) = a.value_of("owner_api_port") {
+			let ip = server_config
+				.owner_api_http_addr
+				.as_ref()
+				.and_then(|addr| addr.parse::<SocketAddr>().ok())
+				.map(|addr| addr.ip())
+				.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+			let port = owner_api_port.parse().expect("Invalid owner api port");
+			server_config.owner_api_http_addr = Some(SocketAddr::new(ip, port).to_string());
+		}
+
 		if let Some(wallet_url) = a.value_of("wallet_url") {
 			server_config
 				.stratum_mining_config
@@ -15,13 +15,14 @@
 
 //! Implementation of the persistent Backend for the prunable MMR tree.
 
+use std::collections::BTreeMap;
 use std::fs::{self, File};
 use std::{io, time};
 
-use crate::core::core::hash::{Hash, Hashed};
-use crate::core::core::pmmr::{self, family, Backend};
+use crate::core::core::hash::{Hash, Hashed, ZERO_HASH};
+use crate::core::core::pmmr::{self, family, Backend, Segment};
 use crate::core::core::BlockHeader;
-use crate::core::ser::{FixedLength, PMMRable, ProtocolVersion};
+use crate::core::ser::{FixedLength, PMMRIndexHashable, PMMRable, ProtocolVersion};
 use crate::leaf_set::LeafSet;
 use crate::prune_list::PruneList;
 use crate::types::{AppendOnlyFile, DataFile, SizeEntry, SizeInfo};
@@ -312,6 +313,133 @@ impl<T: PMMRable> PMMRBackend<T> {
 		self.leaf_set.flush()
 	}
 
+	/// Append a leaf at the provided position, when rebuilding the MMR from
+	/// segments. The leaf is added to the leaf_set unless it's spent.
+	pub fn append_leaf(
+		&mut self,
+		pos: u64,
+		data: &T::E,
+		hash: Hash,
+		spent: bool,
+	) -> io::Result<()> {
+		self.data_file.append(data)?;
+		self.hash_file.append(&hash)?;
+		if self.prunable && !spent {
+			self.leaf_set.add(pos);
+		}
+		Ok(())
+	}
+
+	/// Append the hash of a parent node, when rebuilding the MMR from segments.
+	pub fn append_hash(&mut self, hash: Hash) -> io::Result<()> {
+		self.hash_file.append(&hash)?;
+		Ok(())
+	}
+
+	/// Append the root of a pruned subtree, when rebuilding the MMR from
+	/// segments. Everything beneath it is considered compacted already.
+	pub fn append_pruned_root(&mut self, pos: u64, hash: Hash) -> io::Result<()> {
+		assert!(self.prunable, "Pruned subtree on non-prunable MMR");
+		self.hash_file.append(&hash)?;
+		self.prune_list.add(pos);
+		Ok(())
+	}
+
+	/// Rebuilds an empty backend from the segments of a MMR of the provided
+	/// size. Segments are requested in order through `get_segment` (by index)
+	/// and are expected to be validated already. Returns the root of the
+	/// rebuilt MMR, for the caller to check it against the expected one.
+	pub fn rebuild_from_segments<F>(
+		&mut self,
+		mmr_size: u64,
+		height: u8,
+		mut get_segment: F,
+	) -> Result<Hash, String>
+	where
+		T: PMMRable<E = T> + PMMRIndexHashable,
+		F: FnMut(u64) -> Result<Segment<T>, String>,
+	{
+		if self.unpruned_size() != 0 {
+			return Err("rebuild from segments on a non-empty MMR".to_owned());
+		}
+		let capacity = 1u64 << height;
+		let mut stack: Vec<Hash> = vec![];
+		let mut current = None;
+		// pruned roots by leftmost leaf pos, and leaf data by pos
+		let mut pruned: BTreeMap<u64, (u64, Hash)> = BTreeMap::new();
+		let mut leaves: BTreeMap<u64, (T, bool)> = BTreeMap::new();
+
+		let mut pos = 1;
+		while pos <= mmr_size {
+			if pmmr::is_leaf(pos) {
+				let idx = (pmmr::n_leaves(pos) - 1) / capacity;
+				if current != Some(idx) {
+					let segment = get_segment(idx)?;
+					pruned.clear();
+					leaves.clear();
+					for (root, hash) in segment.hashes {
+						pruned.insert(pmmr::bintree_leftmost(root), (root, hash));
+					}
+					for (leaf_pos, data) in segment.leaves {
+						let spent = segment.spent.contains(&leaf_pos);
+						leaves.insert(leaf_pos, (data, spent));
+					}
+					current = Some(idx);
+				}
+
+				if let Some((root, hash)) = pruned.remove(&pos) {
+					self.append_pruned_root(root, hash)
+						.map_err(|e| format!("Failed to append pruned root. {}", e))?;
+					stack.push(hash);
+					pos = root + 1;
+					continue;
+				}
+
+				let (data, spent) = leaves
+					.remove(&pos)
+					.ok_or_else(|| format!("missing leaf at pos {}", pos))?;
+				let hash = data.hash_with_index(pos - 1);
+				self.append_leaf(pos, &data, hash, spent)
+					.map_err(|e| format!("Failed to append leaf. {}", e))?;
+				stack.push(hash);
+			} else {
+				let right = stack.pop();
+				let left = stack.pop();
+				let hash = match (left, right) {
+					(Some(left), Some(right)) => (left, right).hash_with_index(pos - 1),
+					_ => return Err(format!("missing children at pos {}", pos)),
+				};
+				self.append_hash(hash)
+					.map_err(|e| format!("Failed to append hash. {}", e))?;
+				stack.push(hash);
+			}
+			pos += 1;
+		}
+		if pos != mmr_size + 1 {
+			return Err(format!("pruned subtree beyond mmr size {}", mmr_size));
+		}
+
+		// bag the peaks, which is all that's left on our stack
+		let mut root = None;
+		for peak in stack.iter().rev() {
+			root = match root {
+				None => Some(*peak),
+				Some(rhash) => Some((*peak, rhash).hash_with_index(mmr_size)),
+			};
+		}
+		Ok(root.unwrap_or(ZERO_HASH))
+	}
+
+	/// Syncs all files to disk after a rebuild from segments, including the
+	/// prune_list which is otherwise only updated on compaction.
+	pub fn sync_rebuilt(&mut self) -> io::Result<()> {
+		self.sync()?;
+		if self.prunable {
+			self.prune_list.flush()?;
+		}
+		Ok(())
+	}
+
 	/// Discard the current, non synced state of the backend.
 	pub fn discard(&mut self) {
 		self.hash_file.discard();
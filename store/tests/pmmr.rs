@@ -23,7 +23,7 @@ use chrono::prelude::Utc;
 use croaring::Bitmap;
 
 use crate::core::core::hash::{DefaultHashable, Hash, Hashed};
-use crate::core::core::pmmr::{Backend, PMMR};
+use crate::core::core::pmmr::{Backend, Segment, SegmentIdentifier, PMMR};
 use crate::core::core::transaction::KernelFeatures;
 use crate::core::ser::{
 	Error, FixedLength, PMMRIndexHashable, PMMRable, Readable, Reader, Writeable, Writer,
@@ -873,6 +873,68 @@ fn create_numbered_files(
 	}
 }
 
+#[test]
+fn pmmr_rebuild_from_segments() {
+	let (data_dir, elems) = setup("rebuild_from_segments");
+	let rebuilt_dir = format!("{}/rebuilt", data_dir);
+	{
+		let mut backend =
+			store::pmmr::PMMRBackend::new(data_dir.to_string(), true, false, None).unwrap();
+		let mmr_size = load(0, &elems[..], &mut backend);
+		backend.sync().unwrap();
+
+		// prune a whole subtree, a single leaf and a spent leaf left uncompacted
+		{
+			let mut pmmr: PMMR<'_, TestElem, _> = PMMR::at(&mut backend, mmr_size);
+			pmmr.prune(1).unwrap();
+			pmmr.prune(2).unwrap();
+			pmmr.prune(4).unwrap();
+			pmmr.prune(8).unwrap();
+			pmmr.prune(9).unwrap();
+			pmmr.prune(11).unwrap();
+			pmmr.prune(12).unwrap();
+		}
+		backend.sync().unwrap();
+		backend.check_compact(16, &Bitmap::create()).unwrap();
+		{
+			let mut pmmr: PMMR<'_, TestElem, _> = PMMR::at(&mut backend, mmr_size);
+			pmmr.prune(19).unwrap();
+		}
+		backend.sync().unwrap();
+
+		let pmmr: PMMR<'_, TestElem, _> = PMMR::at(&mut backend, mmr_size);
+		let root = pmmr.root().unwrap();
+
+		for height in 1..4 {
+			let segments = SegmentIdentifier::all_segments(mmr_size, height)
+				.into_iter()
+				.map(|id| Segment::from_pmmr(id, &pmmr.readonly_pmmr(), true).unwrap())
+				.collect::<Vec<_>>();
+			for segment in &segments {
+				segment.validate(mmr_size, root).unwrap();
+			}
+
+			let dir = format!("{}-{}", rebuilt_dir, height);
+			fs::create_dir_all(dir.clone()).unwrap();
+			let mut rebuilt =
+				store::pmmr::PMMRBackend::<TestElem>::new(dir, true, false, None).unwrap();
+			let rebuilt_root = rebuilt
+				.rebuild_from_segments(mmr_size, height, |idx| Ok(segments[idx as usize].clone()))
+				.unwrap();
+			rebuilt.sync_rebuilt().unwrap();
+			assert_eq!(rebuilt_root, root);
+			assert_eq!(rebuilt.unpruned_size(), mmr_size);
+
+			let rebuilt_pmmr: PMMR<'_, TestElem, _> = PMMR::at(&mut rebuilt, mmr_size);
+			assert_eq!(rebuilt_pmmr.root().unwrap(), root);
+			assert_eq!(rebuilt_pmmr.get_data(5).unwrap(), TestElem(4));
+			assert_eq!(rebuilt_pmmr.get_data(19), None);
+			assert_eq!(rebuilt_pmmr.get_data(32).unwrap(), TestElem(17));
+		}
+	}
+	teardown(data_dir);
+}
+
 fn setup(tag: &str) -> (String, Vec<TestElem>) {
 	match env_logger::try_init() {
 		Ok(_) => println!("Initializing env logger"),
@@ -29,7 +29,7 @@ extern crate lazy_static;
 #[macro_use]
 extern crate serde_derive;
 // Re-export so only has to be included once
-pub use parking_lot::Mutex;
+pub use parking_lot::{Condvar, Mutex};
 pub use parking_lot::{RwLock, RwLockReadGuard};
 
 // Re-export so only has to be included once
@@ -108,6 +108,11 @@ pub fn to_base64(s: &str) -> String {
 	base64::encode(s)
 }
 
+/// Encode bytes to a base64 string
+pub fn bytes_to_base64(b: &[u8]) -> String {
+	base64::encode(b)
+}
+
 /// Global stopped/paused state shared across various subcomponents of Gotts.
 ///
 /// "Stopped" allows a clean shutdown of the Gotts server.